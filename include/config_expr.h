@@ -0,0 +1,67 @@
+#ifndef CONFIG_EXPR_H
+#define CONFIG_EXPR_H
+
+/* Generated by cbindgen from src/ffi.rs; do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Opaque handle to a compiled rule set
+typedef struct ConfigExprEvaluator ConfigExprEvaluator;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Load and validate JSON rules
+//
+// Returns NULL on failure, including a panic inside the library, storing a
+// message in `*error` unless `error` is NULL. Release the evaluator with
+// [`config_expr_free`].
+//
+// # Safety
+//
+// `json` must be NULL or a NUL-terminated string; `error` must be NULL or
+// point to writable storage for a pointer.
+struct ConfigExprEvaluator *config_expr_new_from_json(const char *json, char **error);
+
+// Evaluate params given as a JSON object
+//
+// Returns the matching result as JSON, or the string `null` when no rule
+// matches. Returns NULL on failure (invalid params, a matching assertion
+// rule, a panic inside the library), storing a message in `*error` unless
+// `error` is NULL. Release the returned string with
+// [`config_expr_string_free`].
+//
+// # Safety
+//
+// `evaluator` must be NULL or a live pointer returned by
+// [`config_expr_new_from_json`]; `params_json` and `error` as for
+// [`config_expr_new_from_json`].
+char *config_expr_evaluate(const struct ConfigExprEvaluator *evaluator,
+                           const char *params_json,
+                           char **error);
+
+// Release an evaluator; NULL is ignored
+//
+// # Safety
+//
+// `evaluator` must be NULL or a pointer returned by
+// [`config_expr_new_from_json`] that has not been freed yet.
+void config_expr_free(struct ConfigExprEvaluator *evaluator);
+
+// Release a string returned by the library; NULL is ignored
+//
+// # Safety
+//
+// `s` must be NULL or a string returned by this library that has not been
+// freed yet.
+void config_expr_string_free(char *s);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CONFIG_EXPR_H */
@@ -0,0 +1,697 @@
+//! Static analysis of rule sets
+//!
+//! The analyses reason about conditions symbolically. They are conservative:
+//! when two conditions cannot be proven disjoint (e.g. two regexes, or a
+//! script), they are assumed to overlap.
+
+use crate::expr;
+use crate::membership::item_matches;
+use crate::number::Number;
+use crate::prelude::*;
+use crate::report::Phase;
+use crate::version;
+use crate::{Condition, ConfigEvaluator, MatchMode, Operator, Rule};
+use core::fmt;
+#[cfg(feature = "regex")]
+use regex::Regex;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Upper bound on the number of conjunctions a condition expands to
+const MAX_DNF_TERMS: usize = 256;
+
+/// Two rules whose relative order matters
+///
+/// Some input may satisfy both conditions while the rules return different
+/// results, so swapping them would change the outcome for that input.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct OrderConflict {
+    /// Index of the rule evaluated first
+    pub first: usize,
+    /// Index of the rule evaluated later
+    pub second: usize,
+    pub first_id: Option<String>,
+    pub second_id: Option<String>,
+}
+
+/// Problem found by [`ConfigEvaluator::analyze`]
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct AnalysisWarning {
+    /// Index of the main-phase rule the warning is about
+    pub rule: usize,
+    pub rule_id: Option<String>,
+    pub kind: WarningKind,
+}
+
+/// Why a rule can never be selected
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum WarningKind {
+    /// Every input matching the rule also matches an earlier rule, which
+    /// is selected instead
+    Shadowed { by: usize, by_id: Option<String> },
+    /// No input can satisfy the rule's condition
+    Unsatisfiable,
+}
+
+impl fmt::Display for AnalysisWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = |index: usize, id: &Option<String>| match id {
+            Some(id) => format!("rule {} ('{}')", index, id),
+            None => format!("rule {}", index),
+        };
+        write!(f, "R{}", &label(self.rule, &self.rule_id)[1..])?;
+        match &self.kind {
+            WarningKind::Shadowed { by, by_id } => write!(
+                f,
+                " is unreachable: {} matches every input it does",
+                label(*by, by_id)
+            ),
+            WarningKind::Unsatisfiable => {
+                write!(f, " can never match: its condition is unsatisfiable")
+            }
+        }
+    }
+}
+
+/// A condition in disjunctive normal form: any conjunction of simple or
+/// negated conditions may hold. `None` means the expansion was too large to
+/// analyze.
+pub(crate) type Dnf<'a> = Option<Vec<Vec<&'a Condition>>>;
+
+/// Expand a condition into disjunctive normal form
+pub(crate) fn to_dnf(condition: &Condition) -> Dnf<'_> {
+    match condition {
+        // Negations and unresolved refs are kept as opaque literals
+        Condition::Simple { .. } | Condition::Not { .. } | Condition::Ref { .. } => {
+            Some(vec![vec![condition]])
+        }
+        Condition::Or { or } => {
+            let mut terms = Vec::new();
+            for c in or {
+                terms.extend(to_dnf(c)?);
+                if terms.len() > MAX_DNF_TERMS {
+                    return None;
+                }
+            }
+            Some(terms)
+        }
+        Condition::And { and } => {
+            let mut terms = vec![Vec::new()];
+            for c in and {
+                let sub = to_dnf(c)?;
+                if terms.len() * sub.len() > MAX_DNF_TERMS {
+                    return None;
+                }
+                terms = terms
+                    .iter()
+                    .flat_map(|term| {
+                        sub.iter().map(move |s| {
+                            let mut merged = term.clone();
+                            merged.extend(s.iter().copied());
+                            merged
+                        })
+                    })
+                    .collect();
+            }
+            Some(terms)
+        }
+    }
+}
+
+/// Numeric bound of a comparison: (value, inclusive)
+type Bound = (Number, bool);
+
+/// Number a condition value compares against, if any
+fn condition_number(value: &Value) -> Option<Number> {
+    match value {
+        Value::String(s) => Number::parse(s.trim()),
+        Value::Number(n) => Number::from_json(n),
+        _ => None,
+    }
+}
+
+/// Interval of numbers accepted by a numeric comparison
+fn numeric_interval(op: &Operator, value: &Value) -> Option<(Option<Bound>, Option<Bound>)> {
+    if *op == Operator::Between {
+        let (min, max) = crate::numeric_range(value)?;
+        return Some((Some((min, true)), Some((max, true))));
+    }
+    let n = condition_number(value)?;
+    match op {
+        Operator::GreaterThan => Some((Some((n, false)), None)),
+        Operator::GreaterThanOrEqual => Some((Some((n, true)), None)),
+        Operator::LessThan => Some((None, Some((n, false)))),
+        Operator::LessThanOrEqual => Some((None, Some((n, true)))),
+        // Number values are compared numerically by `equals`
+        Operator::Equals if value.is_number() => Some((Some((n, true)), Some((n, true)))),
+        _ => None,
+    }
+}
+
+fn intervals_disjoint(
+    (low_a, high_a): (Option<Bound>, Option<Bound>),
+    (low_b, high_b): (Option<Bound>, Option<Bound>),
+) -> bool {
+    let below = |high: Option<Bound>, low: Option<Bound>| match (high, low) {
+        (Some((h, h_incl)), Some((l, l_incl))) => h < l || (h == l && !(h_incl && l_incl)),
+        _ => false,
+    };
+    below(high_a, low_b) || below(high_b, low_a)
+}
+
+/// Check whether a concrete param value satisfies a simple condition, if decidable
+fn literal_matches(op: &Operator, value: &Value, literal: &str) -> Option<bool> {
+    if let Some(interval) = numeric_interval(op, value) {
+        return Some(match Number::parse(literal.trim()) {
+            Some(n) => !intervals_disjoint(interval, (Some((n, true)), Some((n, true)))),
+            None => false,
+        });
+    }
+    if let Value::Array(items) = value {
+        let listed = items.iter().any(|item| item_matches(item, literal));
+        return match op {
+            Operator::In => Some(listed),
+            Operator::NotIn => Some(!listed),
+            _ => None,
+        };
+    }
+    let value = value.as_str()?;
+    match op {
+        Operator::Equals => Some(literal == value),
+        Operator::Contains => Some(literal.contains(value)),
+        Operator::Prefix => Some(literal.starts_with(value)),
+        Operator::Suffix => Some(literal.ends_with(value)),
+        #[cfg(feature = "regex")]
+        Operator::Regex => Regex::new(value).ok().map(|re| re.is_match(literal)),
+        #[cfg(feature = "regex")]
+        Operator::Glob => crate::glob(value).ok().map(|glob| glob.is_match(literal)),
+        _ if op.is_semver() => Some(version::satisfies(op, literal, value)),
+        Operator::IEquals | Operator::IContains | Operator::IPrefix | Operator::ISuffix => {
+            let (literal, value) = (literal.to_lowercase(), value.to_lowercase());
+            Some(match op {
+                Operator::IEquals => literal == value,
+                Operator::IContains => literal.contains(&value),
+                Operator::IPrefix => literal.starts_with(&value),
+                _ => literal.ends_with(&value),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Check whether two simple conditions see the same param values
+///
+/// Normalized conditions are not analyzed, since their literal values may
+/// differ from what they match, and neither are conditions with a `mode`,
+/// since different values of a repeated param may satisfy each.
+fn same_preparation(a: &Condition, b: &Condition) -> bool {
+    matches!(
+        (a, b),
+        (
+            Condition::Simple {
+                trim: trim_a,
+                normalize: None,
+                mode: None,
+                ..
+            },
+            Condition::Simple {
+                trim: trim_b,
+                normalize: None,
+                mode: None,
+                ..
+            },
+        ) if trim_a == trim_b
+    )
+}
+
+/// Check whether two simple or negated conditions can never hold at the same time
+pub(crate) fn simple_disjoint(a: &Condition, b: &Condition) -> bool {
+    match (a, b) {
+        (Condition::Not { not }, other) | (other, Condition::Not { not }) => {
+            return **not == *other
+        }
+        _ => {}
+    }
+    let (
+        Condition::Simple {
+            field: field_a,
+            op: op_a,
+            value: value_a,
+            value_field: None,
+            ..
+        },
+        Condition::Simple {
+            field: field_b,
+            op: op_b,
+            value: value_b,
+            value_field: None,
+            ..
+        },
+    ) = (a, b)
+    else {
+        // Comparisons against other params are not analyzed
+        return false;
+    };
+    if field_a != field_b || !same_preparation(a, b) {
+        return false;
+    }
+    // Only `missing` and `equals null` hold for an absent param
+    let absent = |op: &Operator, value: &Value| {
+        *op == Operator::Missing || (*op == Operator::Equals && value.is_null())
+    };
+    if absent(op_a, value_a) != absent(op_b, value_b) {
+        return true;
+    }
+    if let (Some(a), Some(b)) = (
+        numeric_interval(op_a, value_a),
+        numeric_interval(op_b, value_b),
+    ) {
+        return intervals_disjoint(a, b);
+    }
+
+    let excludes = |op: &Operator, value: &Value, literal: &Value| {
+        literal
+            .as_str()
+            .is_some_and(|literal| literal_matches(op, value, literal) == Some(false))
+    };
+    match (op_a, value_a.as_str(), op_b, value_b.as_str()) {
+        (Operator::Equals, _, _, _) => excludes(op_b, value_b, value_a),
+        (_, _, Operator::Equals, _) => excludes(op_a, value_a, value_b),
+        (Operator::Prefix, Some(a), Operator::Prefix, Some(b)) => {
+            !a.starts_with(b) && !b.starts_with(a)
+        }
+        (Operator::Suffix, Some(a), Operator::Suffix, Some(b)) => {
+            !a.ends_with(b) && !b.ends_with(a)
+        }
+        _ => false,
+    }
+}
+
+/// Check whether some input may satisfy every condition of a conjunction
+pub(crate) fn conjunction_satisfiable(term: &[&Condition]) -> bool {
+    term.iter().enumerate().all(|(i, a)| {
+        // A numeric comparison against a non-numeric value never matches
+        if let Condition::Simple {
+            op,
+            value,
+            value_field: None,
+            ..
+        } = a
+        {
+            if op.is_numeric()
+                && condition_number(value).is_none()
+                && !value.as_str().is_some_and(expr::has_placeholders)
+            {
+                return false;
+            }
+        }
+        term[i + 1..].iter().all(|b| !simple_disjoint(a, b))
+    })
+}
+
+/// Check whether some input may satisfy both conditions
+pub(crate) fn may_overlap(a: &Condition, b: &Condition) -> bool {
+    let (Some(dnf_a), Some(dnf_b)) = (to_dnf(a), to_dnf(b)) else {
+        return true;
+    };
+    dnf_a.iter().any(|term_a| {
+        dnf_b.iter().any(|term_b| {
+            let mut term = term_a.clone();
+            term.extend(term_b.iter().copied());
+            conjunction_satisfiable(&term)
+        })
+    })
+}
+
+/// Check whether a simple or negated condition holds whenever `b` holds
+fn literal_implies(b: &Condition, a: &Condition) -> bool {
+    if a == b {
+        return true;
+    }
+    if let Condition::Not { not } = a {
+        return simple_disjoint(b, not);
+    }
+    let (
+        Condition::Simple {
+            field: field_a,
+            op: op_a,
+            value: value_a,
+            value_field: None,
+            ..
+        },
+        Condition::Simple {
+            field: field_b,
+            op: op_b,
+            value: value_b,
+            value_field: value_field_b,
+            ..
+        },
+    ) = (a, b)
+    else {
+        return false;
+    };
+    if field_a != field_b || !same_preparation(a, b) {
+        return false;
+    }
+    if value_field_b.is_some() {
+        // Comparisons against other params require the param to be present
+        return *op_a == Operator::Exists;
+    }
+    // Every condition but `missing` and `equals null` requires a present param
+    let absent = *op_b == Operator::Missing || (*op_b == Operator::Equals && value_b.is_null());
+    if *op_a == Operator::Exists {
+        return !absent;
+    }
+    if let (Some((low_a, high_a)), Some((low_b, high_b))) = (
+        numeric_interval(op_a, value_a),
+        numeric_interval(op_b, value_b),
+    ) {
+        let low_within = match (low_a, low_b) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some((a, a_incl)), Some((b, b_incl))) => b > a || (b == a && (a_incl || !b_incl)),
+        };
+        let high_within = match (high_a, high_b) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some((a, a_incl)), Some((b, b_incl))) => b < a || (b == a && (a_incl || !b_incl)),
+        };
+        return low_within && high_within;
+    }
+    // Values `b` pins the param to, which must all satisfy `a`
+    let pinned: Vec<&str> = match (op_b, value_b) {
+        (Operator::Equals, Value::String(s)) => vec![s.as_str()],
+        (Operator::In, Value::Array(items)) if items.iter().all(Value::is_string) => {
+            items.iter().filter_map(Value::as_str).collect()
+        }
+        _ => Vec::new(),
+    };
+    if !pinned.is_empty() {
+        return pinned
+            .iter()
+            .all(|literal| literal_matches(op_a, value_a, literal) == Some(true));
+    }
+    // Every value with the prefix (suffix) `b` starts (ends) with also
+    // starts (ends) with, or contains, a part of it
+    match (op_a, value_a.as_str(), op_b, value_b.as_str()) {
+        (Operator::Prefix, Some(a), Operator::Prefix, Some(b)) => b.starts_with(a),
+        (Operator::Suffix, Some(a), Operator::Suffix, Some(b)) => b.ends_with(a),
+        (
+            Operator::Contains,
+            Some(a),
+            Operator::Prefix | Operator::Suffix | Operator::Contains,
+            Some(b),
+        ) => b.contains(a),
+        _ => false,
+    }
+}
+
+/// Check whether `a` holds for every input satisfying `b`
+///
+/// Conservative: `false` when the implication cannot be proven.
+pub(crate) fn implies(b: &Condition, a: &Condition) -> bool {
+    let (Some(dnf_a), Some(dnf_b)) = (to_dnf(a), to_dnf(b)) else {
+        return false;
+    };
+    dnf_b.iter().all(|term_b| {
+        !conjunction_satisfiable(term_b)
+            || dnf_a.iter().any(|term_a| {
+                term_a
+                    .iter()
+                    .all(|lit_a| term_b.iter().any(|lit_b| literal_implies(lit_b, lit_a)))
+            })
+    })
+}
+
+/// Check whether a rule matches every input satisfying its condition, i.e.
+/// nothing but the condition decides whether it is selected
+pub(crate) fn always_active(rule: &Rule) -> bool {
+    rule.enabled
+        && rule.active_from.is_none()
+        && rule.active_until.is_none()
+        && rule.rollout.is_none()
+        && rule.schedule.is_none()
+}
+
+impl ConfigEvaluator {
+    /// Find main-phase rules that can never be selected
+    ///
+    /// Reports rules whose condition is unsatisfiable and, when the first
+    /// matching rule wins, rules shadowed by an earlier rule matching every
+    /// input they match. Shadowing is proven for comparisons such as
+    /// `equals`, `prefix`, `in` and numeric ranges; other rules are assumed
+    /// reachable.
+    pub fn analyze(&self) -> Vec<AnalysisWarning> {
+        let first_wins =
+            self.rules.match_mode == MatchMode::First && self.rules.merge_strategy.is_first();
+        let rules: Vec<(usize, &Rule)> = self.ordered_rules(Phase::Main).collect();
+        let mut warnings = Vec::new();
+        for (position, &(index, rule)) in rules.iter().enumerate() {
+            if !rule.enabled {
+                continue;
+            }
+            let condition = self.rules.inline_refs(&rule.condition);
+            let satisfiable = match to_dnf(&condition) {
+                Some(dnf) => dnf.iter().any(|term| conjunction_satisfiable(term)),
+                None => true,
+            };
+            let kind = if !satisfiable {
+                Some(WarningKind::Unsatisfiable)
+            } else if first_wins {
+                rules[..position]
+                    .iter()
+                    .find(|(_, earlier)| {
+                        always_active(earlier)
+                            && implies(&condition, &self.rules.inline_refs(&earlier.condition))
+                    })
+                    .map(|&(by, earlier)| WarningKind::Shadowed {
+                        by,
+                        by_id: earlier.id.clone(),
+                    })
+            } else {
+                None
+            };
+            if let Some(kind) = kind {
+                warnings.push(AnalysisWarning {
+                    rule: index,
+                    rule_id: rule.id.clone(),
+                    kind,
+                });
+            }
+        }
+        warnings
+    }
+
+    /// Find pairs of main-phase rules whose relative order affects the outcome
+    ///
+    /// An empty list means the rules can be safely reordered or evaluated in
+    /// parallel. Rule activation settings (rollouts, schedules) are ignored.
+    pub fn order_conflicts(&self) -> Vec<OrderConflict> {
+        let rules: Vec<(usize, &Rule)> = self.ordered_rules(Phase::Main).collect();
+        let mut conflicts = Vec::new();
+        for (position, &(i, first)) in rules.iter().enumerate() {
+            for &(j, second) in &rules[position + 1..] {
+                let same_result = first.result == second.result
+                    && first.transform == second.transform
+                    && first.interpolate == second.interpolate;
+                if !same_result
+                    && may_overlap(
+                        &self.rules.inline_refs(&first.condition),
+                        &self.rules.inline_refs(&second.condition),
+                    )
+                {
+                    conflicts.push(OrderConflict {
+                        first: i,
+                        second: j,
+                        first_id: first.id.clone(),
+                        second_id: second.id.clone(),
+                    });
+                }
+            }
+        }
+        conflicts
+    }
+}
+
+#[cfg(all(test, feature = "regex"))]
+mod tests {
+    use super::*;
+
+    fn condition(json: &str) -> Condition {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_disjoint_conditions() {
+        let rtd = condition(r#"{ "field": "platform", "op": "equals", "value": "RTD" }"#);
+        let mt = condition(r#"{ "field": "platform", "op": "prefix", "value": "MT" }"#);
+        let hi = condition(r#"{ "field": "platform", "op": "regex", "value": "^Hi\\d+" }"#);
+        let region = condition(r#"{ "field": "region", "op": "equals", "value": "CN" }"#);
+        assert!(!may_overlap(&rtd, &mt));
+        assert!(!may_overlap(&rtd, &hi));
+        assert!(may_overlap(&rtd, &region));
+        assert!(may_overlap(&mt, &hi));
+
+        let low = condition(r#"{ "field": "score", "op": "lt", "value": "60" }"#);
+        let high = condition(r#"{ "field": "score", "op": "ge", "value": "60" }"#);
+        let mid = condition(r#"{ "field": "score", "op": "le", "value": "60" }"#);
+        assert!(!may_overlap(&low, &high));
+        assert!(may_overlap(&mid, &high));
+
+        let not_rtd = Condition::Not {
+            not: Box::new(rtd.clone()),
+        };
+        assert!(!may_overlap(&rtd, &not_rtd));
+        assert!(may_overlap(&mt, &not_rtd));
+
+        let missing = condition(r#"{ "field": "platform", "op": "missing" }"#);
+        let exists = condition(r#"{ "field": "platform", "op": "exists" }"#);
+        assert!(!may_overlap(&missing, &rtd));
+        assert!(!may_overlap(&missing, &exists));
+        assert!(may_overlap(&exists, &rtd));
+    }
+
+    #[test]
+    fn test_order_conflicts() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "id": "rtd_cn",
+                    "if": {
+                        "and": [
+                            { "field": "platform", "op": "prefix", "value": "RTD" },
+                            { "field": "region", "op": "equals", "value": "CN" }
+                        ]
+                    },
+                    "then": "chip_rtd_cn"
+                },
+                {
+                    "id": "mt",
+                    "if": {
+                        "or": [
+                            { "field": "platform", "op": "equals", "value": "MT9950" },
+                            { "field": "platform", "op": "equals", "value": "MT9638" }
+                        ]
+                    },
+                    "then": "chip_mt"
+                },
+                {
+                    "id": "rtd",
+                    "if": { "field": "platform", "op": "prefix", "value": "RTD" },
+                    "then": "chip_rtd"
+                }
+            ]
+        }
+        "#;
+
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+        let conflicts = evaluator.order_conflicts();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!((conflicts[0].first, conflicts[0].second), (0, 2));
+        assert_eq!(conflicts[0].first_id.as_deref(), Some("rtd_cn"));
+    }
+
+    #[test]
+    fn test_implies() {
+        let rtd = condition(r#"{ "field": "platform", "op": "prefix", "value": "RTD" }"#);
+        let rtd28 = condition(r#"{ "field": "platform", "op": "prefix", "value": "RTD28" }"#);
+        let listed =
+            condition(r#"{ "field": "platform", "op": "in", "value": ["RTD2851", "RTD2873"] }"#);
+        let exact = condition(r#"{ "field": "platform", "op": "equals", "value": "MT9950" }"#);
+        assert!(implies(&rtd28, &rtd));
+        assert!(!implies(&rtd, &rtd28));
+        assert!(implies(&listed, &rtd28));
+        assert!(!implies(&exact, &rtd));
+
+        let adult = condition(r#"{ "field": "age", "op": "ge", "value": "18" }"#);
+        let senior = condition(r#"{ "field": "age", "op": "gt", "value": "65" }"#);
+        assert!(implies(&senior, &adult));
+        assert!(!implies(&adult, &senior));
+
+        let rtd_cn = condition(
+            r#"{ "and": [
+                { "field": "platform", "op": "prefix", "value": "RTD28" },
+                { "field": "region", "op": "equals", "value": "CN" }
+            ] }"#,
+        );
+        assert!(implies(&rtd_cn, &rtd));
+        let rtd_or_mt = condition(
+            r#"{ "or": [
+                { "field": "platform", "op": "prefix", "value": "RTD" },
+                { "field": "platform", "op": "prefix", "value": "MT" }
+            ] }"#,
+        );
+        assert!(implies(&rtd_cn, &rtd_or_mt));
+        assert!(!implies(&rtd_or_mt, &rtd));
+        assert!(implies(&exact, &Condition::Not { not: Box::new(rtd) }));
+    }
+
+    #[test]
+    fn test_analyze() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "id": "rtd",
+                    "if": { "field": "platform", "op": "prefix", "value": "RTD" },
+                    "then": "chip_rtd"
+                },
+                {
+                    "id": "rtd_cn",
+                    "if": {
+                        "and": [
+                            { "field": "platform", "op": "in", "value": ["RTD2851", "RTD2873"] },
+                            { "field": "region", "op": "equals", "value": "CN" }
+                        ]
+                    },
+                    "then": "chip_rtd_cn"
+                },
+                {
+                    "if": {
+                        "and": [
+                            { "field": "score", "op": "lt", "value": "60" },
+                            { "field": "score", "op": "ge", "value": "90" }
+                        ]
+                    },
+                    "then": "impossible"
+                },
+                {
+                    "if": { "field": "platform", "op": "prefix", "value": "MT" },
+                    "then": "chip_mt",
+                    "rollout": { "percent": 50, "bucket_by": "device_id" }
+                },
+                {
+                    "if": { "field": "platform", "op": "equals", "value": "MT9950" },
+                    "then": "chip_mt9950"
+                }
+            ]
+        }
+        "#;
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+        let warnings = evaluator.analyze();
+        assert_eq!(
+            warnings,
+            vec![
+                AnalysisWarning {
+                    rule: 1,
+                    rule_id: Some("rtd_cn".to_string()),
+                    kind: WarningKind::Shadowed {
+                        by: 0,
+                        by_id: Some("rtd".to_string())
+                    },
+                },
+                AnalysisWarning {
+                    rule: 2,
+                    rule_id: None,
+                    kind: WarningKind::Unsatisfiable,
+                },
+            ]
+        );
+        assert_eq!(
+            warnings[0].to_string(),
+            "Rule 1 ('rtd_cn') is unreachable: rule 0 ('rtd') matches every input it does"
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! Command-line tool for validating and testing rule files
+//!
+//! ```text
+//! config-expr validate rules.json other.yaml
+//! config-expr eval rules.json --param platform=RTD-2000 --param region=CN
+//! config-expr explain rules.json --param platform=RTD-2000
+//! ```
+
+use clap::{Parser, Subcommand};
+use clia_config_expr::ConfigEvaluator;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+#[derive(Parser)]
+#[command(
+    name = "config-expr",
+    version,
+    about = "Validate and evaluate config-expr rule files"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Check that rule files parse and validate
+    Validate {
+        /// Rule files (JSON, YAML or TOML)
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Report suspicious but valid rules; exits non-zero on warnings
+    Lint {
+        /// Rule files (JSON, YAML or TOML)
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Evaluate a rule file and print the result as JSON
+    Eval {
+        file: PathBuf,
+        /// Request param, repeatable
+        #[arg(short, long = "param", value_name = "KEY=VALUE", value_parser = parse_param)]
+        params: Vec<(String, String)>,
+    },
+    /// Evaluate a rule file and print why each rule matched or not
+    Explain {
+        file: PathBuf,
+        /// Request param, repeatable
+        #[arg(short, long = "param", value_name = "KEY=VALUE", value_parser = parse_param)]
+        params: Vec<(String, String)>,
+    },
+    /// Show what changes between two rule files
+    Diff { old: PathBuf, new: PathBuf },
+}
+
+/// Parse a `key=value` param
+fn parse_param(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got '{}'", arg)),
+    }
+}
+
+fn main() -> ExitCode {
+    match Cli::parse().command {
+        Command::Validate { files } => {
+            let mut failed = false;
+            for file in files {
+                match ConfigEvaluator::from_file(&file) {
+                    Ok(_) => println!("{}: ok", file.display()),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        failed = true;
+                    }
+                }
+            }
+            if failed {
+                ExitCode::FAILURE
+            } else {
+                ExitCode::SUCCESS
+            }
+        }
+        Command::Lint { files } => {
+            let mut failed = false;
+            for file in files {
+                match ConfigEvaluator::from_file(&file) {
+                    Ok(evaluator) => {
+                        for warning in evaluator.rules().lint() {
+                            println!("{}: {}: {}", file.display(), warning.pointer, warning);
+                            failed = true;
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        failed = true;
+                    }
+                }
+            }
+            if failed {
+                ExitCode::FAILURE
+            } else {
+                ExitCode::SUCCESS
+            }
+        }
+        Command::Eval { file, params } => {
+            let params: HashMap<String, String> = params.into_iter().collect();
+            let result = ConfigEvaluator::from_file(&file)
+                .and_then(|evaluator| evaluator.evaluate_checked(&params))
+                .and_then(|result| Ok(serde_json::to_string_pretty(&result)?));
+            match result {
+                Ok(json) => {
+                    println!("{}", json);
+                    ExitCode::SUCCESS
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    ExitCode::FAILURE
+                }
+            }
+        }
+        Command::Explain { file, params } => {
+            let params: HashMap<String, String> = params.into_iter().collect();
+            match ConfigEvaluator::from_file(&file) {
+                Ok(evaluator) => {
+                    let trace = evaluator.evaluate_with_trace(&params);
+                    println!("{}", trace);
+                    if trace.error.is_some() {
+                        ExitCode::FAILURE
+                    } else {
+                        ExitCode::SUCCESS
+                    }
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    ExitCode::FAILURE
+                }
+            }
+        }
+        Command::Diff { old, new } => {
+            let loaded = ConfigEvaluator::from_file(&old)
+                .and_then(|old| Ok((old, ConfigEvaluator::from_file(&new)?)));
+            match loaded {
+                Ok((old, new)) => {
+                    print!("{}", old.rules().diff(new.rules()));
+                    ExitCode::SUCCESS
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    ExitCode::FAILURE
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_param() {
+        assert_eq!(
+            parse_param("device={\"a\":1}").unwrap(),
+            ("device".to_string(), "{\"a\":1}".to_string())
+        );
+        assert_eq!(
+            parse_param("region=").unwrap(),
+            ("region".to_string(), String::new())
+        );
+        assert!(parse_param("region").is_err());
+        assert!(parse_param("=CN").is_err());
+    }
+}
@@ -0,0 +1,222 @@
+//! Compact binary encoding of evaluators
+//!
+//! [`ConfigEvaluator::to_bytes`] encodes the rule set as CBOR, with
+//! conditions already parsed into trees. Like every serialized evaluator,
+//! the bytes are trusted to hold rules that were validated when first
+//! loaded, so devices can ship them and skip JSON parsing and validation at
+//! boot; the conditions are compiled again when the bytes are loaded. Rules
+//! hold arbitrary JSON values, which need a self-describing format; bincode
+//! and postcard cannot decode them.
+
+use crate::prelude::*;
+use crate::report::Phase;
+use crate::validation::Site;
+use crate::{Condition, ConfigEvaluator, ConfigExprError, ConfigRules, EvaluatorData};
+use alloc::collections::BTreeMap;
+
+/// Most levels of nesting of a decoded rule's condition, with refs replaced
+/// by the named conditions
+const MAX_DEPTH: usize = 128;
+
+/// Refs of a condition, each with the number of levels above it
+type Refs<'a> = Vec<(&'a str, usize)>;
+
+impl ConfigEvaluator {
+    /// Encode the evaluator's rule set in a compact binary format
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ConfigExprError> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(self, &mut bytes)
+            .map_err(|e| ConfigExprError::BinaryError(e.to_string()))?;
+        Ok(bytes)
+    }
+
+    /// Load an evaluator encoded with [`ConfigEvaluator::to_bytes`]
+    ///
+    /// The rules are not validated again; only load bytes produced from a
+    /// validated evaluator. So that corrupt bytes fail with
+    /// [`ConfigExprError::BinaryError`] rather than exhaust the stack while
+    /// the conditions are compiled, refs must not form cycles and conditions
+    /// must be nested at most 128 levels deep.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ConfigExprError> {
+        let data: EvaluatorData = ciborium::from_reader(bytes)
+            .map_err(|e| ConfigExprError::BinaryError(e.to_string()))?;
+        check_nesting(&data.rules).map_err(ConfigExprError::BinaryError)?;
+        Ok(data.into())
+    }
+}
+
+/// Check that refs form no cycle and conditions stay within [`MAX_DEPTH`]
+///
+/// Definitions are visited in dependency order without recursing through
+/// refs, so long chains of definitions cannot exhaust the stack either.
+fn check_nesting(rules: &ConfigRules) -> Result<(), String> {
+    // Nesting of each definition on its own, and the refs it holds with the
+    // levels above them
+    let mut scanned: BTreeMap<&str, (usize, Refs)> = BTreeMap::new();
+    for (name, condition) in &rules.definitions {
+        let mut refs = Vec::new();
+        let depth = scan(condition, 1, &mut refs);
+        refs.retain(|(name, _)| rules.definitions.contains_key(*name));
+        scanned.insert(name, (depth, refs));
+    }
+
+    let mut dependents: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    let mut pending: BTreeMap<&str, usize> = BTreeMap::new();
+    for (name, (_, refs)) in &scanned {
+        pending.insert(name, refs.len());
+        for (referenced, _) in refs {
+            dependents.entry(referenced).or_default().push(name);
+        }
+    }
+    let mut ready: Vec<&str> = pending
+        .iter()
+        .filter(|(_, count)| **count == 0)
+        .map(|(name, _)| *name)
+        .collect();
+    let mut depths: BTreeMap<&str, usize> = BTreeMap::new();
+    while let Some(name) = ready.pop() {
+        let (depth, refs) = &scanned[name];
+        let depth = refs
+            .iter()
+            .map(|(referenced, above)| above.saturating_add(depths[referenced]))
+            .fold(*depth, usize::max);
+        depths.insert(name, depth);
+        for dependent in dependents.get(name).into_iter().flatten() {
+            let count = pending.get_mut(dependent).expect("scanned definition");
+            *count -= 1;
+            if *count == 0 {
+                ready.push(dependent);
+            }
+        }
+    }
+    if let Some(name) = scanned.keys().find(|name| !depths.contains_key(*name)) {
+        return Err(format!("Definition cycle through '{}'", name));
+    }
+
+    let phases = [
+        (Phase::Pre, &rules.pre),
+        (Phase::Main, &rules.rules),
+        (Phase::Post, &rules.post),
+    ];
+    for (phase, phase_rules) in phases {
+        for (index, rule) in phase_rules.iter().enumerate() {
+            let mut refs = Vec::new();
+            let depth = scan(&rule.condition, 1, &mut refs);
+            let depth = refs
+                .iter()
+                .filter_map(|(name, above)| Some(above.saturating_add(*depths.get(name)?)))
+                .fold(depth, usize::max);
+            if depth > MAX_DEPTH {
+                return Err(format!(
+                    "Condition of {} is nested {} levels deep, more than the limit of {}",
+                    Site::rule(phase, index),
+                    depth,
+                    MAX_DEPTH
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Nesting depth of a condition at `level`, not following refs; collects
+/// each ref with the number of levels above it
+fn scan<'a>(condition: &'a Condition, level: usize, refs: &mut Refs<'a>) -> usize {
+    match condition {
+        Condition::Simple { .. } => level,
+        Condition::And { and: nested } | Condition::Or { or: nested } => nested
+            .iter()
+            .map(|c| scan(c, level + 1, refs))
+            .fold(level, usize::max),
+        Condition::Not { not } => scan(not, level + 1, refs),
+        Condition::Ref { name } => {
+            refs.push((name, level - 1));
+            level
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HashMap;
+
+    #[test]
+    fn test_binary_round_trip() {
+        let json = r#"
+        {
+            "pre": [{ "if": "region == \"CN\"", "then": { "mirror": "cn" }, "merge": true }],
+            "rules": [
+                { "if": "platform prefix \"RTD\" && score >= 60", "then": "chip_rtd", "priority": 10 },
+                { "if": { "field": "region", "op": "in", "value": ["US", "CA"] }, "then": 1.5 }
+            ],
+            "fallback": "default"
+        }
+        "#;
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+        let bytes = evaluator.to_bytes().unwrap();
+        assert!(bytes.len() < json.len());
+
+        let loaded = ConfigEvaluator::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.rules(), evaluator.rules());
+        for params in [
+            [("platform", "RTD-2000"), ("score", "75"), ("region", "CN")],
+            [("platform", "MTK"), ("score", "75"), ("region", "US")],
+            [("platform", "MTK"), ("score", "10"), ("region", "EU")],
+        ] {
+            let params: HashMap<String, String> = params
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            assert_eq!(loaded.evaluate(&params), evaluator.evaluate(&params));
+        }
+
+        assert!(matches!(
+            ConfigEvaluator::from_bytes(&bytes[..bytes.len() / 2]),
+            Err(ConfigExprError::BinaryError(_))
+        ));
+    }
+
+    #[test]
+    fn test_binary_rejects_unchecked_nesting() {
+        let encode = |rules: serde_json::Value| {
+            let mut bytes = Vec::new();
+            ciborium::into_writer(&serde_json::json!({ "rules": rules }), &mut bytes).unwrap();
+            bytes
+        };
+        let error = |bytes: &[u8]| match ConfigEvaluator::from_bytes(bytes) {
+            Err(ConfigExprError::BinaryError(message)) => message,
+            other => panic!("expected a binary error, got {:?}", other.map(|_| ())),
+        };
+
+        let cyclic = encode(serde_json::json!({
+            "definitions": { "a": { "ref": "b" }, "b": { "not": { "ref": "a" } } },
+            "rules": [{ "if": { "ref": "a" }, "then": 1 }]
+        }));
+        assert!(error(&cyclic).contains("Definition cycle"));
+
+        // A chain of definitions, each shallow, nesting deeply once inlined
+        let mut definitions = serde_json::Map::new();
+        definitions.insert("d0".to_string(), serde_json::json!("x == \"1\""));
+        for i in 1..200 {
+            let previous = format!("d{}", i - 1);
+            definitions.insert(
+                format!("d{}", i),
+                serde_json::json!({ "not": { "ref": previous } }),
+            );
+        }
+        let deep = encode(serde_json::json!({
+            "definitions": definitions,
+            "rules": [{ "if": { "ref": "d199" }, "then": 1 }]
+        }));
+        assert!(error(&deep).contains("more than the limit of 128"));
+
+        let shallow = encode(serde_json::json!({
+            "definitions": { "a": { "not": "x == \"1\"" } },
+            "rules": [{ "if": { "and": [{ "ref": "a" }, "y == \"2\""] }, "then": 1 }]
+        }));
+        let evaluator = ConfigEvaluator::from_bytes(&shallow).unwrap();
+        let params = HashMap::from([("x", "0"), ("y", "2")]);
+        assert!(evaluator.evaluate(&params).is_some());
+    }
+}
@@ -0,0 +1,80 @@
+//! Deterministic bucketing used by rollouts and experiments
+//!
+//! Rollouts, `percent_lt`, `bucket_in` and seeded choices all assign a
+//! value to a bucket by hashing `salt:value` with 64-bit FNV-1a and taking
+//! the remainder by the number of buckets. Services that call [`bucket`]
+//! with the same salt, or implement the same hash, assign users to the same
+//! buckets as the rules:
+//!
+//! ```
+//! use clia_config_expr::bucketing::bucket;
+//!
+//! let assigned = bucket("user-42", "exp1", 100);
+//! assert!(assigned < 100);
+//! assert_eq!(bucket("user-42", "exp1", 100), assigned);
+//! ```
+
+/// Hash a value with a salt using 64-bit FNV-1a
+///
+/// FNV-1a is stable across platforms and Rust versions, so the same value
+/// always lands in the same bucket.
+fn fnv1a(salt: &str, value: &str) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    let mut hash = OFFSET_BASIS;
+    for byte in salt
+        .bytes()
+        .chain(core::iter::once(b':'))
+        .chain(value.bytes())
+    {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(PRIME);
+    }
+    hash
+}
+
+/// Assign a value to one of `buckets` buckets, numbered from 0
+///
+/// A `bucket_in` condition with the same `seed` as `salt` matches the
+/// values this assigns to its range; 0 buckets count as one.
+pub fn bucket(value: &str, salt: &str, buckets: u32) -> u32 {
+    (fnv1a(salt, value) % u64::from(buckets.max(1))) as u32
+}
+
+/// Map a value to a fraction in `[0, 1)`, in steps of a basis point
+pub(crate) fn fraction(value: &str, salt: &str) -> f64 {
+    f64::from(bucket(value, salt, 10_000)) / 10_000.0
+}
+
+/// Check whether a value falls into the first `percent` percent of buckets
+pub(crate) fn in_percentage(value: &str, salt: &str, percent: f64) -> bool {
+    // Basis points allow fractional percentages such as 0.5%, rounded
+    // without `f64::round`, which needs `std`
+    let threshold = (percent * 100.0 + 0.5) as u32;
+    bucket(value, salt, 10_000) < threshold
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_is_stable() {
+        assert_eq!(
+            bucket("device-1", "exp", 100),
+            bucket("device-1", "exp", 100)
+        );
+        assert!(bucket("device-1", "exp", 100) < 100);
+    }
+
+    #[test]
+    fn test_percentage_distribution() {
+        let selected = (0..10_000)
+            .filter(|i| in_percentage(&format!("device-{}", i), "", 5.0))
+            .count();
+        assert!((400..600).contains(&selected), "selected {}", selected);
+        assert!(!in_percentage("device-1", "", 0.0));
+        assert!(in_percentage("device-1", "", 100.0));
+    }
+}
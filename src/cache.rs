@@ -0,0 +1,256 @@
+//! Evaluation results cached by the params the rules look at
+//!
+//! Caching is opt-in through [`ConfigEvaluator::with_cache`]. Entries are
+//! keyed by the values of the fields the rules reference, so params no rule
+//! looks at do not split the cache, and the least recently used entry is
+//! evicted when it is full. The cache is shared by clones of the evaluator;
+//! evaluators for other rules start with an empty one.
+//!
+//! [`ConfigEvaluator::with_cache`]: crate::ConfigEvaluator::with_cache
+
+use crate::choose::has_unseeded_choice;
+use crate::diff::simple_conditions;
+use crate::{path, Condition, ConfigRules, Outcome, ParamSource, RuleResult};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Values of the referenced fields, in field order, with every value of
+/// repeated params
+type Key = Vec<Vec<String>>;
+
+/// LRU cache of evaluation outcomes
+#[derive(Debug)]
+pub(crate) struct ResultCache {
+    /// Fields making up the key; `None` when outcomes also depend on the
+    /// time or on params the fields do not cover
+    fields: Option<Vec<String>>,
+    capacity: usize,
+    entries: Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    outcomes: HashMap<Key, (Outcome<'static>, u64)>,
+    /// Keys by last use
+    recency: BTreeMap<u64, Key>,
+    clock: u64,
+}
+
+impl ResultCache {
+    pub(crate) fn for_rules(rules: &ConfigRules, capacity: usize) -> Self {
+        let all_rules = || rules.pre.iter().chain(&rules.rules).chain(&rules.post);
+        let uncacheable = all_rules().any(|rule| {
+            rule.active_from.is_some() || rule.active_until.is_some() || rule.schedule.is_some()
+        });
+        // Time-of-day conditions read the clock when their param is missing
+        let uncacheable = uncacheable
+            || rules.conditions().any(|condition| {
+                let mut simple = Vec::new();
+                simple_conditions(condition, &mut simple);
+                simple.into_iter().any(
+                |condition| matches!(condition, Condition::Simple { op, .. } if op.reads_clock()),
+            )
+            });
+        // Choices without a seed differ between evaluations
+        let uncacheable = uncacheable
+            || all_rules()
+                .map(|rule| &rule.result)
+                .chain(&rules.fallback)
+                .any(|result| matches!(result, RuleResult::Object(value) if has_unseeded_choice(value)));
+        // Scripts may read any param
+        #[cfg(feature = "scripting")]
+        let uncacheable = uncacheable || !rules.scripts().is_empty();
+        ResultCache {
+            fields: (!uncacheable).then(|| rules.referenced_fields().into_iter().collect()),
+            capacity,
+            entries: Mutex::default(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Look up the outcome for the params, evaluating and storing it on a
+    /// miss; failed evaluations are not stored
+    pub(crate) fn get_or_evaluate<'a, E>(
+        &self,
+        params: &dyn ParamSource,
+        evaluate: impl FnOnce() -> Result<Outcome<'a>, E>,
+    ) -> Result<Outcome<'a>, E> {
+        let Some(fields) = self.fields.as_ref().filter(|_| self.capacity > 0) else {
+            return evaluate();
+        };
+        let key: Key = fields
+            .iter()
+            .map(|field| {
+                let mut values = params.get_all(field);
+                if values.is_empty() {
+                    values.extend(path::lookup(params, field));
+                }
+                values.into_iter().map(|value| value.into_owned()).collect()
+            })
+            .collect();
+
+        {
+            let mut guard = self.lock();
+            let entries = &mut *guard;
+            entries.clock += 1;
+            let clock = entries.clock;
+            if let Some((outcome, used)) = entries.outcomes.get_mut(&key) {
+                let outcome = outcome.clone();
+                let previous = core::mem::replace(used, clock);
+                entries.recency.remove(&previous);
+                entries.recency.insert(clock, key);
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(outcome);
+            }
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let outcome = evaluate()?;
+        let mut guard = self.lock();
+        let entries = &mut *guard;
+        entries.clock += 1;
+        let clock = entries.clock;
+        if let Some((_, used)) = entries
+            .outcomes
+            .insert(key.clone(), (outcome.clone().into_owned(), clock))
+        {
+            // Stored by a concurrent evaluation meanwhile
+            entries.recency.remove(&used);
+        }
+        entries.recency.insert(clock, key);
+        while entries.outcomes.len() > self.capacity {
+            let Some((_, oldest)) = entries.recency.pop_first() else {
+                break;
+            };
+            entries.outcomes.remove(&oldest);
+        }
+        Ok(outcome)
+    }
+
+    pub(crate) fn clear(&self) {
+        let mut entries = self.lock();
+        entries.outcomes.clear();
+        entries.recency.clear();
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.lock().outcomes.len(),
+            capacity: self.capacity,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Entries> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Snapshot of an evaluator's result cache statistics
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CacheStats {
+    /// Evaluations answered from the cache
+    pub hits: u64,
+    /// Evaluations that had to run the rules
+    pub misses: u64,
+    /// Outcomes currently cached
+    pub entries: usize,
+    /// Maximum number of cached outcomes
+    pub capacity: usize,
+}
+
+impl CacheStats {
+    /// Share of cached evaluations that were hits
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / total as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ConfigEvaluator, MultiParams, RuleResult};
+    use std::collections::HashMap;
+
+    fn params(region: &str, request_id: &str) -> HashMap<String, String> {
+        HashMap::from([
+            ("region".to_string(), region.to_string()),
+            ("request_id".to_string(), request_id.to_string()),
+        ])
+    }
+
+    #[test]
+    fn test_result_cache() {
+        let json =
+            r#"{ "rules": [{ "if": "region == \"CN\"", "then": "cn" }], "fallback": "global" }"#;
+        let evaluator = ConfigEvaluator::from_json(json).unwrap().with_cache(2);
+        assert_eq!(
+            evaluator.evaluate(&params("CN", "1")),
+            Some(RuleResult::String("cn".to_string()))
+        );
+        // Params no rule references do not split the cache
+        assert_eq!(
+            evaluator.evaluate(&params("CN", "2")),
+            Some(RuleResult::String("cn".to_string()))
+        );
+        let stats = evaluator.cache_stats().unwrap();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 1, 1));
+        assert_eq!(stats.hit_rate(), 0.5);
+
+        evaluator.evaluate(&params("JP", "3"));
+        evaluator.evaluate(&params("US", "4"));
+        assert_eq!(evaluator.cache_stats().unwrap().entries, 2);
+        // `CN` was evicted as the least recently used
+        evaluator.evaluate(&params("CN", "5"));
+        assert_eq!(evaluator.cache_stats().unwrap().misses, 4);
+
+        // Evaluators for other rules start over
+        let reloaded = evaluator
+            .with_rules(serde_json::from_str(json).unwrap())
+            .unwrap();
+        let stats = reloaded.cache_stats().unwrap();
+        assert_eq!((stats.hits, stats.entries, stats.capacity), (0, 0, 2));
+
+        evaluator.clear_cache();
+        assert_eq!(evaluator.cache_stats().unwrap().entries, 0);
+
+        // Results of scheduled rules depend on the time
+        let scheduled = ConfigEvaluator::from_json(
+            r#"{ "rules": [{ "if": "region == \"CN\"", "then": "cn", "active_from": "2020-01-01T00:00:00Z" }] }"#,
+        )
+        .unwrap()
+        .with_cache(2);
+        scheduled.evaluate(&params("CN", "1"));
+        scheduled.evaluate(&params("CN", "1"));
+        assert_eq!(scheduled.cache_stats().unwrap().hits, 0);
+
+        // Every value of repeated params makes up the key
+        let repeated = ConfigEvaluator::from_json(
+            r#"{ "rules": [{ "if": { "field": "region", "op": "equals", "value": "CN", "mode": "any" }, "then": "cn" }] }"#,
+        )
+        .unwrap()
+        .with_cache(2);
+        let evaluate = |regions: &[&str]| {
+            let params = MultiParams(HashMap::from([("region", regions.to_vec())]));
+            repeated.evaluate_source(&params).unwrap()
+        };
+        assert_eq!(evaluate(&["JP"]), None);
+        assert_eq!(
+            evaluate(&["JP", "CN"]),
+            Some(RuleResult::String("cn".to_string()))
+        );
+    }
+}
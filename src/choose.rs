@@ -0,0 +1,303 @@
+//! Weighted random choices among result values
+//!
+//! A result value written as a choice picks one of its options in
+//! proportion to their weights, e.g. to split traffic between variants:
+//!
+//! ```json
+//! {
+//!     "$choose": [{ "value": "A", "weight": 90 }, { "value": "B", "weight": 10 }],
+//!     "seed_field": "user_id"
+//! }
+//! ```
+//!
+//! With a `seed_field`, the option is picked by hashing the field's value
+//! like rollouts do, so the same user always gets the same option. Without
+//! one, or when the field is missing, the evaluator's [`RandomSource`]
+//! picks it on every evaluation.
+
+use crate::validation::Site;
+use crate::{bucketing, path, ConfigEvaluator, ConfigExprError, ParamSource};
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use serde_json::Value;
+
+/// Source of random numbers for choices without a seed
+pub trait RandomSource: Send + Sync + fmt::Debug {
+    /// Return the next random number
+    fn next_u64(&self) -> u64;
+}
+
+/// Random source producing a fixed sequence for each seed (SplitMix64)
+///
+/// Evaluators use one seeded from `std`'s hasher keys by default; without
+/// `std` the default seed is 0, so applications should set their own with
+/// [`ConfigEvaluator::with_random`].
+#[derive(Debug)]
+pub struct SeededRandom {
+    seed: u64,
+    counter: AtomicUsize,
+}
+
+impl SeededRandom {
+    pub fn new(seed: u64) -> Self {
+        SeededRandom {
+            seed,
+            counter: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn next_u64(&self) -> u64 {
+        let counter = self.counter.fetch_add(1, Ordering::Relaxed) as u64;
+        let mut z = self
+            .seed
+            .wrapping_add(counter.wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Random source of evaluators until one is set with
+/// [`ConfigEvaluator::with_random`]
+pub(crate) fn default_random() -> SeededRandom {
+    #[cfg(feature = "std")]
+    {
+        use std::hash::{BuildHasher, Hasher};
+        SeededRandom::new(
+            std::collections::hash_map::RandomState::new()
+                .build_hasher()
+                .finish(),
+        )
+    }
+    #[cfg(not(feature = "std"))]
+    SeededRandom::new(0)
+}
+
+/// A `{ "$choose": [...] }` result value
+pub(crate) struct Choice<'a> {
+    options: &'a [Value],
+    seed_field: Option<&'a str>,
+    salt: &'a str,
+}
+
+impl<'a> Choice<'a> {
+    /// The choice a result value is written as, if any
+    pub(crate) fn of(value: &'a Value) -> Option<Self> {
+        let obj = value.as_object()?;
+        Some(Choice {
+            options: obj.get("$choose")?.as_array()?,
+            seed_field: obj.get("seed_field").and_then(Value::as_str),
+            salt: obj.get("salt").and_then(Value::as_str).unwrap_or(""),
+        })
+    }
+
+    /// Field hashed to pick the option, which the result depends on
+    pub(crate) fn seed_field(&self) -> Option<&'a str> {
+        self.seed_field
+    }
+
+    /// Values of the options
+    pub(crate) fn values(&self) -> impl Iterator<Item = &'a Value> {
+        self.options.iter().filter_map(|option| option.get("value"))
+    }
+
+    /// Pick the value of an option
+    pub(crate) fn pick(&self, params: &dyn ParamSource, random: &dyn RandomSource) -> &'a Value {
+        let seed = self
+            .seed_field
+            .and_then(|field| path::lookup(params, field));
+        let point = match seed {
+            Some(seed) => bucketing::fraction(&seed, self.salt),
+            None => unit_interval(random.next_u64()),
+        };
+        let total: f64 = self.options.iter().map(weight).sum();
+        let mut remaining = point * total;
+        let mut picked = &Value::Null;
+        for option in self.options.iter().filter(|option| weight(option) > 0.0) {
+            picked = &option["value"];
+            if remaining < weight(option) {
+                break;
+            }
+            remaining -= weight(option);
+        }
+        picked
+    }
+}
+
+fn weight(option: &Value) -> f64 {
+    option.get("weight").and_then(Value::as_f64).unwrap_or(0.0)
+}
+
+/// Map a random number to `[0, 1)`
+fn unit_interval(n: u64) -> f64 {
+    (n >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Check a value written as a choice, before its option values are
+/// validated like other result values
+pub(crate) fn validate(value: &Value, site: &Site) -> Result<(), ConfigExprError> {
+    let invalid = |site: &Site, reason: &str| -> ConfigExprError {
+        site.error(
+            "invalid_choice",
+            format!("Invalid choice in {}: {}", site, reason),
+        )
+        .into()
+    };
+    let Some(obj) = value.as_object() else {
+        return Ok(());
+    };
+    if let Some(key) = obj
+        .keys()
+        .find(|key| !matches!(key.as_str(), "$choose" | "seed_field" | "salt"))
+    {
+        return Err(invalid(
+            &site.at(key),
+            "only '$choose', 'seed_field' and 'salt' are allowed",
+        ));
+    }
+    let Some(options) = obj["$choose"].as_array().filter(|o| !o.is_empty()) else {
+        return Err(invalid(
+            &site.at("$choose"),
+            "'$choose' must be a non-empty array",
+        ));
+    };
+    for (index, option) in options.iter().enumerate() {
+        let site = site.at("$choose").at(index);
+        let valid_weight = option
+            .get("weight")
+            .and_then(Value::as_f64)
+            .is_some_and(|weight| weight >= 0.0);
+        let valid_keys = option
+            .as_object()
+            .is_some_and(|o| o.len() == 2 && o.contains_key("value"));
+        if !(valid_weight && valid_keys) {
+            return Err(invalid(
+                &site,
+                "options must be objects with a 'value' and a non-negative 'weight'",
+            ));
+        }
+    }
+    if options.iter().map(weight).sum::<f64>() <= 0.0 {
+        return Err(invalid(
+            &site.at("$choose"),
+            "the weights must not all be 0",
+        ));
+    }
+    for key in ["seed_field", "salt"] {
+        if obj.get(key).is_some_and(|v| !v.is_string()) {
+            return Err(invalid(&site.at(key), "must be a string"));
+        }
+    }
+    if let Some(field) = obj.get("seed_field").and_then(Value::as_str) {
+        ConfigEvaluator::validate_field_path(field, &site.at("seed_field"))?;
+    }
+    Ok(())
+}
+
+/// Check whether a result value holds choices made at random, which make
+/// the results of the same params differ
+#[cfg(feature = "std")]
+pub(crate) fn has_unseeded_choice(value: &Value) -> bool {
+    if let Some(choice) = Choice::of(value) {
+        return choice.seed_field.is_none() || choice.values().any(has_unseeded_choice);
+    }
+    match value {
+        Value::Array(items) => items.iter().any(has_unseeded_choice),
+        Value::Object(obj) => obj.values().any(has_unseeded_choice),
+        _ => false,
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{HashMap, RuleResult};
+
+    #[test]
+    fn test_weighted_choice() {
+        let json = r#"{
+            "rules": [
+                {
+                    "if": "platform == \"RTD\"",
+                    "then": {
+                        "$choose": [{ "value": "A", "weight": 90 }, { "value": "B", "weight": 10 }],
+                        "seed_field": "user_id"
+                    }
+                },
+                {
+                    "if": "platform == \"MT\"",
+                    "then": { "variant": { "$choose": [{ "value": "x", "weight": 1 }, { "value": "y", "weight": 0 }] } }
+                }
+            ],
+            "fallback": {
+                "$choose": [{ "value": { "port": "= base + 1" }, "weight": 1 }, { "value": "B", "weight": 1 }]
+            }
+        }"#;
+        let evaluator = ConfigEvaluator::from_json(json)
+            .unwrap()
+            .with_random(SeededRandom::new(7));
+        let evaluate = |params: &[(&str, &str)]| {
+            evaluator
+                .evaluate(&params.iter().copied().collect::<HashMap<_, _>>())
+                .unwrap()
+        };
+
+        let variants: Vec<RuleResult> = (0..1000)
+            .map(|i| evaluate(&[("platform", "RTD"), ("user_id", &format!("user-{}", i))]))
+            .collect();
+        let b = variants
+            .iter()
+            .filter(|v| **v == RuleResult::Object(Value::from("B")))
+            .count();
+        assert!((50..150).contains(&b), "chose B {} times", b);
+        assert_eq!(
+            evaluate(&[("platform", "RTD"), ("user_id", "user-3")]),
+            variants[3]
+        );
+        assert_eq!(
+            evaluate(&[("platform", "MT")]),
+            RuleResult::Object(serde_json::json!({ "variant": "x" }))
+        );
+
+        // Unseeded choices use the random source; chosen values are computed
+        let fallbacks: Vec<RuleResult> = (0..64).map(|_| evaluate(&[("base", "80")])).collect();
+        assert!(fallbacks.contains(&RuleResult::Object(serde_json::json!({ "port": 81 }))));
+        assert!(fallbacks.contains(&RuleResult::Object(Value::from("B"))));
+        let replay = ConfigEvaluator::from_json(json)
+            .unwrap()
+            .with_random(SeededRandom::new(7));
+        let params: HashMap<&str, &str> = [("base", "80")].into_iter().collect();
+        assert_eq!(replay.evaluate(&params).as_ref(), Some(&fallbacks[0]));
+
+        let error = |then: &str| {
+            let json = format!(
+                r#"{{ "rules": [{{ "if": "a == \"1\"", "then": {} }}] }}"#,
+                then
+            );
+            let error = ConfigEvaluator::from_json(&json).unwrap_err();
+            let details = error.rule_error().unwrap().clone();
+            (details.code, details.pointer)
+        };
+        assert_eq!(
+            error(r#"{ "$choose": [] }"#),
+            ("invalid_choice", "/rules/0/then/$choose".to_string())
+        );
+        assert_eq!(
+            error(r#"{ "$choose": [{ "value": "A", "weight": -1 }] }"#),
+            ("invalid_choice", "/rules/0/then/$choose/0".to_string())
+        );
+        assert_eq!(
+            error(r#"{ "$choose": [{ "value": "A", "weight": 1 }], "seed": "x" }"#),
+            ("invalid_choice", "/rules/0/then/seed".to_string())
+        );
+        assert_eq!(
+            error(r#"{ "$choose": [{ "value": "= a *", "weight": 1 }] }"#),
+            (
+                "invalid_expression",
+                "/rules/0/then/$choose/0/value".to_string()
+            )
+        );
+    }
+}
@@ -0,0 +1,111 @@
+//! IP range matching for the `ip_in_cidr` operator
+//!
+//! Condition values are CIDR blocks like `10.0.0.0/8` or `2001:db8::/32`,
+//! given as a string or a list of strings. A bare address matches only
+//! itself. IPv4-mapped IPv6 params (`::ffff:10.1.2.3`) match IPv4 blocks.
+
+use crate::prelude::*;
+use core::net::IpAddr;
+use serde_json::Value;
+
+/// A parsed CIDR block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Parse a block in `address/prefix` form or a bare address
+    pub(crate) fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        let (address, prefix) = match s.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (s, None),
+        };
+        let network: IpAddr = address
+            .parse()
+            .map_err(|_| format!("invalid IP address '{}'", address))?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| format!("invalid prefix length '{}'", prefix))?,
+            None => max,
+        };
+        Ok(Cidr { network, prefix })
+    }
+
+    /// Check whether the block contains an address
+    pub(crate) fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The CIDR block strings of a condition value
+pub(crate) fn blocks(value: &Value) -> Vec<&str> {
+    match value {
+        Value::String(s) => vec![s.as_str()],
+        Value::Array(items) => items.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Check whether a param value is an address inside any of the blocks
+pub(crate) fn matches<'a>(blocks: impl IntoIterator<Item = &'a str>, field_value: &str) -> bool {
+    let Ok(ip) = field_value.trim().parse::<IpAddr>() else {
+        return false;
+    };
+    blocks
+        .into_iter()
+        .any(|block| Cidr::parse(block).is_ok_and(|cidr| cidr.contains(ip)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cidr_contains() {
+        let cidr = Cidr::parse("10.1.0.0/16").unwrap();
+        assert!(cidr.contains("10.1.200.3".parse().unwrap()));
+        assert!(!cidr.contains("10.2.0.1".parse().unwrap()));
+        assert!(cidr.contains("::ffff:10.1.2.3".parse().unwrap()));
+
+        let cidr = Cidr::parse("2001:db8::/32").unwrap();
+        assert!(cidr.contains("2001:db8:1::1".parse().unwrap()));
+        assert!(!cidr.contains("2001:db9::1".parse().unwrap()));
+        assert!(!cidr.contains("10.1.2.3".parse().unwrap()));
+
+        assert!(Cidr::parse("0.0.0.0/0")
+            .unwrap()
+            .contains("8.8.8.8".parse().unwrap()));
+        assert!(Cidr::parse("192.168.1.1")
+            .unwrap()
+            .contains("192.168.1.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_cidr_parse_errors() {
+        assert!(Cidr::parse("10.0.0.0/33").is_err());
+        assert!(Cidr::parse("10.0.0/8").is_err());
+        assert!(Cidr::parse("2001:db8::/129").is_err());
+        assert!(Cidr::parse("10.0.0.0/x").is_err());
+    }
+}
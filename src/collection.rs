@@ -0,0 +1,161 @@
+//! Rule sets of many namespaces in one document
+//!
+//! A service deciding several features can keep one rule set per feature in
+//! a single file and serve them all from one [`ConfigRuleSetCollection`].
+
+use crate::prelude::*;
+use crate::{
+    parse_json_rules, ConfigEvaluator, ConfigExprError, ConfigRules, ParamSource, RuleResult,
+};
+use alloc::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+
+/// Rule sets keyed by namespace, e.g. one per feature
+///
+/// The document maps each namespace to a rule set:
+///
+/// ```json
+/// {
+///     "checkout": { "rules": [...], "fallback": "checkout_v1" },
+///     "search": { "rules": [...] }
+/// }
+/// ```
+///
+/// Namespaces are evaluated independently, each with its own `fallback`,
+/// `vars` and `definitions`. Deserializing validates every rule set.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "BTreeMap<String, ConfigRules>")]
+pub struct ConfigRuleSetCollection {
+    evaluators: BTreeMap<String, ConfigEvaluator>,
+}
+
+impl ConfigRuleSetCollection {
+    /// Validate the rule set of every namespace
+    ///
+    /// Errors are wrapped in [`ConfigExprError::NamespaceError`] naming the
+    /// namespace.
+    pub fn new(
+        rule_sets: impl IntoIterator<Item = (String, ConfigRules)>,
+    ) -> Result<Self, ConfigExprError> {
+        let evaluators = rule_sets
+            .into_iter()
+            .map(|(namespace, rules)| match ConfigEvaluator::new(rules) {
+                Ok(evaluator) => Ok((namespace, evaluator)),
+                Err(e) => Err(namespace_error(namespace, e)),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { evaluators })
+    }
+
+    /// Create a collection from a JSON document
+    pub fn from_json(json: &str) -> Result<Self, ConfigExprError> {
+        Self::new(parse_json_rules::<BTreeMap<String, ConfigRules>>(
+            json.as_bytes(),
+        )?)
+    }
+
+    /// Evaluate request parameters with the rules of a namespace
+    ///
+    /// Returns `None` when no rule matches and the namespace has no
+    /// fallback, or an assertion rule matches; fails for unknown namespaces.
+    pub fn evaluate(
+        &self,
+        namespace: &str,
+        params: &impl ParamSource,
+    ) -> Result<Option<RuleResult>, ConfigExprError> {
+        Ok(self.evaluator(namespace)?.evaluate(params))
+    }
+
+    /// Evaluator of a namespace, for evaluation with reports, traces and the
+    /// like
+    pub fn evaluator(&self, namespace: &str) -> Result<&ConfigEvaluator, ConfigExprError> {
+        self.evaluators
+            .get(namespace)
+            .ok_or_else(|| ConfigExprError::UnknownNamespace(namespace.to_string()))
+    }
+
+    /// Names of the namespaces, in order
+    pub fn namespaces(&self) -> impl Iterator<Item = &str> {
+        self.evaluators.keys().map(String::as_str)
+    }
+}
+
+/// Attach the namespace to an error of its rule set
+pub(crate) fn namespace_error(namespace: String, source: ConfigExprError) -> ConfigExprError {
+    ConfigExprError::NamespaceError {
+        namespace,
+        source: Box::new(source),
+    }
+}
+
+impl TryFrom<BTreeMap<String, ConfigRules>> for ConfigRuleSetCollection {
+    type Error = ConfigExprError;
+
+    fn try_from(rule_sets: BTreeMap<String, ConfigRules>) -> Result<Self, Self::Error> {
+        Self::new(rule_sets)
+    }
+}
+
+/// Serializes as the document mapping namespaces to their rules
+impl Serialize for ConfigRuleSetCollection {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(
+            self.evaluators
+                .iter()
+                .map(|(namespace, evaluator)| (namespace, evaluator.rules())),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collection() {
+        let collection = ConfigRuleSetCollection::from_json(
+            r#"{
+                "checkout": {
+                    "rules": [{ "if": "region == \"CN\"", "then": "checkout_cn" }],
+                    "fallback": "checkout_v1"
+                },
+                "search": {
+                    "rules": [{ "if": "region == \"CN\"", "then": "search_cn" }]
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            collection.namespaces().collect::<Vec<_>>(),
+            ["checkout", "search"]
+        );
+
+        let params = HashMap::from([("region".to_string(), "JP".to_string())]);
+        assert_eq!(
+            collection.evaluate("checkout", &params).unwrap(),
+            Some(RuleResult::String("checkout_v1".to_string()))
+        );
+        assert_eq!(collection.evaluate("search", &params).unwrap(), None);
+        let params = HashMap::from([("region".to_string(), "CN".to_string())]);
+        assert_eq!(
+            collection.evaluate("search", &params).unwrap(),
+            Some(RuleResult::String("search_cn".to_string()))
+        );
+        let error = collection.evaluate("billing", &params).unwrap_err();
+        assert_eq!(error.error_code(), "unknown_namespace");
+
+        let json = serde_json::to_string(&collection).unwrap();
+        let reloaded: ConfigRuleSetCollection = serde_json::from_str(&json).unwrap();
+        assert_eq!(reloaded, collection);
+
+        let error = ConfigRuleSetCollection::from_json(
+            r#"{ "search": { "rules": [{ "if": { "and": [] }, "then": "x" }] } }"#,
+        )
+        .unwrap_err();
+        assert_eq!(error.error_code(), "empty_condition");
+        assert_eq!(
+            error.to_string(),
+            "Error in namespace 'search': Validation error: AND condition cannot be empty in rule 0"
+        );
+    }
+}
@@ -0,0 +1,623 @@
+//! Compiled form of rule conditions
+//!
+//! [`ConfigEvaluator`] compiles every rule condition once, when it is
+//! created: nested AND / OR chains are flattened, regexes and globs
+//! compiled, numeric operands parsed and long `in` / `not_in` lists hashed.
+//! Each rule's fields are interned, so a field checked by several leaves is
+//! looked up (and its dot-path resolved) once per rule. Refs to named
+//! conditions are compiled into each rule using them. Traces and other diagnostics evaluate
+//! the original [`Condition`] tree instead.
+//!
+//! When at least [`DISPATCH_THRESHOLD`] main-phase rules start with
+//! `field == "literal"` on the same field, they are also compiled into a
+//! [`DispatchTable`], so first-match evaluation only checks the rules keyed
+//! by the param's value and the rules not keyed on the field.
+
+use crate::expr::{self, Expr};
+use crate::membership::HashedList;
+use crate::number::Number;
+use crate::patterns::RegexConfig;
+use crate::prelude::*;
+use crate::report::Phase;
+use crate::text::{self, Normalization};
+use crate::{path, Condition, ConfigEvaluator, ConfigRules, Operator, ParamSource, Rule};
+use alloc::borrow::Cow;
+use alloc::collections::BTreeMap;
+#[cfg(feature = "regex")]
+use regex::Regex;
+use serde_json::Value;
+
+/// Minimum number of main-phase rules keyed on one field for a dispatch table
+const DISPATCH_THRESHOLD: usize = 16;
+
+/// Compiled conditions of every phase, indexed like the rules
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CompiledRules {
+    pre: Vec<CompiledRule>,
+    rules: Vec<CompiledRule>,
+    post: Vec<CompiledRule>,
+    dispatch: Option<DispatchTable>,
+    /// Compiled `params_spec` patterns by param field
+    #[cfg(feature = "regex")]
+    param_patterns: BTreeMap<String, Regex>,
+}
+
+impl CompiledRules {
+    /// Compile the rules of every phase; `order` is the evaluation order of
+    /// the main phase, and `regex` limits the size of compiled patterns
+    pub(crate) fn for_rules(rules: &ConfigRules, order: &[usize], regex: &RegexConfig) -> Self {
+        let compile = |phase: &[Rule]| {
+            phase
+                .iter()
+                .map(|rule| CompiledRule::new(rule, &rules.definitions, regex))
+                .collect()
+        };
+        let main: Vec<CompiledRule> = compile(&rules.rules);
+        CompiledRules {
+            pre: compile(&rules.pre),
+            dispatch: DispatchTable::new(&main, order),
+            rules: main,
+            post: compile(&rules.post),
+            #[cfg(feature = "regex")]
+            param_patterns: rules
+                .params_spec
+                .iter()
+                .filter_map(|(field, spec)| {
+                    let pattern = regex.compile(spec.pattern.as_deref()?).ok()?;
+                    Some((field.clone(), pattern))
+                })
+                .collect(),
+        }
+    }
+
+    /// Compiled `pattern` of a param's spec
+    #[cfg(feature = "regex")]
+    pub(crate) fn param_pattern(&self, field: &str) -> Option<&Regex> {
+        self.param_patterns.get(field)
+    }
+
+    /// Dispatch table of the main phase, if its rules have one
+    pub(crate) fn dispatch(&self) -> Option<&DispatchTable> {
+        self.dispatch.as_ref()
+    }
+
+    pub(crate) fn rule(&self, phase: Phase, index: usize) -> &CompiledRule {
+        match phase {
+            Phase::Pre => &self.pre[index],
+            Phase::Main => &self.rules[index],
+            Phase::Post => &self.post[index],
+        }
+    }
+}
+
+/// Compiled condition of one rule with its interned fields
+#[derive(Debug, Clone)]
+pub(crate) struct CompiledRule {
+    condition: CompiledCondition,
+    fields: Vec<String>,
+}
+
+impl CompiledRule {
+    fn new(rule: &Rule, definitions: &BTreeMap<String, Condition>, regex: &RegexConfig) -> Self {
+        let mut fields = Vec::new();
+        let condition =
+            CompiledCondition::compile(&rule.condition, definitions, regex, &mut fields);
+        CompiledRule { condition, fields }
+    }
+}
+
+impl CompiledRule {
+    /// Field and literal of a leading `field == "literal"` leaf
+    fn key(&self) -> Option<(&str, &str)> {
+        let first = match &self.condition {
+            CompiledCondition::All(all) => all.first()?,
+            condition => condition,
+        };
+        match first {
+            CompiledCondition::Leaf(Leaf {
+                field,
+                value_field: None,
+                op: Operator::Equals,
+                value: Value::String(literal),
+                trim: false,
+                normalize: None,
+                ..
+            }) => Some((&self.fields[*field], literal)),
+            _ => None,
+        }
+    }
+}
+
+/// Main-phase rules keyed by the literal their leading
+/// `field == "literal"` leaf compares a common field with
+#[derive(Debug, Clone)]
+pub(crate) struct DispatchTable {
+    field: String,
+    /// Positions in evaluation order of the rules keyed by each literal
+    keyed: HashMap<String, Vec<usize>>,
+    /// Positions in evaluation order of the rules not keyed on the field
+    unkeyed: Vec<usize>,
+    /// Main-phase rule indices in evaluation order
+    order: Vec<usize>,
+}
+
+impl DispatchTable {
+    /// Field the rules are keyed on
+    pub(crate) fn field(&self) -> &str {
+        &self.field
+    }
+
+    /// Key the rules on the field most of them start with, if enough do
+    fn new(rules: &[CompiledRule], order: &[usize]) -> Option<Self> {
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for rule in rules {
+            if let Some((field, _)) = rule.key() {
+                *counts.entry(field).or_default() += 1;
+            }
+        }
+        // The first of equally common fields, for a deterministic choice
+        let (field, _) = counts
+            .into_iter()
+            .rev()
+            .max_by_key(|(_, count)| *count)
+            .filter(|(_, count)| *count >= DISPATCH_THRESHOLD)?;
+
+        let mut keyed: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut unkeyed = Vec::new();
+        for (position, &index) in order.iter().enumerate() {
+            match rules[index].key() {
+                Some((f, literal)) if f == field => {
+                    keyed.entry(literal.to_string()).or_default().push(position)
+                }
+                _ => unkeyed.push(position),
+            }
+        }
+        Some(DispatchTable {
+            field: field.to_string(),
+            keyed,
+            unkeyed,
+            order: order.to_vec(),
+        })
+    }
+
+    /// Indices of the rules that can match the params, in evaluation order
+    ///
+    /// Rules keyed by other literals, or keyed at all when the param is
+    /// missing, cannot match and are left out.
+    pub(crate) fn candidates(&self, params: &dyn ParamSource) -> Vec<usize> {
+        let keyed = path::lookup(params, &self.field)
+            .and_then(|value| self.keyed.get(&*value))
+            .map_or(&[][..], Vec::as_slice);
+        let (mut keyed, mut unkeyed) = (keyed.iter().peekable(), self.unkeyed.iter().peekable());
+        let mut candidates = Vec::with_capacity(keyed.len() + unkeyed.len());
+        // Merge the two position lists, both in evaluation order
+        loop {
+            let position = match (keyed.peek(), unkeyed.peek()) {
+                (Some(k), Some(u)) if k < u => keyed.next(),
+                (Some(_), None) => keyed.next(),
+                _ => unkeyed.next(),
+            };
+            match position {
+                Some(&position) => candidates.push(self.order[position]),
+                None => return candidates,
+            }
+        }
+    }
+}
+
+/// Tally of one evaluation of a compiled condition
+#[derive(Debug, Default)]
+pub(crate) struct Visit {
+    /// Condition nodes visited
+    pub(crate) conditions: u64,
+    /// First param a condition read while it was missing, in strict mode
+    pub(crate) missing_field: Option<String>,
+}
+
+impl Visit {
+    fn record_missing(&mut self, field: &str) {
+        if self.missing_field.is_none() {
+            self.missing_field = Some(field.to_string());
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum CompiledCondition {
+    Leaf(Leaf),
+    /// Flattened AND
+    All(Vec<CompiledCondition>),
+    /// Flattened OR
+    Any(Vec<CompiledCondition>),
+    Not(Box<CompiledCondition>),
+    /// Condition over every value of a repeated param, evaluated like the
+    /// original condition
+    Values(Condition),
+}
+
+#[derive(Debug, Clone)]
+struct Leaf {
+    /// Index into the rule's interned fields
+    field: usize,
+    /// Interned field compared against instead of `value`
+    value_field: Option<usize>,
+    op: Operator,
+    /// Condition value, normalized like the params when `normalize` is set
+    value: Value,
+    seed: String,
+    trim: bool,
+    normalize: Option<Normalization>,
+    operand: Operand,
+}
+
+/// Condition value prepared for the leaf's operator
+#[derive(Debug, Clone)]
+enum Operand {
+    #[cfg(feature = "regex")]
+    Regex(Regex),
+    #[cfg(feature = "regex")]
+    Glob(globset::GlobMatcher),
+    Number(Number),
+    /// Arithmetic expression computing the number from the params
+    Expr(Expr),
+    List(HashedList),
+    /// The condition can never match a present param
+    Never,
+    /// Evaluated like the original condition
+    Generic,
+}
+
+impl CompiledCondition {
+    fn compile(
+        condition: &Condition,
+        definitions: &BTreeMap<String, Condition>,
+        regex: &RegexConfig,
+        fields: &mut Vec<String>,
+    ) -> Self {
+        match condition {
+            Condition::Simple { op, mode, .. } if mode.is_some() || op.is_set() => {
+                CompiledCondition::Values(condition.clone())
+            }
+            Condition::Simple {
+                field,
+                op,
+                value,
+                value_field,
+                seed,
+                trim,
+                normalize,
+                ..
+            } => {
+                let value = text::prepare_condition_value(value, *normalize);
+                let mut intern = |field: &String| match fields.iter().position(|f| f == field) {
+                    Some(index) => index,
+                    None => {
+                        fields.push(field.clone());
+                        fields.len() - 1
+                    }
+                };
+                CompiledCondition::Leaf(Leaf {
+                    field: intern(field),
+                    value_field: value_field.as_ref().map(intern),
+                    operand: match value_field {
+                        Some(_) => Operand::Generic,
+                        None => Operand::compile(op, &value, regex),
+                    },
+                    op: op.clone(),
+                    value: value.into_owned(),
+                    seed: seed.clone(),
+                    trim: *trim,
+                    normalize: *normalize,
+                })
+            }
+            Condition::And { and } => {
+                let mut all = Vec::new();
+                for c in and {
+                    match Self::compile(c, definitions, regex, fields) {
+                        CompiledCondition::All(nested) => all.extend(nested),
+                        other => all.push(other),
+                    }
+                }
+                CompiledCondition::All(all)
+            }
+            Condition::Or { or } => {
+                let mut any = Vec::new();
+                for c in or {
+                    match Self::compile(c, definitions, regex, fields) {
+                        CompiledCondition::Any(nested) => any.extend(nested),
+                        other => any.push(other),
+                    }
+                }
+                CompiledCondition::Any(any)
+            }
+            Condition::Not { not } => match Self::compile(not, definitions, regex, fields) {
+                CompiledCondition::Not(inner) => *inner,
+                other => CompiledCondition::Not(Box::new(other)),
+            },
+            Condition::Ref { name } => match definitions.get(name) {
+                Some(definition) => Self::compile(definition, definitions, regex, fields),
+                // An empty OR never matches
+                None => CompiledCondition::Any(Vec::new()),
+            },
+        }
+    }
+}
+
+impl Operand {
+    #[cfg_attr(not(feature = "regex"), allow(unused_variables))]
+    fn compile(op: &Operator, value: &Value, regex: &RegexConfig) -> Self {
+        match (op, value) {
+            #[cfg(feature = "regex")]
+            (Operator::Regex, Value::String(pattern)) => match regex.compile(pattern) {
+                Ok(regex) => Operand::Regex(regex),
+                Err(_) => Operand::Never,
+            },
+            #[cfg(feature = "regex")]
+            (Operator::Glob, Value::String(pattern)) => match crate::glob(pattern) {
+                Ok(glob) => Operand::Glob(glob),
+                Err(_) => Operand::Never,
+            },
+            (Operator::Equals, Value::Number(n)) => Operand::number(&n.to_string()),
+            (_, Value::Number(n)) if op.is_numeric() => Operand::number(&n.to_string()),
+            (_, Value::String(s)) if op.is_numeric() && expr::has_placeholders(s) => {
+                match Expr::parse(s) {
+                    Ok(expr) => Operand::Expr(expr),
+                    Err(_) => Operand::Never,
+                }
+            }
+            (_, Value::String(s)) if op.is_numeric() => Operand::number(s),
+            (Operator::In | Operator::NotIn, Value::Array(items)) => match HashedList::new(items) {
+                Some(list) => Operand::List(list),
+                None => Operand::Generic,
+            },
+            _ => Operand::Generic,
+        }
+    }
+
+    fn number(s: &str) -> Self {
+        match Number::parse(s) {
+            Some(n) => Operand::Number(n),
+            None => Operand::Never,
+        }
+    }
+}
+
+impl ConfigEvaluator {
+    /// Check whether a rule's compiled condition matches, counting the
+    /// condition nodes visited and, in strict mode, recording the first
+    /// missing param read
+    pub(crate) fn compiled_matches(
+        &self,
+        rule: &CompiledRule,
+        params: &dyn ParamSource,
+        visit: &mut Visit,
+    ) -> bool {
+        let mut values = vec![None; rule.fields.len()];
+        self.evaluate_compiled(&rule.condition, &rule.fields, &mut values, params, visit)
+    }
+
+    fn evaluate_compiled<'p>(
+        &self,
+        condition: &CompiledCondition,
+        fields: &[String],
+        values: &mut [Option<Option<Cow<'p, str>>>],
+        params: &'p dyn ParamSource,
+        visit: &mut Visit,
+    ) -> bool {
+        visit.conditions += 1;
+        match condition {
+            CompiledCondition::All(all) => all
+                .iter()
+                .all(|c| self.evaluate_compiled(c, fields, values, params, visit)),
+            CompiledCondition::Any(any) => any
+                .iter()
+                .any(|c| self.evaluate_compiled(c, fields, values, params, visit)),
+            CompiledCondition::Not(not) => {
+                !self.evaluate_compiled(not, fields, values, params, visit)
+            }
+            CompiledCondition::Values(condition) => {
+                if let (
+                    true,
+                    Condition::Simple {
+                        field,
+                        op,
+                        value,
+                        value_field,
+                        ..
+                    },
+                ) = (self.strict_fields, condition)
+                {
+                    if path::lookup(params, field).is_none()
+                        && !op.reads_absence(value, value_field.is_some())
+                    {
+                        visit.record_missing(field);
+                    } else if let Some(value_field) = value_field
+                        .as_ref()
+                        .filter(|value_field| path::lookup(params, value_field).is_none())
+                    {
+                        visit.record_missing(value_field);
+                    }
+                }
+                self.evaluate_condition(condition, params)
+            }
+            CompiledCondition::Leaf(leaf) => {
+                let prepare = |value| text::prepare_param(value, leaf.trim, leaf.normalize);
+                let mut lookup = |index: usize| {
+                    values[index]
+                        .get_or_insert_with(|| path::lookup(params, &fields[index]))
+                        .clone()
+                };
+                if let Some(value_field) = leaf.value_field {
+                    let (field_value, other) = (lookup(leaf.field), lookup(value_field));
+                    if self.strict_fields {
+                        if field_value.is_none() && !leaf.op.reads_absence(&leaf.value, true) {
+                            visit.record_missing(&fields[leaf.field]);
+                        } else if other.is_none() {
+                            visit.record_missing(&fields[value_field]);
+                        }
+                    }
+                    return match (field_value, other) {
+                        (Some(field_value), Some(other)) => self.evaluate_present_condition(
+                            &prepare(field_value),
+                            &leaf.op,
+                            &Value::String(prepare(other).into_owned()),
+                            &leaf.seed,
+                            params,
+                        ),
+                        _ => false,
+                    };
+                }
+                let field_value = values[leaf.field]
+                    .get_or_insert_with(|| path::lookup(params, &fields[leaf.field]));
+                let Some(field_value) = field_value.as_deref() else {
+                    if self.strict_fields && !leaf.op.reads_absence(&leaf.value, false) {
+                        visit.record_missing(&fields[leaf.field]);
+                    }
+                    return self.absent_matches(&leaf.op, &leaf.value);
+                };
+                let field_value = &*prepare(Cow::Borrowed(field_value));
+                match &leaf.operand {
+                    #[cfg(feature = "regex")]
+                    Operand::Regex(regex) => regex.is_match(field_value),
+                    #[cfg(feature = "regex")]
+                    Operand::Glob(glob) => glob.is_match(field_value),
+                    Operand::Number(target) => compare(&leaf.op, field_value, *target),
+                    Operand::Expr(expr) => expr
+                        .eval_number(params)
+                        .is_ok_and(|target| compare(&leaf.op, field_value, target)),
+                    Operand::List(list) => list.contains(field_value) == (leaf.op == Operator::In),
+                    Operand::Never => false,
+                    Operand::Generic => self.evaluate_present_condition(
+                        field_value,
+                        &leaf.op,
+                        &leaf.value,
+                        &leaf.seed,
+                        params,
+                    ),
+                }
+            }
+        }
+    }
+}
+
+/// Compare a param value with a numeric operand
+fn compare(op: &Operator, field_value: &str, target: Number) -> bool {
+    Number::parse(field_value).is_some_and(|n| match op {
+        Operator::GreaterThan => n > target,
+        Operator::LessThan => n < target,
+        Operator::GreaterThanOrEqual => n >= target,
+        Operator::LessThanOrEqual => n <= target,
+        _ => n == target,
+    })
+}
+
+#[cfg(all(test, feature = "regex"))]
+mod tests {
+    use super::*;
+    use crate::RuleResult;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_compile_flattens_and_interns() {
+        let rules: ConfigRules = serde_json::from_value(serde_json::json!({
+            "rules": [{
+                "if": {
+                    "and": [
+                        { "field": "platform", "op": "regex", "value": "^RTD" },
+                        { "and": [
+                            { "field": "score", "op": "ge", "value": "60" },
+                            { "not": { "not": { "field": "platform", "op": "contains", "value": "PRO" } } }
+                        ] }
+                    ]
+                },
+                "then": "chip_rtd"
+            }]
+        }))
+        .unwrap();
+
+        let compiled = CompiledRules::for_rules(&rules, &[0], &RegexConfig::default());
+        let rule = compiled.rule(Phase::Main, 0);
+        assert_eq!(rule.fields, vec!["platform", "score"]);
+        let CompiledCondition::All(all) = &rule.condition else {
+            panic!("Expected flattened AND");
+        };
+        assert_eq!(all.len(), 3);
+        assert!(matches!(
+            all[0],
+            CompiledCondition::Leaf(Leaf {
+                operand: Operand::Regex(_),
+                ..
+            })
+        ));
+        assert!(matches!(
+            all[1],
+            CompiledCondition::Leaf(Leaf {
+                operand: Operand::Number(n),
+                ..
+            }) if n == Number::Int(60)
+        ));
+        assert!(matches!(
+            all[2],
+            CompiledCondition::Leaf(Leaf { field: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_dispatch_table() {
+        let mut rules: Vec<serde_json::Value> = (0..20)
+            .map(|i| {
+                serde_json::json!({
+                    "if": { "and": [format!("platform == \"P{}\"", i % 10), "score >= 60"] },
+                    "then": format!("p{}", i)
+                })
+            })
+            .collect();
+        rules.insert(3, serde_json::json!({ "if": "score >= 90", "then": "top" }));
+        rules.push(
+            serde_json::json!({ "if": "platform == \"P1\"", "then": "urgent", "priority": 1 }),
+        );
+        let evaluator = ConfigEvaluator::from_json(
+            &serde_json::json!({ "rules": rules, "fallback": "none" }).to_string(),
+        )
+        .unwrap();
+        let table = evaluator.compiled.dispatch().unwrap();
+        assert_eq!(table.field, "platform");
+        assert_eq!(table.keyed["P1"].len(), 3);
+
+        let params = |platform: &str, score: &str| {
+            HashMap::from([
+                ("platform".to_string(), platform.to_string()),
+                ("score".to_string(), score.to_string()),
+            ])
+        };
+        let evaluate = |platform, score| {
+            let params = params(platform, score);
+            let result = evaluator.evaluate(&params);
+            // Traces check every rule without the table
+            assert_eq!(evaluator.evaluate_with_trace(&params).result, result);
+            match result {
+                Some(RuleResult::String(result)) => Some(result),
+                _ => None,
+            }
+        };
+        // Candidates keep the evaluation order across keyed and other rules
+        assert_eq!(evaluate("P1", "95").as_deref(), Some("urgent"));
+        assert_eq!(evaluate("P2", "95").as_deref(), Some("p2"));
+        assert_eq!(evaluate("P4", "95").as_deref(), Some("top"));
+        assert_eq!(evaluate("P4", "70").as_deref(), Some("p4"));
+        assert_eq!(evaluate("P4", "10").as_deref(), Some("none"));
+        assert_eq!(evaluate("X", "70").as_deref(), Some("none"));
+        assert_eq!(evaluate("X", "95").as_deref(), Some("top"));
+        assert_eq!(
+            evaluator.evaluate(&HashMap::from([("score".to_string(), "95".to_string())])),
+            Some(RuleResult::String("top".to_string()))
+        );
+
+        // Too few keyed rules for a table
+        let small = ConfigEvaluator::from_json(
+            r#"{ "rules": [{ "if": "platform == \"P1\"", "then": "p1" }] }"#,
+        )
+        .unwrap();
+        assert!(small.compiled.dispatch().is_none());
+    }
+}
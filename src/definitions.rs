@@ -0,0 +1,231 @@
+//! Named conditions shared by rules
+//!
+//! `definitions` maps names to conditions that rules use as
+//! `{ "ref": name }`, so a complex block is written once. The compiled rules
+//! contain the referenced conditions themselves; analyses see refs replaced
+//! by their definitions.
+
+use crate::prelude::*;
+use crate::report::Phase;
+use crate::validation::Site;
+use crate::{Condition, ConfigEvaluator, ConfigExprError, ConfigRules};
+use alloc::borrow::Cow;
+use alloc::collections::BTreeSet;
+
+impl ConfigRules {
+    /// Conditions of every rule and definition
+    pub(crate) fn conditions(&self) -> impl Iterator<Item = &Condition> {
+        self.pre
+            .iter()
+            .chain(&self.rules)
+            .chain(&self.post)
+            .map(|rule| &rule.condition)
+            .chain(self.definitions.values())
+    }
+
+    /// A condition with every ref replaced by the named condition
+    ///
+    /// Unknown refs are kept; refs must not form cycles, as checked when the
+    /// rules are validated.
+    pub(crate) fn inline_refs<'a>(&self, condition: &'a Condition) -> Cow<'a, Condition> {
+        let mut names = Vec::new();
+        refs(condition, &mut names);
+        if names.is_empty() {
+            return Cow::Borrowed(condition);
+        }
+        Cow::Owned(self.inline(condition))
+    }
+
+    fn inline(&self, condition: &Condition) -> Condition {
+        match condition {
+            Condition::Simple { .. } => condition.clone(),
+            Condition::And { and } => Condition::And {
+                and: and.iter().map(|c| self.inline(c)).collect(),
+            },
+            Condition::Or { or } => Condition::Or {
+                or: or.iter().map(|c| self.inline(c)).collect(),
+            },
+            Condition::Not { not } => Condition::Not {
+                not: Box::new(self.inline(not)),
+            },
+            Condition::Ref { name } => match self.definitions.get(name) {
+                Some(definition) => self.inline(definition),
+                None => condition.clone(),
+            },
+        }
+    }
+}
+
+/// Collect the names a condition refers to
+fn refs<'a>(condition: &'a Condition, out: &mut Vec<&'a str>) {
+    match condition {
+        Condition::Simple { .. } => {}
+        Condition::And { and } => and.iter().for_each(|c| refs(c, out)),
+        Condition::Or { or } => or.iter().for_each(|c| refs(c, out)),
+        Condition::Not { not } => refs(not, out),
+        Condition::Ref { name } => out.push(name),
+    }
+}
+
+impl ConfigEvaluator {
+    /// Validate the named conditions, that every ref names one of them and
+    /// that no definition refers to itself, directly or through others
+    pub(crate) fn validate_definitions(rules: &ConfigRules) -> Result<(), ConfigExprError> {
+        fn check(
+            condition: &Condition,
+            rules: &ConfigRules,
+            site: &Site,
+        ) -> Result<(), ConfigExprError> {
+            match condition {
+                Condition::Simple { .. } => Ok(()),
+                Condition::And { and } => and
+                    .iter()
+                    .enumerate()
+                    .try_for_each(|(i, c)| check(c, rules, &site.at("and").at(i))),
+                Condition::Or { or } => or
+                    .iter()
+                    .enumerate()
+                    .try_for_each(|(i, c)| check(c, rules, &site.at("or").at(i))),
+                Condition::Not { not } => check(not, rules, &site.at("not")),
+                Condition::Ref { name } if !rules.definitions.contains_key(name) => Err(site
+                    .at("ref")
+                    .error(
+                        "unknown_definition",
+                        format!("Unknown definition '{}' in {}", name, site),
+                    )
+                    .value(name.as_str())
+                    .into()),
+                Condition::Ref { .. } => Ok(()),
+            }
+        }
+
+        let phases = [
+            (Phase::Pre, &rules.pre),
+            (Phase::Main, &rules.rules),
+            (Phase::Post, &rules.post),
+        ];
+        for (phase, phase_rules) in phases {
+            for (index, rule) in phase_rules.iter().enumerate() {
+                check(&rule.condition, rules, &Site::rule(phase, index).at("if"))?;
+            }
+        }
+        for (name, condition) in &rules.definitions {
+            let site = Site::definition(name);
+            Self::validate_condition(condition, &site)?;
+            check(condition, rules, &site)?;
+        }
+
+        // Depth-first search for cycles among the definitions
+        fn visit<'a>(
+            name: &'a str,
+            rules: &'a ConfigRules,
+            visiting: &mut Vec<&'a str>,
+            done: &mut BTreeSet<&'a str>,
+        ) -> Result<(), ConfigExprError> {
+            if done.contains(name) {
+                return Ok(());
+            }
+            if let Some(start) = visiting.iter().position(|v| *v == name) {
+                let mut cycle = visiting[start..].to_vec();
+                cycle.push(name);
+                return Err(ConfigExprError::ValidationError(format!(
+                    "Definition cycle: {}",
+                    cycle.join(" -> ")
+                )));
+            }
+            visiting.push(name);
+            let mut names = Vec::new();
+            refs(&rules.definitions[name], &mut names);
+            for referenced in names {
+                visit(referenced, rules, visiting, done)?;
+            }
+            visiting.pop();
+            done.insert(name);
+            Ok(())
+        }
+
+        let mut done = BTreeSet::new();
+        for name in rules.definitions.keys() {
+            visit(name, rules, &mut Vec::new(), &mut done)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::RuleResult;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_definitions() {
+        let rules: ConfigRules = serde_json::from_value(serde_json::json!({
+            "definitions": {
+                "is_cn": { "field": "region", "op": "equals", "value": "CN" },
+                "is_cn_prod": { "and": [{ "ref": "is_cn" }, "env == \"prod\""] }
+            },
+            "rules": [
+                { "if": { "ref": "is_cn_prod" }, "then": "cn_prod" },
+                { "if": { "not": { "ref": "is_cn" } }, "then": "global" }
+            ]
+        }))
+        .unwrap();
+        let evaluator = ConfigEvaluator::new(rules.clone()).unwrap();
+        let params = |region: &str, env: &str| {
+            HashMap::from([
+                ("region".to_string(), region.to_string()),
+                ("env".to_string(), env.to_string()),
+            ])
+        };
+        let evaluate = |region, env| evaluator.evaluate(&params(region, env));
+        assert_eq!(
+            evaluate("CN", "prod"),
+            Some(RuleResult::String("cn_prod".into()))
+        );
+        assert_eq!(
+            evaluate("JP", "prod"),
+            Some(RuleResult::String("global".into()))
+        );
+        assert_eq!(evaluate("CN", "dev"), None);
+        assert!(evaluator
+            .evaluate_with_trace(&params("CN", "prod"))
+            .to_string()
+            .contains("ref is_cn_prod: true"));
+        let inlined = rules.inline_refs(&rules.rules[0].condition);
+        assert!(!serde_json::to_string(&inlined).unwrap().contains("ref"));
+
+        // Refs round-trip
+        let json = serde_json::to_value(&rules).unwrap();
+        assert_eq!(
+            json["rules"][0]["if"],
+            serde_json::json!({ "ref": "is_cn_prod" })
+        );
+        assert_eq!(serde_json::from_value::<ConfigRules>(json).unwrap(), rules);
+
+        let mut unknown = rules.clone();
+        unknown.rules[1].condition = Condition::Ref {
+            name: "is_jp".to_string(),
+        };
+        let Err(ConfigExprError::InvalidRule(error)) = ConfigEvaluator::new(unknown) else {
+            panic!("Expected an invalid rule");
+        };
+        assert_eq!(error.code, "unknown_definition");
+        assert_eq!(error.pointer, "/rules/1/if/ref");
+        assert_eq!(error.message, "Unknown definition 'is_jp' in rule 1");
+
+        let mut cyclic = rules;
+        cyclic.definitions.insert(
+            "is_cn".to_string(),
+            Condition::Or {
+                or: vec![Condition::Ref {
+                    name: "is_cn_prod".to_string(),
+                }],
+            },
+        );
+        assert_eq!(
+            ConfigEvaluator::new(cyclic).unwrap_err().to_string(),
+            "Validation error: Definition cycle: is_cn -> is_cn_prod -> is_cn"
+        );
+    }
+}
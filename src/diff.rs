@@ -0,0 +1,567 @@
+//! Explaining why two parameter sets evaluate differently, and what changes
+//! between two rule sets
+
+use crate::prelude::*;
+use crate::report::{Phase, Recorder};
+use crate::{Condition, ConfigEvaluator, ConfigRules, ParamSource, Rule, RuleResult};
+use alloc::borrow::Cow;
+use alloc::collections::BTreeSet;
+use core::fmt;
+use serde::Serialize;
+
+/// Explanation of how two parameter sets evaluate
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct InputDiff {
+    /// Result for the first parameter set
+    pub result_a: Option<RuleResult>,
+    /// Result for the second parameter set
+    pub result_b: Option<RuleResult>,
+    /// Index of the main-phase rule selected for the first parameter set
+    pub rule_a: Option<usize>,
+    /// Index of the main-phase rule selected for the second parameter set
+    pub rule_b: Option<usize>,
+    /// Params whose values differ between the two sets
+    pub fields: Vec<FieldDiff>,
+    /// Conditions on differing params whose outcome differs between the sets
+    pub conditions: Vec<ConditionDiff>,
+}
+
+impl InputDiff {
+    /// Check whether the two parameter sets produce different results
+    pub fn outcome_changed(&self) -> bool {
+        self.result_a != self.result_b
+    }
+}
+
+/// A param whose value differs between two parameter sets
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct FieldDiff {
+    pub field: String,
+    pub a: Option<String>,
+    pub b: Option<String>,
+}
+
+/// A simple condition that evaluates differently for two parameter sets
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct ConditionDiff {
+    /// Index of the main-phase rule containing the condition
+    pub rule_index: usize,
+    pub condition: Condition,
+    pub matched_a: bool,
+    pub matched_b: bool,
+}
+
+fn field_diffs(a: &HashMap<String, String>, b: &HashMap<String, String>) -> Vec<FieldDiff> {
+    let keys: BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+    keys.into_iter()
+        .filter(|key| a.get(*key) != b.get(*key))
+        .map(|key| FieldDiff {
+            field: key.clone(),
+            a: a.get(key).cloned(),
+            b: b.get(key).cloned(),
+        })
+        .collect()
+}
+
+pub(crate) fn simple_conditions<'a>(condition: &'a Condition, out: &mut Vec<&'a Condition>) {
+    match condition {
+        Condition::Simple { .. } => out.push(condition),
+        Condition::And { and } => and.iter().for_each(|c| simple_conditions(c, out)),
+        Condition::Or { or } => or.iter().for_each(|c| simple_conditions(c, out)),
+        Condition::Not { not } => simple_conditions(not, out),
+        Condition::Ref { .. } => {}
+    }
+}
+
+impl ConfigEvaluator {
+    /// Evaluate two parameter sets and explain why their outcomes differ
+    ///
+    /// The report lists the params that differ and, for every main-phase rule
+    /// evaluated up to the later of the two selected rules, the conditions on those
+    /// params that matched for one set but not the other. Differing params
+    /// are found among the [`ParamSource::entries`] of the two sets.
+    pub fn diff_inputs(&self, a: &impl ParamSource, b: &impl ParamSource) -> InputDiff {
+        let (result_a, rule_a) = match self.evaluate_outcome(a, &mut Recorder::disabled()) {
+            Ok(outcome) => (outcome.result.map(Cow::into_owned), outcome.rule_index),
+            Err(_) => (None, None),
+        };
+        let (result_b, rule_b) = match self.evaluate_outcome(b, &mut Recorder::disabled()) {
+            Ok(outcome) => (outcome.result.map(Cow::into_owned), outcome.rule_index),
+            Err(_) => (None, None),
+        };
+
+        let listed_a: HashMap<_, _> = a.entries().into_iter().collect();
+        let listed_b: HashMap<_, _> = b.entries().into_iter().collect();
+        // Conditions are compared on the params as rewritten by the pre phase
+        let (params_a, params_b): (HashMap<_, _>, HashMap<_, _>) = match (
+            self.apply_pre_phase(a, &mut Recorder::disabled()),
+            self.apply_pre_phase(b, &mut Recorder::disabled()),
+        ) {
+            (Ok(params_a), Ok(params_b)) => (
+                params_a.entries().into_iter().collect(),
+                params_b.entries().into_iter().collect(),
+            ),
+            _ => (listed_a.clone(), listed_b.clone()),
+        };
+        let changed: BTreeSet<String> = field_diffs(&params_a, &params_b)
+            .into_iter()
+            .map(|diff| diff.field)
+            .collect();
+
+        let order = self.rule_order();
+        let position = |index| order.iter().position(|&i| i == index).unwrap_or(0);
+        let last_rule = match (rule_a, rule_b) {
+            (Some(rule_a), Some(rule_b)) => position(rule_a).max(position(rule_b)),
+            _ => order.len().saturating_sub(1),
+        };
+        let mut conditions = Vec::new();
+        for (rule_index, rule) in self.ordered_rules(Phase::Main).take(last_rule + 1) {
+            let condition = self.rules.inline_refs(&rule.condition);
+            let mut leaves = Vec::new();
+            simple_conditions(&condition, &mut leaves);
+            for leaf in leaves {
+                let Condition::Simple {
+                    field, value_field, ..
+                } = leaf
+                else {
+                    continue;
+                };
+                if !changed.contains(field)
+                    && !value_field.as_ref().is_some_and(|f| changed.contains(f))
+                {
+                    continue;
+                }
+                let matched_a = self.evaluate_condition(leaf, &params_a);
+                let matched_b = self.evaluate_condition(leaf, &params_b);
+                if matched_a != matched_b {
+                    conditions.push(ConditionDiff {
+                        rule_index,
+                        condition: leaf.clone(),
+                        matched_a,
+                        matched_b,
+                    });
+                }
+            }
+        }
+
+        InputDiff {
+            result_a,
+            result_b,
+            rule_a,
+            rule_b,
+            fields: field_diffs(&listed_a, &listed_b),
+            conditions,
+        }
+    }
+}
+
+/// Differences between two rule sets, for reviewing a config update
+///
+/// Rules are paired by `id` first, then identical rules are paired, and the
+/// remaining rules without an `id` are paired in order as changed rules.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct RuleSetDiff {
+    /// Rules only in the new rule set
+    pub added: Vec<RuleEntry>,
+    /// Rules only in the old rule set
+    pub removed: Vec<RuleEntry>,
+    /// Rules in both rule sets whose definition changed
+    pub changed: Vec<RuleChange>,
+    /// Rules whose position relative to the other paired rules changed,
+    /// which matters when the first matching rule wins
+    pub moved: Vec<RuleMove>,
+    /// The fallback, when it changed
+    pub fallback: Option<FallbackChange>,
+    /// Other top-level settings that changed, like `match_mode`
+    pub settings: Vec<String>,
+}
+
+impl RuleSetDiff {
+    /// Check whether the rule sets are the same
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+            && self.moved.is_empty()
+            && self.fallback.is_none()
+            && self.settings.is_empty()
+    }
+}
+
+/// A rule present in only one of two rule sets
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct RuleEntry {
+    pub phase: Phase,
+    pub index: usize,
+    pub rule: Rule,
+}
+
+/// A rule whose definition differs between two rule sets
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct RuleChange {
+    pub phase: Phase,
+    pub old_index: usize,
+    pub new_index: usize,
+    /// Keys of the rule that changed, such as `if` or `then`
+    pub fields: Vec<String>,
+    pub old: Rule,
+    pub new: Rule,
+}
+
+/// A rule that moved relative to the other rules of its phase
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct RuleMove {
+    pub phase: Phase,
+    pub old_index: usize,
+    pub new_index: usize,
+}
+
+/// Old and new fallback of two rule sets
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct FallbackChange {
+    pub old: Option<RuleResult>,
+    pub new: Option<RuleResult>,
+}
+
+impl ConfigRules {
+    /// Describe what changes from this rule set to `other`
+    pub fn diff(&self, other: &ConfigRules) -> RuleSetDiff {
+        let mut diff = RuleSetDiff {
+            added: Vec::new(),
+            removed: Vec::new(),
+            changed: Vec::new(),
+            moved: Vec::new(),
+            fallback: (self.fallback != other.fallback).then(|| FallbackChange {
+                old: self.fallback.clone(),
+                new: other.fallback.clone(),
+            }),
+            settings: Vec::new(),
+        };
+        diff_phase(Phase::Pre, &self.pre, &other.pre, &mut diff);
+        diff_phase(Phase::Main, &self.rules, &other.rules, &mut diff);
+        diff_phase(Phase::Post, &self.post, &other.post, &mut diff);
+
+        let settings = [
+            ("version", self.version != other.version),
+            ("params_spec", self.params_spec != other.params_spec),
+            ("match_mode", self.match_mode != other.match_mode),
+            (
+                "merge_strategy",
+                self.merge_strategy != other.merge_strategy,
+            ),
+            (
+                "datetime_format",
+                self.datetime_format != other.datetime_format,
+            ),
+        ];
+        diff.settings = settings
+            .into_iter()
+            .filter(|(_, changed)| *changed)
+            .map(|(name, _)| name.to_string())
+            .collect();
+        diff
+    }
+}
+
+fn diff_phase(phase: Phase, old: &[Rule], new: &[Rule], diff: &mut RuleSetDiff) {
+    // Index of the new rule paired with each old rule
+    let mut pairs: Vec<Option<usize>> = vec![None; old.len()];
+    let mut paired = vec![false; new.len()];
+
+    // Rules with the same id
+    for (j, rule) in new.iter().enumerate() {
+        let Some(id) = &rule.id else { continue };
+        if let Some(i) = old.iter().position(|r| r.id.as_ref() == Some(id)) {
+            if pairs[i].is_none() {
+                pairs[i] = Some(j);
+                paired[j] = true;
+            }
+        }
+    }
+    // Identical rules
+    for (j, rule) in new.iter().enumerate() {
+        if paired[j] {
+            continue;
+        }
+        if let Some(i) = (0..old.len()).find(|&i| pairs[i].is_none() && old[i] == *rule) {
+            pairs[i] = Some(j);
+            paired[j] = true;
+        }
+    }
+    // Remaining rules without an id, in order
+    let unpaired_old = (0..old.len()).filter(|&i| pairs[i].is_none() && old[i].id.is_none());
+    let unpaired_new: Vec<usize> = (0..new.len())
+        .filter(|&j| !paired[j] && new[j].id.is_none())
+        .collect();
+    for (i, j) in unpaired_old
+        .collect::<Vec<_>>()
+        .into_iter()
+        .zip(unpaired_new)
+    {
+        pairs[i] = Some(j);
+        paired[j] = true;
+    }
+
+    for (j, rule) in new.iter().enumerate().filter(|(j, _)| !paired[*j]) {
+        diff.added.push(RuleEntry {
+            phase,
+            index: j,
+            rule: rule.clone(),
+        });
+    }
+    for (i, rule) in old.iter().enumerate().filter(|(i, _)| pairs[*i].is_none()) {
+        diff.removed.push(RuleEntry {
+            phase,
+            index: i,
+            rule: rule.clone(),
+        });
+    }
+    let pairs: Vec<(usize, usize)> = pairs
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, j)| j.map(|j| (i, j)))
+        .collect();
+    for &(i, j) in &pairs {
+        let fields = changed_fields(&old[i], &new[j]);
+        if !fields.is_empty() {
+            diff.changed.push(RuleChange {
+                phase,
+                old_index: i,
+                new_index: j,
+                fields,
+                old: old[i].clone(),
+                new: new[j].clone(),
+            });
+        }
+    }
+    let new_order: Vec<usize> = pairs.iter().map(|&(_, j)| j).collect();
+    let in_order = longest_increasing(&new_order);
+    for (k, &(i, j)) in pairs.iter().enumerate() {
+        if !in_order.contains(&k) {
+            diff.moved.push(RuleMove {
+                phase,
+                old_index: i,
+                new_index: j,
+            });
+        }
+    }
+}
+
+/// Serialized keys whose values differ between two rules
+fn changed_fields(old: &Rule, new: &Rule) -> Vec<String> {
+    let (Ok(serde_json::Value::Object(old)), Ok(serde_json::Value::Object(new))) =
+        (serde_json::to_value(old), serde_json::to_value(new))
+    else {
+        return Vec::new();
+    };
+    let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    keys.into_iter()
+        .filter(|key| old.get(*key) != new.get(*key))
+        .cloned()
+        .collect()
+}
+
+/// Positions of a longest strictly increasing subsequence
+fn longest_increasing(values: &[usize]) -> BTreeSet<usize> {
+    // Length of the longest run ending at each position and its predecessor
+    let mut length = vec![1; values.len()];
+    let mut previous = vec![None; values.len()];
+    for k in 0..values.len() {
+        for p in 0..k {
+            if values[p] < values[k] && length[p] + 1 > length[k] {
+                length[k] = length[p] + 1;
+                previous[k] = Some(p);
+            }
+        }
+    }
+    let mut positions = BTreeSet::new();
+    let mut next = (0..values.len()).max_by_key(|&k| (length[k], core::cmp::Reverse(k)));
+    while let Some(k) = next {
+        positions.insert(k);
+        next = previous[k];
+    }
+    positions
+}
+
+fn rule_label(phase: Phase, index: usize, rule: &Rule) -> String {
+    match &rule.id {
+        Some(id) => format!("{} ({})", phase.label(index), id),
+        None => phase.label(index),
+    }
+}
+
+impl fmt::Display for RuleSetDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "no changes");
+        }
+        for entry in &self.added {
+            writeln!(
+                f,
+                "added {}",
+                rule_label(entry.phase, entry.index, &entry.rule)
+            )?;
+        }
+        for entry in &self.removed {
+            writeln!(
+                f,
+                "removed {}",
+                rule_label(entry.phase, entry.index, &entry.rule)
+            )?;
+        }
+        for change in &self.changed {
+            write!(
+                f,
+                "changed {}",
+                rule_label(change.phase, change.old_index, &change.old)
+            )?;
+            if change.new_index != change.old_index {
+                write!(f, " -> {}", change.phase.label(change.new_index))?;
+            }
+            writeln!(f, ": {}", change.fields.join(", "))?;
+        }
+        for moved in &self.moved {
+            writeln!(
+                f,
+                "moved {} -> {}",
+                moved.phase.label(moved.old_index),
+                moved.phase.label(moved.new_index)
+            )?;
+        }
+        if let Some(fallback) = &self.fallback {
+            let show = |result: &Option<RuleResult>| {
+                serde_json::to_string(result).unwrap_or_else(|_| "?".to_string())
+            };
+            writeln!(
+                f,
+                "fallback: {} -> {}",
+                show(&fallback.old),
+                show(&fallback.new)
+            )?;
+        }
+        if !self.settings.is_empty() {
+            writeln!(f, "changed settings: {}", self.settings.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_inputs() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "and": [
+                            { "field": "platform", "op": "prefix", "value": "RTD" },
+                            { "field": "firmware", "op": "ge", "value": "3" }
+                        ]
+                    },
+                    "then": "chip_rtd_v3"
+                },
+                {
+                    "if": { "field": "platform", "op": "prefix", "value": "RTD" },
+                    "then": "chip_rtd"
+                }
+            ]
+        }
+        "#;
+
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+        let a = HashMap::from([
+            ("platform".to_string(), "RTD-2000".to_string()),
+            ("firmware".to_string(), "3.1".to_string()),
+            ("serial".to_string(), "A1".to_string()),
+        ]);
+        let mut b = a.clone();
+        b.insert("firmware".to_string(), "2.9".to_string());
+        b.insert("serial".to_string(), "B2".to_string());
+
+        let diff = evaluator.diff_inputs(&a, &b);
+        assert!(diff.outcome_changed());
+        assert_eq!((diff.rule_a, diff.rule_b), (Some(0), Some(1)));
+        let fields: Vec<&str> = diff.fields.iter().map(|f| f.field.as_str()).collect();
+        assert_eq!(fields, vec!["firmware", "serial"]);
+        assert_eq!(diff.conditions.len(), 1);
+        assert_eq!(diff.conditions[0].rule_index, 0);
+        assert!(diff.conditions[0].matched_a && !diff.conditions[0].matched_b);
+
+        let same = evaluator.diff_inputs(&a, &a);
+        assert!(!same.outcome_changed());
+        assert!(same.fields.is_empty() && same.conditions.is_empty());
+    }
+
+    #[test]
+    fn test_rule_set_diff() {
+        let old: ConfigRules = serde_json::from_value(serde_json::json!({
+            "rules": [
+                { "id": "rtd", "if": { "field": "platform", "op": "prefix", "value": "RTD" }, "then": "chip_rtd" },
+                { "if": { "field": "platform", "op": "prefix", "value": "MT" }, "then": "chip_mt" },
+                { "if": { "field": "region", "op": "equals", "value": "CN" }, "then": "chip_cn" },
+                { "if": { "field": "region", "op": "equals", "value": "US" }, "then": "chip_us" }
+            ],
+            "fallback": "chip_default"
+        }))
+        .unwrap();
+        let new: ConfigRules = serde_json::from_value(serde_json::json!({
+            "rules": [
+                { "if": { "field": "region", "op": "equals", "value": "CN" }, "then": "chip_cn" },
+                { "id": "rtd", "if": { "field": "platform", "op": "prefix", "value": "RTD" }, "then": "chip_rtd_v2" },
+                { "if": { "field": "platform", "op": "prefix", "value": "MTK" }, "then": "chip_mt" },
+                { "id": "amlogic", "if": { "field": "platform", "op": "prefix", "value": "S9" }, "then": "chip_aml" }
+            ],
+            "fallback": "chip_generic",
+            "match_mode": "all"
+        }))
+        .unwrap();
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.added[0].rule.id.as_deref(), Some("amlogic"));
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!(diff.removed[0].index, 3);
+        let changed: Vec<(usize, usize, Vec<String>)> = diff
+            .changed
+            .iter()
+            .map(|c| (c.old_index, c.new_index, c.fields.clone()))
+            .collect();
+        assert_eq!(
+            changed,
+            vec![
+                (0, 1, vec!["then".to_string()]),
+                (1, 2, vec!["if".to_string()])
+            ]
+        );
+        // Rule 2 jumped ahead of the others; their own shifts are not moves
+        assert_eq!(
+            diff.moved,
+            vec![RuleMove {
+                phase: Phase::Main,
+                old_index: 2,
+                new_index: 0
+            }]
+        );
+        assert_eq!(
+            diff.fallback.as_ref().unwrap().new,
+            Some(RuleResult::String("chip_generic".to_string()))
+        );
+        assert_eq!(diff.settings, vec!["match_mode"]);
+        assert_eq!(
+            diff.to_string(),
+            "added rule 3 (amlogic)\n\
+             removed rule 3\n\
+             changed rule 0 (rtd) -> rule 1: then\n\
+             changed rule 1 -> rule 2: if\n\
+             moved rule 2 -> rule 0\n\
+             fallback: \"chip_default\" -> \"chip_generic\"\n\
+             changed settings: match_mode\n"
+        );
+
+        assert!(old.diff(&old).is_empty());
+    }
+}
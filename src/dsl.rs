@@ -0,0 +1,395 @@
+//! Textual syntax for conditions
+//!
+//! Grammar:
+//!
+//! ```text
+//! or         := and ('||' and)*
+//! and        := unary ('&&' unary)*
+//! unary      := '!' unary | '(' or ')' | comparison
+//! comparison := field op value | field ('exists' | 'missing')
+//! op         := '==' | '!=' | '~' | '!~' | '>' | '>=' | '<' | '<='
+//!             | operator name, e.g. 'prefix', 'in', 'semver_ge'
+//! value      := string | number | 'true' | 'false' | 'null' | '[' value (',' value)* ']'
+//! ```
+//!
+//! `~` is a regex match and `!=` / `!~` negate `==` / `~`. Strings use JSON
+//! escapes, and fields are parameter names or dot-paths like `items[0].id`.
+
+use crate::prelude::*;
+use crate::{Condition, ConfigExprError, Operator};
+use serde_json::Value;
+
+/// Most levels of `!`, parentheses and arrays, bounding the recursion of
+/// the parser
+const MAX_NESTING: usize = 128;
+
+impl Condition {
+    /// Parse a condition from its textual form
+    ///
+    /// ```
+    /// use clia_config_expr::Condition;
+    ///
+    /// let condition = Condition::parse_expr(r#"platform ~ "^RTD" && region == "CN""#).unwrap();
+    /// assert!(matches!(condition, Condition::And { .. }));
+    /// ```
+    pub fn parse_expr(input: &str) -> Result<Condition, ConfigExprError> {
+        let mut parser = Parser {
+            input: input.as_bytes(),
+            pos: 0,
+            depth: 0,
+        };
+        let condition = parser.parse_or()?;
+        if parser.peek().is_some() {
+            return Err(parser.error("unexpected trailing input"));
+        }
+        Ok(condition)
+    }
+}
+
+impl core::str::FromStr for Condition {
+    type Err = ConfigExprError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Condition::parse_expr(s)
+    }
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> ConfigExprError {
+        ConfigExprError::ExpressionError(format!("{} at position {}", message, self.pos))
+    }
+
+    /// Parse a nested construct, failing beyond [`MAX_NESTING`] levels
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, ConfigExprError>,
+    ) -> Result<T, ConfigExprError> {
+        if self.depth == MAX_NESTING {
+            return Err(self.error("nested too deeply"));
+        }
+        self.depth += 1;
+        let parsed = parse(self);
+        self.depth -= 1;
+        parsed
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.input.len() && self.input[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.input.get(self.pos).copied()
+    }
+
+    /// Consume `token` if the input continues with it
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.input[self.pos..].starts_with(token.as_bytes()) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Condition, ConfigExprError> {
+        let mut or = vec![self.parse_and()?];
+        while self.eat("||") {
+            or.push(self.parse_and()?);
+        }
+        Ok(if or.len() == 1 {
+            or.remove(0)
+        } else {
+            Condition::Or { or }
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<Condition, ConfigExprError> {
+        let mut and = vec![self.parse_unary()?];
+        while self.eat("&&") {
+            and.push(self.parse_unary()?);
+        }
+        Ok(if and.len() == 1 {
+            and.remove(0)
+        } else {
+            Condition::And { and }
+        })
+    }
+
+    fn parse_unary(&mut self) -> Result<Condition, ConfigExprError> {
+        match self.peek() {
+            Some(b'!') => {
+                self.pos += 1;
+                Ok(Condition::Not {
+                    not: Box::new(self.nested(Self::parse_unary)?),
+                })
+            }
+            Some(b'(') => {
+                self.pos += 1;
+                let condition = self.nested(Self::parse_or)?;
+                if !self.eat(")") {
+                    return Err(self.error("expected ')'"));
+                }
+                Ok(condition)
+            }
+            _ => self.parse_comparison(),
+        }
+    }
+
+    fn parse_comparison(&mut self) -> Result<Condition, ConfigExprError> {
+        let field = self
+            .parse_word(true)
+            .ok_or_else(|| self.error("expected field"))?;
+
+        let symbols = [
+            ("==", Operator::Equals, false),
+            ("!=", Operator::Equals, true),
+            ("!~", Operator::Regex, true),
+            ("~", Operator::Regex, false),
+            (">=", Operator::GreaterThanOrEqual, false),
+            ("<=", Operator::LessThanOrEqual, false),
+            (">", Operator::GreaterThan, false),
+            ("<", Operator::LessThan, false),
+        ];
+        let (op, negated) = match symbols.into_iter().find(|(symbol, _, _)| self.eat(symbol)) {
+            Some((_, op, negated)) => (op, negated),
+            None => {
+                let start = self.pos;
+                let name = self
+                    .parse_word(false)
+                    .ok_or_else(|| self.error("expected operator"))?;
+                let op = serde_json::from_value(Value::String(name.clone())).map_err(|_| {
+                    ConfigExprError::InvalidOperator(format!("{} at position {}", name, start))
+                })?;
+                (op, false)
+            }
+        };
+
+        let value = match op {
+            Operator::Exists | Operator::Missing => Value::Null,
+            _ => self.parse_value()?,
+        };
+        let condition = Condition::Simple {
+            field,
+            op,
+            value,
+            value_field: None,
+            seed: String::new(),
+            trim: false,
+            normalize: None,
+            mode: None,
+        };
+        Ok(if negated {
+            Condition::Not {
+                not: Box::new(condition),
+            }
+        } else {
+            condition
+        })
+    }
+
+    /// Parse a field name (with dot-path and index syntax) or a keyword
+    fn parse_word(&mut self, field: bool) -> Option<String> {
+        self.skip_whitespace();
+        let start = self.pos;
+        if !self
+            .input
+            .get(self.pos)
+            .is_some_and(|c| c.is_ascii_alphabetic() || *c == b'_')
+        {
+            return None;
+        }
+        while let Some(&c) = self.input.get(self.pos) {
+            let index =
+                field && c == b'[' && self.input.get(self.pos + 1).is_some_and(u8::is_ascii_digit);
+            if c.is_ascii_alphanumeric()
+                || c == b'_'
+                || (field && matches!(c, b'.' | b']'))
+                || index
+            {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        let word = core::str::from_utf8(&self.input[start..self.pos]).ok()?;
+        Some(word.to_string())
+    }
+
+    fn parse_value(&mut self) -> Result<Value, ConfigExprError> {
+        match self.peek() {
+            Some(b'"') => {
+                let start = self.pos;
+                self.pos += 1;
+                while let Some(&c) = self.input.get(self.pos) {
+                    self.pos += match c {
+                        b'\\' => 2,
+                        _ => 1,
+                    };
+                    if c == b'"' {
+                        let literal = core::str::from_utf8(&self.input[start..self.pos])
+                            .map_err(|_| self.error("invalid string"))?;
+                        return serde_json::from_str(literal)
+                            .map_err(|_| self.error("invalid string"));
+                    }
+                }
+                Err(self.error("unterminated string"))
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                if !self.eat("]") {
+                    loop {
+                        items.push(self.nested(Self::parse_value)?);
+                        if self.eat("]") {
+                            break;
+                        }
+                        if !self.eat(",") {
+                            return Err(self.error("expected ',' or ']'"));
+                        }
+                    }
+                }
+                Ok(Value::Array(items))
+            }
+            Some(c) if c.is_ascii_digit() || c == b'-' => {
+                let start = self.pos;
+                while self.input.get(self.pos).is_some_and(|c| {
+                    c.is_ascii_digit() || matches!(c, b'-' | b'+' | b'.' | b'e' | b'E')
+                }) {
+                    self.pos += 1;
+                }
+                let text = core::str::from_utf8(&self.input[start..self.pos]).unwrap_or_default();
+                match serde_json::from_str(text) {
+                    Ok(number @ Value::Number(_)) => Ok(number),
+                    _ => Err(self.error("invalid number")),
+                }
+            }
+            Some(_) => match self.parse_word(false).as_deref() {
+                Some("true") => Ok(Value::Bool(true)),
+                Some("false") => Ok(Value::Bool(false)),
+                Some("null") => Ok(Value::Null),
+                _ => Err(self.error("expected value")),
+            },
+            None => Err(self.error("unexpected end of expression")),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{ConfigEvaluator, RuleResult};
+    use std::collections::HashMap;
+
+    fn simple(field: &str, op: Operator, value: Value) -> Condition {
+        Condition::Simple {
+            field: field.to_string(),
+            op,
+            value,
+            value_field: None,
+            seed: String::new(),
+            trim: false,
+            normalize: None,
+            mode: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_expr() {
+        let condition = Condition::parse_expr(
+            r#"platform ~ "^RTD" && (region in ["CN", "JP"] || !score >= 60.5) && device.items[0].id exists"#,
+        )
+        .unwrap();
+        assert_eq!(
+            condition,
+            Condition::And {
+                and: vec![
+                    simple("platform", Operator::Regex, Value::from("^RTD")),
+                    Condition::Or {
+                        or: vec![
+                            simple("region", Operator::In, serde_json::json!(["CN", "JP"])),
+                            Condition::Not {
+                                not: Box::new(simple(
+                                    "score",
+                                    Operator::GreaterThanOrEqual,
+                                    Value::from(60.5)
+                                )),
+                            },
+                        ],
+                    },
+                    simple("device.items[0].id", Operator::Exists, Value::Null),
+                ],
+            }
+        );
+
+        let condition: Condition = r#"beta != true && name iprefix "a\"b""#.parse().unwrap();
+        assert_eq!(
+            condition,
+            Condition::And {
+                and: vec![
+                    Condition::Not {
+                        not: Box::new(simple("beta", Operator::Equals, Value::Bool(true))),
+                    },
+                    simple("name", Operator::IPrefix, Value::from("a\"b")),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_expr_errors() {
+        assert!(Condition::parse_expr("").is_err());
+        assert!(Condition::parse_expr(r#"platform == "RTD" &&"#).is_err());
+        assert!(Condition::parse_expr(r#"(platform == "RTD""#).is_err());
+        assert!(Condition::parse_expr(r#"platform == "RTD"#).is_err());
+        assert!(Condition::parse_expr(r#"platform == RTD"#).is_err());
+        assert!(Condition::parse_expr("region in [\"CN\" \"JP\"]").is_err());
+        assert!(matches!(
+            Condition::parse_expr(r#"platform like "RTD""#),
+            Err(ConfigExprError::InvalidOperator(_))
+        ));
+    }
+
+    #[test]
+    fn test_string_conditions_in_rules() {
+        let json = r#"
+        {
+            "rules": [
+                { "if": "platform ~ \"^RTD\" && region == \"CN\"", "then": "chip_rtd_cn" },
+                {
+                    "if": { "or": ["firmware >= 3", { "field": "beta", "op": "equals", "value": true }] },
+                    "then": "chip_new"
+                }
+            ]
+        }
+        "#;
+
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+        let params = HashMap::from([
+            ("platform".to_string(), "RTD-2000".to_string()),
+            ("region".to_string(), "CN".to_string()),
+        ]);
+        assert_eq!(
+            evaluator.evaluate(&params),
+            Some(RuleResult::String("chip_rtd_cn".to_string()))
+        );
+        let params = HashMap::from([("firmware".to_string(), "4".to_string())]);
+        assert_eq!(
+            evaluator.evaluate(&params),
+            Some(RuleResult::String("chip_new".to_string()))
+        );
+        assert!(ConfigEvaluator::from_json(
+            r#"{ "rules": [{ "if": "platform ==", "then": "x" }] }"#
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,318 @@
+//! Small arithmetic expression language for computed values
+//!
+//! Grammar:
+//!
+//! ```text
+//! expr   := term (('+' | '-') term)*
+//! term   := unary (('*' | '/') unary)*
+//! unary  := '-' unary | atom
+//! atom   := number | field | '${' field '}' | '(' expr ')'
+//! ```
+//!
+//! Fields are parameter names or dot-paths made of letters, digits, `_` and
+//! `.`, and are resolved as numbers at evaluation time. Computed results
+//! (`= base_timeout * 1.5`) usually name them bare; numeric condition values
+//! (`${base_price} * 1.2`) use placeholders, which is also how they are told
+//! apart from plain numbers.
+
+use crate::number::Number;
+use crate::prelude::*;
+use crate::{path, ConfigExprError, ParamSource};
+#[cfg(feature = "decimal")]
+use rust_decimal::Decimal;
+
+/// Most levels of negations and parentheses, bounding the recursion of the
+/// parser
+const MAX_NESTING: usize = 128;
+
+/// Parsed arithmetic expression
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Expr {
+    Number(f64),
+    Field(String),
+    Neg(Box<Expr>),
+    Binary(Box<Expr>, BinaryOp, Box<Expr>),
+}
+
+/// Binary arithmetic operator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl Expr {
+    /// Parse an expression string
+    pub(crate) fn parse(input: &str) -> Result<Expr, ConfigExprError> {
+        let mut parser = Parser {
+            input: input.as_bytes(),
+            pos: 0,
+            depth: 0,
+        };
+        let expr = parser.parse_expr()?;
+        parser.skip_whitespace();
+        if parser.pos < parser.input.len() {
+            return Err(parser.error("unexpected trailing input"));
+        }
+        Ok(expr)
+    }
+
+    /// Evaluate the expression, resolving fields from the params
+    pub(crate) fn eval(&self, params: &dyn ParamSource) -> Result<f64, ConfigExprError> {
+        match self {
+            Expr::Number(n) => Ok(*n),
+            Expr::Field(field) => {
+                let value = path::lookup(params, field)
+                    .ok_or_else(|| ConfigExprError::FieldNotFound(field.clone()))?;
+                value.trim().parse::<f64>().map_err(|_| {
+                    ConfigExprError::ExpressionError(format!(
+                        "Field '{}' is not a number: '{}'",
+                        field, value
+                    ))
+                })
+            }
+            Expr::Neg(inner) => Ok(-inner.eval(params)?),
+            Expr::Binary(lhs, op, rhs) => {
+                let (lhs, rhs) = (lhs.eval(params)?, rhs.eval(params)?);
+                Ok(match op {
+                    BinaryOp::Add => lhs + rhs,
+                    BinaryOp::Sub => lhs - rhs,
+                    BinaryOp::Mul => lhs * rhs,
+                    BinaryOp::Div => lhs / rhs,
+                })
+            }
+        }
+    }
+
+    /// Evaluate the expression into a number for comparisons
+    ///
+    /// With the `decimal` feature, the arithmetic is decimal while every
+    /// operand and intermediate result is a decimal within its range.
+    pub(crate) fn eval_number(&self, params: &dyn ParamSource) -> Result<Number, ConfigExprError> {
+        #[cfg(feature = "decimal")]
+        if let Some(n) = self.eval_decimal(params) {
+            return Ok(Number::Decimal(n));
+        }
+        self.eval(params).map(Number::Float)
+    }
+
+    /// Evaluate the expression in decimal arithmetic, `None` when it fails
+    #[cfg(feature = "decimal")]
+    fn eval_decimal(&self, params: &dyn ParamSource) -> Option<Decimal> {
+        match self {
+            // Floats print as the shortest string reading back the same
+            // float, which is how the literal was written
+            Expr::Number(n) => crate::number::parse_decimal(&n.to_string()),
+            Expr::Field(field) => crate::number::parse_decimal(path::lookup(params, field)?.trim()),
+            Expr::Neg(inner) => Some(-inner.eval_decimal(params)?),
+            Expr::Binary(lhs, op, rhs) => {
+                let (lhs, rhs) = (lhs.eval_decimal(params)?, rhs.eval_decimal(params)?);
+                match op {
+                    BinaryOp::Add => lhs.checked_add(rhs),
+                    BinaryOp::Sub => lhs.checked_sub(rhs),
+                    BinaryOp::Mul => lhs.checked_mul(rhs),
+                    BinaryOp::Div => lhs.checked_div(rhs),
+                }
+            }
+        }
+    }
+
+    /// Collect the fields referenced by the expression
+    pub(crate) fn fields<'a>(&'a self, out: &mut Vec<&'a str>) {
+        match self {
+            Expr::Number(_) => {}
+            Expr::Field(field) => out.push(field),
+            Expr::Neg(inner) => inner.fields(out),
+            Expr::Binary(lhs, _, rhs) => {
+                lhs.fields(out);
+                rhs.fields(out);
+            }
+        }
+    }
+}
+
+/// Check whether a numeric condition value is an expression rather than a
+/// number
+pub(crate) fn has_placeholders(value: &str) -> bool {
+    value.contains("${")
+}
+
+/// Convert a computed number into a JSON value, preferring integers
+pub(crate) fn number_to_json(n: f64) -> Result<serde_json::Value, ConfigExprError> {
+    if n.abs() < i64::MAX as f64 && n == (n as i64) as f64 {
+        return Ok(serde_json::Value::from(n as i64));
+    }
+    serde_json::Number::from_f64(n)
+        .map(serde_json::Value::Number)
+        .ok_or_else(|| {
+            ConfigExprError::ExpressionError(format!("Result is not a finite number: {}", n))
+        })
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> ConfigExprError {
+        ConfigExprError::ExpressionError(format!("{} at position {}", message, self.pos))
+    }
+
+    /// Parse a nested construct, failing beyond [`MAX_NESTING`] levels
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, ConfigExprError>,
+    ) -> Result<T, ConfigExprError> {
+        if self.depth == MAX_NESTING {
+            return Err(self.error("nested too deeply"));
+        }
+        self.depth += 1;
+        let parsed = parse(self);
+        self.depth -= 1;
+        parsed
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.input.len() && self.input[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.input.get(self.pos).copied()
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, ConfigExprError> {
+        let mut lhs = self.parse_term()?;
+        while let Some(c @ (b'+' | b'-')) = self.peek() {
+            self.pos += 1;
+            let op = if c == b'+' {
+                BinaryOp::Add
+            } else {
+                BinaryOp::Sub
+            };
+            lhs = Expr::Binary(Box::new(lhs), op, Box::new(self.parse_term()?));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_term(&mut self) -> Result<Expr, ConfigExprError> {
+        let mut lhs = self.parse_unary()?;
+        while let Some(c @ (b'*' | b'/')) = self.peek() {
+            self.pos += 1;
+            let op = if c == b'*' {
+                BinaryOp::Mul
+            } else {
+                BinaryOp::Div
+            };
+            lhs = Expr::Binary(Box::new(lhs), op, Box::new(self.parse_unary()?));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ConfigExprError> {
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+            return Ok(Expr::Neg(Box::new(self.nested(Self::parse_unary)?)));
+        }
+        self.parse_atom()
+    }
+
+    fn parse_atom(&mut self) -> Result<Expr, ConfigExprError> {
+        match self.peek() {
+            Some(b'(') => {
+                self.pos += 1;
+                let expr = self.nested(Self::parse_expr)?;
+                if self.peek() != Some(b')') {
+                    return Err(self.error("expected ')'"));
+                }
+                self.pos += 1;
+                Ok(expr)
+            }
+            Some(c) if c.is_ascii_digit() || c == b'.' => {
+                let start = self.pos;
+                while self.pos < self.input.len()
+                    && (self.input[self.pos].is_ascii_digit() || self.input[self.pos] == b'.')
+                {
+                    self.pos += 1;
+                }
+                let text = core::str::from_utf8(&self.input[start..self.pos]).unwrap_or_default();
+                text.parse::<f64>()
+                    .map(Expr::Number)
+                    .map_err(|_| self.error("invalid number"))
+            }
+            Some(c) if c.is_ascii_alphabetic() || c == b'_' => {
+                let start = self.pos;
+                while self.pos < self.input.len()
+                    && (self.input[self.pos].is_ascii_alphanumeric()
+                        || matches!(self.input[self.pos], b'_' | b'.'))
+                {
+                    self.pos += 1;
+                }
+                let name = core::str::from_utf8(&self.input[start..self.pos]).unwrap_or_default();
+                Ok(Expr::Field(name.to_string()))
+            }
+            Some(b'$') if self.input[self.pos..].starts_with(b"${") => {
+                let start = self.pos + 2;
+                let Some(len) = self.input[start..].iter().position(|&c| c == b'}') else {
+                    return Err(self.error("unterminated placeholder"));
+                };
+                let name = core::str::from_utf8(&self.input[start..start + len])
+                    .unwrap_or_default()
+                    .trim();
+                if name.is_empty() {
+                    return Err(self.error("empty placeholder"));
+                }
+                self.pos = start + len + 1;
+                Ok(Expr::Field(name.to_string()))
+            }
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of expression")),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_precedence_and_parentheses() {
+        let params = HashMap::from([("base".to_string(), "10".to_string())]);
+        let expr = Expr::parse("base + 2 * 3").unwrap();
+        assert_eq!(expr.eval(&params).unwrap(), 16.0);
+        let expr = Expr::parse("-(base + 2) * 3 / 4").unwrap();
+        assert_eq!(expr.eval(&params).unwrap(), -9.0);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Expr::parse("1 +").is_err());
+        assert!(Expr::parse("(1 + 2").is_err());
+        assert!(Expr::parse("1 2").is_err());
+        assert!(Expr::parse("a % 2").is_err());
+    }
+
+    #[test]
+    fn test_placeholders() {
+        let params = HashMap::from([("base_price".to_string(), "50".to_string())]);
+        let expr = Expr::parse("${base_price} * 1.2 + ${ base_price }").unwrap();
+        assert_eq!(expr.eval(&params).unwrap(), 110.0);
+        assert!(Expr::parse("${} * 2").is_err());
+        assert!(Expr::parse("${base_price * 2").is_err());
+        assert!(Expr::parse("$base_price").is_err());
+    }
+
+    #[test]
+    fn test_non_numeric_field() {
+        let params = HashMap::from([("base".to_string(), "fast".to_string())]);
+        let err = Expr::parse("base * 2").unwrap().eval(&params).unwrap_err();
+        assert!(err.to_string().contains("not a number"));
+    }
+}
@@ -0,0 +1,209 @@
+//! C API for embedding the evaluator in C and C++ programs
+//!
+//! Built with the `ffi` feature (or its alias `cdylib`), the `cdylib` exports
+//! the functions below; `include/config_expr.h` declares them. Strings cross
+//! the boundary as NUL-terminated UTF-8. Strings returned by the library,
+//! results and error messages alike, are owned by the caller and released
+//! with [`config_expr_string_free`].
+
+use crate::ConfigEvaluator;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+/// Opaque handle to a compiled rule set
+pub struct ConfigExprEvaluator(ConfigEvaluator);
+
+/// Read a borrowed C string argument
+unsafe fn read_str<'a>(s: *const c_char, name: &str) -> Result<&'a str, String> {
+    if s.is_null() {
+        return Err(format!("{} is NULL", name));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|e| format!("{} is not valid UTF-8: {}", name, e))
+}
+
+/// Hand a string to the caller, dropping interior NULs
+fn into_c_string(s: String) -> *mut c_char {
+    let s = CString::new(s).unwrap_or_else(|e| {
+        let mut bytes = e.into_vec();
+        bytes.retain(|&b| b != 0);
+        CString::new(bytes).expect("NULs removed")
+    });
+    s.into_raw()
+}
+
+/// Run the body of an exported function, reporting a panic as an error
+/// rather than unwinding into the caller
+fn catch_panic<T>(body: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|payload| {
+        let cause = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown cause");
+        Err(format!("panicked: {}", cause))
+    })
+}
+
+/// Store an error message in `*error` when the caller asked for it
+unsafe fn set_error(error: *mut *mut c_char, message: String) {
+    if !error.is_null() {
+        *error = into_c_string(message);
+    }
+}
+
+/// Load and validate JSON rules
+///
+/// Returns NULL on failure, including a panic inside the library, storing a
+/// message in `*error` unless `error` is NULL. Release the evaluator with
+/// [`config_expr_free`].
+///
+/// # Safety
+///
+/// `json` must be NULL or a NUL-terminated string; `error` must be NULL or
+/// point to writable storage for a pointer.
+#[no_mangle]
+pub unsafe extern "C" fn config_expr_new_from_json(
+    json: *const c_char,
+    error: *mut *mut c_char,
+) -> *mut ConfigExprEvaluator {
+    let evaluator = catch_panic(|| {
+        read_str(json, "json")
+            .and_then(|json| ConfigEvaluator::from_json(json).map_err(|e| e.to_string()))
+    });
+    match evaluator {
+        Ok(evaluator) => Box::into_raw(Box::new(ConfigExprEvaluator(evaluator))),
+        Err(message) => {
+            set_error(error, message);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Evaluate params given as a JSON object
+///
+/// Returns the matching result as JSON, or the string `null` when no rule
+/// matches. Returns NULL on failure (invalid params, a matching assertion
+/// rule, a panic inside the library), storing a message in `*error` unless
+/// `error` is NULL. Release the returned string with
+/// [`config_expr_string_free`].
+///
+/// # Safety
+///
+/// `evaluator` must be NULL or a live pointer returned by
+/// [`config_expr_new_from_json`]; `params_json` and `error` as for
+/// [`config_expr_new_from_json`].
+#[no_mangle]
+pub unsafe extern "C" fn config_expr_evaluate(
+    evaluator: *const ConfigExprEvaluator,
+    params_json: *const c_char,
+    error: *mut *mut c_char,
+) -> *mut c_char {
+    let result = catch_panic(|| match evaluator.as_ref() {
+        Some(ConfigExprEvaluator(evaluator)) => {
+            read_str(params_json, "params_json").and_then(|params| {
+                let params: serde_json::Value =
+                    serde_json::from_str(params).map_err(|e| e.to_string())?;
+                let result = evaluator
+                    .evaluate_value(&params)
+                    .map_err(|e| e.to_string())?;
+                serde_json::to_string(&result).map_err(|e| e.to_string())
+            })
+        }
+        None => Err("evaluator is NULL".to_string()),
+    });
+    match result {
+        Ok(json) => into_c_string(json),
+        Err(message) => {
+            set_error(error, message);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Release an evaluator; NULL is ignored
+///
+/// # Safety
+///
+/// `evaluator` must be NULL or a pointer returned by
+/// [`config_expr_new_from_json`] that has not been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn config_expr_free(evaluator: *mut ConfigExprEvaluator) {
+    if !evaluator.is_null() {
+        drop(Box::from_raw(evaluator));
+    }
+}
+
+/// Release a string returned by the library; NULL is ignored
+///
+/// # Safety
+///
+/// `s` must be NULL or a string returned by this library that has not been
+/// freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn config_expr_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    unsafe fn take(s: *mut c_char) -> String {
+        let owned = CStr::from_ptr(s).to_str().unwrap().to_string();
+        config_expr_string_free(s);
+        owned
+    }
+
+    #[test]
+    fn test_c_api() {
+        let rules = CString::new(
+            r#"{"rules": [{"if": {"field": "score", "op": "gt", "value": 60}, "then": "pass"}]}"#,
+        )
+        .unwrap();
+        unsafe {
+            let mut error = ptr::null_mut();
+            let evaluator = config_expr_new_from_json(rules.as_ptr(), &mut error);
+            assert!(!evaluator.is_null());
+
+            let params = CString::new(r#"{"score": 75}"#).unwrap();
+            let result = config_expr_evaluate(evaluator, params.as_ptr(), &mut error);
+            assert_eq!(take(result), r#""pass""#);
+
+            let params = CString::new(r#"{"score": 50}"#).unwrap();
+            let result = config_expr_evaluate(evaluator, params.as_ptr(), &mut error);
+            assert_eq!(take(result), "null");
+
+            let params = CString::new("[1]").unwrap();
+            let result = config_expr_evaluate(evaluator, params.as_ptr(), &mut error);
+            assert!(result.is_null());
+            assert!(take(error).contains("Params must be a JSON object"));
+            config_expr_free(evaluator);
+
+            let invalid = CString::new(
+                r#"{"rules": [{"if": {"field": "a", "op": "regex", "value": "("}, "then": 1}]}"#,
+            )
+            .unwrap();
+            assert!(config_expr_new_from_json(invalid.as_ptr(), ptr::null_mut()).is_null());
+            assert!(config_expr_new_from_json(ptr::null(), &mut error).is_null());
+            assert_eq!(take(error), "json is NULL");
+        }
+    }
+
+    #[test]
+    fn test_catch_panic() {
+        assert_eq!(catch_panic(|| Ok::<_, String>(1)), Ok(1));
+        assert_eq!(
+            catch_panic::<()>(|| panic!("rule {} broke", 3)),
+            Err("panicked: rule 3 broke".to_string())
+        );
+        assert_eq!(
+            catch_panic::<()>(|| panic!("static")),
+            Err("panicked: static".to_string())
+        );
+    }
+}
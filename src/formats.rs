@@ -0,0 +1,582 @@
+//! Rule files and formats other than JSON
+//!
+//! Every format deserializes into the same [`ConfigRules`] model, so rules
+//! behave identically whichever format they are written in.
+
+use crate::collection::namespace_error;
+use crate::{
+    parse_json_rules, ConfigEvaluator, ConfigExprError, ConfigRuleSetCollection, ConfigRules,
+    FileLoader,
+};
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Serialization format of a rule file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleFormat {
+    Json,
+    /// YAML (requires the `yaml` feature)
+    Yaml,
+    /// TOML (requires the `toml` feature); read only, as TOML has no `null`
+    Toml,
+}
+
+impl RuleFormat {
+    /// Format implied by the file extension, if it is a known one
+    fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "json" => Some(RuleFormat::Json),
+            "yaml" | "yml" => Some(RuleFormat::Yaml),
+            "toml" => Some(RuleFormat::Toml),
+            _ => None,
+        }
+    }
+
+    /// Guess the format from the content
+    ///
+    /// JSON rule sets are objects; otherwise the first meaningful line tells
+    /// TOML (`[table]` or `key = value`) from YAML (`key: value`).
+    fn sniff(content: &str) -> Self {
+        let content = content.trim_start();
+        if content.starts_with('{') {
+            return RuleFormat::Json;
+        }
+        let line = content
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'))
+            .unwrap_or_default();
+        let toml = match (line.find('='), line.find(':')) {
+            _ if line.starts_with('[') => true,
+            (Some(equals), Some(colon)) => equals < colon,
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        if toml {
+            RuleFormat::Toml
+        } else {
+            RuleFormat::Yaml
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            RuleFormat::Json => "json",
+            RuleFormat::Yaml => "yaml",
+            RuleFormat::Toml => "toml",
+        }
+    }
+
+    /// Parse the rules of a file, locating syntax errors by line and column
+    fn parse<T: DeserializeOwned>(self, path: &Path, content: &str) -> Result<T, ConfigExprError> {
+        match self {
+            RuleFormat::Json => parse_json_rules(content.as_bytes()).map_err(|e| {
+                let position = match &e {
+                    ConfigExprError::JsonError(source)
+                    | ConfigExprError::JsonPathError { source, .. } => {
+                        (source.line() > 0).then(|| (source.line(), source.column()))
+                    }
+                    _ => None,
+                };
+                file_error(path, position, e)
+            }),
+            #[cfg(feature = "yaml")]
+            RuleFormat::Yaml => serde_yaml::from_str(content).map_err(|e| {
+                let position = e.location().map(|l| (l.line(), l.column()));
+                file_error(path, position, e.into())
+            }),
+            #[cfg(feature = "toml")]
+            RuleFormat::Toml => toml::from_str(content).map_err(|e| {
+                let position = e.span().map(|span| line_column(content, span.start));
+                file_error(path, position, e.into())
+            }),
+            #[allow(unreachable_patterns)]
+            _ => Err(file_error(
+                path,
+                None,
+                ConfigExprError::UnsupportedFormat(format!(
+                    "{} rule files require the `{}` feature",
+                    self.name().to_uppercase(),
+                    self.name()
+                )),
+            )),
+        }
+    }
+}
+
+/// Attach the file path and an optional line and column to an error
+fn file_error(
+    path: &Path,
+    position: Option<(usize, usize)>,
+    source: ConfigExprError,
+) -> ConfigExprError {
+    ConfigExprError::FileError {
+        path: path.to_path_buf(),
+        line: position.map(|(line, _)| line),
+        column: position.map(|(_, column)| column),
+        source: Box::new(source),
+    }
+}
+
+/// 1-based line and column of a byte offset
+#[cfg(feature = "toml")]
+fn line_column(content: &str, offset: usize) -> (usize, usize) {
+    let before = &content[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+impl ConfigEvaluator {
+    /// Create evaluator from a rule file
+    ///
+    /// The format is chosen by extension (`.json`, `.yaml`/`.yml`, `.toml`)
+    /// and detected from the content otherwise. Errors are wrapped in
+    /// [`ConfigExprError::FileError`] with the path and, for syntax errors,
+    /// the line and column.
+    ///
+    /// Rule sets it includes are loaded with a [`FileLoader`] resolving
+    /// names relative to the file's directory.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigExprError> {
+        let path = path.as_ref();
+        let rules = resolve_file_includes(path, read_rules(path)?)
+            .map_err(|e| file_error(path, None, e))?;
+        Self::new(rules).map_err(|e| file_error(path, None, e))
+    }
+}
+
+impl ConfigRuleSetCollection {
+    /// Create a collection from a file mapping namespaces to rule sets
+    ///
+    /// Formats, errors and includes are handled as by
+    /// [`ConfigEvaluator::from_file`].
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigExprError> {
+        let path = path.as_ref();
+        let rule_sets: BTreeMap<String, ConfigRules> = read_rules(path)?;
+        let rule_sets = rule_sets
+            .into_iter()
+            .map(
+                |(namespace, rules)| match resolve_file_includes(path, rules) {
+                    Ok(rules) => Ok((namespace, rules)),
+                    Err(e) => Err(namespace_error(namespace, e)),
+                },
+            )
+            .collect::<Result<BTreeMap<_, _>, _>>()
+            .map_err(|e| file_error(path, None, e))?;
+        Self::new(rule_sets).map_err(|e| file_error(path, None, e))
+    }
+}
+
+/// Resolve the includes of rules read from a file relative to its directory
+fn resolve_file_includes(path: &Path, rules: ConfigRules) -> Result<ConfigRules, ConfigExprError> {
+    if rules.include.is_empty() {
+        return Ok(rules);
+    }
+    let dir = path.parent().unwrap_or(Path::new(""));
+    rules.resolve_includes(&FileLoader::new(dir))
+}
+
+/// Read and parse a rule file, without resolving its includes
+pub(crate) fn read_rules<T: DeserializeOwned>(path: &Path) -> Result<T, ConfigExprError> {
+    let content = fs::read_to_string(path).map_err(|e| file_error(path, None, e.into()))?;
+    let format = RuleFormat::from_path(path).unwrap_or_else(|| RuleFormat::sniff(&content));
+    format.parse(path, &content)
+}
+
+impl ConfigRules {
+    /// Serialize the rules in a canonical form, for rule sets built or
+    /// merged by programs and kept in version control
+    ///
+    /// The output is pretty-printed with the keys of every object sorted,
+    /// settings left at their defaults omitted and conditions written as
+    /// JSON trees, so equal rule sets always serialize to the same text and
+    /// changes show up as small diffs. Rules keep their order, which decides
+    /// evaluation.
+    pub fn to_canonical_string(&self, format: RuleFormat) -> Result<String, ConfigExprError> {
+        let mut document = serde_json::to_value(self)?;
+        document.sort_all_objects();
+        match format {
+            RuleFormat::Json => {
+                let mut json = serde_json::to_string_pretty(&document)?;
+                json.push('\n');
+                Ok(json)
+            }
+            #[cfg(feature = "yaml")]
+            RuleFormat::Yaml => Ok(serde_yaml::to_string(&document)?),
+            #[allow(unreachable_patterns)]
+            _ => Err(ConfigExprError::UnsupportedFormat(format!(
+                "canonical {} output is not supported",
+                format.name().to_uppercase()
+            ))),
+        }
+    }
+}
+
+#[cfg(feature = "yaml")]
+impl ConfigEvaluator {
+    /// Create evaluator from YAML string
+    pub fn from_yaml(yaml: &str) -> Result<Self, ConfigExprError> {
+        let rules: ConfigRules = serde_yaml::from_str(yaml)?;
+        Self::new(rules)
+    }
+}
+
+/// Convenience method: validate if YAML rules are valid
+#[cfg(feature = "yaml")]
+pub fn validate_yaml(yaml: &str) -> Result<(), ConfigExprError> {
+    let rules: ConfigRules = serde_yaml::from_str(yaml)?;
+    ConfigEvaluator::load_rules(rules).map(drop)
+}
+
+#[cfg(feature = "toml")]
+impl ConfigEvaluator {
+    /// Create evaluator from TOML string
+    ///
+    /// TOML has no `null`, so `equals null` conditions are written with the
+    /// `missing` operator instead.
+    pub fn from_toml(toml: &str) -> Result<Self, ConfigExprError> {
+        let rules: ConfigRules = toml::from_str(toml)?;
+        Self::new(rules)
+    }
+}
+
+/// Convenience method: validate if TOML rules are valid
+#[cfg(feature = "toml")]
+pub fn validate_toml(toml: &str) -> Result<(), ConfigExprError> {
+    let rules: ConfigRules = toml::from_str(toml)?;
+    ConfigEvaluator::load_rules(rules).map(drop)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RuleResult;
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    /// Write a rule file to a fresh temporary directory
+    fn rule_file(name: &str, content: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "config-expr-{}-{}",
+            std::process::id(),
+            name.replace('.', "-")
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_from_file() {
+        let json = r#"{
+            "rules": [
+                {
+                    "if": { "field": "platform", "op": "equals", "value": "RTD" },
+                    "then": "chip_rtd"
+                }
+            ]
+        }"#;
+        let params = HashMap::from([("platform".to_string(), "RTD".to_string())]);
+        for name in ["rules.json", "rules.conf"] {
+            let evaluator = ConfigEvaluator::from_file(rule_file(name, json)).unwrap();
+            assert_eq!(
+                evaluator.evaluate(&params),
+                Some(RuleResult::String("chip_rtd".to_string()))
+            );
+        }
+
+        let path = rule_file("broken.json", "{\n  \"rules\": [\n    { \"if\": }\n  ]\n}");
+        let error = ConfigEvaluator::from_file(&path).unwrap_err();
+        let ConfigExprError::FileError {
+            line,
+            column,
+            source,
+            ..
+        } = &error
+        else {
+            panic!("Expected file error, got {:?}", error);
+        };
+        assert_eq!((*line, *column), (Some(3), Some(13)));
+        assert!(matches!(
+            **source,
+            ConfigExprError::JsonPathError { ref path, .. } if path == "rules[0].if"
+        ));
+        assert!(error
+            .to_string()
+            .starts_with(&format!("Error in {}:3:13: JSON", path.display())));
+
+        let path = rule_file(
+            "invalid.json",
+            r#"{ "rules": [{ "if": { "field": "a", "op": "regex", "value": "(" }, "then": "x" }] }"#,
+        );
+        let error = ConfigEvaluator::from_file(&path).unwrap_err();
+        assert!(matches!(
+            error,
+            ConfigExprError::FileError { line: None, ref source, .. }
+                if !matches!(**source, ConfigExprError::JsonError(_))
+        ));
+
+        #[cfg(not(feature = "yaml"))]
+        assert!(matches!(
+            ConfigEvaluator::from_file(rule_file("rules.yaml", "rules: []")),
+            Err(ConfigExprError::FileError { ref source, .. })
+                if matches!(**source, ConfigExprError::UnsupportedFormat(_))
+        ));
+
+        let missing = std::env::temp_dir().join("config-expr-missing/rules.json");
+        assert!(matches!(
+            ConfigEvaluator::from_file(missing),
+            Err(ConfigExprError::FileError { ref source, .. })
+                if matches!(**source, ConfigExprError::IoError(_))
+        ));
+    }
+
+    #[test]
+    fn test_from_file_with_includes() {
+        let dir = std::env::temp_dir().join(format!("config-expr-{}-include", std::process::id()));
+        fs::create_dir_all(dir.join("shared")).unwrap();
+        fs::write(
+            dir.join("shared/base.json"),
+            r#"{ "rules": [{ "if": "platform == \"RTD\"", "then": "base" }], "fallback": "none" }"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("service.json"),
+            r#"{ "include": ["shared/base.json"], "rules": [{ "if": "platform == \"MTK\"", "then": "own" }] }"#,
+        )
+        .unwrap();
+
+        let evaluator = ConfigEvaluator::from_file(dir.join("service.json")).unwrap();
+        for (platform, expected) in [("RTD", "base"), ("MTK", "own"), ("X", "none")] {
+            let params = HashMap::from([("platform".to_string(), platform.to_string())]);
+            assert_eq!(
+                evaluator.evaluate(&params),
+                Some(RuleResult::String(expected.to_string()))
+            );
+        }
+
+        fs::write(
+            dir.join("broken.json"),
+            r#"{ "include": ["missing.json"], "rules": [] }"#,
+        )
+        .unwrap();
+        let error = ConfigEvaluator::from_file(dir.join("broken.json")).unwrap_err();
+        assert_eq!(error.error_code(), "io_error");
+        assert!(error.to_string().contains("missing.json"));
+    }
+
+    #[test]
+    fn test_sniff_format() {
+        assert_eq!(RuleFormat::sniff("  { \"rules\": [] }"), RuleFormat::Json);
+        assert_eq!(
+            RuleFormat::sniff("# rules\nversion: \"3\"\nrules: []"),
+            RuleFormat::Yaml
+        );
+        assert_eq!(
+            RuleFormat::sniff("version = \"3\"\n[[rules]]"),
+            RuleFormat::Toml
+        );
+        assert_eq!(
+            RuleFormat::sniff("[[rules]]\nthen = \"a\""),
+            RuleFormat::Toml
+        );
+        assert_eq!(RuleFormat::sniff("fallback: \"a=b\""), RuleFormat::Yaml);
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn test_from_yaml() {
+        let yaml = r#"
+version: "3"
+rules:
+  - id: rtd
+    if:
+      and:
+        - { field: platform, op: prefix, value: RTD }
+        - { field: firmware, op: ge, value: 2 }
+    then: chip_rtd
+  - if:
+      not: { field: region, op: in, value: [CN, JP] }
+    then:
+      chip: intl
+      boost: true
+fallback: default_chip
+"#;
+
+        let evaluator = ConfigEvaluator::from_yaml(yaml).unwrap();
+        let params = HashMap::from([
+            ("platform".to_string(), "RTD-2000".to_string()),
+            ("firmware".to_string(), "3".to_string()),
+        ]);
+        assert_eq!(
+            evaluator.evaluate(&params),
+            Some(RuleResult::String("chip_rtd".to_string()))
+        );
+        let params = HashMap::from([("region".to_string(), "US".to_string())]);
+        assert_eq!(
+            evaluator.evaluate(&params),
+            Some(RuleResult::Object(
+                serde_json::json!({ "chip": "intl", "boost": true })
+            ))
+        );
+
+        assert!(validate_yaml(yaml).is_ok());
+        let path = rule_file("rules.yml", yaml);
+        assert!(ConfigEvaluator::from_file(path).is_ok());
+        let path = rule_file("broken.yaml", "rules:\n  - if: [\n");
+        assert!(matches!(
+            ConfigEvaluator::from_file(path),
+            Err(ConfigExprError::FileError { line: Some(_), .. })
+        ));
+        assert!(matches!(
+            validate_yaml("rules: [{ if: { field: a, op: nope, value: x }, then: y }]"),
+            Err(ConfigExprError::YamlError(_))
+        ));
+        assert!(matches!(
+            validate_yaml("rules: [{ if: { field: a, op: regex, value: '(' }, then: y }]"),
+            Err(ConfigExprError::InvalidRule(_))
+        ));
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn test_from_toml() {
+        let toml = r#"
+version = "3"
+fallback = "default_chip"
+
+[[rules]]
+id = "rtd"
+then = "chip_rtd"
+
+[[rules.if.and]]
+field = "platform"
+op = "prefix"
+value = "RTD"
+
+[[rules.if.and]]
+field = "firmware"
+op = "ge"
+value = 2
+
+[[rules]]
+if = { field = "region", op = "missing" }
+then = "chip_unknown"
+
+[[rules]]
+if = { not = { field = "region", op = "in", value = ["CN", "JP"] } }
+then = { chip = "intl", boost = true }
+"#;
+
+        let evaluator = ConfigEvaluator::from_toml(toml).unwrap();
+        let params = HashMap::from([
+            ("platform".to_string(), "RTD-2000".to_string()),
+            ("firmware".to_string(), "3".to_string()),
+            ("region".to_string(), "CN".to_string()),
+        ]);
+        assert_eq!(
+            evaluator.evaluate(&params),
+            Some(RuleResult::String("chip_rtd".to_string()))
+        );
+        let params = HashMap::from([("region".to_string(), "US".to_string())]);
+        assert_eq!(
+            evaluator.evaluate(&params),
+            Some(RuleResult::Object(
+                serde_json::json!({ "chip": "intl", "boost": true })
+            ))
+        );
+        assert_eq!(
+            evaluator.evaluate(&HashMap::<String, String>::new()),
+            Some(RuleResult::String("chip_unknown".to_string()))
+        );
+
+        assert!(validate_toml(toml).is_ok());
+
+        let path = rule_file("broken.toml", "[[rules]]\nthen = \"y\"\nif = {\n");
+        assert!(matches!(
+            ConfigEvaluator::from_file(path),
+            Err(ConfigExprError::FileError { line: Some(3), .. })
+        ));
+        assert!(matches!(
+            validate_toml("[[rules]]\nthen = \"y\""),
+            Err(ConfigExprError::TomlError(_))
+        ));
+    }
+
+    #[test]
+    fn test_canonical_string() {
+        let text: ConfigRules = serde_json::from_str(
+            r#"{
+                "fallback": "default",
+                "rules": [{ "then": { "size": 2, "chip": "rtd" }, "if": "platform ~ \"^RTD\" && score >= 60" }]
+            }"#,
+        )
+        .unwrap();
+        let tree: ConfigRules = serde_json::from_str(
+            r#"{
+                "rules": [{
+                    "if": { "and": [
+                        { "op": "regex", "value": "^RTD", "field": "platform" },
+                        { "value": 60, "field": "score", "op": "ge" }
+                    ] },
+                    "then": { "chip": "rtd", "size": 2 },
+                    "enabled": true,
+                    "priority": 0
+                }],
+                "fallback": "default"
+            }"#,
+        )
+        .unwrap();
+        let json = text.to_canonical_string(RuleFormat::Json).unwrap();
+        assert_eq!(json, tree.to_canonical_string(RuleFormat::Json).unwrap());
+        assert_eq!(
+            json,
+            r#"{
+  "fallback": "default",
+  "rules": [
+    {
+      "if": {
+        "and": [
+          {
+            "field": "platform",
+            "op": "regex",
+            "value": "^RTD"
+          },
+          {
+            "field": "score",
+            "op": "ge",
+            "value": 60
+          }
+        ]
+      },
+      "then": {
+        "chip": "rtd",
+        "size": 2
+      }
+    }
+  ]
+}
+"#
+        );
+        assert_eq!(serde_json::from_str::<ConfigRules>(&json).unwrap(), tree);
+
+        #[cfg(feature = "yaml")]
+        {
+            let yaml = tree.to_canonical_string(RuleFormat::Yaml).unwrap();
+            assert!(yaml.starts_with("fallback: default\nrules:\n"));
+            assert_eq!(serde_yaml::from_str::<ConfigRules>(&yaml).unwrap(), tree);
+        }
+        assert_eq!(
+            tree.to_canonical_string(RuleFormat::Toml)
+                .unwrap_err()
+                .error_code(),
+            "unsupported_format"
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! gRPC service for evaluating rules from other languages
+//!
+//! [`ConfigExprServer`] implements the `config_expr.v1.ConfigExpr` service
+//! of `proto/config_expr.proto` on top of a [`SharedEvaluator`], so the rule
+//! engine can run as a sidecar. It is a tonic service, served with tonic's
+//! transport:
+//!
+//! ```ignore
+//! tonic::transport::Server::builder()
+//!     .add_service(ConfigExprServer::new(evaluator))
+//!     .serve(addr)
+//!     .await?;
+//! ```
+//!
+//! The messages mirror the proto file, so clients generate their stubs from
+//! it; no `protoc` is needed to build this crate.
+
+use crate::{ConfigExprError, SharedEvaluator};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::task::{Context, Poll};
+use tonic::body::Body;
+use tonic::codegen::{Body as HttpBody, BoxFuture, Service, StdError};
+use tonic::server::{Grpc, NamedService, UnaryService};
+use tonic::{Request, Response, Status};
+
+/// Request of `ConfigExpr.Evaluate`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct EvaluateRequest {
+    /// Request parameters
+    #[prost(map = "string, string", tag = "1")]
+    pub params: HashMap<String, String>,
+}
+
+/// Response of `ConfigExpr.Evaluate`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct EvaluateResponse {
+    /// Whether a rule or the fallback selected a result
+    #[prost(bool, tag = "1")]
+    pub matched: bool,
+    /// The result as JSON text; empty when nothing matched
+    #[prost(string, tag = "2")]
+    pub result: String,
+}
+
+/// tonic service evaluating requests with a [`SharedEvaluator`]
+///
+/// Assertion rules rejecting the params fail the call with
+/// `INVALID_ARGUMENT` and the assertion message.
+#[derive(Debug, Clone)]
+pub struct ConfigExprServer {
+    evaluator: SharedEvaluator,
+}
+
+impl ConfigExprServer {
+    /// Serve the rules of `evaluator`, including later replacements
+    pub fn new(evaluator: impl Into<SharedEvaluator>) -> Self {
+        ConfigExprServer {
+            evaluator: evaluator.into(),
+        }
+    }
+
+    /// Handle an `Evaluate` call
+    pub fn evaluate(&self, request: EvaluateRequest) -> Result<EvaluateResponse, Status> {
+        let result = self
+            .evaluator
+            .current()
+            .evaluate_checked(&request.params)
+            .map_err(|e| match e {
+                ConfigExprError::ValidationError(message) => Status::invalid_argument(message),
+                other => Status::internal(other.to_string()),
+            })?;
+        Ok(match result {
+            Some(result) => EvaluateResponse {
+                matched: true,
+                result: serde_json::to_string(&result)
+                    .map_err(|e| Status::internal(e.to_string()))?,
+            },
+            None => EvaluateResponse::default(),
+        })
+    }
+}
+
+impl NamedService for ConfigExprServer {
+    const NAME: &'static str = "config_expr.v1.ConfigExpr";
+}
+
+/// `Evaluate` method of a server
+struct EvaluateMethod(ConfigExprServer);
+
+impl UnaryService<EvaluateRequest> for EvaluateMethod {
+    type Response = EvaluateResponse;
+    type Future = BoxFuture<Response<EvaluateResponse>, Status>;
+
+    fn call(&mut self, request: Request<EvaluateRequest>) -> Self::Future {
+        let response = self.0.evaluate(request.into_inner()).map(Response::new);
+        Box::pin(async move { response })
+    }
+}
+
+impl<B> Service<::http::Request<B>> for ConfigExprServer
+where
+    B: HttpBody + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = ::http::Response<Body>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Infallible>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: ::http::Request<B>) -> Self::Future {
+        if request.uri().path() != "/config_expr.v1.ConfigExpr/Evaluate" {
+            return Box::pin(async { Ok(Status::unimplemented("").into_http()) });
+        }
+        let method = EvaluateMethod(self.clone());
+        Box::pin(async move {
+            let mut grpc = Grpc::new(tonic_prost::ProstCodec::default());
+            Ok(grpc.unary(method, request).await)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ConfigEvaluator;
+    use futures::executor::block_on;
+    use http_body_util::{BodyExt, Full};
+    use prost::Message;
+
+    /// Call the service with a message in a gRPC frame
+    fn call(
+        server: &mut ConfigExprServer,
+        path: &str,
+        message: &EvaluateRequest,
+    ) -> (::http::HeaderMap, Vec<u8>) {
+        let encoded = message.encode_to_vec();
+        let mut frame = vec![0];
+        frame.extend_from_slice(&(encoded.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&encoded);
+        let request = ::http::Request::builder()
+            .uri(path)
+            .header("content-type", "application/grpc")
+            .body(Full::new(tonic::codegen::Bytes::from(frame)))
+            .unwrap();
+        let response = block_on(server.call(request)).unwrap();
+        let headers = response.headers().clone();
+        let body = block_on(response.into_body().collect()).unwrap();
+        let mut status = headers;
+        status.extend(body.trailers().cloned().unwrap_or_default());
+        (status, body.to_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_grpc_evaluate() {
+        let evaluator = ConfigEvaluator::from_json(
+            r#"{
+                "rules": [
+                    { "if": "platform == \"RTD\"", "then": { "chip": "rtd" } },
+                    { "if": "platform == \"bad\"", "then": { "error": "unsupported platform" } }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let mut server = ConfigExprServer::new(evaluator);
+        let request = |platform: &str| EvaluateRequest {
+            params: HashMap::from([("platform".to_string(), platform.to_string())]),
+        };
+
+        assert_eq!(
+            server.evaluate(request("RTD")).unwrap(),
+            EvaluateResponse {
+                matched: true,
+                result: r#"{"chip":"rtd"}"#.to_string(),
+            }
+        );
+        assert_eq!(
+            server.evaluate(request("MT")).unwrap(),
+            EvaluateResponse::default()
+        );
+        let status = server.evaluate(request("bad")).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert_eq!(status.message(), "unsupported platform");
+
+        let (status, body) = call(
+            &mut server,
+            "/config_expr.v1.ConfigExpr/Evaluate",
+            &request("RTD"),
+        );
+        assert_eq!(status["grpc-status"], "0");
+        let response = EvaluateResponse::decode(&body[5..]).unwrap();
+        assert_eq!(response.result, r#"{"chip":"rtd"}"#);
+
+        let (status, _) = call(
+            &mut server,
+            "/config_expr.v1.ConfigExpr/Other",
+            &request("RTD"),
+        );
+        assert_eq!(status["grpc-status"], "12");
+    }
+}
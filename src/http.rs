@@ -0,0 +1,253 @@
+//! Per-request rule evaluation for HTTP services
+//!
+//! [`RuleLayer`] is a tower layer, so it plugs into axum, hyper and tonic
+//! alike. For every request it evaluates the rules against the request's
+//! [`RequestParams`] and stores the matched [`RuleResult`](crate::RuleResult) as a request
+//! extension for the handlers:
+//!
+//! ```ignore
+//! let app = Router::new()
+//!     .route("/", get(|result: Option<Extension<RuleResult>>| async move { .. }))
+//!     .layer(RuleLayer::new(evaluator));
+//! ```
+
+use crate::{ParamSource, SharedEvaluator};
+use ::http::{HeaderMap, Request};
+use std::borrow::Cow;
+use std::task::{Context, Poll};
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// Prefix of the fields naming request headers
+const HEADER_PREFIX: &str = "header.";
+
+/// Params of an HTTP request
+///
+/// Query parameters are params under their own names, e.g. `region` for
+/// `?region=CN`, and headers under `header.` and their name, e.g.
+/// `header.x-platform`; header names are case-insensitive. Repeated query
+/// parameters and headers yield their first value, and all of them to
+/// conditions with a `mode`.
+#[derive(Debug, Clone)]
+pub struct RequestParams<'a> {
+    query: Vec<(String, String)>,
+    headers: &'a HeaderMap,
+}
+
+impl<'a> RequestParams<'a> {
+    /// Read the params of a request
+    pub fn new<B>(request: &'a Request<B>) -> Self {
+        let query = request
+            .uri()
+            .query()
+            .map(|query| {
+                form_urlencoded::parse(query.as_bytes())
+                    .into_owned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        RequestParams {
+            query,
+            headers: request.headers(),
+        }
+    }
+}
+
+impl ParamSource for RequestParams<'_> {
+    fn get(&self, field: &str) -> Option<Cow<'_, str>> {
+        if let Some(name) = field.strip_prefix(HEADER_PREFIX) {
+            let value = self.headers.get(name)?.to_str().ok()?;
+            return Some(Cow::Borrowed(value));
+        }
+        self.query
+            .iter()
+            .find(|(name, _)| name == field)
+            .map(|(_, value)| Cow::Borrowed(value.as_str()))
+    }
+
+    fn get_all(&self, field: &str) -> Vec<Cow<'_, str>> {
+        if let Some(name) = field.strip_prefix(HEADER_PREFIX) {
+            return self
+                .headers
+                .get_all(name)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .map(Cow::Borrowed)
+                .collect();
+        }
+        self.query
+            .iter()
+            .filter(|(name, _)| name == field)
+            .map(|(_, value)| Cow::Borrowed(value.as_str()))
+            .collect()
+    }
+
+    fn entries(&self) -> Vec<(String, String)> {
+        let mut entries: Vec<(String, String)> = Vec::new();
+        for (name, value) in &self.query {
+            if !entries.iter().any(|(n, _)| n == name) {
+                entries.push((name.clone(), value.clone()));
+            }
+        }
+        for name in self.headers.keys() {
+            let field = format!("{}{}", HEADER_PREFIX, name);
+            if let Some(value) = self.get(&field) {
+                let value = value.into_owned();
+                entries.push((field, value));
+            }
+        }
+        entries
+    }
+}
+
+/// Tower layer evaluating the rules for every request
+///
+/// The evaluator is a [`SharedEvaluator`], so its rules can be replaced
+/// while the service runs.
+#[derive(Debug, Clone)]
+pub struct RuleLayer {
+    evaluator: SharedEvaluator,
+}
+
+impl RuleLayer {
+    /// Evaluate every request with `evaluator`
+    pub fn new(evaluator: impl Into<SharedEvaluator>) -> Self {
+        RuleLayer {
+            evaluator: evaluator.into(),
+        }
+    }
+}
+
+impl<S> Layer<S> for RuleLayer {
+    type Service = RuleService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RuleService {
+            inner,
+            evaluator: self.evaluator.clone(),
+        }
+    }
+}
+
+/// Service created by [`RuleLayer`]
+///
+/// Requests reach the inner service with the matched [`RuleResult`](crate::RuleResult) as an
+/// extension; when no rule matches, or an assertion rule rejects the
+/// params, they carry none.
+#[derive(Debug, Clone)]
+pub struct RuleService<S> {
+    inner: S,
+    evaluator: SharedEvaluator,
+}
+
+impl<S, B> Service<Request<B>> for RuleService<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        let result = self
+            .evaluator
+            .current()
+            .evaluate_source(&RequestParams::new(&request));
+        if let Ok(Some(result)) = result {
+            request.extensions_mut().insert(result);
+        }
+        self.inner.call(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ConfigEvaluator, RuleResult};
+    use std::convert::Infallible;
+    use std::future::{ready, Ready};
+
+    /// Service answering with the rule result extension of the request
+    struct Echo;
+
+    impl Service<Request<()>> for Echo {
+        type Response = Option<RuleResult>;
+        type Error = Infallible;
+        type Future = Ready<Result<Self::Response, Infallible>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<()>) -> Self::Future {
+            ready(Ok(request.extensions().get::<RuleResult>().cloned()))
+        }
+    }
+
+    #[test]
+    fn test_rule_layer() {
+        let evaluator = ConfigEvaluator::from_json(
+            r#"{
+                "rules": [
+                    {
+                        "if": {
+                            "and": [
+                                "region == \"CN\"",
+                                { "field": "header.x-platform", "op": "equals", "value": "RTD" }
+                            ]
+                        },
+                        "then": "cn_rtd"
+                    },
+                    { "if": "region == \"North America\"", "then": "na" },
+                    {
+                        "if": { "field": "capability", "op": "equals", "value": "bt", "mode": "any" },
+                        "then": "bt"
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let mut service = RuleLayer::new(evaluator).layer(Echo);
+        let mut call = |uri: &str, platform: &str| {
+            let request = Request::builder()
+                .uri(uri)
+                .header("X-Platform", platform)
+                .body(())
+                .unwrap();
+            service.call(request).into_inner().unwrap()
+        };
+
+        assert_eq!(
+            call("/config?region=CN", "RTD"),
+            Some(RuleResult::String("cn_rtd".to_string()))
+        );
+        assert_eq!(
+            call("/config?region=North+America&region=CN", "RTD"),
+            Some(RuleResult::String("na".to_string()))
+        );
+        assert_eq!(call("/config?region=CN", "MT"), None);
+        assert_eq!(call("/config", "RTD"), None);
+        assert_eq!(
+            call("/config?capability=wifi&capability=bt", "RTD"),
+            Some(RuleResult::String("bt".to_string()))
+        );
+
+        let request = Request::builder()
+            .uri("/?a=1&b=%20x")
+            .header("X-Platform", "RTD")
+            .body(())
+            .unwrap();
+        assert_eq!(
+            RequestParams::new(&request).entries(),
+            [
+                ("a".to_string(), "1".to_string()),
+                ("b".to_string(), " x".to_string()),
+                ("header.x-platform".to_string(), "RTD".to_string()),
+            ]
+        );
+    }
+}
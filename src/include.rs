@@ -0,0 +1,240 @@
+//! Rule set composition with `include`
+//!
+//! A rule set can list other rule sets to include, such as base rules shared
+//! by several services. [`ConfigRules::resolve_includes`] loads them through
+//! a [`RuleLoader`] and flattens everything into one rule set.
+
+use crate::prelude::*;
+use crate::{ConfigExprError, ConfigRules};
+use alloc::collections::BTreeSet;
+#[cfg(feature = "std")]
+use std::path::PathBuf;
+
+/// Resolves the names listed in `include` to rule sets
+///
+/// Implemented for closures taking the name, e.g. to look rule sets up in a
+/// map or fetch them from a config service.
+pub trait RuleLoader {
+    /// Load the rule set an `include` entry names
+    fn load(&self, name: &str) -> Result<ConfigRules, ConfigExprError>;
+}
+
+impl<F> RuleLoader for F
+where
+    F: Fn(&str) -> Result<ConfigRules, ConfigExprError>,
+{
+    fn load(&self, name: &str) -> Result<ConfigRules, ConfigExprError> {
+        self(name)
+    }
+}
+
+/// Loads included rule files relative to a directory
+///
+/// Names are paths relative to the directory, also in nested includes;
+/// formats are detected as by [`ConfigEvaluator::from_file`](crate::ConfigEvaluator::from_file).
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct FileLoader {
+    dir: PathBuf,
+}
+
+#[cfg(feature = "std")]
+impl FileLoader {
+    /// Create a loader resolving names relative to `dir`
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        FileLoader { dir: dir.into() }
+    }
+}
+
+#[cfg(feature = "std")]
+impl RuleLoader for FileLoader {
+    fn load(&self, name: &str) -> Result<ConfigRules, ConfigExprError> {
+        crate::formats::read_rules(&self.dir.join(name))
+    }
+}
+
+impl ConfigRules {
+    /// Load the rule sets listed in `include` and merge them into this one
+    ///
+    /// - included sets are resolved recursively, depth first; each is
+    ///   included once, where it is first reached, and a set including
+    ///   itself, directly or through others, is an error
+    /// - in every phase, the rules of included sets come first, in `include`
+    ///   order, followed by the including set's own rules; raise the
+    ///   `priority` of a rule to have it evaluated before included ones
+    /// - decision tables likewise follow those of included sets
+    /// - `vars`, `definitions` and `params_spec` entries are merged, the
+    ///   including set's taking precedence; `version`, `fallback` and `datetime_format` are the
+    ///   including set's, or else those of the last include setting them
+    /// - `match_mode` and `merge_strategy` are the including set's
+    ///
+    /// The returned rule set has no includes left and can be passed to
+    /// [`ConfigEvaluator::new`](crate::ConfigEvaluator::new).
+    pub fn resolve_includes(self, loader: &dyn RuleLoader) -> Result<ConfigRules, ConfigExprError> {
+        Resolver {
+            loader,
+            stack: Vec::new(),
+            included: BTreeSet::new(),
+        }
+        .resolve(self)
+    }
+}
+
+struct Resolver<'a> {
+    loader: &'a dyn RuleLoader,
+    /// Names of the sets being resolved, outermost first
+    stack: Vec<String>,
+    /// Names of the sets included so far
+    included: BTreeSet<String>,
+}
+
+impl Resolver<'_> {
+    fn resolve(&mut self, mut rules: ConfigRules) -> Result<ConfigRules, ConfigExprError> {
+        let mut base = ConfigRules {
+            version: None,
+            include: Vec::new(),
+            vars: Default::default(),
+            definitions: Default::default(),
+            pre: Vec::new(),
+            rules: Vec::new(),
+            post: Vec::new(),
+            tables: Vec::new(),
+            fallback: None,
+            params_spec: Default::default(),
+            match_mode: rules.match_mode,
+            merge_strategy: rules.merge_strategy,
+            datetime_format: None,
+        };
+        for name in core::mem::take(&mut rules.include) {
+            if let Some(start) = self.stack.iter().position(|n| *n == name) {
+                let mut cycle = self.stack[start..].to_vec();
+                cycle.push(name);
+                return Err(ConfigExprError::ValidationError(format!(
+                    "Include cycle: {}",
+                    cycle.join(" -> ")
+                )));
+            }
+            if !self.included.insert(name.clone()) {
+                continue;
+            }
+            let included = self.loader.load(&name)?;
+            self.stack.push(name);
+            let included = self.resolve(included)?;
+            self.stack.pop();
+
+            base.pre.extend(included.pre);
+            base.rules.extend(included.rules);
+            base.post.extend(included.post);
+            base.tables.extend(included.tables);
+            base.vars.extend(included.vars);
+            base.definitions.extend(included.definitions);
+            base.params_spec.extend(included.params_spec);
+            base.version = included.version.or(base.version);
+            base.fallback = included.fallback.or(base.fallback);
+            base.datetime_format = included.datetime_format.or(base.datetime_format);
+        }
+
+        base.pre.append(&mut rules.pre);
+        base.rules.append(&mut rules.rules);
+        base.post.append(&mut rules.post);
+        base.tables.append(&mut rules.tables);
+        base.vars.append(&mut rules.vars);
+        base.definitions.append(&mut rules.definitions);
+        base.params_spec.append(&mut rules.params_spec);
+        base.version = rules.version.or(base.version);
+        base.fallback = rules.fallback.or(base.fallback);
+        base.datetime_format = rules.datetime_format.or(base.datetime_format);
+        Ok(base)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{ConfigEvaluator, RuleResult};
+    use std::collections::HashMap;
+
+    fn rules(json: serde_json::Value) -> ConfigRules {
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn results(rules: &ConfigRules) -> Vec<&RuleResult> {
+        rules.rules.iter().map(|rule| &rule.result).collect()
+    }
+
+    #[test]
+    fn test_resolve_includes() {
+        let sets = HashMap::from([
+            (
+                "base",
+                rules(serde_json::json!({
+                    "include": ["common"],
+                    "rules": [{ "if": "a == \"1\"", "then": "base" }],
+                    "fallback": "base_default",
+                    "params_spec": { "a": { "required": true }, "b": {} }
+                })),
+            ),
+            (
+                "common",
+                rules(serde_json::json!({
+                    "rules": [{ "if": "a == \"2\"", "then": "common" }],
+                    "version": "common-1"
+                })),
+            ),
+            (
+                "extra",
+                rules(serde_json::json!({
+                    "include": ["common"],
+                    "pre": [{ "if": "a == \"3\"", "then": { "b": "x" } }],
+                    "rules": [{ "if": "a == \"3\"", "then": "extra" }]
+                })),
+            ),
+        ]);
+        let loader = |name: &str| {
+            sets.get(name).cloned().ok_or_else(|| {
+                ConfigExprError::ValidationError(format!("Unknown rule set {}", name))
+            })
+        };
+
+        let root = rules(serde_json::json!({
+            "include": ["base", "extra"],
+            "rules": [{ "if": "a == \"4\"", "then": "own" }],
+            "params_spec": { "a": {} }
+        }));
+        assert!(ConfigEvaluator::new(root.clone()).is_err());
+
+        let resolved = root.resolve_includes(&loader).unwrap();
+        assert!(resolved.include.is_empty());
+        // `common` is included once, where `base` first reaches it
+        assert_eq!(
+            results(&resolved),
+            ["common", "base", "extra", "own"]
+                .map(|r| RuleResult::String(r.to_string()))
+                .iter()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(resolved.pre.len(), 1);
+        assert_eq!(resolved.version.as_deref(), Some("common-1"));
+        assert_eq!(
+            resolved.fallback,
+            Some(RuleResult::String("base_default".to_string()))
+        );
+        assert!(!resolved.params_spec["a"].required);
+        assert!(resolved.params_spec.contains_key("b"));
+        assert!(ConfigEvaluator::new(resolved).is_ok());
+
+        let cyclic = |name: &str| {
+            Ok(rules(match name {
+                "a" => serde_json::json!({ "include": ["b"], "rules": [] }),
+                _ => serde_json::json!({ "include": ["a"], "rules": [] }),
+            }))
+        };
+        let error = rules(serde_json::json!({ "include": ["a"], "rules": [] }))
+            .resolve_includes(&cyclic)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Validation error: Include cycle: a -> b -> a"
+        );
+    }
+}
@@ -0,0 +1,244 @@
+//! Matching param keys to the fields rules reference
+//!
+//! Upstream systems disagree on how params are spelled: one sends
+//! `User_Agent`, another `user_agent`, a third `UA`. With [`ParamKeys`] set
+//! on the evaluator, rules reference one name and a param missing under it
+//! is looked up under its aliases and, optionally, ignoring case:
+//!
+//! ```
+//! use clia_config_expr::{ConfigEvaluator, HashMap, ParamKeys};
+//!
+//! let evaluator = ConfigEvaluator::from_json(
+//!     r#"{ "rules": [{ "if": "user_agent prefix \"Mozilla\"", "then": "browser" }] }"#,
+//! )
+//! .unwrap()
+//! .with_param_keys(ParamKeys::default().alias("UA", "user_agent").case_insensitive());
+//!
+//! let params = HashMap::from([("ua", "Mozilla/5.0")]);
+//! assert!(evaluator.evaluate(&params).is_some());
+//! ```
+
+use crate::prelude::*;
+use crate::ParamSource;
+use alloc::borrow::Cow;
+use alloc::collections::btree_map::Entry;
+use alloc::collections::BTreeMap;
+use core::cell::RefCell;
+
+/// How param keys are matched to the fields of conditions
+///
+/// A param present under the exact field name always takes precedence.
+/// Among several keys matching a field ignoring case, e.g. `Platform` and
+/// `PLATFORM` for `platform`, the lowest in sort order wins. The default
+/// matches exact names only.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParamKeys {
+    /// Match keys differing from the field only in case, e.g. `Region` for
+    /// `region`; the param source must list its params with
+    /// [`ParamSource::entries`]
+    pub case_insensitive: bool,
+    /// Fields of other keys, e.g. `"UA"` to `"user_agent"`, read when the
+    /// field itself is missing
+    pub aliases: BTreeMap<String, String>,
+}
+
+impl ParamKeys {
+    /// Read the param `alias` for `field` when `field` is missing
+    pub fn alias(mut self, alias: impl Into<String>, field: impl Into<String>) -> Self {
+        self.aliases.insert(alias.into(), field.into());
+        self
+    }
+
+    /// Match keys ignoring case
+    pub fn case_insensitive(mut self) -> Self {
+        self.case_insensitive = true;
+        self
+    }
+}
+
+/// Params of another source with keys matched by [`ParamKeys`]
+pub(crate) struct Keyed<'a> {
+    base: &'a dyn ParamSource,
+    keys: &'a ParamKeys,
+    /// Keys of the base by their lowercase form, listed on the first miss;
+    /// of keys differing only in case, the lowest in sort order
+    folded: RefCell<Option<BTreeMap<String, String>>>,
+}
+
+impl<'a> Keyed<'a> {
+    pub(crate) fn new(base: &'a dyn ParamSource, keys: &'a ParamKeys) -> Self {
+        Keyed {
+            base,
+            keys,
+            folded: RefCell::new(None),
+        }
+    }
+
+    /// Key of the base holding a field missing under its own name
+    fn fallback_key(&self, field: &str) -> Option<String> {
+        let aliases = self
+            .keys
+            .aliases
+            .iter()
+            .filter(|(_, target)| *target == field)
+            .map(|(alias, _)| alias.as_str());
+        let mut candidates = vec![field];
+        candidates.extend(aliases);
+        if let Some(alias) = candidates[1..]
+            .iter()
+            .find(|alias| self.base.get(alias).is_some())
+        {
+            return Some(alias.to_string());
+        }
+        if !self.keys.case_insensitive {
+            return None;
+        }
+        let mut folded = self.folded.borrow_mut();
+        let folded = folded.get_or_insert_with(|| {
+            let mut folded = BTreeMap::new();
+            for (key, _) in self.base.entries() {
+                match folded.entry(key.to_lowercase()) {
+                    Entry::Vacant(entry) => {
+                        entry.insert(key);
+                    }
+                    Entry::Occupied(mut entry) if key < *entry.get() => {
+                        entry.insert(key);
+                    }
+                    Entry::Occupied(_) => {}
+                }
+            }
+            folded
+        });
+        candidates
+            .iter()
+            .find_map(|candidate| folded.get(&candidate.to_lowercase()).cloned())
+    }
+}
+
+impl ParamSource for Keyed<'_> {
+    fn get(&self, field: &str) -> Option<Cow<'_, str>> {
+        if let Some(value) = self.base.get(field) {
+            return Some(value);
+        }
+        let key = self.fallback_key(field)?;
+        self.base.get(&key)
+    }
+
+    fn get_all(&self, field: &str) -> Vec<Cow<'_, str>> {
+        let values = self.base.get_all(field);
+        if !values.is_empty() {
+            return values;
+        }
+        match self.fallback_key(field) {
+            Some(key) => self.base.get_all(&key),
+            None => values,
+        }
+    }
+
+    /// Entries of the base, with aliases renamed to their fields
+    fn entries(&self) -> Vec<(String, String)> {
+        let mut entries = self.base.entries();
+        for (key, _) in entries.iter_mut() {
+            if let Some(field) = self.keys.aliases.get(key) {
+                if self.base.get(field).is_none() {
+                    *key = field.clone();
+                }
+            }
+        }
+        entries
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{ConfigEvaluator, FnSource, RuleResult};
+
+    #[test]
+    fn test_param_keys() {
+        let json = r#"{
+            "rules": [
+                { "if": "user_agent prefix \"Mozilla\" && region == \"CN\"", "then": "cn_browser" },
+                { "if": "user_agent prefix \"Mozilla\"", "then": "browser" }
+            ]
+        }"#;
+        let keys = ParamKeys::default().alias("UA", "user_agent");
+        let evaluator = ConfigEvaluator::from_json(json)
+            .unwrap()
+            .with_param_keys(keys.clone())
+            .with_cache(4);
+        let evaluate = |params: &[(&str, &str)]| {
+            evaluator
+                .evaluate(&params.iter().copied().collect::<HashMap<_, _>>())
+                .map(|result| result.deserialize_into::<String>().unwrap())
+        };
+        assert_eq!(
+            evaluate(&[("UA", "Mozilla/5.0"), ("region", "CN")]),
+            Some("cn_browser".to_string())
+        );
+        // The exact field takes precedence over its alias
+        assert_eq!(
+            evaluate(&[("UA", "Mozilla/5.0"), ("user_agent", "curl/8.0")]),
+            None
+        );
+        assert_eq!(
+            evaluate(&[("Region", "CN"), ("UA", "Mozilla/5.0")]),
+            Some("browser".to_string())
+        );
+
+        let evaluator = evaluator.with_param_keys(keys.case_insensitive());
+        let params = HashMap::from([("Region", "CN"), ("ua", "Mozilla/5.0")]);
+        assert_eq!(
+            evaluator.evaluate(&params),
+            Some(RuleResult::String("cn_browser".to_string()))
+        );
+        let keyed = Keyed::new(&params, &evaluator.param_keys);
+        let mut entries = keyed.entries();
+        entries.sort();
+        assert_eq!(
+            entries,
+            [
+                ("Region".to_string(), "CN".to_string()),
+                ("ua".to_string(), "Mozilla/5.0".to_string())
+            ]
+        );
+
+        // Of keys differing only in case, the exact key and then the lowest wins
+        let evaluate = |params: &[(&str, &str)]| {
+            evaluator.evaluate(&params.iter().copied().collect::<HashMap<_, _>>())
+        };
+        let cn_browser = Some(RuleResult::String("cn_browser".to_string()));
+        for _ in 0..8 {
+            let params = [("UA", "Mozilla/5.0"), ("region", "CN"), ("Region", "EU")];
+            assert_eq!(evaluate(&params), cn_browser);
+            let params = [("UA", "Mozilla/5.0"), ("REGION", "CN"), ("Region", "EU")];
+            assert_eq!(evaluate(&params), cn_browser);
+        }
+
+        // Params are validated with the keys evaluation matches
+        let evaluator = ConfigEvaluator::from_json(
+            r#"{
+                "rules": [{ "if": "user_agent prefix \"Mozilla\"", "then": "browser" }],
+                "params_spec": { "user_agent": { "required": true, "pattern": "^Mozilla/" } }
+            }"#,
+        )
+        .unwrap()
+        .with_param_keys(ParamKeys::default().alias("UA", "user_agent"));
+        let params = HashMap::from([("UA", "Mozilla/5.0")]);
+        assert!(evaluator.validate_params(&params).is_ok());
+        assert!(evaluator.evaluate(&params).is_some());
+        let params = HashMap::from([("UA", "curl/8.0")]);
+        assert!(evaluator
+            .validate_params(&params)
+            .unwrap_err()
+            .to_string()
+            .contains("does not match pattern"));
+
+        // Sources that cannot list their params only match exact names and aliases
+        let source = FnSource(|name: &str| (name == "UA").then(|| "Mozilla/5.0".to_string()));
+        assert_eq!(
+            evaluator.evaluate_source(&source).unwrap(),
+            Some(RuleResult::String("browser".to_string()))
+        );
+    }
+}
@@ -0,0 +1,254 @@
+//! Lenient parsing of rule documents written for other rule engines
+//!
+//! With [`LenientParse`], documents are rewritten into the canonical form
+//! before they are deserialized, so everything after parsing (validation,
+//! serialization, diffs) only ever sees canonical rules.
+
+use crate::prelude::*;
+use crate::{json_path_error, parse_json_rules, ConfigEvaluator, ConfigExprError, ConfigRules};
+use serde_json::{Map, Value};
+
+/// Operator aliases, keyed by their lowercase form without `_`, `-` and
+/// spaces, with the operator they stand for and whether it is negated
+const OPERATOR_ALIASES: &[(&str, &str, bool)] = &[
+    ("eq", "equals", false),
+    ("equal", "equals", false),
+    ("equals", "equals", false),
+    ("is", "equals", false),
+    ("==", "equals", false),
+    ("=", "equals", false),
+    ("ne", "equals", true),
+    ("neq", "equals", true),
+    ("notequal", "equals", true),
+    ("notequals", "equals", true),
+    ("!=", "equals", true),
+    ("<>", "equals", true),
+    ("contains", "contains", false),
+    ("includes", "contains", false),
+    ("notcontains", "contains", true),
+    ("doesnotcontain", "contains", true),
+    ("prefix", "prefix", false),
+    ("startswith", "prefix", false),
+    ("beginswith", "prefix", false),
+    ("suffix", "suffix", false),
+    ("endswith", "suffix", false),
+    ("regex", "regex", false),
+    ("regexp", "regex", false),
+    ("matches", "regex", false),
+    ("=~", "regex", false),
+    ("~", "regex", false),
+    ("notmatches", "regex", true),
+    ("!~", "regex", true),
+    ("gt", "gt", false),
+    ("greaterthan", "gt", false),
+    (">", "gt", false),
+    ("ge", "ge", false),
+    ("gte", "ge", false),
+    ("greaterthanorequal", "ge", false),
+    (">=", "ge", false),
+    ("lt", "lt", false),
+    ("lessthan", "lt", false),
+    ("<", "lt", false),
+    ("le", "le", false),
+    ("lte", "le", false),
+    ("lessthanorequal", "le", false),
+    ("<=", "le", false),
+    ("in", "in", false),
+    ("oneof", "in", false),
+    ("notin", "not_in", false),
+    ("nin", "not_in", false),
+    ("notoneof", "not_in", false),
+    ("intersects", "intersects", false),
+    ("subsetof", "subset_of", false),
+    ("exists", "exists", false),
+    ("present", "exists", false),
+    ("missing", "missing", false),
+    ("notexists", "missing", false),
+    ("absent", "missing", false),
+    ("iequals", "iequals", false),
+    ("icontains", "icontains", false),
+    ("iprefix", "iprefix", false),
+    ("isuffix", "isuffix", false),
+    ("semvereq", "semver_eq", false),
+    ("semvergt", "semver_gt", false),
+    ("semverge", "semver_ge", false),
+    ("semverlt", "semver_lt", false),
+    ("semverle", "semver_le", false),
+    ("percentlt", "percent_lt", false),
+    ("bucketin", "bucket_in", false),
+    ("ipincidr", "ip_in_cidr", false),
+    ("langmatches", "lang_matches", false),
+    ("timebetween", "time_between", false),
+    ("cronmatch", "cron_match", false),
+    ("lengt", "len_gt", false),
+    ("lenlt", "len_lt", false),
+    ("leneq", "len_eq", false),
+];
+
+/// Options accepting rule documents that strict parsing rejects
+///
+/// The default is strict; enable the options to be accepted:
+///
+/// ```
+/// use clia_config_expr::{ConfigRules, LenientParse};
+///
+/// let json = r#"{ "rules": [{ "if": { "field": "platform", "op": "startsWith", "value": "RTD" }, "then": "rtd" }] }"#;
+/// let lenient = LenientParse { operator_aliases: true };
+/// assert!(ConfigRules::from_json_lenient(json, LenientParse::default()).is_err());
+/// assert!(ConfigRules::from_json_lenient(json, lenient).is_ok());
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LenientParse {
+    /// Accept common operator aliases in the `op` of object conditions,
+    /// such as `eq`, `==`, `startswith`, `ends_with`, `>` and `gte`,
+    /// whatever their case. Negated aliases like `!=`, `ne` and `!~` become
+    /// `not` conditions. Textual conditions keep their own syntax.
+    pub operator_aliases: bool,
+}
+
+impl LenientParse {
+    /// Rewrite a rule document into its canonical form
+    fn rewrite(&self, document: &mut Value) {
+        if !self.operator_aliases {
+            return;
+        }
+        let Some(document) = document.as_object_mut() else {
+            return;
+        };
+        for phase in ["pre", "rules", "post"] {
+            let rules = document.get_mut(phase).and_then(Value::as_array_mut);
+            for rule in rules.into_iter().flatten() {
+                if let Some(condition) = rule.get_mut("if") {
+                    rewrite_operators(condition);
+                }
+            }
+        }
+        let definitions = document
+            .get_mut("definitions")
+            .and_then(Value::as_object_mut);
+        for condition in definitions.into_iter().flat_map(Map::values_mut) {
+            rewrite_operators(condition);
+        }
+    }
+}
+
+/// Replace operator aliases in a condition and its sub-conditions
+fn rewrite_operators(condition: &mut Value) {
+    let Some(object) = condition.as_object_mut() else {
+        return;
+    };
+    for key in ["and", "or"] {
+        let nested = object.get_mut(key).and_then(Value::as_array_mut);
+        for condition in nested.into_iter().flatten() {
+            rewrite_operators(condition);
+        }
+    }
+    if let Some(not) = object.get_mut("not") {
+        rewrite_operators(not);
+    }
+
+    let Some(alias) = object.get("op").and_then(Value::as_str) else {
+        return;
+    };
+    let key: String = alias
+        .chars()
+        .filter(|c| !matches!(c, '_' | '-' | ' '))
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    let Some(&(_, op, negated)) = OPERATOR_ALIASES.iter().find(|(name, ..)| *name == key) else {
+        return;
+    };
+    object.insert("op".to_string(), Value::from(op));
+    if negated {
+        let simple = core::mem::take(condition);
+        *condition = serde_json::json!({ "not": simple });
+    }
+}
+
+impl ConfigRules {
+    /// Parse a JSON rule document, accepting what `lenient` allows
+    pub fn from_json_lenient(json: &str, lenient: LenientParse) -> Result<Self, ConfigExprError> {
+        Self::from_value_lenient(parse_json_rules(json.as_bytes())?, lenient)
+    }
+
+    /// Deserialize a rule document, accepting what `lenient` allows
+    ///
+    /// Documents of other formats are converted to a [`Value`] first, e.g.
+    /// with `serde_yaml::from_str`.
+    pub fn from_value_lenient(
+        mut document: Value,
+        lenient: LenientParse,
+    ) -> Result<Self, ConfigExprError> {
+        lenient.rewrite(&mut document);
+        serde_path_to_error::deserialize(document).map_err(json_path_error)
+    }
+}
+
+impl ConfigEvaluator {
+    /// Create an evaluator from a JSON rule document, accepting what
+    /// `lenient` allows
+    pub fn from_json_lenient(json: &str, lenient: LenientParse) -> Result<Self, ConfigExprError> {
+        Self::new(ConfigRules::from_json_lenient(json, lenient)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Condition;
+
+    #[test]
+    fn test_lenient_parse() {
+        let json = r#"{
+            "definitions": { "is_cn": { "field": "region", "op": "EQ", "value": "CN" } },
+            "rules": [
+                {
+                    "if": {
+                        "and": [
+                            { "ref": "is_cn" },
+                            { "field": "platform", "op": "startsWith", "value": "RTD" },
+                            { "field": "score", "op": ">=", "value": 60 }
+                        ]
+                    },
+                    "then": "cn_rtd"
+                },
+                { "if": { "field": "tier", "op": "!=", "value": "free" }, "then": "paid" },
+                { "if": { "field": "platform", "op": "NotIn", "value": ["MT"] }, "then": "other" }
+            ]
+        }"#;
+        let lenient = LenientParse {
+            operator_aliases: true,
+        };
+
+        let error = ConfigEvaluator::from_json(json).unwrap_err();
+        assert!(error.to_string().contains("definitions.is_cn.op"));
+        assert!(ConfigRules::from_json_lenient(json, LenientParse::default()).is_err());
+
+        let rules = ConfigRules::from_json_lenient(json, lenient).unwrap();
+        assert_eq!(
+            rules.rules[1].condition,
+            serde_json::from_str::<Condition>(r#""!(tier == \"free\")""#).unwrap()
+        );
+        assert_eq!(
+            rules.definitions["is_cn"],
+            serde_json::from_str::<Condition>(r#""region == \"CN\"""#).unwrap()
+        );
+
+        let evaluator = ConfigEvaluator::from_json_lenient(json, lenient).unwrap();
+        let evaluate = |params: &[(&str, &str)]| {
+            evaluator
+                .evaluate(&params.iter().copied().collect::<HashMap<_, _>>())
+                .map(|result| result.deserialize_into::<String>().unwrap())
+        };
+        assert_eq!(
+            evaluate(&[("region", "CN"), ("platform", "RTD-1"), ("score", "75")]),
+            Some("cn_rtd".to_string())
+        );
+        assert_eq!(evaluate(&[("tier", "gold")]), Some("paid".to_string()));
+        assert_eq!(
+            evaluate(&[("tier", "free"), ("platform", "Hi")]),
+            Some("other".to_string())
+        );
+        assert_eq!(evaluate(&[("tier", "free"), ("platform", "MT")]), None);
+    }
+}
@@ -1,8 +1,149 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[macro_use]
+extern crate alloc;
+
+use alloc::borrow::Cow;
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::hash::Hash;
+use prelude::*;
+#[cfg(feature = "regex")]
 use regex::Regex;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
+pub mod analysis;
+#[cfg(feature = "binary")]
+mod binary;
+pub mod bucketing;
+#[cfg(feature = "std")]
+mod cache;
+mod choose;
+mod cidr;
+mod collection;
+mod compile;
+mod definitions;
+pub mod diff;
+mod dsl;
+mod expr;
+#[cfg(feature = "ffi")]
+mod ffi;
+#[cfg(feature = "std")]
+mod formats;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "http")]
+mod http;
+mod include;
+mod keys;
+mod lenient;
+mod limits;
+mod lint;
+mod locale;
+mod membership;
+#[cfg(feature = "std")]
+mod metrics;
+mod number;
+mod optimize;
+#[cfg(feature = "otel")]
+mod otel;
+mod path;
+mod patterns;
+mod prelude;
+#[cfg(any(feature = "config", feature = "figment"))]
+mod providers;
+pub mod regression;
+#[cfg(feature = "watch")]
+mod reload;
+#[cfg(feature = "remote")]
+mod remote;
+pub mod report;
+#[cfg(feature = "async")]
+mod resolver;
+mod schedule;
+#[cfg(feature = "schemars")]
+mod schema;
+#[cfg(feature = "scripting")]
+mod scripting;
+#[cfg(feature = "std")]
+mod shared;
+mod source;
+#[cfg(feature = "csv")]
+mod spreadsheet;
+#[cfg(feature = "proptest")]
+pub mod strategy;
+#[cfg(feature = "async")]
+mod stream;
+mod table;
+mod template;
+mod text;
+mod time;
+#[cfg(feature = "tracing")]
+mod tracing;
+mod validation;
+mod vars;
+mod version;
+#[cfg(feature = "wasm")]
+mod wasm;
+
+#[cfg(feature = "http")]
+pub use self::http::{RequestParams, RuleLayer, RuleService};
+use alloc::sync::Arc;
+pub use analysis::{AnalysisWarning, OrderConflict, WarningKind};
+#[cfg(feature = "std")]
+pub use cache::CacheStats;
+pub use choose::{RandomSource, SeededRandom};
+pub use collection::ConfigRuleSetCollection;
+pub use diff::{InputDiff, RuleSetDiff};
+use expr::Expr;
+#[cfg(feature = "toml")]
+pub use formats::validate_toml;
+#[cfg(feature = "yaml")]
+pub use formats::validate_yaml;
+#[cfg(feature = "std")]
+pub use formats::RuleFormat;
+/// hashbrown's `HashMap`, a [`ParamSource`] with or without `std`
+pub use hashbrown::HashMap;
+#[cfg(feature = "std")]
+pub use include::FileLoader;
+pub use include::RuleLoader;
+pub use keys::ParamKeys;
+pub use lenient::LenientParse;
+pub use limits::EvaluatorLimits;
+pub use lint::LintWarning;
+#[cfg(feature = "std")]
+pub use metrics::Metrics;
+use number::Number;
+pub use patterns::RegexConfig;
+#[cfg(feature = "config")]
+pub use providers::RuleOverlay;
+pub use regression::Baseline;
+#[cfg(feature = "watch")]
+pub use reload::ReloadableEvaluator;
+#[cfg(feature = "remote")]
+pub use remote::{RemoteRuleSource, RemoteUpdate};
+pub use report::{EvaluationReport, EvaluationTrace};
+use report::{Phase, Recorder, SkipReason};
+#[cfg(feature = "async")]
+pub use resolver::{AsyncFnSource, AsyncParamSource};
+pub use schedule::Schedule;
+#[cfg(feature = "std")]
+pub use shared::SharedEvaluator;
+use source::Overlay;
+pub use source::{FnSource, LazyParams, MultiParams, MultiValueMode, ParamSource};
+#[cfg(feature = "csv")]
+pub use spreadsheet::CsvSchema;
+pub use table::{Column, DecisionTable, TableRow};
+pub use text::Normalization;
+pub use time::Clock;
+#[cfg(feature = "std")]
+pub use time::{FixedClock, SystemClock};
+pub use validation::RuleError;
+use validation::Site;
+
 /// Configuration expression error types
 #[derive(Error, Debug)]
 pub enum ConfigExprError {
@@ -10,15 +151,156 @@ pub enum ConfigExprError {
     InvalidOperator(String),
     #[error("Field not found: {0}")]
     FieldNotFound(String),
+    #[cfg(feature = "regex")]
     #[error("Regex compilation error: {0}")]
     RegexError(#[from] regex::Error),
     #[error("JSON serialization error: {0}")]
     JsonError(#[from] serde_json::Error),
+    /// A JSON rule document error with the path of the offending value,
+    /// e.g. `rules[3].if.and[1].op`
+    #[error("JSON error at {path}: {source}")]
+    JsonPathError {
+        path: String,
+        source: serde_json::Error,
+    },
     #[error("Validation error: {0}")]
     ValidationError(String),
+    /// A rule or the fallback failed validation; see [`RuleError`] for the
+    /// location and offending value
+    #[error("Validation error: {0}")]
+    InvalidRule(Box<RuleError>),
+    #[error("Expression error: {0}")]
+    ExpressionError(String),
+    #[error("Missing placeholder: {0}")]
+    MissingPlaceholder(String),
+    #[cfg(feature = "config")]
+    #[error("Config source error: {0}")]
+    ConfigSourceError(#[from] config::ConfigError),
+    #[cfg(feature = "figment")]
+    #[error("Figment error: {0}")]
+    FigmentError(Box<figment::Error>),
+    #[cfg(feature = "yaml")]
+    #[error("YAML error: {0}")]
+    YamlError(#[from] serde_yaml::Error),
+    #[cfg(feature = "toml")]
+    #[error("TOML error: {0}")]
+    TomlError(#[from] toml::de::Error),
+    #[cfg(feature = "std")]
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Unsupported rule file format: {0}")]
+    UnsupportedFormat(String),
+    #[cfg(feature = "std")]
+    #[error("Error in {}: {source}", file_location(.path, .line, .column))]
+    FileError {
+        path: PathBuf,
+        /// 1-based line of a parse error
+        line: Option<usize>,
+        /// 1-based column of a parse error
+        column: Option<usize>,
+        source: Box<ConfigExprError>,
+    },
+    #[cfg(feature = "watch")]
+    #[error("File watch error: {0}")]
+    WatchError(Box<notify::Error>),
+    #[cfg(feature = "scripting")]
+    #[error("Script error: {0}")]
+    ScriptError(String),
+    #[cfg(feature = "binary")]
+    #[error("Binary format error: {0}")]
+    BinaryError(String),
+    #[cfg(feature = "csv")]
+    #[error("CSV error: {0}")]
+    CsvError(String),
+    /// An error in the rule set of a [`ConfigRuleSetCollection`] namespace
+    #[error("Error in namespace '{namespace}': {source}")]
+    NamespaceError {
+        namespace: String,
+        source: Box<ConfigExprError>,
+    },
+    #[error("Unknown namespace: {0}")]
+    UnknownNamespace(String),
+    /// An [`AsyncParamSource`] failed to resolve a field
+    #[cfg(feature = "async")]
+    #[error("Param resolver error: {0}")]
+    ResolverError(String),
+    /// Fetching rules from a [`RemoteRuleSource`] failed
+    #[cfg(feature = "remote")]
+    #[error("Remote rule source error: {0}")]
+    RemoteError(String),
+}
+
+impl ConfigExprError {
+    /// Stable machine-readable code of the error, e.g. `invalid_regex`
+    ///
+    /// Rule validation failures report the reason code of their
+    /// [`RuleError`]; errors wrapped with a file location or namespace report
+    /// the code of the underlying error.
+    pub fn error_code(&self) -> &'static str {
+        match self {
+            ConfigExprError::InvalidOperator(_) => "invalid_operator",
+            ConfigExprError::FieldNotFound(_) => "field_not_found",
+            #[cfg(feature = "regex")]
+            ConfigExprError::RegexError(_) => "regex_error",
+            ConfigExprError::JsonError(_) | ConfigExprError::JsonPathError { .. } => "json_error",
+            ConfigExprError::ValidationError(_) => "validation_error",
+            ConfigExprError::InvalidRule(error) => error.code,
+            ConfigExprError::ExpressionError(_) => "expression_error",
+            ConfigExprError::MissingPlaceholder(_) => "missing_placeholder",
+            #[cfg(feature = "config")]
+            ConfigExprError::ConfigSourceError(_) => "config_source_error",
+            #[cfg(feature = "figment")]
+            ConfigExprError::FigmentError(_) => "figment_error",
+            #[cfg(feature = "yaml")]
+            ConfigExprError::YamlError(_) => "yaml_error",
+            #[cfg(feature = "toml")]
+            ConfigExprError::TomlError(_) => "toml_error",
+            #[cfg(feature = "std")]
+            ConfigExprError::IoError(_) => "io_error",
+            ConfigExprError::UnsupportedFormat(_) => "unsupported_format",
+            #[cfg(feature = "std")]
+            ConfigExprError::FileError { source, .. } => source.error_code(),
+            #[cfg(feature = "watch")]
+            ConfigExprError::WatchError(_) => "watch_error",
+            #[cfg(feature = "scripting")]
+            ConfigExprError::ScriptError(_) => "script_error",
+            #[cfg(feature = "binary")]
+            ConfigExprError::BinaryError(_) => "binary_error",
+            #[cfg(feature = "csv")]
+            ConfigExprError::CsvError(_) => "csv_error",
+            ConfigExprError::NamespaceError { source, .. } => source.error_code(),
+            ConfigExprError::UnknownNamespace(_) => "unknown_namespace",
+            #[cfg(feature = "async")]
+            ConfigExprError::ResolverError(_) => "resolver_error",
+            #[cfg(feature = "remote")]
+            ConfigExprError::RemoteError(_) => "remote_error",
+        }
+    }
+
+    /// Details of a rule validation failure, also when wrapped with a file
+    /// location
+    pub fn rule_error(&self) -> Option<&RuleError> {
+        match self {
+            ConfigExprError::InvalidRule(error) => Some(error),
+            #[cfg(feature = "std")]
+            ConfigExprError::FileError { source, .. } => source.rule_error(),
+            _ => None,
+        }
+    }
+}
+
+/// Format a file path with an optional line and column, as in `rules.yaml:3:7`
+#[cfg(feature = "std")]
+fn file_location(path: &Path, line: &Option<usize>, column: &Option<usize>) -> String {
+    match (line, column) {
+        (Some(line), Some(column)) => format!("{}:{}:{}", path.display(), line, column),
+        (Some(line), None) => format!("{}:{}", path.display(), line),
+        _ => path.display().to_string(),
+    }
 }
 
 /// Operator enumeration
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum Operator {
@@ -27,6 +309,9 @@ pub enum Operator {
     Prefix,
     Suffix,
     Regex,
+    /// Whole value matches a shell-style wildcard pattern (`*`, `?`, `[..]`,
+    /// `{a,b}`)
+    Glob,
     #[serde(rename = "gt")]
     GreaterThan,
     #[serde(rename = "lt")]
@@ -35,9 +320,269 @@ pub enum Operator {
     GreaterThanOrEqual,
     #[serde(rename = "le")]
     LessThanOrEqual,
+    /// Rhai script returning a bool (requires the `scripting` feature)
+    Script,
+    /// Case-insensitive `equals`
+    IEquals,
+    /// Case-insensitive `contains`
+    IContains,
+    /// Case-insensitive `prefix`
+    IPrefix,
+    /// Case-insensitive `suffix`
+    ISuffix,
+    /// Value is one of the listed items
+    In,
+    /// Value is none of the listed items
+    #[serde(rename = "not_in")]
+    NotIn,
+    /// Some value of an array-valued param is one of the listed items
+    Intersects,
+    /// Every value of an array-valued param is one of the listed items
+    #[serde(rename = "subset_of")]
+    SubsetOf,
+    /// Param is present, whatever its value
+    Exists,
+    /// Param is absent
+    Missing,
+    /// Version equals (semantic versioning precedence)
+    #[serde(rename = "semver_eq")]
+    SemverEq,
+    /// Version greater than
+    #[serde(rename = "semver_gt")]
+    SemverGt,
+    /// Version greater than or equal
+    #[serde(rename = "semver_ge")]
+    SemverGe,
+    /// Version less than
+    #[serde(rename = "semver_lt")]
+    SemverLt,
+    /// Version less than or equal
+    #[serde(rename = "semver_le")]
+    SemverLe,
+    /// Value hashes (with the condition's `seed`) into a stable bucket below
+    /// the given percentage
+    #[serde(rename = "percent_lt")]
+    PercentLt,
+    /// Value hashes (with the condition's `seed`) into one of a number of
+    /// buckets within a range, given as `{ "buckets": n, "from": a, "to": b }`
+    #[serde(rename = "bucket_in")]
+    BucketIn,
+    /// IP address within one of the listed CIDR blocks
+    #[serde(rename = "ip_in_cidr")]
+    IpInCidr,
+    /// Language tag matched by one of the listed BCP 47 language ranges,
+    /// by basic filtering (`zh` matches `zh-CN` but not `zho`)
+    #[serde(rename = "lang_matches")]
+    LangMatches,
+    /// Time of day within `["HH:MM", "HH:MM"]`, wrapping past midnight
+    /// (`["22:00", "06:00"]`)
+    #[serde(rename = "time_between")]
+    TimeBetween,
+    /// Time matching a 5-field cron expression, to the minute
+    #[serde(rename = "cron_match")]
+    CronMatch,
+    /// Datetime earlier than the given one
+    Before,
+    /// Datetime later than the given one
+    After,
+    /// Datetime within `[start, end)`, or number within `[min, max]`, given
+    /// as a two-item list
+    Between,
+    /// Length in characters greater than
+    #[serde(rename = "len_gt")]
+    LenGt,
+    /// Length in characters less than
+    #[serde(rename = "len_lt")]
+    LenLt,
+    /// Length in characters equal to
+    #[serde(rename = "len_eq")]
+    LenEq,
+    /// Application predicate registered with
+    /// [`ConfigEvaluator::register_operator`], written `custom:<name>`
+    #[serde(untagged)]
+    Custom(CustomOperator),
+}
+
+/// Name of an application-registered operator
+///
+/// Serialized with its `custom:` prefix, as written in rules.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct CustomOperator(String);
+
+impl CustomOperator {
+    const PREFIX: &'static str = "custom:";
+
+    /// Create a custom operator reference from its registered name
+    pub fn new(name: impl Into<String>) -> Self {
+        CustomOperator(format!("{}{}", Self::PREFIX, name.into()))
+    }
+
+    /// The registered name, without the `custom:` prefix
+    pub fn name(&self) -> &str {
+        &self.0[Self::PREFIX.len()..]
+    }
+}
+
+impl Serialize for CustomOperator {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for CustomOperator {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let op = String::deserialize(deserializer)?;
+        match op.strip_prefix(Self::PREFIX) {
+            Some(name) if !name.is_empty() => Ok(CustomOperator(op)),
+            _ => Err(serde::de::Error::custom(format!(
+                "unknown operator '{}'",
+                op
+            ))),
+        }
+    }
 }
 
+/// Predicate of a custom operator, called with the param value and the
+/// condition value
+pub type OperatorFn = dyn Fn(&str, &serde_json::Value) -> bool + Send + Sync;
+
 impl Operator {
+    /// Name of the operator as written in rules
+    fn name(&self) -> &str {
+        match self {
+            Operator::Equals => "equals",
+            Operator::Contains => "contains",
+            Operator::Prefix => "prefix",
+            Operator::Suffix => "suffix",
+            Operator::Regex => "regex",
+            Operator::Glob => "glob",
+            Operator::GreaterThan => "gt",
+            Operator::LessThan => "lt",
+            Operator::GreaterThanOrEqual => "ge",
+            Operator::LessThanOrEqual => "le",
+            Operator::Script => "script",
+            Operator::IEquals => "iequals",
+            Operator::IContains => "icontains",
+            Operator::IPrefix => "iprefix",
+            Operator::ISuffix => "isuffix",
+            Operator::In => "in",
+            Operator::NotIn => "not_in",
+            Operator::Intersects => "intersects",
+            Operator::SubsetOf => "subset_of",
+            Operator::Exists => "exists",
+            Operator::Missing => "missing",
+            Operator::SemverEq => "semver_eq",
+            Operator::SemverGt => "semver_gt",
+            Operator::SemverGe => "semver_ge",
+            Operator::SemverLt => "semver_lt",
+            Operator::SemverLe => "semver_le",
+            Operator::PercentLt => "percent_lt",
+            Operator::BucketIn => "bucket_in",
+            Operator::IpInCidr => "ip_in_cidr",
+            Operator::LangMatches => "lang_matches",
+            Operator::TimeBetween => "time_between",
+            Operator::CronMatch => "cron_match",
+            Operator::Before => "before",
+            Operator::After => "after",
+            Operator::Between => "between",
+            Operator::LenGt => "len_gt",
+            Operator::LenLt => "len_lt",
+            Operator::LenEq => "len_eq",
+            Operator::Custom(custom) => &custom.0,
+        }
+    }
+
+    /// Check whether the operator compares semantic versions
+    fn is_semver(&self) -> bool {
+        matches!(
+            self,
+            Operator::SemverEq
+                | Operator::SemverGt
+                | Operator::SemverGe
+                | Operator::SemverLt
+                | Operator::SemverLe
+        )
+    }
+
+    /// Check whether the operator compares datetimes
+    fn is_datetime(&self) -> bool {
+        matches!(self, Operator::Before | Operator::After | Operator::Between)
+    }
+
+    /// Check whether the operator reads the time of its param, or of the
+    /// evaluator's clock when the param is missing
+    fn reads_clock(&self) -> bool {
+        matches!(self, Operator::TimeBetween | Operator::CronMatch)
+    }
+
+    /// Check whether a condition with the operator is meant for missing
+    /// params, so strict mode lets it read one; `compares_field` is set for
+    /// conditions with a `value_field`
+    fn reads_absence(&self, value: &serde_json::Value, compares_field: bool) -> bool {
+        matches!(self, Operator::Exists | Operator::Missing)
+            || self.reads_clock()
+            || (*self == Operator::Equals && !compares_field && value.is_null())
+    }
+
+    /// Check whether the operator compares the values of an array-valued
+    /// param, read like those of conditions with a `mode`
+    fn is_set(&self) -> bool {
+        matches!(self, Operator::Intersects | Operator::SubsetOf)
+    }
+
+    /// Check whether the operator compares string lengths
+    fn is_length(&self) -> bool {
+        matches!(self, Operator::LenGt | Operator::LenLt | Operator::LenEq)
+    }
+
+    /// Check whether the operator compares numbers
+    fn is_numeric(&self) -> bool {
+        matches!(
+            self,
+            Operator::GreaterThan
+                | Operator::LessThan
+                | Operator::GreaterThanOrEqual
+                | Operator::LessThanOrEqual
+        )
+    }
+
+    /// Check whether the operator accepts a condition value of this type
+    fn supports(&self, value: &serde_json::Value) -> bool {
+        if matches!(self, Operator::Custom(_)) {
+            return true;
+        }
+        if matches!(self, Operator::In | Operator::NotIn | Operator::Between) || self.is_set() {
+            return value.is_array();
+        }
+        if matches!(self, Operator::Exists | Operator::Missing) {
+            return value.is_null();
+        }
+        if matches!(
+            self,
+            Operator::IpInCidr | Operator::LangMatches | Operator::CronMatch
+        ) {
+            return value.is_string() || value.is_array();
+        }
+        if matches!(self, Operator::TimeBetween) {
+            return value.is_array();
+        }
+        if matches!(self, Operator::BucketIn) {
+            return value.is_object();
+        }
+        match value {
+            serde_json::Value::String(_) => true,
+            serde_json::Value::Number(_) => {
+                matches!(self, Operator::Equals | Operator::PercentLt)
+                    || self.is_numeric()
+                    || self.is_length()
+            }
+            serde_json::Value::Bool(_) | serde_json::Value::Null => {
+                matches!(self, Operator::Equals)
+            }
+            serde_json::Value::Array(_) | serde_json::Value::Object(_) => false,
+        }
+    }
+
     /// Validate if the operator is valid
     pub fn is_valid(&self) -> bool {
         matches!(
@@ -47,31 +592,237 @@ impl Operator {
                 | Operator::Prefix
                 | Operator::Suffix
                 | Operator::Regex
+                | Operator::Glob
                 | Operator::GreaterThan
                 | Operator::LessThan
                 | Operator::GreaterThanOrEqual
                 | Operator::LessThanOrEqual
+                | Operator::Script
+                | Operator::IEquals
+                | Operator::IContains
+                | Operator::IPrefix
+                | Operator::ISuffix
+                | Operator::In
+                | Operator::NotIn
+                | Operator::Intersects
+                | Operator::SubsetOf
+                | Operator::Exists
+                | Operator::Missing
+                | Operator::SemverEq
+                | Operator::SemverGt
+                | Operator::SemverGe
+                | Operator::SemverLt
+                | Operator::SemverLe
+                | Operator::PercentLt
+                | Operator::BucketIn
+                | Operator::IpInCidr
+                | Operator::LangMatches
+                | Operator::TimeBetween
+                | Operator::CronMatch
+                | Operator::Before
+                | Operator::After
+                | Operator::Between
+                | Operator::LenGt
+                | Operator::LenLt
+                | Operator::LenEq
+                | Operator::Custom(_)
         )
     }
 }
 
 /// Condition expression
+///
+/// Besides the JSON tree form, a condition can be written as a string in the
+/// textual syntax of [`Condition::parse_expr`], e.g.
+/// `"platform ~ \"^RTD\" && region == \"CN\""`.
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
-#[serde(untagged)]
+#[serde(untagged, try_from = "ConditionRepr")]
 pub enum Condition {
     /// Simple condition: field comparison
+    ///
+    /// The value may be a string, number, bool or null; operators compare
+    /// according to its type. It is omitted for `exists` and `missing`.
+    /// `seed` salts the hash of `percent_lt` and `bucket_in`, so independent experiments
+    /// select independent populations. With `value_field` instead of
+    /// `value`, the param is compared against another param. `trim` strips
+    /// surrounding whitespace from the compared params and `normalize`
+    /// brings params and string values to the same Unicode form. With a
+    /// `mode`, each value of a repeated param is compared, and `any` or
+    /// `all` of them must match.
     Simple {
         field: String,
         op: Operator,
-        value: String,
+        #[serde(default)]
+        value: serde_json::Value,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        value_field: Option<String>,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        seed: String,
+        #[serde(default, skip_serializing_if = "core::ops::Not::not")]
+        trim: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        normalize: Option<Normalization>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mode: Option<MultiValueMode>,
     },
     /// AND condition: all sub-conditions must be satisfied
     And { and: Vec<Condition> },
     /// OR condition: at least one sub-condition must be satisfied
     Or { or: Vec<Condition> },
+    /// NOT condition: the sub-condition must not be satisfied
+    Not { not: Box<Condition> },
+    /// Named condition of the rule set's `definitions`, written
+    /// `{ "ref": "is_cn_prod" }`
+    Ref {
+        #[serde(rename = "ref")]
+        name: String,
+    },
+}
+
+/// Accepted forms of a condition when deserializing
+enum ConditionRepr {
+    Expr(String),
+    Simple {
+        field: String,
+        op: Operator,
+        value: serde_json::Value,
+        value_field: Option<String>,
+        seed: String,
+        trim: bool,
+        normalize: Option<Normalization>,
+        mode: Option<MultiValueMode>,
+    },
+    And {
+        and: Vec<Condition>,
+    },
+    Or {
+        or: Vec<Condition>,
+    },
+    Not {
+        not: Box<Condition>,
+    },
+    Ref {
+        name: String,
+    },
+}
+
+impl<'de> Deserialize<'de> for ConditionRepr {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ConditionVisitor)
+    }
+}
+
+/// Reads condition keys one by one, so errors point at the offending key
+/// (e.g. `rules[3].if.and[1].op`) instead of the whole condition
+struct ConditionVisitor;
+
+impl<'de> serde::de::Visitor<'de> for ConditionVisitor {
+    type Value = ConditionRepr;
+
+    fn expecting(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("a condition object or expression string")
+    }
+
+    fn visit_str<E: serde::de::Error>(self, expr: &str) -> Result<Self::Value, E> {
+        Ok(ConditionRepr::Expr(expr.to_string()))
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let (mut field, mut op, mut value, mut value_field, mut seed) =
+            (None, None, None, None, None);
+        let (mut trim, mut normalize, mut mode) = (None, None, None);
+        let (mut and, mut or, mut not, mut name) = (None, None, None, None);
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "field" => field = Some(map.next_value()?),
+                "op" => op = Some(map.next_value()?),
+                "value" => value = Some(map.next_value()?),
+                "value_field" => value_field = Some(map.next_value()?),
+                "seed" => seed = Some(map.next_value()?),
+                "trim" => trim = Some(map.next_value()?),
+                "normalize" => normalize = Some(map.next_value()?),
+                "mode" => mode = Some(map.next_value()?),
+                "and" => and = Some(map.next_value()?),
+                "or" => or = Some(map.next_value()?),
+                "not" => not = Some(map.next_value()?),
+                "ref" => name = Some(map.next_value()?),
+                _ => {
+                    map.next_value::<serde::de::IgnoredAny>()?;
+                }
+            }
+        }
+        match (field, op) {
+            (Some(field), Some(op)) => {
+                return Ok(ConditionRepr::Simple {
+                    field,
+                    op,
+                    value: value.unwrap_or_default(),
+                    value_field,
+                    seed: seed.unwrap_or_default(),
+                    trim: trim.unwrap_or_default(),
+                    normalize: normalize.flatten(),
+                    mode: mode.flatten(),
+                })
+            }
+            (Some(_), None) => return Err(serde::de::Error::missing_field("op")),
+            (None, Some(_)) => return Err(serde::de::Error::missing_field("field")),
+            (None, None) => {}
+        }
+        if let Some(and) = and {
+            Ok(ConditionRepr::And { and })
+        } else if let Some(or) = or {
+            Ok(ConditionRepr::Or { or })
+        } else if let Some(not) = not {
+            Ok(ConditionRepr::Not { not })
+        } else if let Some(name) = name {
+            Ok(ConditionRepr::Ref { name })
+        } else {
+            Err(serde::de::Error::custom(
+                "expected a condition with `field` and `op`, `and`, `or`, `not` or `ref`",
+            ))
+        }
+    }
+}
+
+impl TryFrom<ConditionRepr> for Condition {
+    type Error = ConfigExprError;
+
+    fn try_from(repr: ConditionRepr) -> Result<Self, Self::Error> {
+        Ok(match repr {
+            ConditionRepr::Expr(expr) => Condition::parse_expr(&expr)?,
+            ConditionRepr::Simple {
+                field,
+                op,
+                value,
+                value_field,
+                seed,
+                trim,
+                normalize,
+                mode,
+            } => Condition::Simple {
+                field,
+                op,
+                value,
+                value_field,
+                seed,
+                trim,
+                normalize,
+                mode,
+            },
+            ConditionRepr::And { and } => Condition::And { and },
+            ConditionRepr::Or { or } => Condition::Or { or },
+            ConditionRepr::Not { not } => Condition::Not { not },
+            ConditionRepr::Ref { name } => Condition::Ref { name },
+        })
+    }
 }
 
 /// Rule return value, supports string or JSON object
+///
+/// String fields of object results starting with `=` are computed expressions
+/// (e.g. `"= base_timeout * 1.5"`) evaluated from the params at match time;
+/// a leading `==` escapes a literal string starting with `=`.
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 #[serde(untagged)]
 pub enum RuleResult {
@@ -79,203 +830,4973 @@ pub enum RuleResult {
     Object(serde_json::Value),
 }
 
+impl RuleResult {
+    /// Return the message of an assertion result (`{ "error": "message" }`)
+    pub fn assertion_message(&self) -> Option<&str> {
+        match self {
+            RuleResult::Object(serde_json::Value::Object(obj)) if obj.len() == 1 => {
+                obj.get("error").and_then(|message| message.as_str())
+            }
+            _ => None,
+        }
+    }
+
+    /// Deserialize the result into a type of the caller's choosing
+    ///
+    /// String results deserialize as JSON strings, so they fit `String`,
+    /// enums with unit variants and the like.
+    pub fn deserialize_into<T: DeserializeOwned>(&self) -> Result<T, ConfigExprError> {
+        Ok(match self {
+            RuleResult::String(s) => T::deserialize(serde_json::Value::String(s.clone()))?,
+            RuleResult::Object(value) => T::deserialize(value)?,
+        })
+    }
+}
+
 /// Single rule definition
+///
+/// The optional `name` is a human-readable label reported alongside results;
+/// rules with an `id` can be referenced by other rules: `requires` lists rules
+/// that must be present and `conflicts_with` lists rules that must not be
+/// enabled alongside it, both checked when the rule set is loaded. `description`, `tags` and
+/// `metadata` are not evaluated; they are kept for tooling and reported in
+/// traces.
+///
+/// Within a phase, rules with a higher `priority` are evaluated first; rules
+/// of equal priority keep their array order.
+///
+/// Rules with `"enabled": false`, or evaluated outside their `active_from` /
+/// `active_until` window (RFC 3339 timestamps, start inclusive, end
+/// exclusive), are skipped.
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 pub struct Rule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Arbitrary data about the rule, such as its owner or ticket
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    pub metadata: serde_json::Value,
     #[serde(rename = "if")]
     pub condition: Condition,
     #[serde(rename = "then")]
     pub result: RuleResult,
+    #[serde(default = "default_true", skip_serializing_if = "is_true")]
+    pub enabled: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_from: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_until: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rollout: Option<Rollout>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<Schedule>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub requires: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conflicts_with: Vec<String>,
+    /// Rhai script post-processing the result (requires the `scripting` feature)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transform: Option<String>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub priority: i32,
+    /// Substitute `${field}` placeholders in the result from the params
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub interpolate: bool,
+    /// Pipeline stage of a pre-phase rule: only the first matching rule of
+    /// each stage merges its result into the params
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stage: Option<String>,
+}
+
+impl Rule {
+    /// Check whether the rule is tagged with `tag`
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+}
+
+fn is_zero(n: &i32) -> bool {
+    *n == 0
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
 }
 
+fn is_true(b: &bool) -> bool {
+    *b
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Percentage rollout gating a rule
+///
+/// The `bucket_by` param is hashed (with the optional salt) into a stable
+/// bucket, and the rule only participates for the first `percent` percent of
+/// buckets. Rules sharing a salt select nested populations: every device in a
+/// 5% rollout is also in a 10% one.
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Rollout {
+    pub percent: f64,
+    pub bucket_by: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub salt: String,
+}
+
+// Percentages are validated to lie within 0..=100, so they are never NaN
+impl Eq for Rollout {}
+
 /// Configuration rule set
+///
+/// Rules are evaluated in three phases:
+/// - `pre`: every matching rule merges its object result into the params,
+///   so later rules see the rewritten values
+/// - `rules`: the first matching rule selects the result (or, with
+///   `"match_mode": "all"`, every matching rule contributes to an array, and
+///   with a merging `merge_strategy`, to one merged object)
+/// - `post`: every matching rule either vetoes the selected result
+///   (`{ "veto": true }`) or annotates it by merging its object result into it
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 pub struct ConfigRules {
+    /// Optional rule set version, reported in instrumentation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Rule sets to include, resolved with
+    /// [`ConfigRules::resolve_includes`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// Constants substituted into condition values written
+    /// `"${vars.name}"` when the rules are loaded
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub vars: BTreeMap<String, serde_json::Value>,
+    /// Named conditions, used by rules as `{ "ref": name }`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub definitions: BTreeMap<String, Condition>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre: Vec<Rule>,
     pub rules: Vec<Rule>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post: Vec<Rule>,
+    /// Decision tables, whose rows are appended to `rules` when the rules
+    /// are loaded
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tables: Vec<DecisionTable>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub fallback: Option<RuleResult>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub params_spec: BTreeMap<String, ParamSpec>,
+    #[serde(default, skip_serializing_if = "MatchMode::is_first")]
+    pub match_mode: MatchMode,
+    #[serde(default, skip_serializing_if = "MergeStrategy::is_first")]
+    pub merge_strategy: MergeStrategy,
+    /// strftime-style format of the datetimes compared by `before`, `after`
+    /// and `between`, such as `"%Y/%m/%d %H:%M"`; RFC 3339 timestamps and
+    /// `YYYY-MM-DD` dates by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub datetime_format: Option<String>,
 }
 
-/// Configuration expression evaluator
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
-pub struct ConfigEvaluator {
-    rules: ConfigRules,
+/// How main-phase rules select the result
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    /// The first matching rule selects the result
+    #[default]
+    First,
+    /// The results of all matching rules are returned as a JSON array
+    All,
 }
 
-impl ConfigEvaluator {
-    /// Create a new evaluator
-    pub fn new(rules: ConfigRules) -> Result<Self, ConfigExprError> {
-        // Validate rule set
-        Self::validate_rules(&rules)?;
-        Ok(Self { rules })
+impl MatchMode {
+    fn is_first(&self) -> bool {
+        *self == MatchMode::First
     }
+}
 
-    /// Create evaluator from JSON string
-    pub fn from_json(json: &str) -> Result<Self, ConfigExprError> {
-        let rules: ConfigRules = serde_json::from_str(json)?;
-        Self::new(rules)
+/// How the object results of matching main-phase rules are combined
+///
+/// Merging strategies apply every matching rule in evaluation order, so keys
+/// of later rules override those of earlier ones; raise a rule's `priority`
+/// to have it applied first and overridden by the rest.
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeStrategy {
+    /// The first matching rule selects the result
+    #[default]
+    First,
+    /// Nested objects are merged recursively
+    AllMergeDeep,
+    /// Top-level keys of later results replace earlier ones entirely
+    AllMergeShallow,
+}
+
+impl MergeStrategy {
+    fn is_first(&self) -> bool {
+        *self == MergeStrategy::First
     }
+}
 
-    /// Evaluate request parameters and return matching result
-    pub fn evaluate(&self, params: &HashMap<String, String>) -> Option<RuleResult> {
-        for rule in &self.rules.rules {
-            if self.evaluate_condition(&rule.condition, params) {
-                return Some(rule.result.clone());
+/// Validation spec for a single input parameter
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct ParamSpec {
+    #[serde(default, skip_serializing_if = "core::ops::Not::not")]
+    pub required: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    #[serde(rename = "enum", default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed: Vec<String>,
+}
+
+impl ConfigRules {
+    /// Collect the sources of all condition and transform scripts
+    #[cfg(feature = "scripting")]
+    pub(crate) fn scripts(&self) -> Vec<&str> {
+        fn condition_scripts<'a>(condition: &'a Condition, out: &mut Vec<&'a str>) {
+            match condition {
+                Condition::Simple {
+                    op: Operator::Script,
+                    value: serde_json::Value::String(value),
+                    ..
+                } => out.push(value),
+                Condition::Simple { .. } => {}
+                Condition::And { and } => and.iter().for_each(|c| condition_scripts(c, out)),
+                Condition::Or { or } => or.iter().for_each(|c| condition_scripts(c, out)),
+                Condition::Not { not } => condition_scripts(not, out),
+                Condition::Ref { .. } => {}
             }
         }
-        self.rules.fallback.clone()
+
+        let mut scripts = Vec::new();
+        for condition in self.conditions() {
+            condition_scripts(condition, &mut scripts);
+        }
+        for rule in self.pre.iter().chain(&self.rules).chain(&self.post) {
+            scripts.extend(rule.transform.as_deref());
+        }
+        scripts
     }
 
-    /// Evaluate a single condition
-    fn evaluate_condition(&self, condition: &Condition, params: &HashMap<String, String>) -> bool {
-        match condition {
-            Condition::Simple { field, op, value } => {
-                self.evaluate_simple_condition(field, op, value, params)
+    /// Collect every param field referenced by conditions, rollouts and
+    /// computed result expressions
+    pub fn referenced_fields(&self) -> BTreeSet<String> {
+        fn condition_fields(condition: &Condition, out: &mut BTreeSet<String>) {
+            match condition {
+                Condition::Simple {
+                    field,
+                    op,
+                    value,
+                    value_field,
+                    ..
+                } => {
+                    out.insert(field.clone());
+                    out.extend(value_field.iter().cloned());
+                    if let Some(source) = value.as_str() {
+                        if op.is_numeric() && expr::has_placeholders(source) {
+                            if let Ok(expr) = Expr::parse(source) {
+                                let mut fields = Vec::new();
+                                expr.fields(&mut fields);
+                                out.extend(fields.into_iter().map(str::to_string));
+                            }
+                        }
+                    }
+                }
+                Condition::And { and } => and.iter().for_each(|c| condition_fields(c, out)),
+                Condition::Or { or } => or.iter().for_each(|c| condition_fields(c, out)),
+                Condition::Not { not } => condition_fields(not, out),
+                Condition::Ref { .. } => {}
             }
-            Condition::And { and } => and.iter().all(|cond| self.evaluate_condition(cond, params)),
-            Condition::Or { or } => or.iter().any(|cond| self.evaluate_condition(cond, params)),
         }
-    }
 
-    /// Evaluate simple condition
-    fn evaluate_simple_condition(
-        &self,
-        field: &str,
-        op: &Operator,
-        value: &str,
-        params: &HashMap<String, String>,
-    ) -> bool {
-        let field_value = match params.get(field) {
-            Some(v) => v,
-            None => return false,
-        };
+        fn result_fields(value: &serde_json::Value, out: &mut BTreeSet<String>) {
+            if let Some(choice) = choose::Choice::of(value) {
+                out.extend(choice.seed_field().map(str::to_string));
+                choice.values().for_each(|v| result_fields(v, out));
+                return;
+            }
+            let source = match value {
+                serde_json::Value::String(s) => computed_expression(s).and_then(Result::ok),
+                _ => expr_object(value),
+            };
+            if let Some(source) = source {
+                if let Ok(expr) = Expr::parse(source) {
+                    let mut fields = Vec::new();
+                    expr.fields(&mut fields);
+                    out.extend(fields.into_iter().map(str::to_string));
+                }
+                return;
+            }
+            match value {
+                serde_json::Value::Array(items) => items.iter().for_each(|v| result_fields(v, out)),
+                serde_json::Value::Object(obj) => obj.values().for_each(|v| result_fields(v, out)),
+                _ => {}
+            }
+        }
 
-        match op {
-            Operator::Equals => field_value == value,
-            Operator::Contains => field_value.contains(value),
-            Operator::Prefix => field_value.starts_with(value),
-            Operator::Suffix => field_value.ends_with(value),
-            Operator::Regex => {
-                match Regex::new(value) {
-                    Ok(regex) => regex.is_match(field_value),
-                    Err(_) => false, // Return false if regex is invalid
+        let mut fields = BTreeSet::new();
+        for condition in self.conditions() {
+            condition_fields(condition, &mut fields);
+        }
+        for rule in self.pre.iter().chain(&self.rules).chain(&self.post) {
+            if let Some(rollout) = &rule.rollout {
+                fields.insert(rollout.bucket_by.clone());
+            }
+            if let RuleResult::Object(value) = &rule.result {
+                result_fields(value, &mut fields);
+            }
+            if rule.interpolate {
+                for source in template::templates(&rule.result) {
+                    fields.extend(template::fields(source).into_iter().map(str::to_string));
                 }
             }
-            Operator::GreaterThan => self.compare_numbers(field_value, value, |a, b| a > b),
-            Operator::LessThan => self.compare_numbers(field_value, value, |a, b| a < b),
-            Operator::GreaterThanOrEqual => self.compare_numbers(field_value, value, |a, b| a >= b),
-            Operator::LessThanOrEqual => self.compare_numbers(field_value, value, |a, b| a <= b),
         }
+        if let Some(RuleResult::Object(value)) = &self.fallback {
+            result_fields(value, &mut fields);
+        }
+        fields
     }
+}
 
-    /// Compare two strings as numbers
-    fn compare_numbers<F>(&self, field_value: &str, target_value: &str, compare_fn: F) -> bool
-    where
-        F: Fn(f64, f64) -> bool,
-    {
-        match (field_value.parse::<f64>(), target_value.parse::<f64>()) {
-            (Ok(field_num), Ok(target_num)) => compare_fn(field_num, target_num),
-            _ => false, // Return false if any value cannot be parsed as a number
+/// Identifies what produced an evaluation result
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum RuleId {
+    /// A main-phase rule, by index within `rules` and its `id` and `name`
+    Rule {
+        index: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+    },
+    /// The rule set's fallback
+    Fallback,
+}
+
+/// Renders the rule's `id`, else its `name`, else `rule <index>`
+impl core::fmt::Display for RuleId {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            RuleId::Rule { id: Some(id), .. } => write!(f, "{}", id),
+            RuleId::Rule {
+                name: Some(name), ..
+            } => write!(f, "{}", name),
+            RuleId::Rule { index, .. } => write!(f, "rule {}", index),
+            RuleId::Fallback => write!(f, "fallback"),
         }
     }
+}
 
-    /// Validate if the rule set is valid
-    fn validate_rules(rules: &ConfigRules) -> Result<(), ConfigExprError> {
-        // if rules.rules.is_empty() {
-        //     return Err(ConfigExprError::ValidationError(
-        //         "Rules cannot be empty".to_string(),
-        //     ));
-        // }
+/// Result of an evaluation together with the main-phase rule that produced it
+#[derive(Debug, Clone)]
+struct Outcome<'a> {
+    /// Borrowed from the rules when evaluation left the result unchanged
+    result: Option<Cow<'a, RuleResult>>,
+    /// Index of the matching main-phase rule, `None` for fallback or no match
+    rule_index: Option<usize>,
+}
 
-        for (index, rule) in rules.rules.iter().enumerate() {
-            Self::validate_condition(&rule.condition, index)?;
+impl Outcome<'_> {
+    /// Copy a borrowed result, detaching the outcome from the rules
+    #[cfg(feature = "std")]
+    fn into_owned(self) -> Outcome<'static> {
+        Outcome {
+            result: self.result.map(|result| Cow::Owned(result.into_owned())),
+            rule_index: self.rule_index,
         }
+    }
+}
 
-        Ok(())
+/// Evaluation order of each phase
+///
+/// Rule indices sorted by descending priority, ties kept in array order.
+#[derive(Debug, Clone, Default)]
+struct RuleOrder {
+    pre: Vec<usize>,
+    rules: Vec<usize>,
+    post: Vec<usize>,
+}
+
+impl RuleOrder {
+    fn for_rules(rules: &ConfigRules) -> Self {
+        fn by_priority(rules: &[Rule]) -> Vec<usize> {
+            let mut order: Vec<usize> = (0..rules.len()).collect();
+            // Stable, so equal priorities keep their array order
+            order.sort_by_key(|&index| core::cmp::Reverse(rules[index].priority));
+            order
+        }
+
+        RuleOrder {
+            pre: by_priority(&rules.pre),
+            rules: by_priority(&rules.rules),
+            post: by_priority(&rules.post),
+        }
     }
+}
 
-    /// Validate if the condition is valid
-    fn validate_condition(condition: &Condition, rule_index: usize) -> Result<(), ConfigExprError> {
-        match condition {
-            Condition::Simple { field, op, value } => {
-                if field.is_empty() {
-                    return Err(ConfigExprError::ValidationError(format!(
-                        "Field name cannot be empty in rule {}",
-                        rule_index
-                    )));
-                }
+/// Serialized form of an evaluator
+#[derive(Deserialize)]
+struct EvaluatorData {
+    rules: ConfigRules,
+}
 
-                if !op.is_valid() {
-                    return Err(ConfigExprError::InvalidOperator(format!("{:?}", op)));
-                }
+impl From<EvaluatorData> for ConfigEvaluator {
+    fn from(data: EvaluatorData) -> Self {
+        ConfigEvaluator::from_validated(data.rules, EvaluatorLimits::default())
+    }
+}
 
-                // 验证正则表达式
-                if matches!(op, Operator::Regex) {
-                    Regex::new(value).map_err(|e| {
-                        ConfigExprError::ValidationError(format!(
-                            "Invalid regex '{}' in rule {}: {}",
-                            value, rule_index, e
-                        ))
-                    })?;
-                }
-            }
-            Condition::And { and } => {
-                if and.is_empty() {
-                    return Err(ConfigExprError::ValidationError(format!(
-                        "AND condition cannot be empty in rule {}",
-                        rule_index
-                    )));
-                }
-                for cond in and {
-                    Self::validate_condition(cond, rule_index)?;
-                }
-            }
-            Condition::Or { or } => {
-                if or.is_empty() {
-                    return Err(ConfigExprError::ValidationError(format!(
-                        "OR condition cannot be empty in rule {}",
-                        rule_index
-                    )));
-                }
-                for cond in or {
-                    Self::validate_condition(cond, rule_index)?;
-                }
-            }
+/// Configuration expression evaluator
+#[derive(Serialize, Deserialize)]
+#[serde(from = "EvaluatorData")]
+pub struct ConfigEvaluator {
+    rules: ConfigRules,
+    #[serde(skip)]
+    order: RuleOrder,
+    #[serde(skip, default = "default_clock")]
+    clock: Arc<dyn Clock>,
+    #[serde(skip, default = "default_random")]
+    random: Arc<dyn RandomSource>,
+    #[serde(skip)]
+    param_keys: ParamKeys,
+    #[serde(skip)]
+    strict_fields: bool,
+    #[cfg(feature = "scripting")]
+    #[serde(skip)]
+    scripts: Arc<scripting::ScriptEngine>,
+    /// Hashed `in` / `not_in` lists, keyed by address within `rules`
+    #[serde(skip)]
+    membership: membership::MembershipSets,
+    #[serde(skip)]
+    custom_operators: HashMap<String, Arc<OperatorFn>>,
+    #[serde(skip)]
+    compiled: compile::CompiledRules,
+    #[serde(skip)]
+    limits: EvaluatorLimits,
+    #[cfg(feature = "std")]
+    #[serde(skip)]
+    metrics: Option<Arc<metrics::Counters>>,
+    #[cfg(feature = "std")]
+    #[serde(skip)]
+    cache: Option<Arc<cache::ResultCache>>,
+}
+
+impl Clone for ConfigEvaluator {
+    fn clone(&self) -> Self {
+        let rules = self.rules.clone();
+        Self {
+            // The cloned lists live at new addresses
+            membership: membership::MembershipSets::for_rules(&rules),
+            order: self.order.clone(),
+            rules,
+            clock: self.clock.clone(),
+            random: self.random.clone(),
+            param_keys: self.param_keys.clone(),
+            strict_fields: self.strict_fields,
+            #[cfg(feature = "scripting")]
+            scripts: self.scripts.clone(),
+            custom_operators: self.custom_operators.clone(),
+            compiled: self.compiled.clone(),
+            limits: self.limits,
+            #[cfg(feature = "std")]
+            metrics: self.metrics.clone(),
+            #[cfg(feature = "std")]
+            cache: self.cache.clone(),
         }
-        Ok(())
     }
 }
 
-/// Convenience method: directly evaluate from JSON string
-pub fn evaluate_json(
-    json: &str,
-    params: &HashMap<String, String>,
-) -> Result<Option<RuleResult>, ConfigExprError> {
-    let evaluator = ConfigEvaluator::from_json(json)?;
-    Ok(evaluator.evaluate(params))
+fn default_clock() -> Arc<dyn Clock> {
+    #[cfg(feature = "std")]
+    return Arc::new(SystemClock);
+    #[cfg(not(feature = "std"))]
+    return Arc::new(time::UnknownTime);
 }
 
-/// Convenience method: validate if JSON rules are valid
-pub fn validate_json(json: &str) -> Result<(), ConfigExprError> {
-    let rules: ConfigRules = serde_json::from_str(json)?;
-    ConfigEvaluator::validate_rules(&rules)
+fn default_random() -> Arc<dyn RandomSource> {
+    Arc::new(choose::default_random())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl core::fmt::Debug for ConfigEvaluator {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ConfigEvaluator")
+            .field("rules", &self.rules)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for ConfigEvaluator {
+    fn eq(&self, other: &Self) -> bool {
+        self.rules == other.rules
+    }
+}
+
+impl Eq for ConfigEvaluator {}
+
+impl ConfigEvaluator {
+    /// Create a new evaluator
+    pub fn new(rules: ConfigRules) -> Result<Self, ConfigExprError> {
+        Self::new_with_limits(rules, EvaluatorLimits::default())
+    }
+
+    /// Create a new evaluator whose `regex` patterns compile within `regex`
+    ///
+    /// Rule sets with larger patterns fail validation, so limits below the
+    /// defaults guard against catastrophic patterns in untrusted rule files.
+    pub fn new_with_regex_config(
+        rules: ConfigRules,
+        regex: RegexConfig,
+    ) -> Result<Self, ConfigExprError> {
+        let limits = EvaluatorLimits {
+            regex,
+            ..EvaluatorLimits::default()
+        };
+        Self::new_with_limits(rules, limits)
+    }
+
+    /// Create a new evaluator for rules within `limits`
+    ///
+    /// The limits are checked before the rules are validated and compiled,
+    /// bounding the memory and time spent on rule files uploaded by users.
+    pub fn new_with_limits(
+        mut rules: ConfigRules,
+        limits: EvaluatorLimits,
+    ) -> Result<Self, ConfigExprError> {
+        // Expand tables, substitute vars and validate rule set
+        #[cfg(feature = "otel")]
+        let span = otel::LoadSpan::start(&rules);
+        let validation = rules
+            .expand_tables()
+            .and_then(|()| rules.substitute_vars())
+            .and_then(|()| Self::validate_rules(&rules, &limits));
+        #[cfg(feature = "otel")]
+        span.finish(&validation);
+        validation?;
+        Ok(Self::from_validated(rules, limits))
+    }
+
+    /// Create an evaluator from a rule set that has already been validated
+    fn from_validated(rules: ConfigRules, limits: EvaluatorLimits) -> Self {
+        let order = RuleOrder::for_rules(&rules);
+        let compiled = compile::CompiledRules::for_rules(&rules, &order.rules, &limits.regex);
+        Self {
+            #[cfg(feature = "scripting")]
+            scripts: Arc::new(scripting::ScriptEngine::for_rules(&rules)),
+            membership: membership::MembershipSets::for_rules(&rules),
+            order,
+            compiled,
+            limits,
+            rules,
+            clock: default_clock(),
+            random: default_random(),
+            param_keys: ParamKeys::default(),
+            strict_fields: false,
+            custom_operators: HashMap::new(),
+            #[cfg(feature = "std")]
+            metrics: None,
+            #[cfg(feature = "std")]
+            cache: None,
+        }
+    }
+
+    /// Indices of the main-phase rules in evaluation order
+    ///
+    /// Rules are sorted by descending `priority`; rules of equal priority
+    /// keep their array order, so the order is deterministic.
+    pub fn rule_order(&self) -> &[usize] {
+        &self.order.rules
+    }
+
+    /// Rules of a phase with their indices, in evaluation order
+    fn ordered_rules(&self, phase: Phase) -> impl Iterator<Item = (usize, &Rule)> {
+        let (rules, order) = match phase {
+            Phase::Pre => (&self.rules.pre, &self.order.pre),
+            Phase::Main => (&self.rules.rules, &self.order.rules),
+            Phase::Post => (&self.rules.post, &self.order.post),
+        };
+        order.iter().map(move |&index| (index, &rules[index]))
+    }
+
+    /// Use a custom clock for time-dependent rules
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Use a custom random source for choices without a seed
+    pub fn with_random(mut self, random: impl RandomSource + 'static) -> Self {
+        self.random = Arc::new(random);
+        self
+    }
+
+    /// Match param keys to the fields of conditions by `keys`, e.g. ignoring
+    /// case or reading aliases
+    ///
+    /// Keys are matched in every phase and for the result cache, which is
+    /// emptied.
+    pub fn with_param_keys(mut self, keys: ParamKeys) -> Self {
+        self.param_keys = keys;
+        #[cfg(feature = "std")]
+        self.clear_cache();
+        self
+    }
+
+    /// Fail evaluations in which a condition reads a missing param
+    ///
+    /// Without it, such conditions silently do not match, so a typo in a
+    /// field name disables a rule unnoticed. In strict mode the evaluation
+    /// fails with [`ConfigExprError::FieldNotFound`] naming the first such
+    /// field, like an assertion rule would; conditions left out by `and`
+    /// and `or` short-circuiting, `exists`, `missing` and `equals null`
+    /// conditions, and time-of-day conditions reading the clock are fine.
+    /// [`ConfigEvaluator::evaluate_report`] lists the missing fields as soft
+    /// errors in either mode.
+    pub fn with_strict_fields(mut self) -> Self {
+        self.strict_fields = true;
+        self
+    }
+
+    /// Create an evaluator for other rules with this one's clock, random
+    /// source, param keys, strict mode, custom operators, limits, metrics
+    /// and cache settings
+    ///
+    /// The new evaluator starts with empty metrics and cache.
+    pub fn with_rules(&self, rules: ConfigRules) -> Result<Self, ConfigExprError> {
+        let mut evaluator = Self::new_with_limits(rules, self.limits)?;
+        evaluator.clock = self.clock.clone();
+        evaluator.random = self.random.clone();
+        evaluator.param_keys = self.param_keys.clone();
+        evaluator.strict_fields = self.strict_fields;
+        evaluator.custom_operators = self.custom_operators.clone();
+        #[cfg(feature = "std")]
+        if self.metrics.is_some() {
+            evaluator = evaluator.with_metrics();
+        }
+        #[cfg(feature = "std")]
+        if let Some(cache) = &self.cache {
+            evaluator = evaluator.with_cache(cache.capacity());
+        }
+        Ok(evaluator)
+    }
+
+    /// Access the evaluator's rule set
+    pub fn rules(&self) -> &ConfigRules {
+        &self.rules
+    }
+
+    /// Limits the evaluator's `regex` patterns compile within
+    pub fn regex_config(&self) -> RegexConfig {
+        self.limits.regex
+    }
+
+    /// Limits the evaluator's rules were checked against
+    pub fn limits(&self) -> EvaluatorLimits {
+        self.limits
+    }
+
+    /// Collect rule hit counters and short-circuit statistics
+    ///
+    /// Counters are shared by clones of the evaluator and read with
+    /// [`ConfigEvaluator::metrics`].
+    #[cfg(feature = "std")]
+    pub fn with_metrics(mut self) -> Self {
+        self.metrics = Some(Arc::new(metrics::Counters::for_rules(&self.rules)));
+        self
+    }
+
+    /// Snapshot of the collected metrics, `None` unless enabled with
+    /// [`ConfigEvaluator::with_metrics`]
+    #[cfg(feature = "std")]
+    pub fn metrics(&self) -> Option<Metrics> {
+        self.metrics.as_ref().map(|counters| counters.snapshot())
+    }
+
+    /// Cache up to `capacity` evaluation results, least recently used first out
+    ///
+    /// Results are keyed by the values of the fields the rules reference
+    /// (see [`ConfigRules::referenced_fields`]), so requests differing only
+    /// in other params share an entry. Evaluations failing, such as by an
+    /// assertion rule, are not cached, and neither are rule sets with
+    /// activation windows, schedules or scripts, whose results depend on
+    /// more than those fields. Cache hits are not counted in
+    /// [`ConfigEvaluator::metrics`]; traced evaluations bypass the cache.
+    /// Custom operators must be pure functions of their arguments.
+    ///
+    /// The cache is shared by clones of the evaluator and emptied when a
+    /// custom operator is registered.
+    #[cfg(feature = "std")]
+    pub fn with_cache(mut self, capacity: usize) -> Self {
+        self.cache = Some(Arc::new(cache::ResultCache::for_rules(
+            &self.rules,
+            capacity,
+        )));
+        self
+    }
+
+    /// Hit and miss counts of the result cache, `None` unless enabled with
+    /// [`ConfigEvaluator::with_cache`]
+    #[cfg(feature = "std")]
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.cache.as_ref().map(|cache| cache.stats())
+    }
+
+    /// Drop every cached result
+    #[cfg(feature = "std")]
+    pub fn clear_cache(&self) {
+        if let Some(cache) = &self.cache {
+            cache.clear();
+        }
+    }
+
+    /// Register the predicate of a custom operator
+    ///
+    /// Rules reference it as `"op": "custom:<name>"`; the predicate receives
+    /// the param value and the condition's `value`. Conditions using an
+    /// operator that is not registered never match.
+    pub fn register_operator(
+        &mut self,
+        name: impl Into<String>,
+        predicate: Box<OperatorFn>,
+    ) -> &mut Self {
+        self.custom_operators
+            .insert(name.into(), Arc::from(predicate));
+        #[cfg(feature = "std")]
+        self.clear_cache();
+        self
+    }
+
+    /// Custom operators referenced by the rules but not registered
+    pub fn unregistered_operators(&self) -> BTreeSet<String> {
+        fn visit(condition: &Condition, out: &mut BTreeSet<String>) {
+            match condition {
+                Condition::Simple {
+                    op: Operator::Custom(custom),
+                    ..
+                } => {
+                    out.insert(custom.name().to_string());
+                }
+                Condition::Simple { .. } => {}
+                Condition::And { and } => and.iter().for_each(|c| visit(c, out)),
+                Condition::Or { or } => or.iter().for_each(|c| visit(c, out)),
+                Condition::Not { not } => visit(not, out),
+                Condition::Ref { .. } => {}
+            }
+        }
+
+        let mut operators = BTreeSet::new();
+        for condition in self.rules.conditions() {
+            visit(condition, &mut operators);
+        }
+        operators.retain(|name| !self.custom_operators.contains_key(name));
+        operators
+    }
+
+    /// Create evaluator from JSON string
+    pub fn from_json(json: &str) -> Result<Self, ConfigExprError> {
+        Self::new(parse_json_rules(json.as_bytes())?)
+    }
+
+    /// Evaluate request parameters and return matching result
+    ///
+    /// Returns `None` when an assertion rule matches; use
+    /// [`ConfigEvaluator::evaluate_checked`] to receive the assertion error.
+    ///
+    /// Keys and values may be borrowed, e.g. a `HashMap<&str, &str>` of
+    /// slices into the request, so hot paths need not allocate the params.
+    pub fn evaluate(&self, params: &impl ParamSource) -> Option<RuleResult> {
+        self.evaluate_checked(params).ok().flatten()
+    }
+
+    /// Evaluate request parameters, borrowing the result from the rules
+    ///
+    /// Like [`ConfigEvaluator::evaluate`], but saves copying large results:
+    /// the result is borrowed unless evaluation builds a new one, as
+    /// interpolation, transform scripts, post-phase annotations, computed
+    /// values, merging strategies, `"match_mode": "all"` and the result
+    /// cache do.
+    pub fn evaluate_ref(&self, params: &impl ParamSource) -> Option<Cow<'_, RuleResult>> {
+        self.evaluate_instrumented(params, &mut Recorder::disabled())
+            .ok()?
+            .result
+    }
+
+    /// Evaluate request parameters, failing when a matching rule is an assertion
+    ///
+    /// Rules whose result is `{ "error": "message" }` reject the parameters with
+    /// a validation error carrying that message, in any phase and as fallback.
+    pub fn evaluate_checked(
+        &self,
+        params: &impl ParamSource,
+    ) -> Result<Option<RuleResult>, ConfigExprError> {
+        self.evaluate_source(params)
+    }
+
+    /// Evaluate params read from a [`ParamSource`], such as HTTP headers,
+    /// environment variables or a database row
+    ///
+    /// Only the params conditions actually look at are read. Like
+    /// [`ConfigEvaluator::evaluate_checked`], assertion rules fail the
+    /// evaluation.
+    pub fn evaluate_source(
+        &self,
+        params: &impl ParamSource,
+    ) -> Result<Option<RuleResult>, ConfigExprError> {
+        self.evaluate_instrumented(params, &mut Recorder::disabled())
+            .map(|outcome| outcome.result.map(Cow::into_owned))
+    }
+
+    /// Evaluate structured params, such as a JSON object or a serializable struct
+    ///
+    /// Top-level fields become params: numbers and bools keep their JSON
+    /// spelling so typed condition values compare them natively, `null`
+    /// fields are treated as missing, and nested values are passed as JSON
+    /// text that conditions reach with dot-paths like `device.info.platform`.
+    /// Like [`ConfigEvaluator::evaluate_checked`], assertion rules fail the
+    /// evaluation.
+    pub fn evaluate_value<T: Serialize + ?Sized>(
+        &self,
+        params: &T,
+    ) -> Result<Option<RuleResult>, ConfigExprError> {
+        let params = params_from_value(serde_json::to_value(params)?)?;
+        self.evaluate_checked(&params)
+    }
+
+    /// Evaluate request parameters and deserialize the result into `T`
+    ///
+    /// See [`RuleResult::deserialize_into`]. Like
+    /// [`ConfigEvaluator::evaluate_checked`], assertion rules fail the
+    /// evaluation.
+    pub fn evaluate_typed<T: DeserializeOwned>(
+        &self,
+        params: &impl ParamSource,
+    ) -> Result<Option<T>, ConfigExprError> {
+        self.evaluate_checked(params)?
+            .map(|result| result.deserialize_into())
+            .transpose()
+    }
+
+    /// Evaluate request parameters and return the result with the rule that produced it
+    ///
+    /// With `"match_mode": "all"` or a merging `merge_strategy` the first
+    /// contributing rule is reported. Like [`ConfigEvaluator::evaluate`],
+    /// returns `None` when an assertion rule matches.
+    pub fn evaluate_with_rule(&self, params: &impl ParamSource) -> Option<(RuleId, RuleResult)> {
+        let outcome = self
+            .evaluate_instrumented(params, &mut Recorder::disabled())
+            .ok()?;
+        let result = outcome.result?.into_owned();
+        let rule_id = match outcome.rule_index {
+            Some(index) => {
+                let rule = &self.rules.rules[index];
+                RuleId::Rule {
+                    index,
+                    id: rule.id.clone(),
+                    name: rule.name.clone(),
+                }
+            }
+            None => RuleId::Fallback,
+        };
+        Some((rule_id, result))
+    }
+
+    /// Evaluate request parameters considering only the rules `filter`
+    /// accepts, such as those tagged for one surface
+    ///
+    /// One rule file can serve several clients this way:
+    /// `evaluator.evaluate_filtered(&params, |rule| rule.has_tag("mobile"))`.
+    /// The filter applies to the rules of every phase; the fallback is used
+    /// when no accepted main-phase rule matches. Filtered evaluations bypass
+    /// the result cache. Like [`ConfigEvaluator::evaluate`], returns `None`
+    /// when an assertion rule matches.
+    pub fn evaluate_filtered(
+        &self,
+        params: &impl ParamSource,
+        filter: impl Fn(&Rule) -> bool,
+    ) -> Option<RuleResult> {
+        let outcome = self
+            .evaluate_instrumented(params, &mut Recorder::filtered(&filter))
+            .ok()?;
+        outcome.result.map(Cow::into_owned)
+    }
+
+    /// Evaluate within the telemetry spans of the enabled features
+    fn evaluate_instrumented(
+        &self,
+        params: &dyn ParamSource,
+        recorder: &mut Recorder,
+    ) -> Result<Outcome<'_>, ConfigExprError> {
+        #[cfg(feature = "otel")]
+        let span = otel::EvaluationSpan::start(&self.rules);
+        #[cfg(feature = "tracing")]
+        let tracing_span = self::tracing::EvaluationSpan::start(&self.rules);
+        let outcome = self.evaluate_outcome(params, recorder);
+        #[cfg(feature = "tracing")]
+        tracing_span.finish(&self.rules, &outcome);
+        #[cfg(feature = "otel")]
+        span.finish(&self.rules, &outcome);
+        outcome
+    }
+
+    /// Run all phases and record which main-phase rule selected the result,
+    /// using the result cache unless tracing
+    fn evaluate_outcome(
+        &self,
+        params: &dyn ParamSource,
+        recorder: &mut Recorder,
+    ) -> Result<Outcome<'_>, ConfigExprError> {
+        #[cfg(feature = "std")]
+        if let Some(cache) = self.cache.as_ref().filter(|_| recorder.is_cacheable()) {
+            let keyed = keys::Keyed::new(params, &self.param_keys);
+            return cache.get_or_evaluate(&keyed, || self.run_phases(params, recorder));
+        }
+        self.run_phases(params, recorder)
+    }
+
+    /// Run all phases, failing in strict mode when a condition read a
+    /// missing param
+    fn run_phases(
+        &self,
+        params: &dyn ParamSource,
+        recorder: &mut Recorder,
+    ) -> Result<Outcome<'_>, ConfigExprError> {
+        let outcome = self.select_outcome(params, recorder)?;
+        match recorder.take_missing_field() {
+            Some(field) => Err(ConfigExprError::FieldNotFound(field)),
+            None => Ok(outcome),
+        }
+    }
+
+    /// Run all phases and record which main-phase rule selected the result
+    fn select_outcome(
+        &self,
+        params: &dyn ParamSource,
+        recorder: &mut Recorder,
+    ) -> Result<Outcome<'_>, ConfigExprError> {
+        if recorder.is_counted() {
+            self.record_evaluation();
+        }
+        let params = self.apply_pre_phase(params, recorder)?;
+        if self.rules.match_mode == MatchMode::All {
+            let matches = self.collect_matches(&params, recorder)?;
+            if matches.is_empty() {
+                return Ok(Outcome {
+                    result: None,
+                    rule_index: None,
+                });
+            }
+            let rule_index = matches.first().and_then(|(index, _)| *index);
+            let results = matches
+                .into_iter()
+                .map(|(_, result)| serde_json::to_value(result))
+                .collect::<Result<Vec<_>, _>>()?;
+            return Ok(Outcome {
+                result: Some(Cow::Owned(RuleResult::Object(serde_json::Value::Array(
+                    results,
+                )))),
+                rule_index,
+            });
+        }
+
+        if !self.rules.merge_strategy.is_first() {
+            return self.merge_matches(&params, recorder);
+        }
+
+        // Traces list every rule, so they do not use the dispatch table; nor
+        // does strict mode when the param it is keyed on is missing, since
+        // the keyed rules read it
+        let dispatch = self.compiled.dispatch().filter(|table| {
+            !recorder.is_tracing()
+                && (!self.strict_fields || path::lookup(&params, table.field()).is_some())
+        });
+        let matched = match dispatch {
+            Some(table) => table
+                .candidates(&params)
+                .into_iter()
+                .map(|index| (index, &self.rules.rules[index]))
+                .find(|(index, rule)| {
+                    self.rule_matches_traced(recorder, Phase::Main, *index, rule, &params)
+                }),
+            None => self.ordered_rules(Phase::Main).find(|(index, rule)| {
+                self.rule_matches_traced(recorder, Phase::Main, *index, rule, &params)
+            }),
+        };
+        let (rule_index, result) = match matched {
+            Some((index, rule)) => (Some(index), self.rule_result(rule, &params)?),
+            None => match &self.rules.fallback {
+                Some(fallback) => (None, Cow::Borrowed(fallback)),
+                None => {
+                    return Ok(Outcome {
+                        result: None,
+                        rule_index: None,
+                    })
+                }
+            },
+        };
+        let result = self.finish_result(result, &params, recorder)?;
+        Ok(Outcome { result, rule_index })
+    }
+
+    /// Evaluate a batch of request parameters, returning results in input order
+    ///
+    /// Meant for dry runs: replay recorded params against a proposed rule set
+    /// and compare the results with those of the current one. With the
+    /// `rayon` feature the batch is evaluated in parallel.
+    pub fn evaluate_batch(&self, batch: &[impl ParamSource + Sync]) -> Vec<Option<RuleResult>> {
+        #[cfg(feature = "rayon")]
+        {
+            use rayon::prelude::*;
+            batch
+                .par_iter()
+                .map(|params| self.evaluate(params))
+                .collect()
+        }
+        #[cfg(not(feature = "rayon"))]
+        {
+            batch.iter().map(|params| self.evaluate(params)).collect()
+        }
+    }
+
+    /// Evaluate request parameters and return the results of all matching rules
+    ///
+    /// Results appear in rule order, each passed through the post phase, so
+    /// vetoed results are left out. The fallback is returned alone when no
+    /// rule matches. Evaluation errors yield an empty list.
+    pub fn evaluate_all(&self, params: &impl ParamSource) -> Vec<RuleResult> {
+        let mut recorder = Recorder::disabled();
+        self.record_evaluation();
+        self.apply_pre_phase(params, &mut recorder)
+            .and_then(|params| self.collect_matches(&params, &mut recorder))
+            .ok()
+            .filter(|_| recorder.take_missing_field().is_none())
+            .map(|matches| matches.into_iter().map(|(_, result)| result).collect())
+            .unwrap_or_default()
+    }
+
+    /// Finish the results of every matching main-phase rule, or the fallback
+    fn collect_matches(
+        &self,
+        params: &dyn ParamSource,
+        recorder: &mut Recorder,
+    ) -> Result<Vec<(Option<usize>, RuleResult)>, ConfigExprError> {
+        let mut matches = Vec::new();
+        for (index, rule) in self.ordered_rules(Phase::Main) {
+            if !self.rule_matches_traced(recorder, Phase::Main, index, rule, params) {
+                continue;
+            }
+            let result = self.rule_result(rule, params)?;
+            if let Some(result) = self.finish_result(result, params, recorder)? {
+                matches.push((Some(index), result.into_owned()));
+            }
+        }
+        if matches.is_empty() {
+            if let Some(fallback) = &self.rules.fallback {
+                let result = self.finish_result(Cow::Borrowed(fallback), params, recorder)?;
+                matches.extend(result.map(|result| (None, result.into_owned())));
+            }
+        }
+        Ok(matches)
+    }
+
+    /// Merge the results of all matching main-phase rules into one object
+    fn merge_matches(
+        &self,
+        params: &dyn ParamSource,
+        recorder: &mut Recorder,
+    ) -> Result<Outcome<'_>, ConfigExprError> {
+        let deep = self.rules.merge_strategy == MergeStrategy::AllMergeDeep;
+        let mut merged: Option<(usize, serde_json::Map<String, serde_json::Value>)> = None;
+        for (index, rule) in self.ordered_rules(Phase::Main) {
+            if !self.rule_matches_traced(recorder, Phase::Main, index, rule, params) {
+                continue;
+            }
+            let result = self.rule_result(rule, params)?;
+            Self::check_assertion(&result)?;
+            let RuleResult::Object(serde_json::Value::Object(obj)) = result.into_owned() else {
+                return Err(ConfigExprError::ExpressionError(format!(
+                    "Cannot merge non-object result of rule {}",
+                    index
+                )));
+            };
+            match &mut merged {
+                Some((_, target)) => merge_objects(target, obj, deep),
+                None => merged = Some((index, obj)),
+            }
+        }
+
+        let (rule_index, result) = match merged {
+            Some((index, obj)) => (
+                Some(index),
+                Cow::Owned(RuleResult::Object(serde_json::Value::Object(obj))),
+            ),
+            None => match &self.rules.fallback {
+                Some(fallback) => (None, Cow::Borrowed(fallback)),
+                None => {
+                    return Ok(Outcome {
+                        result: None,
+                        rule_index: None,
+                    })
+                }
+            },
+        };
+        let result = self.finish_result(result, params, recorder)?;
+        Ok(Outcome { result, rule_index })
+    }
+
+    /// The result of a matched rule, after interpolation and its transform script
+    fn rule_result<'a>(
+        &self,
+        rule: &'a Rule,
+        params: &dyn ParamSource,
+    ) -> Result<Cow<'a, RuleResult>, ConfigExprError> {
+        let result = if rule.interpolate {
+            Cow::Owned(match &rule.result {
+                RuleResult::String(s) => RuleResult::String(template::render(s, params)?),
+                RuleResult::Object(value) => {
+                    let mut value = value.clone();
+                    template::render_value(&mut value, params)?;
+                    RuleResult::Object(value)
+                }
+            })
+        } else {
+            Cow::Borrowed(&rule.result)
+        };
+        Ok(match &rule.transform {
+            Some(transform) => Cow::Owned(self.transform_result(transform, &result, params)?),
+            None => result,
+        })
+    }
+
+    /// Check assertions, run the post phase and compute values of a selected result
+    ///
+    /// The result is only copied when the post phase or computed values
+    /// change it.
+    fn finish_result<'a>(
+        &self,
+        result: Cow<'a, RuleResult>,
+        params: &dyn ParamSource,
+        recorder: &mut Recorder,
+    ) -> Result<Option<Cow<'a, RuleResult>>, ConfigExprError> {
+        Self::check_assertion(&result)?;
+        let Some(mut result) = self.apply_post_phase(result, params, recorder)? else {
+            return Ok(None);
+        };
+        if matches!(&*result, RuleResult::Object(value) if has_computed_value(value)) {
+            if let RuleResult::Object(value) = result.to_mut() {
+                self.compute_value(value, params)?;
+            }
+        }
+        Ok(Some(result))
+    }
+
+    /// Post-process a matched result with its transform script
+    #[cfg(feature = "scripting")]
+    fn transform_result(
+        &self,
+        transform: &str,
+        result: &RuleResult,
+        params: &dyn ParamSource,
+    ) -> Result<RuleResult, ConfigExprError> {
+        self.scripts.transform(transform, result, params)
+    }
+
+    #[cfg(not(feature = "scripting"))]
+    fn transform_result(
+        &self,
+        _transform: &str,
+        result: &RuleResult,
+        _params: &dyn ParamSource,
+    ) -> Result<RuleResult, ConfigExprError> {
+        // Rejected during validation without the `scripting` feature
+        Ok(result.clone())
+    }
+
+    /// Replace computed expression strings and choices inside a result value
+    fn compute_value(
+        &self,
+        value: &mut serde_json::Value,
+        params: &dyn ParamSource,
+    ) -> Result<(), ConfigExprError> {
+        if let Some(choice) = choose::Choice::of(value) {
+            *value = choice.pick(params, &*self.random).clone();
+            return self.compute_value(value, params);
+        }
+        if let Some(source) = expr_object(value) {
+            let number = Expr::parse(source)?.eval(params)?;
+            *value = expr::number_to_json(number)?;
+            return Ok(());
+        }
+        match value {
+            serde_json::Value::String(s) => match computed_expression(s) {
+                Some(Ok(source)) => {
+                    let number = Expr::parse(source)?.eval(params)?;
+                    *value = expr::number_to_json(number)?;
+                }
+                Some(Err(literal)) => *s = literal.to_string(),
+                None => {}
+            },
+            serde_json::Value::Array(items) => {
+                for item in items {
+                    self.compute_value(item, params)?;
+                }
+            }
+            serde_json::Value::Object(obj) => {
+                for item in obj.values_mut() {
+                    self.compute_value(item, params)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Fail with the assertion message if the result is an assertion
+    fn check_assertion(result: &RuleResult) -> Result<(), ConfigExprError> {
+        match result.assertion_message() {
+            Some(message) => Err(ConfigExprError::ValidationError(message.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    /// Run the pre phase, merging the results of all matching rules into the params
+    fn apply_pre_phase<'a>(
+        &'a self,
+        params: &'a dyn ParamSource,
+        recorder: &mut Recorder,
+    ) -> Result<Overlay<'a>, ConfigExprError> {
+        let mut params = Overlay::new(params, &self.param_keys);
+        let mut decided_stages = BTreeSet::new();
+        for (index, rule) in self.ordered_rules(Phase::Pre) {
+            let stage = rule.stage.as_deref();
+            if stage.is_some_and(|stage| decided_stages.contains(stage)) {
+                continue;
+            }
+            if !self.rule_matches_traced(recorder, Phase::Pre, index, rule, &params) {
+                continue;
+            }
+            if let Some(stage) = stage {
+                decided_stages.insert(stage);
+            }
+            Self::check_assertion(&rule.result)?;
+            if let RuleResult::Object(serde_json::Value::Object(updates)) = &rule.result {
+                for (key, value) in updates {
+                    let value = match value {
+                        serde_json::Value::String(s) => s.clone(),
+                        other => other.to_string(),
+                    };
+                    params.insert(key.clone(), value);
+                }
+            }
+        }
+        Ok(params)
+    }
+
+    /// Run the post phase, letting matching rules veto or annotate the result
+    fn apply_post_phase<'a>(
+        &self,
+        mut result: Cow<'a, RuleResult>,
+        params: &dyn ParamSource,
+        recorder: &mut Recorder,
+    ) -> Result<Option<Cow<'a, RuleResult>>, ConfigExprError> {
+        for (index, rule) in self.ordered_rules(Phase::Post) {
+            if !self.rule_matches_traced(recorder, Phase::Post, index, rule, params) {
+                continue;
+            }
+            Self::check_assertion(&rule.result)?;
+            let RuleResult::Object(serde_json::Value::Object(annotations)) = &rule.result else {
+                continue;
+            };
+            if annotations.get("veto") == Some(&serde_json::Value::Bool(true)) {
+                return Ok(None);
+            }
+            // Annotations only apply to object results, not computed values
+            let annotated = match &*result {
+                RuleResult::Object(value) => {
+                    value.is_object()
+                        && expr_object(value).is_none()
+                        && choose::Choice::of(value).is_none()
+                }
+                RuleResult::String(_) => false,
+            };
+            if annotated {
+                if let RuleResult::Object(serde_json::Value::Object(target)) = result.to_mut() {
+                    for (key, value) in annotations {
+                        target.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        Ok(Some(result))
+    }
+
+    /// Check whether a rule participates in evaluation and its condition
+    /// matches, recording the check in the metrics and `tracing` events
+    /// when `counted`, and in strict mode the first missing param read
+    /// unless `missing_field` already holds one
+    #[cfg_attr(not(feature = "std"), allow(unused_variables))]
+    fn rule_matches(
+        &self,
+        phase: Phase,
+        index: usize,
+        rule: &Rule,
+        params: &dyn ParamSource,
+        counted: bool,
+        missing_field: &mut Option<String>,
+    ) -> bool {
+        let skipped = self.skip_reason(rule, params);
+        if skipped.is_some() {
+            #[cfg(feature = "tracing")]
+            if counted {
+                self.trace_rule_check(phase, index, rule, params, skipped, false);
+            }
+            return false;
+        }
+        let mut visit = compile::Visit::default();
+        let matched = self.compiled_matches(self.compiled.rule(phase, index), params, &mut visit);
+        if missing_field.is_none() {
+            *missing_field = visit.missing_field;
+        }
+        #[cfg(feature = "std")]
+        if let Some(metrics) = self.metrics.as_ref().filter(|_| counted) {
+            metrics.record_conditions(visit.conditions);
+            if matched {
+                metrics.record_hit(phase, index);
+            }
+        }
+        #[cfg(feature = "tracing")]
+        if counted {
+            self.trace_rule_check(phase, index, rule, params, None, matched);
+        }
+        matched
+    }
+
+    fn record_evaluation(&self) {
+        #[cfg(feature = "std")]
+        if let Some(metrics) = &self.metrics {
+            metrics.record_evaluation();
+        }
+    }
+
+    /// Check whether a rule is left out of evaluation by its enabled flag,
+    /// activation window, rollout or schedule
+    fn skip_reason(&self, rule: &Rule, params: &dyn ParamSource) -> Option<SkipReason> {
+        if !rule.enabled {
+            return Some(SkipReason::Disabled);
+        }
+        if rule.active_from.is_some() || rule.active_until.is_some() {
+            let Some(now) = time::now(&*self.clock) else {
+                return Some(SkipReason::Window);
+            };
+            // Timestamps are checked during validation
+            let started = rule
+                .active_from
+                .as_deref()
+                .and_then(time::parse_rfc3339)
+                .is_none_or(|from| now >= from);
+            let ended = rule
+                .active_until
+                .as_deref()
+                .and_then(time::parse_rfc3339)
+                .is_some_and(|until| now >= until);
+            if !started || ended {
+                return Some(SkipReason::Window);
+            }
+        }
+        if let Some(rollout) = &rule.rollout {
+            let in_rollout = path::lookup(params, &rollout.bucket_by).is_some_and(|value| {
+                bucketing::in_percentage(&value, &rollout.salt, rollout.percent)
+            });
+            if !in_rollout {
+                return Some(SkipReason::Rollout);
+            }
+        }
+        if let Some(schedule) = &rule.schedule {
+            if !time::now(&*self.clock).is_some_and(|now| schedule.is_active_at(now)) {
+                return Some(SkipReason::Schedule);
+            }
+        }
+        None
+    }
+
+    /// Validate input params against the rule set's `params_spec`
+    ///
+    /// Params are matched to the spec's fields by the evaluator's
+    /// [`ParamKeys`], as in evaluation.
+    pub fn validate_params(&self, params: &impl ParamSource) -> Result<(), ConfigExprError> {
+        let params = keys::Keyed::new(params, &self.param_keys);
+        for (field, spec) in &self.rules.params_spec {
+            let Some(value) = params.get(field) else {
+                if spec.required {
+                    return Err(ConfigExprError::ValidationError(format!(
+                        "Missing required param '{}'",
+                        field
+                    )));
+                }
+                continue;
+            };
+            #[cfg(feature = "regex")]
+            if let Some(pattern) = &spec.pattern {
+                let matches = match self.compiled.param_pattern(field) {
+                    Some(regex) => regex.is_match(&value),
+                    None => self.limits.regex.compile(pattern)?.is_match(&value),
+                };
+                if !matches {
+                    return Err(ConfigExprError::ValidationError(format!(
+                        "Param '{}' value '{}' does not match pattern '{}'",
+                        field, value, pattern
+                    )));
+                }
+            }
+            if !spec.allowed.is_empty() && !spec.allowed.iter().any(|allowed| *allowed == value) {
+                return Err(ConfigExprError::ValidationError(format!(
+                    "Param '{}' value '{}' is not one of {:?}",
+                    field, value, spec.allowed
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// List the observed param fields that no rule ever references
+    ///
+    /// Useful to trim collected data and to spot fields that were renamed
+    /// upstream and silently disconnected from the rules.
+    pub fn unused_params<I, S>(&self, observed_fields: I) -> Vec<String>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let referenced = self.rules.referenced_fields();
+        observed_fields
+            .into_iter()
+            .filter(|field| !referenced.contains(field.as_ref()))
+            .map(|field| field.as_ref().to_string())
+            .collect()
+    }
+
+    /// Evaluate a single condition
+    fn evaluate_condition(&self, condition: &Condition, params: &dyn ParamSource) -> bool {
+        match condition {
+            Condition::Simple {
+                field,
+                op,
+                value,
+                value_field,
+                seed,
+                trim,
+                normalize,
+                mode,
+            } => {
+                let prepare = |value| text::prepare_param(value, *trim, *normalize);
+                // Set operators check the param's values against the list
+                let (op, mode) = match op {
+                    Operator::Intersects => (&Operator::In, Some(MultiValueMode::Any)),
+                    Operator::SubsetOf => (&Operator::In, Some(MultiValueMode::All)),
+                    op => (op, *mode),
+                };
+                let field_values = match mode {
+                    Some(_) => path::lookup_all(params, field),
+                    None => path::lookup(params, field).into_iter().collect(),
+                };
+                if field_values.is_empty() {
+                    return value_field.is_none() && self.absent_matches(op, value);
+                }
+                let value = match value_field {
+                    Some(value_field) => match path::lookup(params, value_field) {
+                        Some(other) => {
+                            Cow::Owned(serde_json::Value::String(prepare(other).into_owned()))
+                        }
+                        // Nothing to compare against
+                        None => return false,
+                    },
+                    None => text::prepare_condition_value(value, *normalize),
+                };
+                mode.unwrap_or(MultiValueMode::Any)
+                    .matches(field_values, |field_value| {
+                        self.evaluate_present_condition(
+                            &prepare(field_value),
+                            op,
+                            &value,
+                            seed,
+                            params,
+                        )
+                    })
+            }
+            Condition::And { and } => and.iter().all(|cond| self.evaluate_condition(cond, params)),
+            Condition::Or { or } => or.iter().any(|cond| self.evaluate_condition(cond, params)),
+            Condition::Not { not } => !self.evaluate_condition(not, params),
+            Condition::Ref { name } => self
+                .rules
+                .definitions
+                .get(name)
+                .is_some_and(|definition| self.evaluate_condition(definition, params)),
+        }
+    }
+
+    /// Check whether a simple condition matches a missing param
+    ///
+    /// Time-of-day and cron conditions compare the clock's time instead.
+    fn absent_matches(&self, op: &Operator, value: &serde_json::Value) -> bool {
+        if op.reads_clock() {
+            return time::now(&*self.clock).is_some_and(|now| time_matches(op, value, now));
+        }
+        // `missing` and `equals null` match a missing param
+        matches!(op, Operator::Missing) || (value.is_null() && matches!(op, Operator::Equals))
+    }
+
+    /// Evaluate a simple condition against the value of a present param
+    fn evaluate_present_condition(
+        &self,
+        field_value: &str,
+        op: &Operator,
+        value: &serde_json::Value,
+        seed: &str,
+        params: &dyn ParamSource,
+    ) -> bool {
+        match op {
+            Operator::Exists => return true,
+            Operator::Missing => return false,
+            Operator::Custom(custom) => {
+                return self
+                    .custom_operators
+                    .get(custom.name())
+                    .is_some_and(|predicate| predicate(field_value, value))
+            }
+            Operator::PercentLt => {
+                return percentage(value)
+                    .is_some_and(|percent| bucketing::in_percentage(field_value, seed, percent))
+            }
+            Operator::LangMatches => return locale::matches(locale::ranges(value), field_value),
+            Operator::TimeBetween | Operator::CronMatch => {
+                return self
+                    .parse_datetime(field_value)
+                    .is_some_and(|unix| time_matches(op, value, unix))
+            }
+            Operator::BucketIn => {
+                return bucket_range(value).is_some_and(|(buckets, from, to)| {
+                    (from..=to).contains(&bucketing::bucket(field_value, seed, buckets))
+                })
+            }
+            _ => {}
+        }
+
+        match value {
+            serde_json::Value::Array(items) => match op {
+                Operator::In => self.membership.contains(items, field_value),
+                Operator::NotIn => !self.membership.contains(items, field_value),
+                Operator::IpInCidr => cidr::matches(cidr::blocks(value), field_value),
+                Operator::Between => match numeric_range(value) {
+                    Some((min, max)) => {
+                        Number::parse(field_value).is_some_and(|n| min <= n && n <= max)
+                    }
+                    None => match (
+                        self.parse_datetime(field_value),
+                        items.first().and_then(|v| v.as_str()),
+                        items.get(1).and_then(|v| v.as_str()),
+                    ) {
+                        (Some(at), Some(start), Some(end)) => {
+                            self.parse_datetime(start).is_some_and(|start| at >= start)
+                                && self.parse_datetime(end).is_some_and(|end| at < end)
+                        }
+                        _ => false,
+                    },
+                },
+                _ => false,
+            },
+            serde_json::Value::String(value) => {
+                self.evaluate_string_condition(field_value, op, value, params)
+            }
+            serde_json::Value::Number(number) => {
+                let number = number.to_string();
+                match op {
+                    Operator::Equals => {
+                        self.compare_numbers(field_value, &number, params, |a, b| a == b)
+                    }
+                    _ => self.evaluate_string_condition(field_value, op, &number, params),
+                }
+            }
+            serde_json::Value::Bool(expected) => {
+                matches!(op, Operator::Equals) && field_value.parse::<bool>() == Ok(*expected)
+            }
+            _ => false,
+        }
+    }
+
+    /// Evaluate a simple condition whose value is a string
+    #[cfg_attr(not(feature = "scripting"), allow(unused_variables))]
+    fn evaluate_string_condition(
+        &self,
+        field_value: &str,
+        op: &Operator,
+        value: &str,
+        params: &dyn ParamSource,
+    ) -> bool {
+        match op {
+            Operator::Equals => field_value == value,
+            Operator::Contains => field_value.contains(value),
+            Operator::Prefix => field_value.starts_with(value),
+            Operator::Suffix => field_value.ends_with(value),
+            #[cfg(feature = "regex")]
+            Operator::Regex => {
+                match self.limits.regex.compile(value) {
+                    Ok(regex) => regex.is_match(field_value),
+                    Err(_) => false, // Return false if regex is invalid
+                }
+            }
+            #[cfg(feature = "regex")]
+            Operator::Glob => glob(value).is_ok_and(|glob| glob.is_match(field_value)),
+            #[cfg(not(feature = "regex"))]
+            Operator::Regex | Operator::Glob => false,
+            Operator::GreaterThan => self.compare_numbers(field_value, value, params, |a, b| a > b),
+            Operator::LessThan => self.compare_numbers(field_value, value, params, |a, b| a < b),
+            Operator::GreaterThanOrEqual => {
+                self.compare_numbers(field_value, value, params, |a, b| a >= b)
+            }
+            Operator::LessThanOrEqual => {
+                self.compare_numbers(field_value, value, params, |a, b| a <= b)
+            }
+            #[cfg(feature = "scripting")]
+            Operator::Script => self
+                .scripts
+                .check_condition(value, field_value, params)
+                .unwrap_or(false),
+            #[cfg(not(feature = "scripting"))]
+            Operator::Script => false,
+            Operator::IEquals => field_value.to_lowercase() == value.to_lowercase(),
+            Operator::IContains => field_value.to_lowercase().contains(&value.to_lowercase()),
+            Operator::IPrefix => field_value
+                .to_lowercase()
+                .starts_with(&value.to_lowercase()),
+            Operator::ISuffix => field_value.to_lowercase().ends_with(&value.to_lowercase()),
+            Operator::SemverEq
+            | Operator::SemverGt
+            | Operator::SemverGe
+            | Operator::SemverLt
+            | Operator::SemverLe => version::satisfies(op, field_value, value),
+            Operator::IpInCidr => cidr::matches([value], field_value),
+            Operator::Before => self.compare_datetimes(field_value, value, |a, b| a < b),
+            Operator::After => self.compare_datetimes(field_value, value, |a, b| a > b),
+            Operator::LenGt | Operator::LenLt | Operator::LenEq => {
+                value.trim().parse::<usize>().is_ok_and(|len| {
+                    let actual = field_value.chars().count();
+                    match op {
+                        Operator::LenGt => actual > len,
+                        Operator::LenLt => actual < len,
+                        _ => actual == len,
+                    }
+                })
+            }
+            // Handled before comparing string values
+            Operator::In
+            | Operator::NotIn
+            | Operator::Intersects
+            | Operator::SubsetOf
+            | Operator::Exists
+            | Operator::Missing
+            | Operator::PercentLt
+            | Operator::BucketIn
+            | Operator::LangMatches
+            | Operator::TimeBetween
+            | Operator::CronMatch
+            | Operator::Between
+            | Operator::Custom(_) => false,
+        }
+    }
+
+    /// Compare two strings as numbers
+    ///
+    /// A target containing `${field}` placeholders is an arithmetic
+    /// expression evaluated against the params. Integers compare exactly.
+    fn compare_numbers<F>(
+        &self,
+        field_value: &str,
+        target_value: &str,
+        params: &dyn ParamSource,
+        compare_fn: F,
+    ) -> bool
+    where
+        F: Fn(Number, Number) -> bool,
+    {
+        let target = if expr::has_placeholders(target_value) {
+            Expr::parse(target_value)
+                .and_then(|expr| expr.eval_number(params))
+                .ok()
+        } else {
+            Number::parse(target_value)
+        };
+        match (Number::parse(field_value), target) {
+            (Some(field_num), Some(target_num)) => compare_fn(field_num, target_num),
+            _ => false, // Return false if any value cannot be parsed as a number
+        }
+    }
+
+    /// Parse a datetime in the rule set's `datetime_format`
+    fn parse_datetime(&self, s: &str) -> Option<i64> {
+        time::parse_datetime(s, self.rules.datetime_format.as_deref())
+    }
+
+    /// Compare two strings as datetimes
+    fn compare_datetimes<F>(&self, field_value: &str, target_value: &str, compare_fn: F) -> bool
+    where
+        F: Fn(i64, i64) -> bool,
+    {
+        match (
+            self.parse_datetime(field_value),
+            self.parse_datetime(target_value),
+        ) {
+            (Some(field_time), Some(target_time)) => compare_fn(field_time, target_time),
+            _ => false,
+        }
+    }
+
+    /// Substitute the vars of a rule set and validate it
+    fn load_rules(mut rules: ConfigRules) -> Result<ConfigRules, ConfigExprError> {
+        rules.expand_tables()?;
+        rules.substitute_vars()?;
+        Self::validate_rules(&rules, &EvaluatorLimits::default())?;
+        Ok(rules)
+    }
+
+    /// Validate if the rule set is valid
+    fn validate_rules(
+        rules: &ConfigRules,
+        limits: &EvaluatorLimits,
+    ) -> Result<(), ConfigExprError> {
+        limits.check(rules)?;
+        if !rules.include.is_empty() {
+            return Err(ConfigExprError::ValidationError(format!(
+                "Unresolved include of {}; load the rules with ConfigRules::resolve_includes",
+                rules.include.join(", ")
+            )));
+        }
+
+        // if rules.rules.is_empty() {
+        //     return Err(ConfigExprError::ValidationError(
+        //         "Rules cannot be empty".to_string(),
+        //     ));
+        // }
+
+        for (index, rule) in rules.pre.iter().enumerate() {
+            let site = Site::rule(Phase::Pre, index);
+            Self::validate_rule(rule, &site)?;
+            Self::validate_phase_result(&rule.result, &site)?;
+        }
+
+        if !rules.merge_strategy.is_first() && rules.match_mode == MatchMode::All {
+            return Err(ConfigExprError::ValidationError(
+                "merge_strategy cannot be combined with match_mode 'all'".to_string(),
+            ));
+        }
+
+        for (index, rule) in rules.rules.iter().enumerate() {
+            let site = Site::rule(Phase::Main, index);
+            Self::validate_rule(rule, &site)?;
+            Self::validate_no_stage(rule, &site)?;
+            if !rules.merge_strategy.is_first() {
+                Self::validate_phase_result(&rule.result, &site)?;
+            }
+            Self::validate_result(&rule.result, &site.at("then"))?;
+        }
+
+        for (index, rule) in rules.post.iter().enumerate() {
+            let site = Site::rule(Phase::Post, index);
+            Self::validate_rule(rule, &site)?;
+            Self::validate_no_stage(rule, &site)?;
+            Self::validate_phase_result(&rule.result, &site)?;
+            Self::validate_result(&rule.result, &site.at("then"))?;
+        }
+
+        if let Some(fallback) = &rules.fallback {
+            Self::validate_result(fallback, &Site::fallback())?;
+        }
+
+        Self::validate_dependencies(rules)?;
+        Self::validate_definitions(rules)?;
+        Self::validate_datetimes(rules)?;
+
+        for (field, spec) in &rules.params_spec {
+            if let Some(pattern) = &spec.pattern {
+                #[cfg(feature = "regex")]
+                limits.regex.compile(pattern).map_err(|e| {
+                    ConfigExprError::ValidationError(format!(
+                        "Invalid pattern '{}' for param '{}': {}",
+                        pattern, field, e
+                    ))
+                })?;
+                #[cfg(not(feature = "regex"))]
+                return Err(ConfigExprError::ValidationError(format!(
+                    "Pattern '{}' for param '{}' requires the `regex` feature",
+                    pattern, field
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Validate rule ids and the `requires` / `conflicts_with` declarations
+    fn validate_dependencies(rules: &ConfigRules) -> Result<(), ConfigExprError> {
+        let all_rules = || rules.pre.iter().chain(&rules.rules).chain(&rules.post);
+
+        let mut by_id: HashMap<&str, &Rule> = HashMap::new();
+        for rule in all_rules() {
+            let Some(id) = rule.id.as_deref() else {
+                continue;
+            };
+            if id.is_empty() {
+                return Err(ConfigExprError::ValidationError(
+                    "Rule id cannot be empty".to_string(),
+                ));
+            }
+            if by_id.insert(id, rule).is_some() {
+                return Err(ConfigExprError::ValidationError(format!(
+                    "Duplicate rule id '{}'",
+                    id
+                )));
+            }
+        }
+
+        for rule in all_rules() {
+            let name = rule.id.as_deref().unwrap_or("<unnamed>");
+            if let Some(missing) = rule
+                .requires
+                .iter()
+                .find(|dep| !by_id.contains_key(dep.as_str()))
+            {
+                return Err(ConfigExprError::ValidationError(format!(
+                    "Rule '{}' requires missing rule '{}'",
+                    name, missing
+                )));
+            }
+            // A conflict only matters while both rules are enabled
+            if let Some(conflict) = rule.conflicts_with.iter().find(|other| {
+                rule.enabled && by_id.get(other.as_str()).is_some_and(|other| other.enabled)
+            }) {
+                return Err(ConfigExprError::ValidationError(format!(
+                    "Rule '{}' conflicts with rule '{}'",
+                    name, conflict
+                )));
+            }
+        }
+
+        // Depth-first search for cycles in the `requires` graph
+        fn visit<'a>(
+            id: &'a str,
+            by_id: &HashMap<&'a str, &'a Rule>,
+            visiting: &mut Vec<&'a str>,
+            done: &mut HashSet<&'a str>,
+        ) -> Result<(), ConfigExprError> {
+            if done.contains(id) {
+                return Ok(());
+            }
+            if let Some(start) = visiting.iter().position(|v| *v == id) {
+                let mut cycle = visiting[start..].to_vec();
+                cycle.push(id);
+                return Err(ConfigExprError::ValidationError(format!(
+                    "Rule dependency cycle: {}",
+                    cycle.join(" -> ")
+                )));
+            }
+            visiting.push(id);
+            for dep in &by_id[id].requires {
+                visit(dep, by_id, visiting, done)?;
+            }
+            visiting.pop();
+            done.insert(id);
+            Ok(())
+        }
+
+        let mut done = HashSet::new();
+        for id in by_id.keys() {
+            visit(id, &by_id, &mut Vec::new(), &mut done)?;
+        }
+
+        Ok(())
+    }
+
+    /// Validate `datetime_format` and the datetimes of `before` / `after` /
+    /// `between` conditions
+    fn validate_datetimes(rules: &ConfigRules) -> Result<(), ConfigExprError> {
+        fn visit(
+            condition: &Condition,
+            format: Option<&str>,
+            site: &Site,
+        ) -> Result<(), ConfigExprError> {
+            match condition {
+                Condition::Simple {
+                    field,
+                    op,
+                    value,
+                    value_field: None,
+                    ..
+                } if op.is_datetime() && numeric_range(value).is_none() => {
+                    let values: Vec<&serde_json::Value> = match value {
+                        serde_json::Value::Array(items) => items.iter().collect(),
+                        other => vec![other],
+                    };
+                    let mut times = Vec::new();
+                    for (index, item) in values.into_iter().enumerate() {
+                        let time = item
+                            .as_str()
+                            .and_then(|s| time::parse_datetime(s, format))
+                            .ok_or_else(|| {
+                                let item_site = match value {
+                                    serde_json::Value::Array(_) => site.at("value").at(index),
+                                    _ => site.at("value"),
+                                };
+                                item_site
+                                    .error(
+                                        "invalid_datetime",
+                                        format!("Invalid datetime {} in {}", item, site),
+                                    )
+                                    .field(field)
+                                    .value(item.clone())
+                            })?;
+                        times.push(time);
+                    }
+                    if *op == Operator::Between && !(times.len() == 2 && times[0] < times[1]) {
+                        return Err(site
+                            .at("value")
+                            .error(
+                                "invalid_range",
+                                format!(
+                                    "'between' takes a start and a later end datetime in {}",
+                                    site
+                                ),
+                            )
+                            .field(field)
+                            .value(value.clone())
+                            .into());
+                    }
+                    Ok(())
+                }
+                Condition::Simple { .. } => Ok(()),
+                Condition::And { and } => and
+                    .iter()
+                    .enumerate()
+                    .try_for_each(|(i, c)| visit(c, format, &site.at("and").at(i))),
+                Condition::Or { or } => or
+                    .iter()
+                    .enumerate()
+                    .try_for_each(|(i, c)| visit(c, format, &site.at("or").at(i))),
+                Condition::Not { not } => visit(not, format, &site.at("not")),
+                Condition::Ref { .. } => Ok(()),
+            }
+        }
+
+        let format = rules.datetime_format.as_deref();
+        if let Some(format) = format {
+            time::validate_format(format).map_err(|e| {
+                ConfigExprError::ValidationError(format!(
+                    "Invalid datetime_format '{}': {}",
+                    format, e
+                ))
+            })?;
+        }
+        let phases = [
+            (Phase::Pre, &rules.pre),
+            (Phase::Main, &rules.rules),
+            (Phase::Post, &rules.post),
+        ];
+        for (phase, phase_rules) in phases {
+            for (index, rule) in phase_rules.iter().enumerate() {
+                visit(&rule.condition, format, &Site::rule(phase, index).at("if"))?;
+            }
+        }
+        for (name, condition) in &rules.definitions {
+            visit(condition, format, &Site::definition(name))?;
+        }
+        Ok(())
+    }
+
+    /// Validate the computed expressions inside a result
+    fn validate_result(result: &RuleResult, site: &Site) -> Result<(), ConfigExprError> {
+        fn visit(value: &serde_json::Value, site: &Site) -> Result<(), ConfigExprError> {
+            if value.get("$choose").is_some() {
+                choose::validate(value, site)?;
+                let choice = choose::Choice::of(value).into_iter();
+                return choice
+                    .flat_map(|choice| choice.values())
+                    .enumerate()
+                    .try_for_each(|(i, item)| visit(item, &site.at("$choose").at(i).at("value")));
+            }
+            let expression = match value {
+                serde_json::Value::String(s) => computed_expression(s)
+                    .and_then(Result::ok)
+                    .map(|source| (source, site.clone())),
+                _ => expr_object(value).map(|source| (source, site.at("$expr"))),
+            };
+            if let Some((source, site)) = expression {
+                Expr::parse(source).map_err(|e| {
+                    site.error(
+                        "invalid_expression",
+                        format!("Invalid expression '{}' in {}: {}", source, site, e),
+                    )
+                    .value(source)
+                })?;
+                return Ok(());
+            }
+            match value {
+                serde_json::Value::String(_) => Ok(()),
+                serde_json::Value::Array(items) => items
+                    .iter()
+                    .enumerate()
+                    .try_for_each(|(i, item)| visit(item, &site.at(i))),
+                serde_json::Value::Object(obj) => obj
+                    .iter()
+                    .try_for_each(|(key, item)| visit(item, &site.at(key))),
+                _ => Ok(()),
+            }
+        }
+
+        match result {
+            RuleResult::Object(value) => visit(value, site),
+            RuleResult::String(_) => Ok(()),
+        }
+    }
+
+    /// Validate a single rule's condition and activation settings
+    fn validate_rule(rule: &Rule, site: &Site) -> Result<(), ConfigExprError> {
+        Self::validate_condition(&rule.condition, &site.at("if"))?;
+
+        if let Some(transform) = &rule.transform {
+            Self::validate_script(transform, &site.at("transform"))?;
+        }
+
+        if rule.interpolate {
+            for source in template::templates(&rule.result) {
+                template::validate(source).map_err(|e| {
+                    site.at("then")
+                        .error(
+                            "invalid_template",
+                            format!("Invalid template '{}' in {}: {}", source, site, e),
+                        )
+                        .value(source)
+                })?;
+            }
+        }
+
+        if let Some(rollout) = &rule.rollout {
+            let rollout_site = site.at("rollout");
+            if !(0.0..=100.0).contains(&rollout.percent) {
+                return Err(rollout_site
+                    .at("percent")
+                    .error(
+                        "invalid_percentage",
+                        format!("Rollout percent must be between 0 and 100 in {}", site),
+                    )
+                    .value(rollout.percent)
+                    .into());
+            }
+            if rollout.bucket_by.is_empty() {
+                return Err(rollout_site
+                    .at("bucket_by")
+                    .error(
+                        "empty_field",
+                        format!("Rollout bucket_by cannot be empty in {}", site),
+                    )
+                    .into());
+            }
+            Self::validate_field_path(&rollout.bucket_by, &rollout_site.at("bucket_by"))?;
+        }
+
+        let parse_timestamp = |timestamp: &Option<String>, name: &str| {
+            timestamp
+                .as_deref()
+                .map(|value| {
+                    time::parse_rfc3339(value).ok_or_else(|| {
+                        ConfigExprError::from(
+                            site.at(name)
+                                .error(
+                                    "invalid_timestamp",
+                                    format!("Invalid {} timestamp '{}' in {}", name, value, site),
+                                )
+                                .value(value),
+                        )
+                    })
+                })
+                .transpose()
+        };
+        let from = parse_timestamp(&rule.active_from, "active_from")?;
+        let until = parse_timestamp(&rule.active_until, "active_until")?;
+        if let (Some(from), Some(until)) = (from, until) {
+            if from >= until {
+                return Err(site
+                    .at("active_until")
+                    .error(
+                        "invalid_window",
+                        format!("active_from must be before active_until in {}", site),
+                    )
+                    .into());
+            }
+        }
+
+        if let Some(schedule) = &rule.schedule {
+            schedule.validate().map_err(|e| {
+                site.at("schedule").error(
+                    "invalid_schedule",
+                    format!("Invalid schedule in {}: {}", site, e),
+                )
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Validate that a script compiles (and that scripting is enabled)
+    fn validate_script(source: &str, site: &Site) -> Result<(), ConfigExprError> {
+        #[cfg(feature = "scripting")]
+        {
+            scripting::ScriptEngine::default()
+                .compile(source)
+                .map(|_| ())
+                .map_err(|e| {
+                    site.error(
+                        "invalid_script",
+                        format!("Invalid script in {}: {}", site, e),
+                    )
+                    .value(source)
+                    .into()
+                })
+        }
+        #[cfg(not(feature = "scripting"))]
+        {
+            Err(site
+                .error(
+                    "scripting_disabled",
+                    format!("Scripts in {} require the `scripting` feature", site),
+                )
+                .value(source)
+                .into())
+        }
+    }
+
+    /// Validate that a field is a plain param name or a well-formed path
+    fn validate_field_path(field: &str, site: &Site) -> Result<(), ConfigExprError> {
+        path::validate(field).map_err(|e| {
+            site.error(
+                "invalid_field_path",
+                format!("Invalid field path '{}' in {}: {}", field, site, e),
+            )
+            .field(field)
+            .into()
+        })
+    }
+
+    /// Validate that a pre/post phase rule returns a JSON object
+    fn validate_phase_result(result: &RuleResult, site: &Site) -> Result<(), ConfigExprError> {
+        match result {
+            RuleResult::Object(serde_json::Value::Object(_)) => Ok(()),
+            _ => Err(site
+                .at("then")
+                .error(
+                    "invalid_result",
+                    format!("Result of {} must be a JSON object", site),
+                )
+                .into()),
+        }
+    }
+
+    /// Validate that a main or post phase rule has no pipeline stage
+    fn validate_no_stage(rule: &Rule, site: &Site) -> Result<(), ConfigExprError> {
+        match &rule.stage {
+            None => Ok(()),
+            Some(stage) => Err(site
+                .at("stage")
+                .error(
+                    "unsupported_stage",
+                    format!(
+                        "Stage '{}' of {} only applies to pre-phase rules",
+                        stage, site
+                    ),
+                )
+                .value(stage.as_str())
+                .into()),
+        }
+    }
+
+    /// Validate the arithmetic expression of a numeric comparison
+    fn validate_threshold(field: &str, value: &str, site: &Site) -> Result<(), ConfigExprError> {
+        let expr = Expr::parse(value).map_err(|e| {
+            site.at("value")
+                .error(
+                    "invalid_expression",
+                    format!("Invalid expression '{}' in {}: {}", value, site, e),
+                )
+                .field(field)
+                .value(value)
+        })?;
+        let mut fields = Vec::new();
+        expr.fields(&mut fields);
+        fields
+            .into_iter()
+            .try_for_each(|field| Self::validate_field_path(field, &site.at("value")))
+    }
+
+    /// Validate a condition comparing a param against another param
+    fn validate_value_field(
+        field: &str,
+        op: &Operator,
+        value: &serde_json::Value,
+        value_field: &str,
+        seed: &str,
+        site: &Site,
+    ) -> Result<(), ConfigExprError> {
+        if !value.is_null() {
+            return Err(site
+                .at("value")
+                .error(
+                    "conflicting_value",
+                    format!(
+                        "Condition on '{}' must have exactly one of 'value' and 'value_field' in {}",
+                        field, site
+                    ),
+                )
+                .field(field)
+                .value(value.clone())
+                .into());
+        }
+        if value_field.is_empty() {
+            return Err(site
+                .at("value_field")
+                .error(
+                    "empty_field",
+                    format!("Field name cannot be empty in {}", site),
+                )
+                .into());
+        }
+        Self::validate_field_path(value_field, &site.at("value_field"))?;
+        // Operators taking lists, percentages, buckets, scripts or no value at all
+        if matches!(
+            op,
+            Operator::In
+                | Operator::NotIn
+                | Operator::Intersects
+                | Operator::SubsetOf
+                | Operator::Between
+                | Operator::Exists
+                | Operator::Missing
+                | Operator::PercentLt
+                | Operator::BucketIn
+                | Operator::TimeBetween
+                | Operator::CronMatch
+                | Operator::Script
+        ) {
+            return Err(site
+                .at("value_field")
+                .error(
+                    "unsupported_value",
+                    format!(
+                        "Operator '{}' does not support 'value_field' in {}",
+                        op.name(),
+                        site
+                    ),
+                )
+                .field(field)
+                .value(value_field)
+                .into());
+        }
+        if !seed.is_empty() {
+            return Err(site
+                .at("seed")
+                .error(
+                    "unsupported_seed",
+                    format!(
+                        "Seed only applies to 'percent_lt' and 'bucket_in' in {}",
+                        site
+                    ),
+                )
+                .field(field)
+                .value(seed)
+                .into());
+        }
+        Ok(())
+    }
+
+    /// Validate if the condition is valid
+    fn validate_condition(condition: &Condition, site: &Site) -> Result<(), ConfigExprError> {
+        match condition {
+            Condition::Simple {
+                field,
+                op,
+                value,
+                value_field,
+                seed,
+                mode,
+                ..
+            } => {
+                if field.is_empty() {
+                    return Err(site
+                        .at("field")
+                        .error(
+                            "empty_field",
+                            format!("Field name cannot be empty in {}", site),
+                        )
+                        .into());
+                }
+                Self::validate_field_path(field, &site.at("field"))?;
+
+                if !op.is_valid() {
+                    return Err(ConfigExprError::InvalidOperator(format!("{:?}", op)));
+                }
+                if mode.is_some() && op.is_set() {
+                    return Err(site
+                        .at("mode")
+                        .error(
+                            "unsupported_mode",
+                            format!(
+                                "Operator '{}' compares every value already, so 'mode' does not apply in {}",
+                                op.name(),
+                                site
+                            ),
+                        )
+                        .field(field)
+                        .into());
+                }
+
+                if let Some(value_field) = value_field {
+                    return Self::validate_value_field(field, op, value, value_field, seed, site);
+                }
+
+                let invalid = |code: &'static str, message: String| {
+                    site.at("value")
+                        .error(code, message)
+                        .field(field)
+                        .value(value.clone())
+                };
+                if !op.supports(value) {
+                    return Err(invalid(
+                        "unsupported_value",
+                        format!(
+                            "Operator '{}' does not support {} values in {}",
+                            op.name(),
+                            json_type(value),
+                            site
+                        ),
+                    )
+                    .into());
+                }
+                if *op == Operator::IpInCidr {
+                    if value.as_array().is_some_and(|items| {
+                        items.is_empty() || items.iter().any(|item| !item.is_string())
+                    }) {
+                        return Err(invalid(
+                            "invalid_list",
+                            format!(
+                                "'ip_in_cidr' lists must be non-empty lists of strings in {}",
+                                site
+                            ),
+                        )
+                        .into());
+                    }
+                    for block in cidr::blocks(value) {
+                        cidr::Cidr::parse(block).map_err(|e| {
+                            invalid(
+                                "invalid_cidr",
+                                format!("Invalid CIDR block '{}' in {}: {}", block, site, e),
+                            )
+                            .value(block)
+                        })?;
+                    }
+                    return Ok(());
+                }
+                if *op == Operator::LangMatches {
+                    if value.as_array().is_some_and(|items| {
+                        items.is_empty() || items.iter().any(|item| !item.is_string())
+                    }) {
+                        return Err(invalid(
+                            "invalid_list",
+                            format!(
+                                "'lang_matches' lists must be non-empty lists of strings in {}",
+                                site
+                            ),
+                        )
+                        .into());
+                    }
+                    if let Some(range) = locale::ranges(value)
+                        .into_iter()
+                        .find(|range| !locale::is_valid_range(range))
+                    {
+                        return Err(invalid(
+                            "invalid_language_range",
+                            format!("Invalid language range '{}' in {}", range, site),
+                        )
+                        .value(range)
+                        .into());
+                    }
+                    return Ok(());
+                }
+                if let serde_json::Value::Array(items) = value {
+                    if items
+                        .iter()
+                        .any(|item| !(item.is_string() || item.is_number() || item.is_boolean()))
+                    {
+                        return Err(invalid(
+                            "invalid_list",
+                            format!(
+                                "Items of '{}' lists must be strings, numbers or bools in {}",
+                                op.name(),
+                                site
+                            ),
+                        )
+                        .into());
+                    }
+                }
+                if !seed.is_empty() && !matches!(op, Operator::PercentLt | Operator::BucketIn) {
+                    return Err(site
+                        .at("seed")
+                        .error(
+                            "unsupported_seed",
+                            format!(
+                                "Seed only applies to 'percent_lt' and 'bucket_in' in {}",
+                                site
+                            ),
+                        )
+                        .field(field)
+                        .value(seed.as_str())
+                        .into());
+                }
+                if *op == Operator::PercentLt
+                    && !percentage(value).is_some_and(|p| (0.0..=100.0).contains(&p))
+                {
+                    return Err(invalid(
+                        "invalid_percentage",
+                        format!("Percentage must be a number between 0 and 100 in {}", site),
+                    )
+                    .into());
+                }
+                if op.reads_clock() {
+                    let schedule = time_schedule(op, value).ok_or_else(|| {
+                        invalid(
+                            "invalid_schedule",
+                            format!(
+                                "'{}' takes {} with an optional UTC offset as the last item in {}",
+                                op.name(),
+                                match op {
+                                    Operator::TimeBetween => "[\"HH:MM\", \"HH:MM\"]",
+                                    _ => "a cron expression",
+                                },
+                                site
+                            ),
+                        )
+                    })?;
+                    schedule.validate().map_err(|e| {
+                        invalid(
+                            "invalid_schedule",
+                            format!("Invalid '{}' value in {}: {}", op.name(), site, e),
+                        )
+                    })?;
+                }
+                if *op == Operator::BucketIn && bucket_range(value).is_none() {
+                    return Err(invalid(
+                        "invalid_bucket_range",
+                        format!(
+                            "Bucket range must be {{ \"buckets\": n, \"from\": a, \"to\": b }} with a <= b < n in {}",
+                            site
+                        ),
+                    )
+                    .into());
+                }
+                if op.is_length() && length(value).is_none() {
+                    return Err(invalid(
+                        "invalid_length",
+                        format!("Length must be a non-negative integer in {}", site),
+                    )
+                    .into());
+                }
+                if *op == Operator::Between {
+                    if let Some((min, max)) = numeric_range(value) {
+                        if min > max {
+                            return Err(invalid(
+                                "invalid_range",
+                                format!(
+                                    "'between' takes a minimum and a maximum no lower than it in {}",
+                                    site
+                                ),
+                            )
+                            .into());
+                        }
+                    }
+                }
+                let Some(value) = value.as_str() else {
+                    return Ok(());
+                };
+
+                // 验证正则表达式
+                #[cfg(feature = "regex")]
+                // Sizes are checked against the evaluator's RegexConfig
+                if matches!(op, Operator::Regex) {
+                    match Regex::new(value) {
+                        Ok(_) | Err(regex::Error::CompiledTooBig(_)) => {}
+                        Err(e) => {
+                            return Err(invalid(
+                                "invalid_regex",
+                                format!("Invalid regex '{}' in {}: {}", value, site, e),
+                            )
+                            .into())
+                        }
+                    }
+                }
+
+                #[cfg(feature = "regex")]
+                if matches!(op, Operator::Glob) {
+                    glob(value).map_err(|e| {
+                        invalid(
+                            "invalid_glob",
+                            format!("Invalid glob '{}' in {}: {}", value, site, e),
+                        )
+                    })?;
+                }
+
+                #[cfg(not(feature = "regex"))]
+                if matches!(op, Operator::Regex | Operator::Glob) {
+                    return Err(site
+                        .at("op")
+                        .error(
+                            "regex_disabled",
+                            format!(
+                                "'{}' conditions in {} require the `regex` feature",
+                                op.name(),
+                                site
+                            ),
+                        )
+                        .field(field)
+                        .into());
+                }
+
+                if matches!(op, Operator::Script) {
+                    Self::validate_script(value, &site.at("value"))?;
+                }
+
+                if op.is_numeric() && expr::has_placeholders(value) {
+                    Self::validate_threshold(field, value, site)?;
+                }
+
+                if op.is_semver() && version::parse(value).is_none() {
+                    return Err(invalid(
+                        "invalid_version",
+                        format!("Invalid version '{}' in {}", value, site),
+                    )
+                    .into());
+                }
+            }
+            Condition::And { and } => {
+                if and.is_empty() {
+                    return Err(site
+                        .at("and")
+                        .error(
+                            "empty_condition",
+                            format!("AND condition cannot be empty in {}", site),
+                        )
+                        .into());
+                }
+                for (i, cond) in and.iter().enumerate() {
+                    Self::validate_condition(cond, &site.at("and").at(i))?;
+                }
+            }
+            Condition::Or { or } => {
+                if or.is_empty() {
+                    return Err(site
+                        .at("or")
+                        .error(
+                            "empty_condition",
+                            format!("OR condition cannot be empty in {}", site),
+                        )
+                        .into());
+                }
+                for (i, cond) in or.iter().enumerate() {
+                    Self::validate_condition(cond, &site.at("or").at(i))?;
+                }
+            }
+            Condition::Not { not } => Self::validate_condition(not, &site.at("not"))?,
+            // Checked by `validate_definitions`, which sees the definitions
+            Condition::Ref { .. } => {}
+        }
+        Ok(())
+    }
+}
+
+/// Convert a JSON object into string params
+fn params_from_value(value: serde_json::Value) -> Result<HashMap<String, String>, ConfigExprError> {
+    let serde_json::Value::Object(fields) = value else {
+        return Err(ConfigExprError::ValidationError(format!(
+            "Params must be a JSON object, got {}",
+            json_type(&value)
+        )));
+    };
+    Ok(fields
+        .into_iter()
+        .filter_map(|(key, value)| match value {
+            serde_json::Value::Null => None,
+            serde_json::Value::String(s) => Some((key, s)),
+            other => Some((key, other.to_string())),
+        })
+        .collect())
+}
+
+/// Compile a `glob` condition value
+#[cfg(feature = "regex")]
+fn glob(pattern: &str) -> Result<globset::GlobMatcher, globset::Error> {
+    globset::Glob::new(pattern).map(|glob| glob.compile_matcher())
+}
+
+/// Read a `percent_lt` condition value, given as a number or numeric string
+fn percentage(value: &serde_json::Value) -> Option<f64> {
+    match value {
+        serde_json::Value::Number(n) => n.as_f64(),
+        serde_json::Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// Read a `time_between` or `cron_match` condition value as a schedule
+///
+/// Values are `["HH:MM", "HH:MM"]` and a cron expression, the latter also
+/// as a one-item list; a further list item is the UTC offset of the times.
+fn time_schedule(op: &Operator, value: &serde_json::Value) -> Option<Schedule> {
+    let items: Vec<&str> = match value {
+        serde_json::Value::String(s) => vec![s],
+        serde_json::Value::Array(items) => items
+            .iter()
+            .map(serde_json::Value::as_str)
+            .collect::<Option<_>>()?,
+        _ => return None,
+    };
+    let mut schedule = Schedule {
+        cron: None,
+        days: Vec::new(),
+        hours: None,
+        timezone: None,
+    };
+    let timezone = match (op, items.as_slice()) {
+        (Operator::TimeBetween, [from, to, timezone @ ..]) => {
+            schedule.hours = Some(format!("{}-{}", from, to));
+            timezone
+        }
+        (Operator::CronMatch, [cron, timezone @ ..]) => {
+            schedule.cron = Some(cron.to_string());
+            timezone
+        }
+        _ => return None,
+    };
+    match timezone {
+        [] => {}
+        [timezone] => schedule.timezone = Some(timezone.to_string()),
+        _ => return None,
+    }
+    Some(schedule)
+}
+
+/// Check whether a time matches a `time_between` or `cron_match` condition
+fn time_matches(op: &Operator, value: &serde_json::Value, unix: i64) -> bool {
+    time_schedule(op, value).is_some_and(|schedule| schedule.is_active_at(unix))
+}
+
+/// Read a `bucket_in` condition value: the number of buckets and the
+/// inclusive range of buckets within it
+fn bucket_range(value: &serde_json::Value) -> Option<(u32, u32, u32)> {
+    let read = |key| value.get(key)?.as_u64().and_then(|n| u32::try_from(n).ok());
+    let (buckets, from, to) = (read("buckets")?, read("from")?, read("to")?);
+    (from <= to && to < buckets).then_some((buckets, from, to))
+}
+
+/// Read the bounds of a numeric `between` condition, given as two numbers
+fn numeric_range(value: &serde_json::Value) -> Option<(Number, Number)> {
+    match value.as_array()?.as_slice() {
+        [min, max] => Some((
+            Number::from_json(min.as_number()?)?,
+            Number::from_json(max.as_number()?)?,
+        )),
+        _ => None,
+    }
+}
+
+/// Read a length condition value, given as a non-negative integer or
+/// integer string
+fn length(value: &serde_json::Value) -> Option<usize> {
+    match value {
+        serde_json::Value::Number(n) => n.as_u64().and_then(|n| usize::try_from(n).ok()),
+        serde_json::Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// Name of a JSON value's type for messages
+fn json_type(value: &serde_json::Value) -> &'static str {
+    match value {
+        serde_json::Value::Null => "null",
+        serde_json::Value::Bool(_) => "bool",
+        serde_json::Value::Number(_) => "number",
+        serde_json::Value::String(_) => "string",
+        serde_json::Value::Array(_) => "array",
+        serde_json::Value::Object(_) => "object",
+    }
+}
+
+/// Check whether a result value holds computed expressions, choices or
+/// escaped literals, which evaluation rewrites
+fn has_computed_value(value: &serde_json::Value) -> bool {
+    if expr_object(value).is_some() || choose::Choice::of(value).is_some() {
+        return true;
+    }
+    match value {
+        serde_json::Value::String(s) => computed_expression(s).is_some(),
+        serde_json::Value::Array(items) => items.iter().any(has_computed_value),
+        serde_json::Value::Object(obj) => obj.values().any(has_computed_value),
+        _ => false,
+    }
+}
+
+/// Expression of a `{ "$expr": source }` result value, computed into a number
+fn expr_object(value: &serde_json::Value) -> Option<&str> {
+    match value {
+        serde_json::Value::Object(obj) if obj.len() == 1 => obj.get("$expr")?.as_str(),
+        _ => None,
+    }
+}
+
+/// Classify a result string: `Some(Ok(source))` for a computed expression,
+/// `Some(Err(literal))` for an escaped literal, `None` for a plain string
+fn computed_expression(s: &str) -> Option<Result<&str, &str>> {
+    if s.starts_with("==") {
+        Some(Err(&s[1..]))
+    } else {
+        s.strip_prefix('=').map(Ok)
+    }
+}
+
+/// Merge `source` into `target`, with keys of `source` taking precedence
+///
+/// A deep merge combines objects present on both sides recursively; any
+/// other value, including arrays, replaces the existing one.
+fn merge_objects(
+    target: &mut serde_json::Map<String, serde_json::Value>,
+    source: serde_json::Map<String, serde_json::Value>,
+    deep: bool,
+) {
+    for (key, value) in source {
+        match (target.get_mut(&key), value) {
+            (Some(serde_json::Value::Object(existing)), serde_json::Value::Object(value))
+                if deep =>
+            {
+                merge_objects(existing, value, deep)
+            }
+            (_, value) => {
+                target.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Rule set that has passed validation
+///
+/// Its `Deserialize` impl validates the rules, so rule blocks embedded in a
+/// larger application config are rejected at config-load time with the
+/// deserializer's location info instead of failing later in
+/// [`ConfigEvaluator::new`].
+#[derive(Debug, Clone, Serialize, Eq, PartialEq)]
+#[serde(transparent)]
+pub struct ValidatedRules(ConfigRules);
+
+impl ValidatedRules {
+    /// Validate a rule set
+    pub fn new(rules: ConfigRules) -> Result<Self, ConfigExprError> {
+        ConfigEvaluator::load_rules(rules).map(Self)
+    }
+
+    /// Access the validated rule set
+    pub fn rules(&self) -> &ConfigRules {
+        &self.0
+    }
+
+    /// Unwrap the validated rule set
+    pub fn into_inner(self) -> ConfigRules {
+        self.0
+    }
+
+    /// Create an evaluator without validating the rules again
+    pub fn into_evaluator(self) -> ConfigEvaluator {
+        ConfigEvaluator::from_validated(self.0, EvaluatorLimits::default())
+    }
+}
+
+impl<'de> Deserialize<'de> for ValidatedRules {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let rules = ConfigRules::deserialize(deserializer)?;
+        ValidatedRules::new(rules).map_err(serde::de::Error::custom)
+    }
+}
+
+impl TryFrom<ConfigRules> for ValidatedRules {
+    type Error = ConfigExprError;
+
+    fn try_from(rules: ConfigRules) -> Result<Self, Self::Error> {
+        ValidatedRules::new(rules)
+    }
+}
+
+impl From<ValidatedRules> for ConfigEvaluator {
+    fn from(rules: ValidatedRules) -> Self {
+        rules.into_evaluator()
+    }
+}
+
+/// Convenience method: directly evaluate from JSON string
+pub fn evaluate_json(
+    json: &str,
+    params: &impl ParamSource,
+) -> Result<Option<RuleResult>, ConfigExprError> {
+    let evaluator = ConfigEvaluator::from_json(json)?;
+    Ok(evaluator.evaluate_ref(params).map(Cow::into_owned))
+}
+
+/// Convenience method: validate if JSON rules are valid
+pub fn validate_json(json: &str) -> Result<(), ConfigExprError> {
+    ConfigEvaluator::load_rules(parse_json_rules(json.as_bytes())?).map(drop)
+}
+
+/// Parse and validate a JSON rule document given as bytes
+///
+/// Returns an error for every malformed or invalid input, without
+/// panicking, so it serves as the entry point for fuzzing:
+///
+/// ```ignore
+/// fuzz_target!(|data: &[u8]| {
+///     let _ = clia_config_expr::parse_and_validate(data);
+/// });
+/// ```
+pub fn parse_and_validate(bytes: &[u8]) -> Result<ValidatedRules, ConfigExprError> {
+    ValidatedRules::new(parse_json_rules(bytes)?)
+}
+
+/// Parse a JSON rule document, reporting errors with the path of the value
+/// they occur in
+fn parse_json_rules<T: DeserializeOwned>(json: &[u8]) -> Result<T, ConfigExprError> {
+    let mut deserializer = serde_json::Deserializer::from_slice(json);
+    let rules = serde_path_to_error::deserialize(&mut deserializer).map_err(json_path_error)?;
+    deserializer.end()?;
+    Ok(rules)
+}
+
+/// Convert a JSON error into one naming the path of the value it occurs in
+fn json_path_error(error: serde_path_to_error::Error<serde_json::Error>) -> ConfigExprError {
+    let path = error.path().to_string();
+    let source = error.into_inner();
+    if path == "." {
+        ConfigExprError::JsonError(source)
+    } else {
+        ConfigExprError::JsonPathError { path, source }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simple_condition() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "field": "platform",
+                        "op": "equals",
+                        "value": "RTD"
+                    },
+                    "then": "chip_rtd"
+                }
+            ]
+        }
+        "#;
+
+        let mut params = HashMap::new();
+        params.insert("platform".to_string(), "RTD".to_string());
+
+        let result = evaluate_json(json, &params).unwrap();
+        assert!(result.is_some());
+
+        if let Some(RuleResult::String(s)) = result {
+            assert_eq!(s, "chip_rtd");
+        } else {
+            panic!("Expected string result");
+        }
+    }
+
+    #[test]
+    fn test_and_condition() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "and": [
+                            { "field": "platform", "op": "contains", "value": "RTD" },
+                            { "field": "region", "op": "equals", "value": "CN" }
+                        ]
+                    },
+                    "then": "chip_rtd_cn"
+                }
+            ]
+        }
+        "#;
+
+        let mut params = HashMap::new();
+        params.insert("platform".to_string(), "RTD-2000".to_string());
+        params.insert("region".to_string(), "CN".to_string());
+
+        let result = evaluate_json(json, &params).unwrap();
+        assert!(result.is_some());
+
+        if let Some(RuleResult::String(s)) = result {
+            assert_eq!(s, "chip_rtd_cn");
+        } else {
+            panic!("Expected string result");
+        }
+    }
+
+    #[test]
+    fn test_or_condition() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "or": [
+                            { "field": "platform", "op": "equals", "value": "MT9950" },
+                            { "field": "platform", "op": "equals", "value": "MT9638" }
+                        ]
+                    },
+                    "then": "chip_mt"
+                }
+            ]
+        }
+        "#;
+
+        let mut params = HashMap::new();
+        params.insert("platform".to_string(), "MT9950".to_string());
+
+        let result = evaluate_json(json, &params).unwrap();
+        assert!(result.is_some());
+
+        if let Some(RuleResult::String(s)) = result {
+            assert_eq!(s, "chip_mt");
+        } else {
+            panic!("Expected string result");
+        }
+    }
+
+    #[test]
+    fn test_not_condition() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "and": [
+                            { "field": "platform", "op": "prefix", "value": "RTD" },
+                            { "not": { "field": "platform", "op": "contains", "value": "DEBUG" } }
+                        ]
+                    },
+                    "then": "chip_rtd"
+                }
+            ]
+        }
+        "#;
+
+        let mut params = HashMap::new();
+        params.insert("platform".to_string(), "RTD-2000".to_string());
+        let result = evaluate_json(json, &params).unwrap();
+        assert_eq!(result, Some(RuleResult::String("chip_rtd".to_string())));
+
+        params.insert("platform".to_string(), "RTD-DEBUG".to_string());
+        let result = evaluate_json(json, &params).unwrap();
+        assert!(result.is_none());
+
+        let invalid = r#"
+        {
+            "rules": [
+                { "if": { "not": { "or": [] } }, "then": "chip_rtd" }
+            ]
+        }
+        "#;
+        assert!(validate_json(invalid)
+            .unwrap_err()
+            .to_string()
+            .contains("OR condition cannot be empty"));
+    }
+
+    #[test]
+    fn test_case_insensitive_conditions() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "and": [
+                            { "field": "platform", "op": "iprefix", "value": "rtd" },
+                            { "field": "region", "op": "iequals", "value": "cn" },
+                            { "field": "model", "op": "icontains", "value": "PRO" },
+                            { "field": "model", "op": "isuffix", "value": "MAX" }
+                        ]
+                    },
+                    "then": "chip_rtd"
+                }
+            ]
+        }
+        "#;
+
+        let mut params = HashMap::new();
+        params.insert("platform".to_string(), "RTD-2000".to_string());
+        params.insert("region".to_string(), "Cn".to_string());
+        params.insert("model".to_string(), "tv-pro-max".to_string());
+        let result = evaluate_json(json, &params).unwrap();
+        assert_eq!(result, Some(RuleResult::String("chip_rtd".to_string())));
+
+        params.insert("region".to_string(), "CNX".to_string());
+        assert!(evaluate_json(json, &params).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_exists_and_missing_conditions() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": { "field": "override", "op": "exists" },
+                    "then": "chip_override"
+                },
+                {
+                    "if": { "field": "region", "op": "missing" },
+                    "then": "chip_global"
+                }
+            ]
+        }
+        "#;
+
+        let mut params = HashMap::new();
+        params.insert("override".to_string(), String::new());
+        let result = evaluate_json(json, &params).unwrap();
+        assert_eq!(
+            result,
+            Some(RuleResult::String("chip_override".to_string()))
+        );
+
+        let result = evaluate_json(json, &HashMap::<String, String>::new()).unwrap();
+        assert_eq!(result, Some(RuleResult::String("chip_global".to_string())));
+
+        let mut params = HashMap::new();
+        params.insert("region".to_string(), "CN".to_string());
+        assert!(evaluate_json(json, &params).unwrap().is_none());
+
+        let invalid = json.replace(r#""op": "exists""#, r#""op": "exists", "value": "yes""#);
+        assert!(validate_json(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_in_and_not_in_conditions() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "and": [
+                            { "field": "region", "op": "in", "value": ["CN", "JP", "KR"] },
+                            { "field": "tier", "op": "not_in", "value": [0, 1] }
+                        ]
+                    },
+                    "then": "chip_asia"
+                }
+            ]
+        }
+        "#;
+
+        let mut params = HashMap::new();
+        params.insert("region".to_string(), "JP".to_string());
+        params.insert("tier".to_string(), "2".to_string());
+        let result = evaluate_json(json, &params).unwrap();
+        assert_eq!(result, Some(RuleResult::String("chip_asia".to_string())));
+
+        params.insert("tier".to_string(), "1.0".to_string());
+        assert!(evaluate_json(json, &params).unwrap().is_none());
+
+        params.insert("tier".to_string(), "2".to_string());
+        params.insert("region".to_string(), "US".to_string());
+        assert!(evaluate_json(json, &params).unwrap().is_none());
+
+        let invalid = json.replace(r#"["CN", "JP", "KR"]"#, r#""CN""#);
+        let err = validate_json(&invalid).unwrap_err().to_string();
+        assert!(
+            err.contains("Operator 'in' does not support string values"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_ip_in_cidr_condition() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": { "field": "client_ip", "op": "ip_in_cidr", "value": ["10.0.0.0/8", "2001:db8::/32"] },
+                    "then": "internal"
+                },
+                {
+                    "if": { "field": "client_ip", "op": "ip_in_cidr", "value": "203.0.113.0/24" },
+                    "then": "partner"
+                }
+            ]
+        }
+        "#;
+
+        let mut params = HashMap::new();
+        for (ip, expected) in [
+            ("10.20.30.40", Some("internal")),
+            ("2001:db8::1", Some("internal")),
+            ("203.0.113.9", Some("partner")),
+            ("198.51.100.1", None),
+            ("not-an-ip", None),
+        ] {
+            params.insert("client_ip".to_string(), ip.to_string());
+            let result = evaluate_json(json, &params).unwrap();
+            assert_eq!(
+                result,
+                expected.map(|s| RuleResult::String(s.to_string())),
+                "{}",
+                ip
+            );
+        }
+
+        let invalid = json.replace("10.0.0.0/8", "10.0.0.0/40");
+        let err = validate_json(&invalid).unwrap_err().to_string();
+        assert!(
+            err.contains("Invalid CIDR block '10.0.0.0/40' in rule 0"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_lang_matches_condition() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": { "field": "locale", "op": "lang_matches", "value": ["zh-Hant", "zh-TW"] },
+                    "then": "traditional"
+                },
+                {
+                    "if": { "field": "locale", "op": "lang_matches", "value": "zh" },
+                    "then": "simplified"
+                }
+            ]
+        }
+        "#;
+
+        let mut params = HashMap::new();
+        for (locale, expected) in [
+            ("zh-Hant-HK", Some("traditional")),
+            ("zh_TW", Some("traditional")),
+            ("zh-CN", Some("simplified")),
+            ("ZH-Hans-CN", Some("simplified")),
+            ("zho", None),
+            ("en-US", None),
+        ] {
+            params.insert("locale".to_string(), locale.to_string());
+            let result = evaluate_json(json, &params).unwrap();
+            assert_eq!(
+                result,
+                expected.map(|s| RuleResult::String(s.to_string())),
+                "{}",
+                locale
+            );
+        }
+
+        let invalid = json.replace(r#""zh-TW""#, r#""zh_TW""#);
+        let err = validate_json(&invalid).unwrap_err();
+        assert_eq!(err.error_code(), "invalid_language_range");
+        assert_eq!(err.rule_error().unwrap().pointer, "/rules/0/if/value");
+    }
+
+    #[test]
+    fn test_datetime_conditions() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": { "field": "activated_at", "op": "between", "value": ["2024-12-01", "2025-01-01"] },
+                    "then": "holiday_cohort"
+                },
+                {
+                    "if": { "field": "activated_at", "op": "after", "value": "2025-01-01T00:00:00+08:00" },
+                    "then": "new_cohort"
+                },
+                {
+                    "if": { "field": "activated_at", "op": "before", "value": "2024-12-01" },
+                    "then": "legacy_cohort"
+                }
+            ]
+        }
+        "#;
+
+        let mut params = HashMap::new();
+        for (activated_at, expected) in [
+            ("2024-12-24T18:00:00Z", Some("holiday_cohort")),
+            ("2025-01-01T12:00:00+08:00", Some("new_cohort")),
+            ("2025-03-01", Some("new_cohort")),
+            ("2023-06-15T08:30:00-05:00", Some("legacy_cohort")),
+            // Lexicographically after 2025, but not a datetime
+            ("9 May 2025", None),
+        ] {
+            params.insert("activated_at".to_string(), activated_at.to_string());
+            let result = evaluate_json(json, &params).unwrap();
+            assert_eq!(
+                result,
+                expected.map(|s| RuleResult::String(s.to_string())),
+                "{}",
+                activated_at
+            );
+        }
+
+        let formatted = json
+            .replace(
+                r#""rules": ["#,
+                r#""datetime_format": "%d/%m/%Y", "rules": ["#,
+            )
+            .replace("2024-12-01", "01/12/2024")
+            .replace("2025-01-01T00:00:00+08:00", "01/01/2025")
+            .replace("2025-01-01", "01/01/2025");
+        params.insert("activated_at".to_string(), "24/12/2024".to_string());
+        let result = evaluate_json(&formatted, &params).unwrap();
+        assert_eq!(
+            result,
+            Some(RuleResult::String("holiday_cohort".to_string()))
+        );
+
+        let reversed = json.replace(
+            r#"["2024-12-01", "2025-01-01"]"#,
+            r#"["2025-01-01", "2024-12-01"]"#,
+        );
+        assert!(validate_json(&reversed)
+            .unwrap_err()
+            .to_string()
+            .contains("'between' takes a start and a later end datetime in rule 0"));
+
+        let invalid = json.replace(
+            r#""before", "value": "2024-12-01""#,
+            r#""before", "value": "Dec 2024""#,
+        );
+        assert!(validate_json(&invalid)
+            .unwrap_err()
+            .to_string()
+            .contains(r#"Invalid datetime "Dec 2024" in rule 2"#));
+    }
+
+    #[test]
+    fn test_length_and_range_operators() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": { "field": "username", "op": "len_lt", "value": 3 },
+                    "then": "too_short"
+                },
+                {
+                    "if": { "field": "username", "op": "len_gt", "value": "8" },
+                    "then": "too_long"
+                },
+                {
+                    "if": "pin len_eq 4 && age between [18, 65]",
+                    "then": "ok"
+                }
+            ],
+            "fallback": "rejected"
+        }
+        "#;
+
+        let mut params = HashMap::new();
+        for (username, age, expected) in [
+            ("ab", "30", "too_short"),
+            ("abcdefghi", "30", "too_long"),
+            // Lengths count characters, not bytes
+            ("日本語", "30", "ok"),
+            ("alice", "18", "ok"),
+            ("alice", "65", "ok"),
+            ("alice", "65.5", "rejected"),
+            ("alice", "adult", "rejected"),
+        ] {
+            params.insert("username".to_string(), username.to_string());
+            params.insert("age".to_string(), age.to_string());
+            params.insert("pin".to_string(), "1234".to_string());
+            let result = evaluate_json(json, &params).unwrap();
+            assert_eq!(
+                result,
+                Some(RuleResult::String(expected.to_string())),
+                "{} {}",
+                username,
+                age
+            );
+        }
+
+        let report = ConfigEvaluator::from_json(json)
+            .unwrap()
+            .evaluate_report(&params);
+        assert!(report
+            .soft_errors
+            .contains(&"Param 'age' value 'adult' is not a number in rule 2".to_string()));
+
+        let reversed = json.replace("[18, 65]", "[65, 18]");
+        assert!(validate_json(&reversed)
+            .unwrap_err()
+            .to_string()
+            .contains("'between' takes a minimum and a maximum no lower than it in rule 2"));
+        let negative = json.replace(r#""value": 3"#, r#""value": -3"#);
+        assert!(validate_json(&negative)
+            .unwrap_err()
+            .to_string()
+            .contains("Length must be a non-negative integer in rule 0"));
+    }
+
+    #[test]
+    fn test_custom_operator() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": { "field": "country", "op": "custom:geo_in", "value": "EU" },
+                    "then": "gdpr"
+                },
+                {
+                    "if": { "field": "license", "op": "custom:licensed" },
+                    "then": "pro"
+                }
+            ]
+        }
+        "#;
+        let mut evaluator = ConfigEvaluator::from_json(json).unwrap();
+        assert_eq!(
+            evaluator.unregistered_operators(),
+            BTreeSet::from(["geo_in".to_string(), "licensed".to_string()])
+        );
+
+        let mut params = HashMap::new();
+        params.insert("country".to_string(), "FR".to_string());
+        // Unregistered operators never match
+        assert!(evaluator.evaluate(&params).is_none());
+
+        evaluator.register_operator(
+            "geo_in",
+            Box::new(|country, region| region == "EU" && ["DE", "FR", "IT"].contains(&country)),
+        );
+        assert!(evaluator.unregistered_operators().contains("licensed"));
+        assert_eq!(
+            evaluator.evaluate(&params),
+            Some(RuleResult::String("gdpr".to_string()))
+        );
+        params.insert("country".to_string(), "US".to_string());
+        assert!(evaluator.evaluate(&params).is_none());
+
+        let rules: ConfigRules = serde_json::from_str(json).unwrap();
+        let serialized = serde_json::to_value(&rules).unwrap();
+        assert_eq!(serialized["rules"][0]["if"]["op"], "custom:geo_in");
+        assert_eq!(
+            rules.rules[1].condition,
+            Condition::Simple {
+                field: "license".to_string(),
+                op: Operator::Custom(CustomOperator::new("licensed")),
+                value: serde_json::Value::Null,
+                value_field: None,
+                seed: String::new(),
+                trim: false,
+                normalize: None,
+                mode: None,
+            }
+        );
+
+        assert!(validate_json(&json.replace("custom:geo_in", "geo_in")).is_err());
+    }
+
+    #[test]
+    fn test_evaluate_source() {
+        use core::cell::RefCell;
+
+        let json = r#"
+        {
+            "pre": [
+                {
+                    "if": { "field": "region", "op": "missing" },
+                    "then": { "region": "CN" }
+                }
+            ],
+            "rules": [
+                {
+                    "if": "platform ~ \"^RTD\" && region == \"CN\"",
+                    "then": { "chip": "rtd", "timeout": "= timeout * 2" }
+                }
+            ]
+        }
+        "#;
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+
+        let requested = RefCell::new(Vec::new());
+        let headers = FnSource(|name: &str| {
+            requested.borrow_mut().push(name.to_string());
+            match name {
+                "platform" => Some("RTD-2000".to_string()),
+                "timeout" => Some("15".to_string()),
+                _ => None,
+            }
+        });
+        assert_eq!(
+            evaluator.evaluate_source(&headers).unwrap(),
+            Some(RuleResult::Object(
+                serde_json::json!({ "chip": "rtd", "timeout": 30 })
+            ))
+        );
+        // The pre phase supplied the region without asking the source again
+        assert_eq!(
+            requested.borrow().iter().filter(|f| *f == "region").count(),
+            1
+        );
+
+        let row = BTreeMap::from([
+            ("platform".to_string(), "MT9950".to_string()),
+            ("region".to_string(), "CN".to_string()),
+        ]);
+        assert!(evaluator.evaluate_source(&row).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_lazy_params() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "and": [
+                            { "field": "platform", "op": "prefix", "value": "RTD" },
+                            { "field": "license", "op": "equals", "value": "pro" }
+                        ]
+                    },
+                    "then": "chip_rtd_pro"
+                },
+                {
+                    "if": { "field": "platform", "op": "prefix", "value": "MT" },
+                    "then": "chip_mt"
+                }
+            ]
+        }
+        "#;
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+
+        let lookups = core::cell::Cell::new(0);
+        let remote = |name: &str| {
+            lookups.set(lookups.get() + 1);
+            match name {
+                "platform" => Some("MT9950".to_string()),
+                "license" => Some("pro".to_string()),
+                _ => None,
+            }
+        };
+        let params = LazyParams::new(FnSource(remote));
+        assert_eq!(
+            evaluator.evaluate_source(&params).unwrap(),
+            Some(RuleResult::String("chip_mt".to_string()))
+        );
+        // The failed prefix check short-circuits before `license`, and
+        // `platform` is fetched once for both rules
+        assert_eq!(params.resolved_fields(), vec!["platform"]);
+        assert_eq!(lookups.get(), 1);
+    }
+
+    #[test]
+    fn test_metrics() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "and": [
+                            { "field": "platform", "op": "prefix", "value": "RTD" },
+                            { "field": "license", "op": "equals", "value": "pro" }
+                        ]
+                    },
+                    "then": "chip_rtd_pro"
+                },
+                {
+                    "if": { "field": "platform", "op": "prefix", "value": "MT" },
+                    "then": "chip_mt"
+                }
+            ]
+        }
+        "#;
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+        assert!(evaluator.metrics().is_none());
+
+        let evaluator = evaluator.with_metrics();
+        let shared = evaluator.clone();
+        let mut params = HashMap::new();
+        params.insert("platform".to_string(), "MT9950".to_string());
+        evaluator.evaluate(&params);
+        params.insert("platform".to_string(), "RTD2851".to_string());
+        params.insert("license".to_string(), "pro".to_string());
+        shared.evaluate(&params);
+        // Traces are not counted
+        evaluator.evaluate_with_trace(&params);
+
+        let metrics = evaluator.metrics().unwrap();
+        assert_eq!(metrics.evaluations, 2);
+        assert_eq!(metrics.rule_hits, vec![1, 1]);
+        assert!(metrics.pre_hits.is_empty());
+        // `and` plus the failed prefix, then the second rule; `and` plus both leaves
+        assert_eq!(metrics.conditions_evaluated, 6);
+        assert_eq!(metrics.average_condition_depth(), 3.0);
+    }
+
+    #[test]
+    fn test_evaluate_batch() {
+        let current = ConfigEvaluator::from_json(
+            r#"{ "rules": [{ "if": { "field": "platform", "op": "prefix", "value": "RTD" }, "then": "chip_rtd" }] }"#,
+        )
+        .unwrap();
+        let proposed = ConfigEvaluator::from_json(
+            r#"{ "rules": [{ "if": { "field": "platform", "op": "prefix", "value": "RT" }, "then": "chip_rtd" }] }"#,
+        )
+        .unwrap();
+        let batch: Vec<HashMap<String, String>> = ["RTD2851", "RTX3090", "MT9950"]
+            .iter()
+            .map(|platform| HashMap::from([("platform".to_string(), platform.to_string())]))
+            .collect();
+
+        let before = current.evaluate_batch(&batch);
+        let after = proposed.evaluate_batch(&batch);
+        let rtd = Some(RuleResult::String("chip_rtd".to_string()));
+        assert_eq!(before, vec![rtd.clone(), None, None]);
+        assert_eq!(after, vec![rtd.clone(), rtd, None]);
+        let changed: Vec<usize> = (0..batch.len())
+            .filter(|&i| before[i] != after[i])
+            .collect();
+        assert_eq!(changed, vec![1]);
+    }
+
+    #[test]
+    fn test_trim_and_normalize() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": { "field": "device_name", "op": "equals", "value": "Café TV", "trim": true, "normalize": "nfc" },
+                    "then": "cafe"
+                },
+                {
+                    "if": { "field": "model", "op": "in", "value": ["ＲＴＤ", "MT9950"], "normalize": "nfkc" },
+                    "then": "known_model"
+                }
+            ],
+            "fallback": "none"
+        }
+        "#;
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+        let result = |key: &str, value: &str| {
+            let params = HashMap::from([(key.to_string(), value.to_string())]);
+            match evaluator.evaluate(&params) {
+                Some(RuleResult::String(s)) => s,
+                other => panic!("Unexpected result {:?}", other),
+            }
+        };
+
+        assert_eq!(result("device_name", " Cafe\u{301} TV\n"), "cafe");
+        assert_eq!(result("device_name", "Café TV"), "cafe");
+        assert_eq!(result("device_name", "Cafe TV"), "none");
+        // Full-width letters fold to ASCII on both sides
+        assert_eq!(result("model", "RTD"), "known_model");
+        assert_eq!(result("model", "ＭＴ９９５０"), "known_model");
+        assert_eq!(result("model", " RTD"), "none");
+
+        let params = HashMap::from([("device_name".to_string(), " Café TV ".to_string())]);
+        assert!(evaluator
+            .evaluate_with_trace(&params)
+            .to_string()
+            .contains(r#"(actual "Café TV"): true"#));
+
+        // Options are kept when rules are written back out
+        let rules = serde_json::to_string(evaluator.rules()).unwrap();
+        assert!(rules.contains(r#""trim":true"#) && rules.contains(r#""normalize":"nfkc""#));
+        assert!(ConfigEvaluator::from_json(
+            r#"{ "rules": [{ "if": { "field": "a", "op": "equals", "value": "x", "normalize": "nfd" }, "then": "x" }] }"#,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_multi_value_params() {
+        let json = r#"{
+            "rules": [
+                {
+                    "if": {
+                        "and": [
+                            { "field": "capability", "op": "equals", "value": "bt", "mode": "any" },
+                            { "field": "score", "op": "ge", "value": 60, "mode": "all" }
+                        ]
+                    },
+                    "then": "bt_high"
+                },
+                {
+                    "if": { "field": "capability", "op": "in", "value": ["wifi", "bt"], "mode": "all" },
+                    "then": "wireless"
+                }
+            ],
+            "fallback": "wired"
+        }"#;
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+        let evaluate = |params: &[(&str, &[&str])]| {
+            let params: HashMap<&str, Vec<&str>> =
+                params.iter().map(|(k, v)| (*k, v.to_vec())).collect();
+            evaluator
+                .evaluate_source(&MultiParams(params))
+                .unwrap()
+                .map(|result| result.deserialize_into::<String>().unwrap())
+        };
+        let result = |s: &str| Some(s.to_string());
+        assert_eq!(
+            evaluate(&[("capability", &["wifi", "bt"]), ("score", &["70", "80"])]),
+            result("bt_high")
+        );
+        assert_eq!(
+            evaluate(&[("capability", &["wifi", "bt"]), ("score", &["70", "50"])]),
+            result("wireless")
+        );
+        assert_eq!(
+            evaluate(&[("capability", &["wifi", "eth"])]),
+            result("wired")
+        );
+        assert_eq!(evaluate(&[("capability", &[])]), result("wired"));
+
+        // Single values and JSON arrays are compared item by item too
+        let params: HashMap<&str, &str> = [("capability", "bt"), ("score", "90")].into();
+        assert_eq!(
+            evaluator.evaluate(&params),
+            Some(RuleResult::String("bt_high".to_string()))
+        );
+        let value = serde_json::json!({ "capability": ["bt", "wifi"], "score": [61] });
+        assert_eq!(
+            evaluator.evaluate_value(&value).unwrap(),
+            Some(RuleResult::String("bt_high".to_string()))
+        );
+
+        let rules = serde_json::to_string(evaluator.rules()).unwrap();
+        assert!(rules.contains(r#""mode":"any""#) && rules.contains(r#""mode":"all""#));
+    }
+
+    #[test]
+    fn test_strict_fields() {
+        let json = r#"{
+            "rules": [
+                { "if": "platfrom == \"RTD\"", "then": "rtd" },
+                { "if": { "field": "override", "op": "exists" }, "then": "override" },
+                { "if": "region == \"CN\" || tier == \"gold\"", "then": "cn_or_gold" }
+            ],
+            "fallback": "default"
+        }"#;
+        let lenient = ConfigEvaluator::from_json(json).unwrap();
+        let strict = lenient.clone().with_strict_fields();
+        let params = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        let result = |s: &str| Some(RuleResult::String(s.to_string()));
+
+        let typo = params(&[("platform", "RTD"), ("region", "CN")]);
+        assert_eq!(
+            lenient.evaluate_checked(&typo).unwrap(),
+            result("cn_or_gold")
+        );
+        let error = strict.evaluate_checked(&typo).unwrap_err();
+        assert!(matches!(&error, ConfigExprError::FieldNotFound(field) if field == "platfrom"));
+        assert_eq!(strict.evaluate(&typo), None);
+        assert_eq!(
+            strict.evaluate_with_trace(&typo).error.as_deref(),
+            Some("Field not found: platfrom")
+        );
+
+        // `exists` conditions and conditions left out by `||` may read missing params
+        assert_eq!(
+            strict
+                .evaluate_checked(&params(&[("platfrom", "MT"), ("region", "CN")]))
+                .unwrap(),
+            result("cn_or_gold")
+        );
+        let error = strict
+            .evaluate_checked(&params(&[("platfrom", "MT"), ("region", "JP")]))
+            .unwrap_err();
+        assert!(matches!(&error, ConfigExprError::FieldNotFound(field) if field == "tier"));
+        assert!(strict
+            .with_rules(lenient.rules().clone())
+            .unwrap()
+            .evaluate_all(&params(&[("platfrom", "MT"), ("region", "JP")]))
+            .is_empty());
+
+        // Rules keyed on a missing param in a dispatch table still read it
+        let keyed: Vec<_> = (0..20)
+            .map(|i| serde_json::json!({ "if": format!("platform == \"P{}\"", i), "then": i }))
+            .collect();
+        let dispatched: ConfigRules =
+            serde_json::from_value(serde_json::json!({ "rules": keyed, "fallback": "none" }))
+                .unwrap();
+        let strict = ConfigEvaluator::new(dispatched)
+            .unwrap()
+            .with_strict_fields();
+        assert!(strict.compiled.dispatch().is_some());
+        let error = strict
+            .evaluate_checked(&params(&[("platfrom", "P3")]))
+            .unwrap_err();
+        assert!(matches!(&error, ConfigExprError::FieldNotFound(field) if field == "platform"));
+        assert_eq!(
+            strict
+                .evaluate_checked(&params(&[("platform", "P3")]))
+                .unwrap(),
+            Some(RuleResult::Object(serde_json::json!(3)))
+        );
+
+        // Each condition is evaluated once
+        let calls = Arc::new(core::sync::atomic::AtomicUsize::new(0));
+        let counted = calls.clone();
+        let mut strict = ConfigEvaluator::from_json(
+            r#"{ "rules": [{ "if": { "field": "tier", "op": "custom:count", "value": "" }, "then": 1 }] }"#,
+        )
+        .unwrap()
+        .with_strict_fields();
+        strict.register_operator(
+            "count",
+            Box::new(move |_, _| {
+                counted.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+                false
+            }),
+        );
+        assert_eq!(
+            strict
+                .evaluate_checked(&params(&[("tier", "gold")]))
+                .unwrap(),
+            None
+        );
+        assert_eq!(calls.load(core::sync::atomic::Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_set_operators() {
+        let json = r#"{
+            "rules": [
+                {
+                    "if": { "field": "capabilities", "op": "subset_of", "value": ["wifi", "bt", "hdmi"] },
+                    "then": "supported"
+                },
+                {
+                    "if": { "field": "device.capabilities", "op": "intersects", "value": ["wifi", "bt"] },
+                    "then": "wireless"
+                }
+            ],
+            "fallback": "unsupported"
+        }"#;
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+        let evaluate = |value: serde_json::Value| {
+            evaluator
+                .evaluate_value(&value)
+                .unwrap()
+                .map(|result| result.deserialize_into::<String>().unwrap())
+        };
+        let result = |s: &str| Some(s.to_string());
+        assert_eq!(
+            evaluate(serde_json::json!({ "capabilities": ["bt", "wifi"] })),
+            result("supported")
+        );
+        assert_eq!(
+            evaluate(serde_json::json!({ "capabilities": ["bt", "usb"] })),
+            result("unsupported")
+        );
+        assert_eq!(
+            evaluate(serde_json::json!({ "device": { "capabilities": ["usb", "bt"] } })),
+            result("wireless")
+        );
+        assert_eq!(evaluate(serde_json::json!({})), result("unsupported"));
+
+        let params = MultiParams(BTreeMap::from([("capabilities", vec!["hdmi", "wifi"])]));
+        assert_eq!(
+            evaluator.evaluate_source(&params).unwrap(),
+            Some(RuleResult::String("supported".to_string()))
+        );
+
+        let error = ConfigEvaluator::from_json(
+            r#"{ "rules": [{ "if": { "field": "a", "op": "intersects", "value": ["x"], "mode": "all" }, "then": "x" }] }"#,
+        )
+        .unwrap_err();
+        assert_eq!(error.error_code(), "unsupported_mode");
+        assert!(validate_json(
+            r#"{ "rules": [{ "if": { "field": "a", "op": "subset_of", "value": "x" }, "then": "x" }] }"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_value_field() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": { "field": "actual_memory", "op": "ge", "value_field": "required_memory" },
+                    "then": "fits"
+                },
+                {
+                    "if": { "field": "region", "op": "equals", "value_field": "device.home_region" },
+                    "then": "home"
+                }
+            ],
+            "fallback": "none"
+        }
+        "#;
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+        let params = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        let result = |params: &HashMap<String, String>| match evaluator.evaluate(params) {
+            Some(RuleResult::String(s)) => s,
+            other => panic!("Unexpected result {:?}", other),
+        };
+
+        assert_eq!(
+            result(&params(&[
+                ("actual_memory", "16"),
+                ("required_memory", "8")
+            ])),
+            "fits"
+        );
+        assert_eq!(
+            result(&params(&[("actual_memory", "4"), ("required_memory", "8")])),
+            "none"
+        );
+        // A missing param on either side never matches
+        assert_eq!(result(&params(&[("actual_memory", "16")])), "none");
+        assert_eq!(
+            result(&params(&[
+                ("region", "CN"),
+                ("device", r#"{ "home_region": "CN" }"#)
+            ])),
+            "home"
+        );
+
+        let fields = evaluator.rules().referenced_fields();
+        assert!(fields.contains("required_memory") && fields.contains("device.home_region"));
+        let trace = evaluator
+            .evaluate_with_trace(&params(&[("actual_memory", "4"), ("required_memory", "8")]));
+        assert!(trace
+            .to_string()
+            .contains(r#"actual_memory ge required_memory="8" (actual "4"): false"#));
+
+        let error = ConfigEvaluator::from_json(
+            r#"{ "rules": [{ "if": { "field": "a", "op": "ge", "value": "1", "value_field": "b" }, "then": "x" }] }"#,
+        )
+        .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("exactly one of 'value' and 'value_field' in rule 0"));
+        let error = ConfigEvaluator::from_json(
+            r#"{ "rules": [{ "if": { "field": "a", "op": "in", "value_field": "b" }, "then": "x" }] }"#,
+        )
+        .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("Operator 'in' does not support 'value_field'"));
+    }
+
+    #[test]
+    fn test_threshold_expression() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": { "field": "price", "op": "gt", "value": "${base_price} * 1.2" },
+                    "then": "expensive"
+                },
+                {
+                    "if": "price <= \"(${base_price} - ${discount.amount}) / 2\"",
+                    "then": "bargain"
+                }
+            ],
+            "fallback": "normal"
+        }
+        "#;
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+        let params = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        let result = |params: &HashMap<String, String>| match evaluator.evaluate(params) {
+            Some(RuleResult::String(s)) => s,
+            other => panic!("Unexpected result {:?}", other),
+        };
+
+        assert_eq!(
+            result(&params(&[("price", "130"), ("base_price", "100")])),
+            "expensive"
+        );
+        assert_eq!(
+            result(&params(&[("price", "110"), ("base_price", "100")])),
+            "normal"
+        );
+        assert_eq!(
+            result(&params(&[
+                ("price", "40"),
+                ("base_price", "100"),
+                ("discount", r#"{ "amount": 20 }"#)
+            ])),
+            "bargain"
+        );
+        // A missing or non-numeric operand never matches
+        assert_eq!(result(&params(&[("price", "130")])), "normal");
+        assert_eq!(
+            result(&params(&[("price", "130"), ("base_price", "n/a")])),
+            "normal"
+        );
+
+        let fields = evaluator.rules().referenced_fields();
+        assert!(fields.contains("base_price") && fields.contains("discount.amount"));
+        let report = evaluator.evaluate_report(&params(&[("price", "130")]));
+        assert!(report
+            .soft_errors
+            .iter()
+            .any(|e| e.contains("base_price") && e.ends_with("in rule 0")));
+
+        let error = ConfigEvaluator::from_json(
+            r#"{ "rules": [{ "if": { "field": "a", "op": "lt", "value": "${b} *" }, "then": "x" }] }"#,
+        )
+        .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("Invalid expression '${b} *' in rule 0"));
+    }
+
+    #[test]
+    fn test_json_error_path() {
+        let json = r#"
+        {
+            "rules": [
+                { "if": { "field": "platform", "op": "prefix", "value": "RTD" }, "then": "chip_rtd" },
+                {
+                    "if": {
+                        "and": [
+                            { "field": "region", "op": "equals", "value": "CN" },
+                            { "field": "platform", "op": "startswith", "value": "MT" }
+                        ]
+                    },
+                    "then": "chip_mt"
+                }
+            ]
+        }
+        "#;
+        let error = ConfigEvaluator::from_json(json).unwrap_err();
+        let ConfigExprError::JsonPathError { path, source } = &error else {
+            panic!("Expected JSON path error, got {:?}", error);
+        };
+        assert_eq!(path, "rules[1].if.and[1].op");
+        assert_eq!(source.line(), 9);
+        assert!(error
+            .to_string()
+            .starts_with("JSON error at rules[1].if.and[1].op:"));
+
+        let error = validate_json(r#"{ "rules": [{ "if": { "field": "a" }, "then": "x" }] }"#);
+        assert!(matches!(
+            error,
+            Err(ConfigExprError::JsonPathError { ref path, .. }) if path == "rules[0].if"
+        ));
+        assert!(matches!(
+            validate_json("{ \"rules\": [] } trailing"),
+            Err(ConfigExprError::JsonError(_))
+        ));
+    }
+
+    #[test]
+    fn test_structured_validation_errors() {
+        let json = r#"
+        {
+            "rules": [
+                { "if": { "field": "platform", "op": "prefix", "value": "RTD" }, "then": "chip_rtd" },
+                {
+                    "if": {
+                        "and": [
+                            { "field": "region", "op": "equals", "value": "CN" },
+                            { "field": "platform", "op": "regex", "value": "^(MT" }
+                        ]
+                    },
+                    "then": "chip_mt"
+                }
+            ]
+        }
+        "#;
+        let error = ConfigEvaluator::from_json(json).unwrap_err();
+        assert_eq!(error.error_code(), "invalid_regex");
+        let details = error.rule_error().unwrap();
+        assert_eq!(
+            (details.phase, details.rule_index),
+            (Some(Phase::Main), Some(1))
+        );
+        assert_eq!(details.pointer, "/rules/1/if/and/1/value");
+        assert_eq!(details.field.as_deref(), Some("platform"));
+        assert_eq!(details.value, Some(serde_json::json!("^(MT")));
+        assert!(error
+            .to_string()
+            .starts_with("Validation error: Invalid regex '^(MT' in rule 1:"));
+        let serialized = serde_json::to_value(details).unwrap();
+        assert_eq!(serialized["code"], "invalid_regex");
+        assert_eq!(serialized["phase"], "main");
+
+        let error =
+            validate_json(r#"{ "rules": [], "fallback": { "timeout": "= base *" } }"#).unwrap_err();
+        assert_eq!(error.error_code(), "invalid_expression");
+        let details = error.rule_error().unwrap();
+        assert_eq!((details.phase, details.rule_index), (None, None));
+        assert_eq!(details.pointer, "/fallback/timeout");
+
+        // Errors outside rules keep their own codes
+        assert_eq!(
+            validate_json("{ \"rules\": [] } trailing")
+                .unwrap_err()
+                .error_code(),
+            "json_error"
+        );
+        let error = validate_json(
+            r#"{ "rules": [{ "id": "a", "if": "x == 1", "then": "a" }, { "id": "a", "if": "x == 2", "then": "b" }] }"#,
+        )
+        .unwrap_err();
+        assert_eq!(error.error_code(), "validation_error");
+        assert!(error.rule_error().is_none());
+    }
+
+    #[test]
+    fn test_prefix_condition() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "field": "platform",
+                        "op": "prefix",
+                        "value": "Hi"
+                    },
+                    "then": "chip_hi"
+                }
+            ]
+        }
+        "#;
+
+        let mut params = HashMap::new();
+        params.insert("platform".to_string(), "Hi3516".to_string());
+
+        let result = evaluate_json(json, &params).unwrap();
+        assert!(result.is_some());
+
+        if let Some(RuleResult::String(s)) = result {
+            assert_eq!(s, "chip_hi");
+        } else {
+            panic!("Expected string result");
+        }
+    }
+
+    #[test]
+    fn test_json_object_result() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "field": "platform",
+                        "op": "equals",
+                        "value": "RTD"
+                    },
+                    "then": {
+                        "chip": "rtd",
+                        "config": {
+                            "memory": "2GB",
+                            "cpu": "ARM"
+                        }
+                    }
+                }
+            ]
+        }
+        "#;
+
+        let mut params = HashMap::new();
+        params.insert("platform".to_string(), "RTD".to_string());
+
+        let result = evaluate_json(json, &params).unwrap();
+        assert!(result.is_some());
+
+        if let Some(RuleResult::Object(obj)) = result {
+            assert_eq!(obj["chip"], "rtd");
+            assert_eq!(obj["config"]["memory"], "2GB");
+        } else {
+            panic!("Expected object result");
+        }
+    }
+
+    #[test]
+    fn test_fallback() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "field": "platform",
+                        "op": "equals",
+                        "value": "RTD"
+                    },
+                    "then": "chip_rtd"
+                }
+            ],
+            "fallback": "default_chip"
+        }
+        "#;
+
+        let mut params = HashMap::new();
+        params.insert("platform".to_string(), "Unknown".to_string());
+
+        let result = evaluate_json(json, &params).unwrap();
+        assert!(result.is_some());
+
+        if let Some(RuleResult::String(s)) = result {
+            assert_eq!(s, "default_chip");
+        } else {
+            panic!("Expected string result");
+        }
+    }
+
+    #[test]
+    fn test_no_match_no_fallback() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "field": "platform",
+                        "op": "equals",
+                        "value": "RTD"
+                    },
+                    "then": "chip_rtd"
+                }
+            ]
+        }
+        "#;
+
+        let mut params = HashMap::new();
+        params.insert("platform".to_string(), "Unknown".to_string());
+
+        let result = evaluate_json(json, &params).unwrap();
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn test_regex_condition() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "field": "platform",
+                        "op": "regex",
+                        "value": "^Hi\\d+"
+                    },
+                    "then": "chip_hi"
+                }
+            ]
+        }
+        "#;
+
+        let mut params = HashMap::new();
+        params.insert("platform".to_string(), "Hi3516".to_string());
+
+        let result = evaluate_json(json, &params).unwrap();
+        assert!(result.is_some());
+
+        if let Some(RuleResult::String(s)) = result {
+            assert_eq!(s, "chip_hi");
+        } else {
+            panic!("Expected string result");
+        }
+    }
+
+    #[test]
+    fn test_glob_condition() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": { "field": "platform", "op": "glob", "value": "Hi35*" },
+                    "then": "chip_hi"
+                },
+                {
+                    "if": "platform glob \"MT9?50\"",
+                    "then": "chip_mt"
+                }
+            ]
+        }
+        "#;
+
+        let mut params = HashMap::new();
+        for (platform, expected) in [
+            ("Hi3516", Some("chip_hi")),
+            ("MT9950", Some("chip_mt")),
+            ("MT9638", None),
+            // The pattern must match the whole value
+            ("XHi3516", None),
+        ] {
+            params.insert("platform".to_string(), platform.to_string());
+            let result = evaluate_json(json, &params).unwrap();
+            assert_eq!(
+                result,
+                expected.map(|s| RuleResult::String(s.to_string())),
+                "{}",
+                platform
+            );
+        }
+
+        let invalid = json.replace("Hi35*", "Hi35[");
+        assert!(validate_json(&invalid)
+            .unwrap_err()
+            .to_string()
+            .contains("Invalid glob 'Hi35[' in rule 0"));
+    }
+
+    #[test]
+    fn test_suffix_condition() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "field": "platform",
+                        "op": "suffix",
+                        "value": "Pro"
+                    },
+                    "then": "chip_pro"
+                }
+            ]
+        }
+        "#;
+
+        let mut params = HashMap::new();
+        params.insert("platform".to_string(), "RTD-Pro".to_string());
+
+        let result = evaluate_json(json, &params).unwrap();
+        assert!(result.is_some());
+
+        if let Some(RuleResult::String(s)) = result {
+            assert_eq!(s, "chip_pro");
+        } else {
+            panic!("Expected string result");
+        }
+    }
+
+    // #[test]
+    // fn test_validation_empty_rules() {
+    //     let json = r#"
+    //     {
+    //         "rules": []
+    //     }
+    //     "#;
+
+    //     let result = validate_json(json);
+    //     assert!(result.is_err());
+    //     assert!(result
+    //         .unwrap_err()
+    //         .to_string()
+    //         .contains("Rules cannot be empty"));
+    // }
+
+    #[test]
+    fn test_validation_empty_field() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "field": "",
+                        "op": "equals",
+                        "value": "RTD"
+                    },
+                    "then": "chip_rtd"
+                }
+            ]
+        }
+        "#;
+
+        let result = validate_json(json);
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Field name cannot be empty"));
+    }
+
+    #[test]
+    fn test_validation_invalid_regex() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "field": "platform",
+                        "op": "regex",
+                        "value": "[invalid"
+                    },
+                    "then": "chip_rtd"
+                }
+            ]
+        }
+        "#;
+
+        let result = validate_json(json);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("Invalid regex"));
+    }
+
+    #[test]
+    fn test_validation_empty_and_condition() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "and": []
+                    },
+                    "then": "chip_rtd"
+                }
+            ]
+        }
+        "#;
+
+        let result = validate_json(json);
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("AND condition cannot be empty"));
+    }
+
+    #[test]
+    fn test_validation_empty_or_condition() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "or": []
+                    },
+                    "then": "chip_rtd"
+                }
+            ]
+        }
+        "#;
+
+        let result = validate_json(json);
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("OR condition cannot be empty"));
+    }
+
+    #[test]
+    fn test_complex_nested_conditions() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "and": [
+                            {
+                                "or": [
+                                    { "field": "platform", "op": "prefix", "value": "Hi" },
+                                    { "field": "platform", "op": "prefix", "value": "MT" }
+                                ]
+                            },
+                            { "field": "region", "op": "equals", "value": "CN" }
+                        ]
+                    },
+                    "then": "chip_cn"
+                }
+            ]
+        }
+        "#;
+
+        let mut params = HashMap::new();
+        params.insert("platform".to_string(), "Hi3516".to_string());
+        params.insert("region".to_string(), "CN".to_string());
+
+        let result = evaluate_json(json, &params).unwrap();
+        assert!(result.is_some());
+
+        if let Some(RuleResult::String(s)) = result {
+            assert_eq!(s, "chip_cn");
+        } else {
+            panic!("Expected string result");
+        }
+    }
+
+    #[test]
+    fn test_greater_than_condition() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "field": "score",
+                        "op": "gt",
+                        "value": "80"
+                    },
+                    "then": "high_score"
+                }
+            ]
+        }
+        "#;
+
+        let mut params = HashMap::new();
+        params.insert("score".to_string(), "85".to_string());
+
+        let result = evaluate_json(json, &params).unwrap();
+        assert!(result.is_some());
+
+        if let Some(RuleResult::String(s)) = result {
+            assert_eq!(s, "high_score");
+        } else {
+            panic!("Expected string result");
+        }
+
+        // 测试不满足条件的情况
+        let mut params = HashMap::new();
+        params.insert("score".to_string(), "75".to_string());
+
+        let result = evaluate_json(json, &params).unwrap();
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn test_less_than_condition() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "field": "age",
+                        "op": "lt",
+                        "value": "18"
+                    },
+                    "then": "minor"
+                }
+            ]
+        }
+        "#;
+
+        let mut params = HashMap::new();
+        params.insert("age".to_string(), "16".to_string());
+
+        let result = evaluate_json(json, &params).unwrap();
+        assert!(result.is_some());
+
+        if let Some(RuleResult::String(s)) = result {
+            assert_eq!(s, "minor");
+        } else {
+            panic!("Expected string result");
+        }
+    }
+
+    #[test]
+    fn test_greater_than_or_equal_condition() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "field": "level",
+                        "op": "ge",
+                        "value": "5"
+                    },
+                    "then": "advanced"
+                }
+            ]
+        }
+        "#;
+
+        let mut params = HashMap::new();
+        params.insert("level".to_string(), "5".to_string());
+
+        let result = evaluate_json(json, &params).unwrap();
+        assert!(result.is_some());
+
+        if let Some(RuleResult::String(s)) = result {
+            assert_eq!(s, "advanced");
+        } else {
+            panic!("Expected string result");
+        }
+    }
+
+    #[test]
+    fn test_less_than_or_equal_condition() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "field": "temperature",
+                        "op": "le",
+                        "value": "25.5"
+                    },
+                    "then": "cool"
+                }
+            ]
+        }
+        "#;
+
+        let mut params = HashMap::new();
+        params.insert("temperature".to_string(), "23.8".to_string());
+
+        let result = evaluate_json(json, &params).unwrap();
+        assert!(result.is_some());
+
+        if let Some(RuleResult::String(s)) = result {
+            assert_eq!(s, "cool");
+        } else {
+            panic!("Expected string result");
+        }
+    }
+
+    #[test]
+    fn test_numeric_comparison_with_invalid_numbers() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "field": "value",
+                        "op": "gt",
+                        "value": "10"
+                    },
+                    "then": "valid"
+                }
+            ],
+            "fallback": "invalid"
+        }
+        "#;
+
+        // 测试无效数字
+        let mut params = HashMap::new();
+        params.insert("value".to_string(), "not_a_number".to_string());
+
+        let result = evaluate_json(json, &params).unwrap();
+        assert!(result.is_some());
+
+        if let Some(RuleResult::String(s)) = result {
+            assert_eq!(s, "invalid"); // 应该使用fallback
+        } else {
+            panic!("Expected string result");
+        }
+    }
+
+    #[test]
+    fn test_numeric_comparison_with_decimal_numbers() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "field": "price",
+                        "op": "ge",
+                        "value": "99.99"
+                    },
+                    "then": "expensive"
+                }
+            ]
+        }
+        "#;
+
+        let mut params = HashMap::new();
+        params.insert("price".to_string(), "100.50".to_string());
+
+        let result = evaluate_json(json, &params).unwrap();
+        assert!(result.is_some());
+
+        if let Some(RuleResult::String(s)) = result {
+            assert_eq!(s, "expensive");
+        } else {
+            panic!("Expected string result");
+        }
+    }
+
+    #[test]
+    fn test_numeric_comparison_with_large_integers() {
+        let json = r#"
+        {
+            "rules": [
+                { "if": { "field": "id", "op": "equals", "value": 18446744073709551615 }, "then": "max" },
+                { "if": { "field": "id", "op": "gt", "value": "9007199254740992" }, "then": "gt" },
+                { "if": { "field": "id", "op": "between", "value": [9007199254740990, 9007199254740991] }, "then": "between" },
+                { "if": { "field": "id", "op": "in", "value": [9007199254740989] }, "then": "in" }
+            ]
+        }
+        "#;
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+        let evaluate = |id: &str| {
+            let params = HashMap::from([("id".to_string(), id.to_string())]);
+            let result = evaluator.evaluate(&params);
+            // Traces evaluate the conditions without compiling them
+            assert_eq!(result, evaluator.evaluate_with_trace(&params).result);
+            result.map(|result| result.deserialize_into::<String>().unwrap())
+        };
+        // All of these are the same f64
+        assert_eq!(evaluate("9007199254740993").as_deref(), Some("gt"));
+        assert_eq!(evaluate("9007199254740992"), None);
+        assert_eq!(evaluate("18446744073709551615").as_deref(), Some("max"));
+        assert_eq!(evaluate("18446744073709551614").as_deref(), Some("gt"));
+        assert_eq!(evaluate("9007199254740991").as_deref(), Some("between"));
+        assert_eq!(evaluate("9007199254740989").as_deref(), Some("in"));
+        assert_eq!(evaluate("9007199254740988"), None);
+    }
+
+    #[cfg(feature = "decimal")]
+    #[test]
+    fn test_decimal_comparisons() {
+        let json = r#"
+        {
+            "rules": [
+                { "if": { "field": "paid", "op": "ge", "value": "${price} + ${shipping}" }, "then": "settled" },
+                { "if": { "field": "paid", "op": "gt", "value": 0.3 }, "then": "over" }
+            ]
+        }
+        "#;
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+        let evaluate = |paid: &str| {
+            let params = HashMap::from([
+                ("paid".to_string(), paid.to_string()),
+                ("price".to_string(), "0.1".to_string()),
+                ("shipping".to_string(), "0.2".to_string()),
+            ]);
+            let result = evaluator.evaluate(&params);
+            assert_eq!(result, evaluator.evaluate_with_trace(&params).result);
+            result.map(|result| result.deserialize_into::<String>().unwrap())
+        };
+        // 0.1 + 0.2 is 0.30000000000000004 in f64
+        assert_eq!(evaluate("0.3").as_deref(), Some("settled"));
+        assert_eq!(evaluate("0.29").as_deref(), None);
+        assert_eq!(evaluate("0.30000000000000001").as_deref(), Some("settled"));
+    }
+
+    #[test]
+    fn test_typed_condition_values() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "and": [
+                            { "field": "memory_gb", "op": "equals", "value": 2 },
+                            { "field": "hdr", "op": "equals", "value": true },
+                            { "field": "debug", "op": "equals", "value": null }
+                        ]
+                    },
+                    "then": "chip_hdr"
+                },
+                {
+                    "if": { "field": "score", "op": "ge", "value": 80 },
+                    "then": "chip_high"
+                }
+            ]
+        }
+        "#;
+
+        let mut params = HashMap::new();
+        params.insert("memory_gb".to_string(), "2.0".to_string());
+        params.insert("hdr".to_string(), "true".to_string());
+        let result = evaluate_json(json, &params).unwrap();
+        assert_eq!(result, Some(RuleResult::String("chip_hdr".to_string())));
+
+        // `equals null` requires the param to be missing
+        params.insert("debug".to_string(), "1".to_string());
+        assert!(evaluate_json(json, &params).unwrap().is_none());
+
+        let mut params = HashMap::new();
+        params.insert("score".to_string(), "85".to_string());
+        let result = evaluate_json(json, &params).unwrap();
+        assert_eq!(result, Some(RuleResult::String("chip_high".to_string())));
+    }
+
+    #[test]
+    fn test_evaluate_value() {
+        #[derive(Serialize)]
+        struct Device {
+            platform: &'static str,
+            memory_gb: f64,
+            hdr: bool,
+            debug: Option<bool>,
+        }
+
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "and": [
+                            { "field": "platform", "op": "prefix", "value": "RTD" },
+                            { "field": "memory_gb", "op": "ge", "value": 2 },
+                            { "field": "hdr", "op": "equals", "value": true },
+                            { "field": "debug", "op": "equals", "value": null }
+                        ]
+                    },
+                    "then": "chip_rtd_hdr"
+                }
+            ]
+        }
+        "#;
+
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+        let device = Device {
+            platform: "RTD-2000",
+            memory_gb: 2.5,
+            hdr: true,
+            debug: None,
+        };
+        assert_eq!(
+            evaluator.evaluate_value(&device).unwrap(),
+            Some(RuleResult::String("chip_rtd_hdr".to_string()))
+        );
+
+        let params = serde_json::json!({ "platform": "RTD-2000", "memory_gb": 1, "hdr": true });
+        assert_eq!(evaluator.evaluate_value(&params).unwrap(), None);
+        assert!(evaluator
+            .evaluate_value(&serde_json::json!([1, 2]))
+            .is_err());
+    }
+
+    #[test]
+    fn test_dot_path_fields() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "and": [
+                            { "field": "device.info.platform", "op": "prefix", "value": "RTD" },
+                            { "field": "device.panels[0].hdr", "op": "equals", "value": true }
+                        ]
+                    },
+                    "then": "chip_rtd_hdr"
+                }
+            ]
+        }
+        "#;
+
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+        let params = serde_json::json!({
+            "device": {
+                "info": { "platform": "RTD-2000" },
+                "panels": [{ "hdr": true }]
+            }
+        });
+        assert_eq!(
+            evaluator.evaluate_value(&params).unwrap(),
+            Some(RuleResult::String("chip_rtd_hdr".to_string()))
+        );
+
+        let invalid = json.replace("device.panels[0].hdr", "device.panels[first].hdr");
+        let err = validate_json(&invalid).unwrap_err().to_string();
+        assert!(
+            err.contains("Invalid field path 'device.panels[first].hdr' in rule 0"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_validation_unsupported_value_type() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": { "field": "platform", "op": "prefix", "value": 42 },
+                    "then": "chip_rtd"
+                }
+            ]
+        }
+        "#;
+
+        let err = validate_json(json).unwrap_err().to_string();
+        assert!(
+            err.contains("Operator 'prefix' does not support number values in rule 0"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_semver_conditions() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": {
+                        "and": [
+                            { "field": "firmware", "op": "semver_ge", "value": "1.10.0" },
+                            { "field": "firmware", "op": "semver_lt", "value": "2" }
+                        ]
+                    },
+                    "then": "chip_v1_late"
+                }
+            ]
+        }
+        "#;
+
+        let mut params = HashMap::new();
+        params.insert("firmware".to_string(), "1.10.2".to_string());
+        let result = evaluate_json(json, &params).unwrap();
+        assert_eq!(result, Some(RuleResult::String("chip_v1_late".to_string())));
+
+        // 1.9.0 > 1.10.0 as a float, but not as a version
+        params.insert("firmware".to_string(), "1.9.0".to_string());
+        assert!(evaluate_json(json, &params).unwrap().is_none());
+
+        let invalid = json.replace(r#""1.10.0""#, r#""latest""#);
+        let err = validate_json(&invalid).unwrap_err().to_string();
+        assert!(
+            err.contains("Invalid version 'latest' in rule 0"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_evaluate_all_and_match_mode() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": { "field": "platform", "op": "prefix", "value": "RTD" },
+                    "then": { "flag": "hdr" }
+                },
+                {
+                    "if": { "field": "region", "op": "equals", "value": "CN" },
+                    "then": "cn_payments"
+                },
+                {
+                    "if": { "field": "region", "op": "equals", "value": "EU" },
+                    "then": "gdpr_banner"
+                }
+            ],
+            "fallback": "defaults"
+        }
+        "#;
+
+        let mut params = HashMap::new();
+        params.insert("platform".to_string(), "RTD-2000".to_string());
+        params.insert("region".to_string(), "CN".to_string());
+
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+        assert_eq!(
+            evaluator.evaluate_all(&params),
+            vec![
+                RuleResult::Object(serde_json::json!({ "flag": "hdr" })),
+                RuleResult::String("cn_payments".to_string()),
+            ]
+        );
+        assert_eq!(
+            evaluator.evaluate_all(&HashMap::<String, String>::new()),
+            vec![RuleResult::String("defaults".to_string())]
+        );
+
+        let mut rules: ConfigRules = serde_json::from_str(json).unwrap();
+        rules.match_mode = MatchMode::All;
+        let evaluator = ConfigEvaluator::new(rules).unwrap();
+        assert_eq!(
+            evaluator.evaluate(&params),
+            Some(RuleResult::Object(
+                serde_json::json!([{ "flag": "hdr" }, "cn_payments"])
+            ))
+        );
+    }
+
+    #[test]
+    fn test_rule_priority() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": { "field": "platform", "op": "prefix", "value": "RTD" },
+                    "then": "chip_rtd"
+                },
+                {
+                    "if": { "field": "platform", "op": "equals", "value": "RTD-2000" },
+                    "then": "chip_rtd_2000",
+                    "priority": 10
+                },
+                {
+                    "if": { "field": "platform", "op": "prefix", "value": "RTD" },
+                    "then": "chip_rtd_late"
+                }
+            ]
+        }
+        "#;
+
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+        assert_eq!(evaluator.rule_order(), &[1, 0, 2]);
+
+        let mut params = HashMap::new();
+        params.insert("platform".to_string(), "RTD-2000".to_string());
+        assert_eq!(
+            evaluator.evaluate(&params),
+            Some(RuleResult::String("chip_rtd_2000".to_string()))
+        );
+        params.insert("platform".to_string(), "RTD-1000".to_string());
+        assert_eq!(
+            evaluator.evaluate(&params),
+            Some(RuleResult::String("chip_rtd".to_string()))
+        );
+
+        // The order is rebuilt when a serialized evaluator is loaded
+        let restored: ConfigEvaluator =
+            serde_json::from_str(&serde_json::to_string(&evaluator).unwrap()).unwrap();
+        assert_eq!(restored.rule_order(), &[1, 0, 2]);
+    }
 
     #[test]
-    fn test_simple_condition() {
+    fn test_pre_phase_rewrites_params() {
         let json = r#"
         {
+            "pre": [
+                {
+                    "if": { "field": "platform", "op": "prefix", "value": "rtd" },
+                    "then": { "platform": "RTD" }
+                }
+            ],
             "rules": [
                 {
-                    "if": {
-                        "field": "platform",
-                        "op": "equals",
-                        "value": "RTD"
-                    },
+                    "if": { "field": "platform", "op": "equals", "value": "RTD" },
                     "then": "chip_rtd"
                 }
             ]
@@ -283,595 +5804,1124 @@ mod tests {
         "#;
 
         let mut params = HashMap::new();
-        params.insert("platform".to_string(), "RTD".to_string());
+        params.insert("platform".to_string(), "rtd-2000".to_string());
 
         let result = evaluate_json(json, &params).unwrap();
-        assert!(result.is_some());
+        assert_eq!(result, Some(RuleResult::String("chip_rtd".to_string())));
+    }
 
-        if let Some(RuleResult::String(s)) = result {
-            assert_eq!(s, "chip_rtd");
-        } else {
-            panic!("Expected string result");
+    #[test]
+    fn test_pre_phase_stages() {
+        let json = r#"
+        {
+            "pre": [
+                { "if": "platform prefix \"RTD\"", "then": { "device": { "class": "tv", "inches": 55 } }, "stage": "classify" },
+                { "if": "platform prefix \"R\"", "then": { "device": { "class": "router" } }, "stage": "classify" },
+                { "if": "device.class == \"tv\" && device.inches >= 50", "then": { "bundle": "tv_large" }, "stage": "bundle" },
+                { "if": "device.class == \"tv\"", "then": { "bundle": "tv" }, "stage": "bundle" },
+                { "if": "region == \"CN\"", "then": { "bundle": "cn" } }
+            ],
+            "rules": [
+                { "if": "bundle == \"tv_large\"", "then": "config_tv_large" },
+                { "if": "device.class == \"router\"", "then": "config_router" }
+            ]
         }
+        "#;
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+        let evaluate = |params: &[(&str, &str)]| {
+            evaluator
+                .evaluate(&params.iter().copied().collect::<HashMap<_, _>>())
+                .map(|result| result.deserialize_into::<String>().unwrap())
+        };
+        assert_eq!(
+            evaluate(&[("platform", "RTD-2000")]),
+            Some("config_tv_large".to_string())
+        );
+        assert_eq!(
+            evaluate(&[("platform", "RT-AX88")]),
+            Some("config_router".to_string())
+        );
+        // Rules without a stage still all apply
+        assert_eq!(
+            evaluate(&[("platform", "RTD-2000"), ("region", "CN")]),
+            None
+        );
+
+        let json = r#"{ "rules": [{ "if": "a == \"b\"", "then": "c", "stage": "s" }] }"#;
+        let error = validate_json(json).unwrap_err();
+        assert_eq!(error.error_code(), "unsupported_stage");
+        assert_eq!(error.rule_error().unwrap().pointer, "/rules/0/stage");
     }
 
     #[test]
-    fn test_and_condition() {
+    fn test_post_phase_veto_and_annotate() {
         let json = r#"
         {
             "rules": [
                 {
-                    "if": {
-                        "and": [
-                            { "field": "platform", "op": "contains", "value": "RTD" },
-                            { "field": "region", "op": "equals", "value": "CN" }
-                        ]
-                    },
-                    "then": "chip_rtd_cn"
+                    "if": { "field": "platform", "op": "equals", "value": "RTD" },
+                    "then": { "chip": "rtd" }
+                }
+            ],
+            "post": [
+                {
+                    "if": { "field": "region", "op": "equals", "value": "EU" },
+                    "then": { "veto": true }
+                },
+                {
+                    "if": { "field": "region", "op": "equals", "value": "CN" },
+                    "then": { "reviewed": true }
                 }
             ]
         }
         "#;
 
         let mut params = HashMap::new();
-        params.insert("platform".to_string(), "RTD-2000".to_string());
+        params.insert("platform".to_string(), "RTD".to_string());
         params.insert("region".to_string(), "CN".to_string());
 
         let result = evaluate_json(json, &params).unwrap();
-        assert!(result.is_some());
+        assert_eq!(
+            result,
+            Some(RuleResult::Object(
+                serde_json::json!({ "chip": "rtd", "reviewed": true })
+            ))
+        );
 
-        if let Some(RuleResult::String(s)) = result {
-            assert_eq!(s, "chip_rtd_cn");
-        } else {
-            panic!("Expected string result");
+        params.insert("region".to_string(), "EU".to_string());
+        let result = evaluate_json(json, &params).unwrap();
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn test_validation_phase_result_must_be_object() {
+        let json = r#"
+        {
+            "pre": [
+                {
+                    "if": { "field": "platform", "op": "equals", "value": "rtd" },
+                    "then": "RTD"
+                }
+            ],
+            "rules": []
         }
+        "#;
+
+        let result = validate_json(json);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Result of pre rule 0 must be a JSON object"));
     }
 
     #[test]
-    fn test_or_condition() {
+    fn test_assertion_rule_fails_evaluation() {
         let json = r#"
         {
             "rules": [
                 {
                     "if": {
-                        "or": [
-                            { "field": "platform", "op": "equals", "value": "MT9950" },
-                            { "field": "platform", "op": "equals", "value": "MT9638" }
+                        "and": [
+                            { "field": "platform", "op": "equals", "value": "RTD" },
+                            { "field": "region", "op": "equals", "value": "EU" }
                         ]
                     },
-                    "then": "chip_mt"
+                    "then": { "error": "RTD is not available in EU" }
+                },
+                {
+                    "if": { "field": "platform", "op": "equals", "value": "RTD" },
+                    "then": "chip_rtd"
                 }
             ]
         }
         "#;
 
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
         let mut params = HashMap::new();
-        params.insert("platform".to_string(), "MT9950".to_string());
+        params.insert("platform".to_string(), "RTD".to_string());
+        params.insert("region".to_string(), "EU".to_string());
 
-        let result = evaluate_json(json, &params).unwrap();
-        assert!(result.is_some());
+        let err = evaluator.evaluate_checked(&params).unwrap_err();
+        assert!(matches!(err, ConfigExprError::ValidationError(_)));
+        assert!(err.to_string().contains("RTD is not available in EU"));
+        assert!(evaluator.evaluate(&params).is_none());
 
-        if let Some(RuleResult::String(s)) = result {
-            assert_eq!(s, "chip_mt");
-        } else {
-            panic!("Expected string result");
-        }
+        params.insert("region".to_string(), "CN".to_string());
+        let result = evaluator.evaluate_checked(&params).unwrap();
+        assert_eq!(result, Some(RuleResult::String("chip_rtd".to_string())));
     }
 
     #[test]
-    fn test_prefix_condition() {
+    fn test_computed_result_expression() {
         let json = r#"
         {
             "rules": [
                 {
-                    "if": {
-                        "field": "platform",
-                        "op": "prefix",
-                        "value": "Hi"
-                    },
-                    "then": "chip_hi"
+                    "if": { "field": "platform", "op": "equals", "value": "RTD" },
+                    "then": {
+                        "timeout_ms": "= base_timeout * 1.5",
+                        "retries": ["= (base_timeout - 1000) / 500"],
+                        "label": "==literal"
+                    }
                 }
             ]
         }
         "#;
 
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
         let mut params = HashMap::new();
-        params.insert("platform".to_string(), "Hi3516".to_string());
+        params.insert("platform".to_string(), "RTD".to_string());
+        params.insert("base_timeout".to_string(), "2000".to_string());
 
-        let result = evaluate_json(json, &params).unwrap();
-        assert!(result.is_some());
+        let result = evaluator.evaluate_checked(&params).unwrap();
+        assert_eq!(
+            result,
+            Some(RuleResult::Object(serde_json::json!({
+                "timeout_ms": 3000,
+                "retries": [2],
+                "label": "=literal"
+            })))
+        );
 
-        if let Some(RuleResult::String(s)) = result {
-            assert_eq!(s, "chip_hi");
-        } else {
-            panic!("Expected string result");
+        params.remove("base_timeout");
+        let err = evaluator.evaluate_checked(&params).unwrap_err();
+        assert!(matches!(err, ConfigExprError::FieldNotFound(_)));
+    }
+
+    #[test]
+    fn test_expr_result() {
+        let json = r#"
+        {
+            "rules": [
+                { "if": "tier == \"gold\"", "then": { "$expr": "price * 0.9" } },
+                { "if": "tier == \"silver\"", "then": { "limit": { "$expr": "base + 10" }, "tier": "silver" } }
+            ],
+            "post": [{ "if": "region == \"EU\"", "then": { "vat": true } }]
         }
+        "#;
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+        assert!(evaluator.rules().referenced_fields().contains("price"));
+
+        let params = HashMap::from([("tier", "gold"), ("price", "200"), ("region", "EU")]);
+        assert_eq!(
+            evaluator.evaluate(&params),
+            Some(RuleResult::Object(serde_json::json!(180)))
+        );
+        assert_eq!(
+            evaluator.evaluate_typed::<f64>(&params).unwrap(),
+            Some(180.0)
+        );
+
+        let params = HashMap::from([("tier", "silver"), ("base", "5")]);
+        assert_eq!(
+            evaluator.evaluate(&params),
+            Some(RuleResult::Object(
+                serde_json::json!({ "limit": 15, "tier": "silver" })
+            ))
+        );
+
+        let json = r#"{ "rules": [{ "if": "a == \"b\"", "then": { "$expr": "price *" } }] }"#;
+        let error = validate_json(json).unwrap_err();
+        assert_eq!(error.error_code(), "invalid_expression");
+        assert_eq!(error.rule_error().unwrap().pointer, "/rules/0/then/$expr");
     }
 
     #[test]
-    fn test_json_object_result() {
+    fn test_validation_invalid_result_expression() {
         let json = r#"
         {
             "rules": [
                 {
-                    "if": {
-                        "field": "platform",
-                        "op": "equals",
-                        "value": "RTD"
-                    },
-                    "then": {
-                        "chip": "rtd",
-                        "config": {
-                            "memory": "2GB",
-                            "cpu": "ARM"
-                        }
-                    }
+                    "if": { "field": "platform", "op": "equals", "value": "RTD" },
+                    "then": { "timeout_ms": "= base_timeout *" }
                 }
             ]
         }
         "#;
 
-        let mut params = HashMap::new();
-        params.insert("platform".to_string(), "RTD".to_string());
-
-        let result = evaluate_json(json, &params).unwrap();
-        assert!(result.is_some());
-
-        if let Some(RuleResult::Object(obj)) = result {
-            assert_eq!(obj["chip"], "rtd");
-            assert_eq!(obj["config"]["memory"], "2GB");
-        } else {
-            panic!("Expected object result");
-        }
+        let result = validate_json(json);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Invalid expression"));
     }
 
     #[test]
-    fn test_fallback() {
+    fn test_rule_rollout() {
         let json = r#"
         {
             "rules": [
                 {
-                    "if": {
-                        "field": "platform",
-                        "op": "equals",
-                        "value": "RTD"
-                    },
-                    "then": "chip_rtd"
+                    "if": { "field": "platform", "op": "equals", "value": "RTD" },
+                    "then": "new_chip_config",
+                    "rollout": { "percent": 5, "bucket_by": "device_id" }
                 }
             ],
-            "fallback": "default_chip"
+            "fallback": "old_chip_config"
         }
         "#;
 
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+        let selected = (0..2000)
+            .filter(|i| {
+                let mut params = HashMap::new();
+                params.insert("platform".to_string(), "RTD".to_string());
+                params.insert("device_id".to_string(), format!("device-{}", i));
+                evaluator.evaluate(&params)
+                    == Some(RuleResult::String("new_chip_config".to_string()))
+            })
+            .count();
+        assert!((50..150).contains(&selected), "selected {}", selected);
+
+        // Without the bucketing field the rule never participates
         let mut params = HashMap::new();
-        params.insert("platform".to_string(), "Unknown".to_string());
+        params.insert("platform".to_string(), "RTD".to_string());
+        assert_eq!(
+            evaluator.evaluate(&params),
+            Some(RuleResult::String("old_chip_config".to_string()))
+        );
+    }
 
-        let result = evaluate_json(json, &params).unwrap();
-        assert!(result.is_some());
+    #[test]
+    fn test_percent_lt_condition() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": { "field": "user_id", "op": "percent_lt", "value": "20", "seed": "exp1" },
+                    "then": "variant_b"
+                }
+            ],
+            "fallback": "variant_a"
+        }
+        "#;
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
 
-        if let Some(RuleResult::String(s)) = result {
-            assert_eq!(s, "default_chip");
-        } else {
-            panic!("Expected string result");
+        let mut selected = 0;
+        for i in 0..1000 {
+            let params = HashMap::from([("user_id".to_string(), format!("user-{}", i))]);
+            let result = evaluator.evaluate(&params);
+            // Stable across evaluations
+            assert_eq!(result, evaluator.evaluate(&params));
+            if result == Some(RuleResult::String("variant_b".to_string())) {
+                selected += 1;
+            }
         }
+        assert!((150..250).contains(&selected), "selected {}", selected);
+
+        // Matches the population of a rule-level rollout with the same salt
+        let params = HashMap::from([("user_id".to_string(), "user-7".to_string())]);
+        assert_eq!(
+            evaluator.evaluate(&params) == Some(RuleResult::String("variant_b".to_string())),
+            bucketing::in_percentage("user-7", "exp1", 20.0)
+        );
+
+        assert_eq!(
+            evaluator.evaluate(&HashMap::<String, String>::new()),
+            Some(RuleResult::String("variant_a".to_string()))
+        );
+
+        let invalid = json.replace(r#""value": "20""#, r#""value": "120""#);
+        assert!(validate_json(&invalid)
+            .unwrap_err()
+            .to_string()
+            .contains("Percentage must be a number between 0 and 100"));
+
+        let misplaced = json.replace(r#""op": "percent_lt""#, r#""op": "equals""#);
+        assert!(validate_json(&misplaced)
+            .unwrap_err()
+            .to_string()
+            .contains("Seed only applies to 'percent_lt'"));
     }
 
     #[test]
-    fn test_no_match_no_fallback() {
+    fn test_bucket_in_condition() {
         let json = r#"
         {
             "rules": [
                 {
-                    "if": {
-                        "field": "platform",
-                        "op": "equals",
-                        "value": "RTD"
-                    },
-                    "then": "chip_rtd"
+                    "if": { "field": "user_id", "op": "bucket_in", "value": { "buckets": 100, "from": 10, "to": 29 }, "seed": "exp1" },
+                    "then": "variant_b"
                 }
-            ]
+            ],
+            "fallback": "variant_a"
         }
         "#;
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
 
-        let mut params = HashMap::new();
-        params.insert("platform".to_string(), "Unknown".to_string());
+        // Matches the buckets other services compute with the same salt
+        for i in 0..200 {
+            let user = format!("user-{}", i);
+            let params = HashMap::from([("user_id".to_string(), user.clone())]);
+            let in_range = (10..=29).contains(&bucketing::bucket(&user, "exp1", 100));
+            assert_eq!(
+                evaluator.evaluate(&params),
+                Some(RuleResult::String(
+                    if in_range { "variant_b" } else { "variant_a" }.to_string()
+                ))
+            );
+        }
 
-        let result = evaluate_json(json, &params).unwrap();
-        assert!(result.is_none());
+        let error = |value: &str| {
+            validate_json(&json.replace(r#"{ "buckets": 100, "from": 10, "to": 29 }"#, value))
+                .unwrap_err()
+                .error_code()
+        };
+        assert_eq!(
+            error(r#"{ "buckets": 100, "from": 10, "to": 100 }"#),
+            "invalid_bucket_range"
+        );
+        assert_eq!(
+            error(r#"{ "buckets": 100, "from": 30, "to": 29 }"#),
+            "invalid_bucket_range"
+        );
+        assert_eq!(error(r#"{ "buckets": 100 }"#), "invalid_bucket_range");
+        assert_eq!(error(r#""10-29""#), "unsupported_value");
     }
 
     #[test]
-    fn test_regex_condition() {
+    fn test_validation_rollout_percent() {
         let json = r#"
         {
             "rules": [
                 {
-                    "if": {
-                        "field": "platform",
-                        "op": "regex",
-                        "value": "^Hi\\d+"
-                    },
-                    "then": "chip_hi"
+                    "if": { "field": "platform", "op": "equals", "value": "RTD" },
+                    "then": "chip_rtd",
+                    "rollout": { "percent": 150, "bucket_by": "device_id" }
                 }
             ]
         }
         "#;
 
-        let mut params = HashMap::new();
-        params.insert("platform".to_string(), "Hi3516".to_string());
-
-        let result = evaluate_json(json, &params).unwrap();
-        assert!(result.is_some());
-
-        if let Some(RuleResult::String(s)) = result {
-            assert_eq!(s, "chip_hi");
-        } else {
-            panic!("Expected string result");
-        }
+        let result = validate_json(json);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Rollout percent must be between 0 and 100"));
     }
 
     #[test]
-    fn test_suffix_condition() {
+    fn test_rule_schedule() {
         let json = r#"
         {
             "rules": [
                 {
-                    "if": {
-                        "field": "platform",
-                        "op": "suffix",
-                        "value": "Pro"
-                    },
-                    "then": "chip_pro"
+                    "if": { "field": "platform", "op": "equals", "value": "RTD" },
+                    "then": "maintenance_mode",
+                    "schedule": { "days": ["sat", "sun"], "hours": "02:00-04:00", "timezone": "+08:00" }
                 }
-            ]
+            ],
+            "fallback": "normal_mode"
         }
         "#;
 
         let mut params = HashMap::new();
-        params.insert("platform".to_string(), "RTD-Pro".to_string());
+        params.insert("platform".to_string(), "RTD".to_string());
 
-        let result = evaluate_json(json, &params).unwrap();
-        assert!(result.is_some());
+        // Saturday 2024-03-02T03:00:00+08:00
+        let evaluator = ConfigEvaluator::from_json(json)
+            .unwrap()
+            .with_clock(FixedClock::from_unix_seconds(1_709_319_600));
+        assert_eq!(
+            evaluator.evaluate(&params),
+            Some(RuleResult::String("maintenance_mode".to_string()))
+        );
 
-        if let Some(RuleResult::String(s)) = result {
-            assert_eq!(s, "chip_pro");
-        } else {
-            panic!("Expected string result");
-        }
+        // One day earlier is a Friday
+        let evaluator = evaluator.with_clock(FixedClock::from_unix_seconds(1_709_233_200));
+        assert_eq!(
+            evaluator.evaluate(&params),
+            Some(RuleResult::String("normal_mode".to_string()))
+        );
     }
 
-    // #[test]
-    // fn test_validation_empty_rules() {
-    //     let json = r#"
-    //     {
-    //         "rules": []
-    //     }
-    //     "#;
+    #[test]
+    fn test_time_of_day_conditions() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": { "field": "now", "op": "time_between", "value": ["22:00", "06:00", "+08:00"] },
+                    "then": "night_mode"
+                },
+                {
+                    "if": { "field": "now", "op": "cron_match", "value": "*/15 9-17 * * mon-fri" },
+                    "then": "sync"
+                }
+            ],
+            "fallback": "day_mode"
+        }
+        "#;
+        // Saturday 2024-03-02T03:00:00+08:00
+        let evaluator = ConfigEvaluator::from_json(json)
+            .unwrap()
+            .with_clock(FixedClock::from_unix_seconds(1_709_319_600));
+        let evaluate = |now: Option<&str>| {
+            let params: HashMap<&str, &str> = now.map(|now| ("now", now)).into_iter().collect();
+            evaluator
+                .evaluate(&params)
+                .map(|result| result.deserialize_into::<String>().unwrap())
+                .unwrap()
+        };
+        assert_eq!(evaluate(None), "night_mode");
+        assert_eq!(evaluate(Some("2024-03-04T10:30:00Z")), "sync");
+        assert_eq!(evaluate(Some("2024-03-04T10:31:00Z")), "day_mode");
+        assert_eq!(evaluate(Some("2024-03-04T23:00:00+08:00")), "night_mode");
+        assert_eq!(evaluate(Some("tonight")), "day_mode");
 
-    //     let result = validate_json(json);
-    //     assert!(result.is_err());
-    //     assert!(result
-    //         .unwrap_err()
-    //         .to_string()
-    //         .contains("Rules cannot be empty"));
-    // }
+        let error = |value: &str| {
+            validate_json(&json.replace(r#"["22:00", "06:00", "+08:00"]"#, value))
+                .unwrap_err()
+                .error_code()
+        };
+        assert_eq!(error(r#"["22:00"]"#), "invalid_schedule");
+        assert_eq!(error(r#"["25:00", "06:00"]"#), "invalid_schedule");
+        assert_eq!(error(r#"["22:00", "06:00", "CST"]"#), "invalid_schedule");
+        assert_eq!(error(r#""22:00-06:00""#), "unsupported_value");
+    }
 
     #[test]
-    fn test_validation_empty_field() {
+    fn test_rule_enabled_and_active_window() {
         let json = r#"
         {
             "rules": [
                 {
-                    "if": {
-                        "field": "",
-                        "op": "equals",
-                        "value": "RTD"
-                    },
-                    "then": "chip_rtd"
+                    "if": { "field": "platform", "op": "equals", "value": "RTD" },
+                    "then": "chip_disabled",
+                    "enabled": false
+                },
+                {
+                    "if": { "field": "platform", "op": "equals", "value": "RTD" },
+                    "then": "chip_campaign",
+                    "active_from": "2024-03-01T00:00:00+08:00",
+                    "active_until": "2024-03-08T00:00:00+08:00"
                 }
-            ]
+            ],
+            "fallback": "chip_default"
         }
         "#;
 
-        let result = validate_json(json);
-        assert!(result.is_err());
-        assert!(result
+        let mut params = HashMap::new();
+        params.insert("platform".to_string(), "RTD".to_string());
+
+        // 2024-03-02T03:00:00+08:00, inside the window
+        let evaluator = ConfigEvaluator::from_json(json)
+            .unwrap()
+            .with_clock(FixedClock::from_unix_seconds(1_709_319_600));
+        assert_eq!(
+            evaluator.evaluate(&params),
+            Some(RuleResult::String("chip_campaign".to_string()))
+        );
+
+        // The end of the window is exclusive
+        let evaluator = evaluator.with_clock(FixedClock::from_unix_seconds(1_709_827_200));
+        assert_eq!(
+            evaluator.evaluate(&params),
+            Some(RuleResult::String("chip_default".to_string()))
+        );
+
+        let invalid = json.replace("2024-03-01T00:00:00+08:00", "2024-03-01");
+        assert!(validate_json(&invalid)
             .unwrap_err()
             .to_string()
-            .contains("Field name cannot be empty"));
+            .contains("Invalid active_from timestamp '2024-03-01' in rule 1"));
+
+        let reversed = json.replace("2024-03-08", "2024-02-08");
+        assert!(validate_json(&reversed)
+            .unwrap_err()
+            .to_string()
+            .contains("active_from must be before active_until"));
     }
 
     #[test]
-    fn test_validation_invalid_regex() {
+    fn test_rule_dependencies() {
         let json = r#"
         {
             "rules": [
                 {
-                    "if": {
-                        "field": "platform",
-                        "op": "regex",
-                        "value": "[invalid"
-                    },
+                    "id": "rtd_eu",
+                    "if": { "field": "platform", "op": "equals", "value": "RTD" },
+                    "then": "chip_rtd_eu",
+                    "requires": ["rtd_base"],
+                    "conflicts_with": ["rtd_legacy"]
+                },
+                {
+                    "id": "rtd_base",
+                    "if": { "field": "platform", "op": "prefix", "value": "RTD" },
                     "then": "chip_rtd"
                 }
             ]
         }
         "#;
+        assert!(validate_json(json).is_ok());
 
-        let result = validate_json(json);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("Invalid regex"));
+        let missing = json.replace(r#"["rtd_base"]"#, r#"["rtd_core"]"#);
+        assert!(validate_json(&missing)
+            .unwrap_err()
+            .to_string()
+            .contains("Rule 'rtd_eu' requires missing rule 'rtd_core'"));
+
+        let conflict = json.replace(r#"["rtd_legacy"]"#, r#"["rtd_base"]"#);
+        assert!(validate_json(&conflict)
+            .unwrap_err()
+            .to_string()
+            .contains("Rule 'rtd_eu' conflicts with rule 'rtd_base'"));
+        // Disabling either rule of the pair resolves the conflict
+        let disabled_base = conflict.replace(
+            r#""id": "rtd_base","#,
+            r#""id": "rtd_base", "enabled": false,"#,
+        );
+        assert!(validate_json(&disabled_base).is_ok());
+        let disabled_eu =
+            conflict.replace(r#""id": "rtd_eu","#, r#""id": "rtd_eu", "enabled": false,"#);
+        assert!(validate_json(&disabled_eu).is_ok());
+
+        let duplicate = json.replace(r#""id": "rtd_base""#, r#""id": "rtd_eu""#);
+        assert!(validate_json(&duplicate)
+            .unwrap_err()
+            .to_string()
+            .contains("Duplicate rule id 'rtd_eu'"));
     }
 
     #[test]
-    fn test_validation_empty_and_condition() {
+    fn test_rule_dependency_cycle() {
         let json = r#"
         {
             "rules": [
                 {
-                    "if": {
-                        "and": []
-                    },
-                    "then": "chip_rtd"
+                    "id": "a",
+                    "if": { "field": "platform", "op": "equals", "value": "RTD" },
+                    "then": "a",
+                    "requires": ["b"]
+                },
+                {
+                    "id": "b",
+                    "if": { "field": "platform", "op": "equals", "value": "MT" },
+                    "then": "b",
+                    "requires": ["a"]
                 }
             ]
         }
         "#;
 
-        let result = validate_json(json);
-        assert!(result.is_err());
-        assert!(result
-            .unwrap_err()
-            .to_string()
-            .contains("AND condition cannot be empty"));
+        let err = validate_json(json).unwrap_err().to_string();
+        assert!(err.contains("Rule dependency cycle"), "{}", err);
     }
 
     #[test]
-    fn test_validation_empty_or_condition() {
+    fn test_validate_params() {
         let json = r#"
         {
             "rules": [
                 {
-                    "if": {
-                        "or": []
-                    },
+                    "if": { "field": "platform", "op": "equals", "value": "RTD" },
                     "then": "chip_rtd"
                 }
-            ]
+            ],
+            "params_spec": {
+                "platform": { "required": true, "pattern": "^[A-Z0-9-]+$" },
+                "region": { "enum": ["CN", "EU", "US"] }
+            }
         }
         "#;
 
-        let result = validate_json(json);
-        assert!(result.is_err());
-        assert!(result
-            .unwrap_err()
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+        let mut params = HashMap::new();
+        let err = evaluator.validate_params(&params).unwrap_err();
+        assert!(err
             .to_string()
-            .contains("OR condition cannot be empty"));
+            .contains("Missing required param 'platform'"));
+
+        params.insert("platform".to_string(), "rtd".to_string());
+        let err = evaluator.validate_params(&params).unwrap_err();
+        assert!(err.to_string().contains("does not match pattern"));
+
+        params.insert("platform".to_string(), "RTD".to_string());
+        assert!(evaluator.validate_params(&params).is_ok());
+
+        params.insert("region".to_string(), "JP".to_string());
+        let err = evaluator.validate_params(&params).unwrap_err();
+        assert!(err.to_string().contains("is not one of"));
     }
 
     #[test]
-    fn test_complex_nested_conditions() {
+    fn test_unused_params() {
         let json = r#"
         {
             "rules": [
                 {
                     "if": {
-                        "and": [
-                            {
-                                "or": [
-                                    { "field": "platform", "op": "prefix", "value": "Hi" },
-                                    { "field": "platform", "op": "prefix", "value": "MT" }
-                                ]
-                            },
-                            { "field": "region", "op": "equals", "value": "CN" }
+                        "or": [
+                            { "field": "platform", "op": "equals", "value": "RTD" },
+                            { "field": "chip", "op": "prefix", "value": "rtd" }
                         ]
                     },
-                    "then": "chip_cn"
+                    "then": { "timeout_ms": "= base_timeout * 2" },
+                    "rollout": { "percent": 50, "bucket_by": "device_id" }
                 }
             ]
         }
         "#;
 
-        let mut params = HashMap::new();
-        params.insert("platform".to_string(), "Hi3516".to_string());
-        params.insert("region".to_string(), "CN".to_string());
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+        let unused = evaluator.unused_params([
+            "platform",
+            "device_id",
+            "base_timeout",
+            "firmware",
+            "chip_name",
+        ]);
+        assert_eq!(
+            unused,
+            vec!["firmware".to_string(), "chip_name".to_string()]
+        );
+    }
 
-        let result = evaluate_json(json, &params).unwrap();
-        assert!(result.is_some());
+    #[test]
+    fn test_validated_rules_in_app_config() {
+        #[derive(Deserialize)]
+        struct AppConfig {
+            #[allow(dead_code)]
+            name: String,
+            chip_rules: ValidatedRules,
+        }
 
-        if let Some(RuleResult::String(s)) = result {
-            assert_eq!(s, "chip_cn");
-        } else {
-            panic!("Expected string result");
+        let json = r#"
+        {
+            "name": "device-service",
+            "chip_rules": {
+                "rules": [
+                    {
+                        "if": { "field": "platform", "op": "equals", "value": "RTD" },
+                        "then": "chip_rtd"
+                    }
+                ]
+            }
         }
+        "#;
+
+        let config: AppConfig = serde_json::from_str(json).unwrap();
+        let evaluator = config.chip_rules.into_evaluator();
+        let mut params = HashMap::new();
+        params.insert("platform".to_string(), "RTD".to_string());
+        assert_eq!(
+            evaluator.evaluate(&params),
+            Some(RuleResult::String("chip_rtd".to_string()))
+        );
+
+        let invalid = json.replace(
+            r#""op": "equals", "value": "RTD""#,
+            r#""op": "regex", "value": "[RTD""#,
+        );
+        let err = serde_json::from_str::<AppConfig>(&invalid)
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("Invalid regex"), "{}", err);
+        assert!(err.contains("line"), "{}", err);
     }
 
+    #[cfg(feature = "scripting")]
     #[test]
-    fn test_greater_than_condition() {
+    fn test_script_condition_and_transform() {
         let json = r#"
         {
             "rules": [
                 {
                     "if": {
-                        "field": "score",
-                        "op": "gt",
-                        "value": "80"
+                        "field": "serial",
+                        "op": "script",
+                        "value": "value.len() == 8 && value.starts_with(params.region)"
                     },
-                    "then": "high_score"
+                    "then": { "chip": "rtd", "memory_mb": 1024 },
+                    "transform": "result.memory_mb *= 2; result"
                 }
             ]
         }
         "#;
 
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
         let mut params = HashMap::new();
-        params.insert("score".to_string(), "85".to_string());
+        params.insert("serial".to_string(), "CN123456".to_string());
+        params.insert("region".to_string(), "CN".to_string());
+        assert_eq!(
+            evaluator.evaluate(&params),
+            Some(RuleResult::Object(
+                serde_json::json!({ "chip": "rtd", "memory_mb": 2048 })
+            ))
+        );
 
-        let result = evaluate_json(json, &params).unwrap();
-        assert!(result.is_some());
+        params.insert("region".to_string(), "EU".to_string());
+        assert!(evaluator.evaluate(&params).is_none());
+    }
 
-        if let Some(RuleResult::String(s)) = result {
-            assert_eq!(s, "high_score");
-        } else {
-            panic!("Expected string result");
+    #[cfg(not(feature = "scripting"))]
+    #[test]
+    fn test_validation_script_requires_feature() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": { "field": "serial", "op": "script", "value": "true" },
+                    "then": "chip_rtd"
+                }
+            ]
         }
+        "#;
 
-        // 测试不满足条件的情况
-        let mut params = HashMap::new();
-        params.insert("score".to_string(), "75".to_string());
-
-        let result = evaluate_json(json, &params).unwrap();
-        assert!(result.is_none());
+        let result = validate_json(json);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("require the `scripting` feature"));
     }
 
     #[test]
-    fn test_less_than_condition() {
+    fn test_interpolated_result() {
         let json = r#"
         {
             "rules": [
                 {
-                    "if": {
-                        "field": "age",
-                        "op": "lt",
-                        "value": "18"
-                    },
-                    "then": "minor"
+                    "if": { "field": "platform", "op": "exists" },
+                    "then": "chip_${platform}_${region}",
+                    "interpolate": true
+                },
+                {
+                    "if": { "field": "model", "op": "exists" },
+                    "then": { "chip": "chip_${model}", "price": "$$${price}", "limit": "= price * 2" },
+                    "interpolate": true
                 }
-            ]
+            ],
+            "fallback": "chip_${platform}"
         }
         "#;
 
-        let mut params = HashMap::new();
-        params.insert("age".to_string(), "16".to_string());
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+        let params = HashMap::from([
+            ("platform".to_string(), "RTD".to_string()),
+            ("region".to_string(), "CN".to_string()),
+        ]);
+        assert_eq!(
+            evaluator.evaluate(&params),
+            Some(RuleResult::String("chip_RTD_CN".to_string()))
+        );
 
-        let result = evaluate_json(json, &params).unwrap();
-        assert!(result.is_some());
+        let params = HashMap::from([
+            ("model".to_string(), "X1".to_string()),
+            ("price".to_string(), "5".to_string()),
+        ]);
+        assert_eq!(
+            evaluator.evaluate(&params),
+            Some(RuleResult::Object(
+                serde_json::json!({ "chip": "chip_X1", "price": "$5", "limit": 10 })
+            ))
+        );
 
-        if let Some(RuleResult::String(s)) = result {
-            assert_eq!(s, "minor");
-        } else {
-            panic!("Expected string result");
-        }
+        // Placeholders without a param fail the evaluation
+        let params = HashMap::from([("platform".to_string(), "RTD".to_string())]);
+        assert!(matches!(
+            evaluator.evaluate_checked(&params),
+            Err(ConfigExprError::MissingPlaceholder(field)) if field == "region"
+        ));
+
+        // Only rules with `interpolate` are rendered
+        assert_eq!(
+            evaluator.evaluate(&HashMap::<String, String>::new()),
+            Some(RuleResult::String("chip_${platform}".to_string()))
+        );
+        assert!(evaluator.rules.referenced_fields().contains("region"));
+
+        let invalid = json.replace("${region}", "${region");
+        assert!(matches!(
+            ConfigEvaluator::from_json(&invalid),
+            Err(ConfigExprError::InvalidRule(error)) if error.message.contains("rule 0")
+        ));
     }
 
     #[test]
-    fn test_greater_than_or_equal_condition() {
+    fn test_merge_strategy() {
         let json = r#"
         {
+            "merge_strategy": "all_merge_deep",
             "rules": [
                 {
-                    "if": {
-                        "field": "level",
-                        "op": "ge",
-                        "value": "5"
-                    },
-                    "then": "advanced"
+                    "if": { "field": "platform", "op": "exists" },
+                    "then": { "video": { "codec": "h264", "hdr": false }, "tags": ["base"] }
+                },
+                {
+                    "if": { "field": "platform", "op": "prefix", "value": "RTD" },
+                    "then": { "video": { "hdr": true }, "tags": ["rtd"] }
+                },
+                {
+                    "if": { "field": "region", "op": "equals", "value": "CN" },
+                    "then": { "region": "cn" }
                 }
-            ]
+            ],
+            "fallback": { "video": { "codec": "none" } }
         }
         "#;
 
-        let mut params = HashMap::new();
-        params.insert("level".to_string(), "5".to_string());
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+        let params = HashMap::from([
+            ("platform".to_string(), "RTD-2000".to_string()),
+            ("region".to_string(), "US".to_string()),
+        ]);
+        assert_eq!(
+            evaluator.evaluate(&params),
+            Some(RuleResult::Object(serde_json::json!({
+                "video": { "codec": "h264", "hdr": true },
+                "tags": ["rtd"]
+            })))
+        );
+        assert_eq!(
+            evaluator.evaluate(&HashMap::<String, String>::new()),
+            Some(RuleResult::Object(
+                serde_json::json!({ "video": { "codec": "none" } })
+            ))
+        );
 
-        let result = evaluate_json(json, &params).unwrap();
-        assert!(result.is_some());
+        let shallow = json.replace("all_merge_deep", "all_merge_shallow");
+        let evaluator = ConfigEvaluator::from_json(&shallow).unwrap();
+        assert_eq!(
+            evaluator.evaluate(&params),
+            Some(RuleResult::Object(serde_json::json!({
+                "video": { "hdr": true },
+                "tags": ["rtd"]
+            })))
+        );
 
-        if let Some(RuleResult::String(s)) = result {
-            assert_eq!(s, "advanced");
-        } else {
-            panic!("Expected string result");
-        }
+        let strings = json.replace(r#"{ "region": "cn" }"#, r#""cn""#);
+        assert!(ConfigEvaluator::from_json(&strings).is_err());
+        let all = json.replace(
+            r#""merge_strategy""#,
+            r#""match_mode": "all", "merge_strategy""#,
+        );
+        assert!(ConfigEvaluator::from_json(&all).is_err());
     }
 
     #[test]
-    fn test_less_than_or_equal_condition() {
+    fn test_evaluate_typed() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct ChipConfig {
+            chip: String,
+            #[serde(default)]
+            cores: u32,
+        }
+
+        #[derive(Debug, Deserialize, PartialEq)]
+        #[serde(rename_all = "snake_case")]
+        enum Tier {
+            Basic,
+        }
+
         let json = r#"
         {
             "rules": [
                 {
-                    "if": {
-                        "field": "temperature",
-                        "op": "le",
-                        "value": "25.5"
-                    },
-                    "then": "cool"
+                    "if": { "field": "platform", "op": "equals", "value": "RTD" },
+                    "then": { "chip": "rtd", "cores": 4 }
+                },
+                {
+                    "if": { "field": "platform", "op": "equals", "value": "MT" },
+                    "then": { "chip": 9950 }
                 }
-            ]
+            ],
+            "fallback": "basic"
         }
         "#;
 
-        let mut params = HashMap::new();
-        params.insert("temperature".to_string(), "23.8".to_string());
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+        let params = HashMap::from([("platform".to_string(), "RTD".to_string())]);
+        assert_eq!(
+            evaluator.evaluate_typed::<ChipConfig>(&params).unwrap(),
+            Some(ChipConfig {
+                chip: "rtd".to_string(),
+                cores: 4
+            })
+        );
 
-        let result = evaluate_json(json, &params).unwrap();
-        assert!(result.is_some());
+        let params = HashMap::from([("platform".to_string(), "MT".to_string())]);
+        assert!(matches!(
+            evaluator.evaluate_typed::<ChipConfig>(&params),
+            Err(ConfigExprError::JsonError(_))
+        ));
 
-        if let Some(RuleResult::String(s)) = result {
-            assert_eq!(s, "cool");
-        } else {
-            panic!("Expected string result");
-        }
+        let fallback = evaluator
+            .evaluate(&HashMap::<String, String>::new())
+            .unwrap();
+        assert_eq!(fallback.deserialize_into::<Tier>().unwrap(), Tier::Basic);
     }
 
     #[test]
-    fn test_numeric_comparison_with_invalid_numbers() {
+    fn test_evaluate_with_rule() {
         let json = r#"
         {
             "rules": [
                 {
-                    "if": {
-                        "field": "value",
-                        "op": "gt",
-                        "value": "10"
-                    },
-                    "then": "valid"
+                    "id": "rtd",
+                    "name": "Realtek boards",
+                    "if": { "field": "platform", "op": "prefix", "value": "RTD" },
+                    "then": "chip_rtd"
+                },
+                {
+                    "name": "MediaTek boards",
+                    "if": { "field": "platform", "op": "prefix", "value": "MT" },
+                    "then": "chip_mt"
+                },
+                {
+                    "if": { "field": "platform", "op": "prefix", "value": "Hi" },
+                    "then": "chip_hi"
                 }
             ],
-            "fallback": "invalid"
+            "fallback": "default_chip"
         }
         "#;
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
 
-        // 测试无效数字
-        let mut params = HashMap::new();
-        params.insert("value".to_string(), "not_a_number".to_string());
+        let params = HashMap::from([("platform".to_string(), "RTD-2000".to_string())]);
+        let (rule_id, result) = evaluator.evaluate_with_rule(&params).unwrap();
+        assert_eq!(
+            rule_id,
+            RuleId::Rule {
+                index: 0,
+                id: Some("rtd".to_string()),
+                name: Some("Realtek boards".to_string()),
+            }
+        );
+        assert_eq!(rule_id.to_string(), "rtd");
+        assert_eq!(result, RuleResult::String("chip_rtd".to_string()));
 
-        let result = evaluate_json(json, &params).unwrap();
-        assert!(result.is_some());
+        let params = HashMap::from([("platform".to_string(), "MT9950".to_string())]);
+        let (rule_id, _) = evaluator.evaluate_with_rule(&params).unwrap();
+        assert_eq!(rule_id.to_string(), "MediaTek boards");
 
-        if let Some(RuleResult::String(s)) = result {
-            assert_eq!(s, "invalid"); // 应该使用fallback
-        } else {
-            panic!("Expected string result");
-        }
+        let params = HashMap::from([("platform".to_string(), "Hi3516".to_string())]);
+        let (rule_id, _) = evaluator.evaluate_with_rule(&params).unwrap();
+        assert_eq!(rule_id.to_string(), "rule 2");
+        assert_eq!(
+            serde_json::to_value(&rule_id).unwrap(),
+            serde_json::json!({ "kind": "rule", "index": 2 })
+        );
+
+        let (rule_id, result) = evaluator
+            .evaluate_with_rule(&HashMap::<String, String>::new())
+            .unwrap();
+        assert_eq!(rule_id, RuleId::Fallback);
+        assert_eq!(result, RuleResult::String("default_chip".to_string()));
     }
 
     #[test]
-    fn test_numeric_comparison_with_decimal_numbers() {
+    fn test_evaluate_filtered() {
+        let json = r#"{
+            "pre": [{ "if": "beta == \"1\"", "then": { "tier": "beta" }, "tags": ["web"] }],
+            "rules": [
+                { "if": "tier == \"beta\"", "then": "beta_layout", "tags": ["web", "mobile"] },
+                { "if": "platform == \"ios\"", "then": "ios_layout", "tags": ["mobile"] },
+                { "if": "platform == \"ios\"", "then": "web_ios_layout", "tags": ["web"] }
+            ],
+            "fallback": "default_layout"
+        }"#;
+        let evaluator = ConfigEvaluator::from_json(json).unwrap().with_cache(16);
+        let params = HashMap::from([("platform", "ios"), ("beta", "1")]);
+        let evaluate = |tag: &str| {
+            evaluator
+                .evaluate_filtered(&params, |rule| rule.has_tag(tag))
+                .unwrap()
+                .deserialize_into::<String>()
+                .unwrap()
+        };
+
+        assert_eq!(
+            evaluator.evaluate(&params),
+            Some(RuleResult::String("beta_layout".to_string()))
+        );
+        // The pre-phase rule only applies to the web
+        assert_eq!(evaluate("web"), "beta_layout");
+        assert_eq!(evaluate("mobile"), "ios_layout");
+        assert_eq!(evaluate("tv"), "default_layout");
+        // Cached results of unfiltered evaluations are not reused
+        assert_eq!(
+            evaluator.evaluate(&params),
+            Some(RuleResult::String("beta_layout".to_string()))
+        );
+        assert_eq!(
+            evaluator.evaluate_filtered(&params, |rule| !rule.has_tag("mobile")),
+            Some(RuleResult::String("web_ios_layout".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_evaluate_ref() {
         let json = r#"
         {
             "rules": [
-                {
-                    "if": {
-                        "field": "price",
-                        "op": "ge",
-                        "value": "99.99"
-                    },
-                    "then": "expensive"
-                }
-            ]
+                { "if": "platform == \"RTD\"", "then": { "chip": "rtd", "features": ["hdr", "dolby"] } },
+                { "if": "platform == \"MT\"", "then": { "chip": "mt", "limit": "=score * 2" } }
+            ],
+            "post": [
+                { "if": "region == \"CN\"", "then": { "reviewed": true } }
+            ],
+            "fallback": "default_chip"
         }
         "#;
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+        let params = |platform, region| {
+            HashMap::from([("platform", platform), ("region", region), ("score", "21")])
+        };
 
-        let mut params = HashMap::new();
-        params.insert("price".to_string(), "100.50".to_string());
+        let result = evaluator.evaluate_ref(&params("RTD", "EU")).unwrap();
+        assert!(
+            matches!(&result, Cow::Borrowed(r) if core::ptr::eq(*r, &evaluator.rules().rules[0].result))
+        );
+        let fallback = evaluator.evaluate_ref(&params("Hi", "EU")).unwrap();
+        assert!(matches!(fallback, Cow::Borrowed(_)));
 
-        let result = evaluate_json(json, &params).unwrap();
-        assert!(result.is_some());
+        // Annotations and computed values build new results
+        let annotated = evaluator.evaluate_ref(&params("RTD", "CN")).unwrap();
+        assert!(matches!(annotated, Cow::Owned(_)));
+        assert_eq!(
+            Some(annotated.into_owned()),
+            evaluator.evaluate(&params("RTD", "CN"))
+        );
+        let computed = evaluator.evaluate_ref(&params("MT", "EU")).unwrap();
+        assert_eq!(
+            *computed,
+            RuleResult::Object(serde_json::json!({ "chip": "mt", "limit": 42 }))
+        );
+    }
 
-        if let Some(RuleResult::String(s)) = result {
-            assert_eq!(s, "expensive");
-        } else {
-            panic!("Expected string result");
+    #[test]
+    fn test_parse_and_validate() {
+        let rules =
+            parse_and_validate(br#"{ "rules": [{ "if": "platform == \"RTD\"", "then": "rtd" }] }"#)
+                .unwrap();
+        assert_eq!(rules.rules().rules.len(), 1);
+
+        assert_eq!(
+            parse_and_validate(b"\xff{").unwrap_err().error_code(),
+            "json_error"
+        );
+        assert_eq!(
+            parse_and_validate(br#"{ "rules": [{ "if": { "and": [] }, "then": "x" }] }"#)
+                .unwrap_err()
+                .error_code(),
+            "empty_condition"
+        );
+
+        // Deeply nested expressions fail instead of overflowing the stack
+        for condition in [
+            format!("{}platform == \"RTD\"", "!".repeat(100_000)),
+            "(".repeat(100_000),
+            format!("platform in {}", "[".repeat(100_000)),
+        ] {
+            let json = serde_json::json!({ "rules": [{ "if": condition, "then": "x" }] });
+            let error = parse_and_validate(json.to_string().as_bytes()).unwrap_err();
+            assert!(error.to_string().contains("nested too deeply"));
         }
+        let threshold = format!("${{base}}{}", " + (".repeat(100_000));
+        let json = serde_json::json!({
+            "rules": [{ "if": { "field": "score", "op": "gt", "value": threshold }, "then": "x" }]
+        });
+        assert!(parse_and_validate(json.to_string().as_bytes()).is_err());
     }
 }
@@ -0,0 +1,239 @@
+//! Limits on the size of rule sets
+//!
+//! Services loading rule files uploaded by users bound the memory and time
+//! spent on them with [`EvaluatorLimits`]. The limits are checked before the
+//! rules are validated and compiled; rule sets exceeding them fail with the
+//! `too_many_rules`, `condition_too_deep`, `too_many_conditions` or
+//! `regex_too_large` error codes.
+
+use crate::patterns::RegexConfig;
+use crate::prelude::*;
+use crate::report::Phase;
+use crate::validation::Site;
+use crate::{Condition, ConfigExprError, ConfigRules};
+use alloc::collections::BTreeMap;
+
+/// Limits a rule set must stay within to be loaded
+///
+/// Conditions are measured as they are compiled, with refs replaced by the
+/// named conditions. The defaults put no bound on rule sets and the `regex`
+/// crate's on patterns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EvaluatorLimits {
+    /// Most levels of nesting of a rule's condition; a simple condition is
+    /// one level deep, `{ "not": <simple> }` two
+    pub max_depth: usize,
+    /// Most simple conditions in the rules of all phases together
+    pub max_conditions: usize,
+    /// Most rules in all phases together
+    pub max_rules: usize,
+    /// Limits of compiled `regex` patterns
+    pub regex: RegexConfig,
+}
+
+impl Default for EvaluatorLimits {
+    fn default() -> Self {
+        EvaluatorLimits {
+            max_depth: usize::MAX,
+            max_conditions: usize::MAX,
+            max_rules: usize::MAX,
+            regex: RegexConfig::default(),
+        }
+    }
+}
+
+impl EvaluatorLimits {
+    /// Check that the rules stay within the limits
+    pub(crate) fn check(&self, rules: &ConfigRules) -> Result<(), ConfigExprError> {
+        let phases = [
+            (Phase::Pre, &rules.pre),
+            (Phase::Main, &rules.rules),
+            (Phase::Post, &rules.post),
+        ];
+        let mut sizes = Sizes {
+            definitions: &rules.definitions,
+            measured: BTreeMap::new(),
+        };
+        let mut count = 0usize;
+        let mut conditions = 0usize;
+        for (phase, phase_rules) in phases {
+            for (index, rule) in phase_rules.iter().enumerate() {
+                let site = Site::rule(phase, index);
+                count += 1;
+                if count > self.max_rules {
+                    return Err(site
+                        .error(
+                            "too_many_rules",
+                            format!("Rule limit of {} exceeded at {}", self.max_rules, site),
+                        )
+                        .into());
+                }
+
+                let size = sizes.measure(&rule.condition);
+                if size.depth > self.max_depth {
+                    return Err(site
+                        .at("if")
+                        .error(
+                            "condition_too_deep",
+                            format!(
+                                "Condition of {} is nested {} levels deep, more than the limit of {}",
+                                site, size.depth, self.max_depth
+                            ),
+                        )
+                        .into());
+                }
+                conditions = conditions.saturating_add(size.conditions);
+                if conditions > self.max_conditions {
+                    return Err(site
+                        .at("if")
+                        .error(
+                            "too_many_conditions",
+                            format!(
+                                "Condition limit of {} exceeded at {}",
+                                self.max_conditions, site
+                            ),
+                        )
+                        .into());
+                }
+            }
+        }
+
+        #[cfg(feature = "regex")]
+        self.regex.check(rules)?;
+        Ok(())
+    }
+}
+
+/// Nesting depth and number of simple conditions of a condition
+#[derive(Debug, Clone, Copy)]
+struct Size {
+    depth: usize,
+    conditions: usize,
+}
+
+/// Measures conditions, measuring each named condition once
+struct Sizes<'a> {
+    definitions: &'a BTreeMap<String, Condition>,
+    /// Sizes of the named conditions; `None` while being measured
+    measured: BTreeMap<&'a str, Option<Size>>,
+}
+
+impl<'a> Sizes<'a> {
+    fn measure(&mut self, condition: &'a Condition) -> Size {
+        match condition {
+            Condition::Simple { .. } => Size {
+                depth: 1,
+                conditions: 1,
+            },
+            Condition::And { and: nested } | Condition::Or { or: nested } => {
+                let mut size = Size {
+                    depth: 0,
+                    conditions: 0,
+                };
+                for condition in nested {
+                    let inner = self.measure(condition);
+                    size.depth = size.depth.max(inner.depth);
+                    size.conditions = size.conditions.saturating_add(inner.conditions);
+                }
+                size.depth = size.depth.saturating_add(1);
+                size
+            }
+            Condition::Not { not } => {
+                let inner = self.measure(not);
+                Size {
+                    depth: inner.depth.saturating_add(1),
+                    ..inner
+                }
+            }
+            Condition::Ref { name } => {
+                // Unknown and cyclic refs are left to validation
+                let unknown = Size {
+                    depth: 1,
+                    conditions: 0,
+                };
+                match self.measured.get(name.as_str()) {
+                    Some(size) => size.unwrap_or(unknown),
+                    None => match self.definitions.get_key_value(name) {
+                        Some((name, definition)) => {
+                            self.measured.insert(name, None);
+                            let size = self.measure(definition);
+                            self.measured.insert(name, Some(size));
+                            size
+                        }
+                        None => unknown,
+                    },
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ConfigEvaluator, RuleResult};
+
+    #[test]
+    fn test_evaluator_limits() {
+        let rules: ConfigRules = serde_json::from_str(
+            r#"{
+                "definitions": { "is_cn": { "or": ["region == \"CN\"", "region == \"HK\""] } },
+                "rules": [
+                    { "if": { "and": [{ "ref": "is_cn" }, { "not": "tier == \"free\"" }] }, "then": "cn_paid" },
+                    { "if": "platform == \"RTD\"", "then": "rtd" }
+                ],
+                "post": [{ "if": { "ref": "is_cn" }, "then": { "$merge": { "cn": true } } }]
+            }"#,
+        )
+        .unwrap();
+        let fits = EvaluatorLimits {
+            max_depth: 3,
+            max_conditions: 6,
+            max_rules: 3,
+            ..EvaluatorLimits::default()
+        };
+        let evaluator = ConfigEvaluator::new_with_limits(rules.clone(), fits).unwrap();
+        assert_eq!(evaluator.limits(), fits);
+        let params = HashMap::from([("region", "US"), ("platform", "RTD")]);
+        assert_eq!(
+            evaluator.evaluate(&params),
+            Some(RuleResult::String("rtd".to_string()))
+        );
+
+        let error = |limits: EvaluatorLimits| {
+            let error = ConfigEvaluator::new_with_limits(rules.clone(), limits).unwrap_err();
+            let rule_error = error.rule_error().unwrap().clone();
+            (rule_error.code, rule_error.pointer)
+        };
+        assert_eq!(
+            error(EvaluatorLimits {
+                max_rules: 2,
+                ..fits
+            }),
+            ("too_many_rules", "/post/0".to_string())
+        );
+        assert_eq!(
+            error(EvaluatorLimits {
+                max_depth: 2,
+                ..fits
+            }),
+            ("condition_too_deep", "/rules/0/if".to_string())
+        );
+        assert_eq!(
+            error(EvaluatorLimits {
+                max_conditions: 5,
+                ..fits
+            }),
+            ("too_many_conditions", "/post/0/if".to_string())
+        );
+
+        // Limits carry over to replacement rules
+        let evaluator = evaluator.with_rules(rules.clone()).unwrap();
+        let mut more = rules;
+        more.rules.push(more.rules[1].clone());
+        assert_eq!(
+            evaluator.with_rules(more).unwrap_err().error_code(),
+            "too_many_rules"
+        );
+    }
+}
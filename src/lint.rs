@@ -0,0 +1,245 @@
+//! Non-fatal checks of rule sets
+//!
+//! [`ConfigRules::lint`] reports rules that are valid but likely mistaken or
+//! needlessly slow, for review in CI. Unlike validation failures, warnings
+//! never prevent loading the rules.
+
+use crate::analysis::always_active;
+use crate::prelude::*;
+use crate::report::Phase;
+use crate::validation::Site;
+use crate::{expr, Condition, ConfigRules, Operator};
+use core::fmt;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Characters with a meaning in regex patterns
+const REGEX_META: &[char] = &[
+    '\\', '.', '+', '*', '?', '(', ')', '|', '[', ']', '{', '}', '^', '$', '#',
+];
+
+/// Problem found by [`ConfigRules::lint`]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LintWarning {
+    /// Stable reason code: `literal_regex`, `non_numeric_comparison`,
+    /// `duplicate_rule` or `unreachable_fallback`
+    pub code: &'static str,
+    /// Phase of the rule; `None` for the fallback and definitions
+    pub phase: Option<Phase>,
+    /// Index of the rule within its phase; `None` for the fallback and
+    /// definitions
+    pub rule_index: Option<usize>,
+    /// JSON pointer to the value in the rule document
+    pub pointer: String,
+    /// Human-readable description, naming the rule
+    pub message: String,
+}
+
+impl LintWarning {
+    fn new(site: &Site, code: &'static str, message: String) -> Self {
+        let error = site.error(code, message);
+        LintWarning {
+            code,
+            phase: error.phase,
+            rule_index: error.rule_index,
+            pointer: error.pointer,
+            message: error.message,
+        }
+    }
+}
+
+impl fmt::Display for LintWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl ConfigRules {
+    /// Find valid but suspicious rules
+    ///
+    /// - `regex` conditions whose pattern is a plain string, which
+    ///   `equals`, `prefix`, `suffix` or `contains` match faster
+    /// - numeric comparisons against values that are not numbers, which
+    ///   never match
+    /// - rules with the same condition and result as an earlier rule of
+    ///   their phase
+    /// - a fallback that is never used because an always-active main rule
+    ///   matches every input
+    pub fn lint(&self) -> Vec<LintWarning> {
+        let mut warnings = Vec::new();
+        let phases = [
+            (Phase::Pre, &self.pre),
+            (Phase::Main, &self.rules),
+            (Phase::Post, &self.post),
+        ];
+        for (phase, rules) in phases {
+            for (index, rule) in rules.iter().enumerate() {
+                let site = Site::rule(phase, index);
+                lint_condition(&rule.condition, &site.at("if"), &mut warnings);
+                let duplicated = rules[..index].iter().position(|earlier| {
+                    earlier.condition == rule.condition && earlier.result == rule.result
+                });
+                if let Some(earlier) = duplicated {
+                    warnings.push(LintWarning::new(
+                        &site,
+                        "duplicate_rule",
+                        format!(
+                            "{} has the same condition and result as {}",
+                            phase.label(index),
+                            phase.label(earlier)
+                        ),
+                    ));
+                }
+            }
+        }
+        for (name, condition) in &self.definitions {
+            lint_condition(condition, &Site::definition(name), &mut warnings);
+        }
+
+        if self.fallback.is_some() {
+            let catch_all = self.rules.iter().position(|rule| {
+                always_active(rule) && self.always_holds(&self.inline_refs(&rule.condition))
+            });
+            if let Some(index) = catch_all {
+                warnings.push(LintWarning::new(
+                    &Site::fallback(),
+                    "unreachable_fallback",
+                    format!(
+                        "Fallback is unreachable: {} matches every input",
+                        Phase::Main.label(index)
+                    ),
+                ));
+            }
+        }
+        warnings
+    }
+}
+
+/// Lint the simple conditions of a condition
+fn lint_condition(condition: &Condition, site: &Site, warnings: &mut Vec<LintWarning>) {
+    match condition {
+        Condition::Simple {
+            op,
+            value,
+            value_field: None,
+            ..
+        } => {
+            if let (Operator::Regex, Value::String(pattern)) = (op, value) {
+                if let Some(op) = literal_operator(pattern) {
+                    warnings.push(LintWarning::new(
+                        &site.at("op"),
+                        "literal_regex",
+                        format!(
+                            "Regex '{}' in {} has no special characters; use '{}' instead",
+                            pattern,
+                            site,
+                            op.name()
+                        ),
+                    ));
+                }
+            }
+            let numeric = match value {
+                Value::Number(_) => true,
+                Value::String(s) => s.trim().parse::<f64>().is_ok() || expr::has_placeholders(s),
+                _ => false,
+            };
+            if op.is_numeric() && !numeric {
+                warnings.push(LintWarning::new(
+                    &site.at("value"),
+                    "non_numeric_comparison",
+                    format!(
+                        "'{}' condition in {} compares with {}, which is not a number, so it never matches",
+                        op.name(),
+                        site,
+                        value
+                    ),
+                ));
+            }
+        }
+        Condition::Simple { .. } | Condition::Ref { .. } => {}
+        Condition::And { and } => {
+            for (i, c) in and.iter().enumerate() {
+                lint_condition(c, &site.at("and").at(i), warnings);
+            }
+        }
+        Condition::Or { or } => {
+            for (i, c) in or.iter().enumerate() {
+                lint_condition(c, &site.at("or").at(i), warnings);
+            }
+        }
+        Condition::Not { not } => lint_condition(not, &site.at("not"), warnings),
+    }
+}
+
+/// Operator matching the same values as a regex of a plain string, with
+/// optional `^` and `$` anchors
+fn literal_operator(pattern: &str) -> Option<Operator> {
+    let (start, rest) = match pattern.strip_prefix('^') {
+        Some(rest) => (true, rest),
+        None => (false, pattern),
+    };
+    let (end, literal) = match rest.strip_suffix('$') {
+        Some(literal) => (true, literal),
+        None => (false, rest),
+    };
+    if literal.is_empty() || literal.contains(REGEX_META) {
+        return None;
+    }
+    Some(match (start, end) {
+        (true, true) => Operator::Equals,
+        (true, false) => Operator::Prefix,
+        (false, true) => Operator::Suffix,
+        (false, false) => Operator::Contains,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lint() {
+        let rules: ConfigRules = serde_json::from_str(
+            r#"{
+                "definitions": { "is_rtd": { "field": "platform", "op": "regex", "value": "^RTD" } },
+                "rules": [
+                    { "if": { "and": ["region == \"CN\"", { "field": "score", "op": "gt", "value": "high" }] }, "then": "cn" },
+                    { "if": { "field": "platform", "op": "regex", "value": "^RTD-(2000|3000)$" }, "then": "rtd" },
+                    { "if": { "and": ["region == \"CN\"", { "field": "score", "op": "gt", "value": "high" }] }, "then": "cn" },
+                    { "if": { "field": "platform", "op": "regex", "value": "^MT9632$" }, "then": "mt" },
+                    { "if": { "field": "score", "op": "gt", "value": "${base} + 10" }, "then": "high" },
+                    { "if": "tier == \"gold\" || !(tier == \"gold\")", "then": "all" }
+                ],
+                "fallback": "default"
+            }"#,
+        )
+        .unwrap();
+        let warnings: Vec<(&str, String)> = rules
+            .lint()
+            .into_iter()
+            .map(|warning| (warning.code, warning.pointer))
+            .collect();
+        assert_eq!(
+            warnings,
+            [
+                ("non_numeric_comparison", "/rules/0/if/and/1/value"),
+                ("non_numeric_comparison", "/rules/2/if/and/1/value"),
+                ("duplicate_rule", "/rules/2"),
+                ("literal_regex", "/rules/3/if/op"),
+                ("literal_regex", "/definitions/is_rtd/op"),
+                ("unreachable_fallback", "/fallback"),
+            ]
+            .map(|(code, pointer)| (code, pointer.to_string()))
+        );
+
+        let warning = &rules.lint()[3];
+        assert_eq!(
+            warning.to_string(),
+            "Regex '^MT9632$' in rule 3 has no special characters; use 'equals' instead"
+        );
+        assert_eq!(literal_operator("^RTD"), Some(Operator::Prefix));
+        assert_eq!(literal_operator("-pro$"), Some(Operator::Suffix));
+        assert_eq!(literal_operator("pro"), Some(Operator::Contains));
+        assert_eq!(literal_operator("^RTD.*$"), None);
+    }
+}
@@ -0,0 +1,75 @@
+//! Language tag matching for the `lang_matches` operator
+//!
+//! Condition values are BCP 47 language ranges like `zh` or `en-US`, given
+//! as a string or a list of strings, and match params by basic filtering
+//! (RFC 4647): a range matches a tag equal to it or starting with it
+//! followed by `-`, ignoring case. So `zh` matches `zh-CN` and
+//! `zh-Hans-CN` but not `zho`, and `*` matches every tag. Params written
+//! with `_`, as in `zh_CN`, are read as `zh-CN`.
+
+use crate::prelude::*;
+use serde_json::Value;
+
+/// The language ranges of a condition value
+pub(crate) fn ranges(value: &Value) -> Vec<&str> {
+    match value {
+        Value::String(s) => vec![s.as_str()],
+        Value::Array(items) => items.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Check whether a language range is well-formed: `*`, or subtags of 1 to 8
+/// letters and digits joined by `-`, the first of letters only
+pub(crate) fn is_valid_range(range: &str) -> bool {
+    if range == "*" {
+        return true;
+    }
+    range.split('-').enumerate().all(|(index, subtag)| {
+        (1..=8).contains(&subtag.len())
+            && subtag.chars().all(|c| match index {
+                0 => c.is_ascii_alphabetic(),
+                _ => c.is_ascii_alphanumeric(),
+            })
+    })
+}
+
+/// Check whether a param value is a language tag any of the ranges match
+pub(crate) fn matches<'a>(ranges: impl IntoIterator<Item = &'a str>, field_value: &str) -> bool {
+    let tag = field_value.trim().replace('_', "-");
+    if tag.is_empty() {
+        return false;
+    }
+    ranges.into_iter().any(|range| {
+        range == "*"
+            || tag.get(..range.len()).is_some_and(|prefix| {
+                prefix.eq_ignore_ascii_case(range)
+                    && matches!(tag.as_bytes().get(range.len()), None | Some(b'-'))
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_basic_filtering() {
+        assert!(matches(["zh"], "zh-CN"));
+        assert!(matches(["zh"], "zh-Hans-CN"));
+        assert!(matches(["ZH-cn"], "zh_CN"));
+        assert!(matches(["zh"], "zh"));
+        assert!(!matches(["zh"], "zho"));
+        assert!(!matches(["zh-CN"], "zh"));
+        assert!(!matches(["zh-Hans"], "zh-Hant-TW"));
+        assert!(matches(["en", "zh"], "zh-TW"));
+        assert!(matches(["*"], "de-DE"));
+        assert!(!matches(["*"], ""));
+
+        assert!(is_valid_range("zh-Hans-CN"));
+        assert!(is_valid_range("de-419"));
+        assert!(!is_valid_range("zh_CN"));
+        assert!(!is_valid_range("419"));
+        assert!(!is_valid_range("en-"));
+    }
+}
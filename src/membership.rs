@@ -0,0 +1,136 @@
+//! Set membership for the `in` / `not_in` operators
+//!
+//! Short lists are scanned linearly. Lists with more than
+//! [`HASH_THRESHOLD`] items get a hash set of their string items, built once
+//! when the evaluator is created and keyed by the list's address inside the
+//! evaluator's own rules.
+
+use crate::number::Number;
+use crate::prelude::*;
+use crate::{Condition, ConfigRules, Operator};
+use serde_json::Value;
+
+/// Lists longer than this are hashed
+const HASH_THRESHOLD: usize = 16;
+
+/// Precomputed lookup for one list
+#[derive(Debug, Clone, Default)]
+pub(crate) struct HashedList {
+    strings: HashSet<String>,
+    /// Numbers and bools, which compare by value rather than spelling
+    others: Vec<Value>,
+}
+
+/// Hashed lists of a rule set
+#[derive(Debug, Default)]
+pub(crate) struct MembershipSets {
+    lists: HashMap<usize, HashedList>,
+}
+
+/// Check whether a param value equals a scalar list item
+pub(crate) fn item_matches(item: &Value, field_value: &str) -> bool {
+    match item {
+        Value::String(s) => s == field_value,
+        Value::Number(n) => Number::from_json(n)
+            .is_some_and(|n| Number::parse(field_value).is_some_and(|value| value == n)),
+        Value::Bool(b) => field_value.parse::<bool>() == Ok(*b),
+        _ => false,
+    }
+}
+
+impl HashedList {
+    /// Hash a list, `None` when it is short enough to scan
+    pub(crate) fn new(items: &[Value]) -> Option<Self> {
+        if items.len() <= HASH_THRESHOLD {
+            return None;
+        }
+        let mut list = HashedList::default();
+        for item in items {
+            match item {
+                Value::String(s) => {
+                    list.strings.insert(s.clone());
+                }
+                other => list.others.push(other.clone()),
+            }
+        }
+        Some(list)
+    }
+
+    /// Check whether a param value is one of the list items
+    pub(crate) fn contains(&self, field_value: &str) -> bool {
+        self.strings.contains(field_value)
+            || self
+                .others
+                .iter()
+                .any(|item| item_matches(item, field_value))
+    }
+}
+
+impl MembershipSets {
+    /// Hash every long `in` / `not_in` list of the rule set
+    pub(crate) fn for_rules(rules: &ConfigRules) -> Self {
+        fn collect(condition: &Condition, sets: &mut MembershipSets) {
+            match condition {
+                Condition::Simple {
+                    op: Operator::In | Operator::NotIn,
+                    value: Value::Array(items),
+                    ..
+                } => {
+                    if let Some(list) = HashedList::new(items) {
+                        sets.lists.insert(items.as_ptr() as usize, list);
+                    }
+                }
+                Condition::Simple { .. } => {}
+                Condition::And { and } => and.iter().for_each(|c| collect(c, sets)),
+                Condition::Or { or } => or.iter().for_each(|c| collect(c, sets)),
+                Condition::Not { not } => collect(not, sets),
+                Condition::Ref { .. } => {}
+            }
+        }
+
+        let mut sets = MembershipSets::default();
+        for condition in rules.conditions() {
+            collect(condition, &mut sets);
+        }
+        sets
+    }
+
+    /// Check whether a param value is one of the list items
+    pub(crate) fn contains(&self, items: &[Value], field_value: &str) -> bool {
+        match self.lists.get(&(items.as_ptr() as usize)) {
+            Some(list) => list.contains(field_value),
+            None => items.iter().any(|item| item_matches(item, field_value)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hashed_list() {
+        let mut items: Vec<Value> = (0..40).map(|i| Value::from(format!("R{}", i))).collect();
+        items.push(Value::from(7));
+        let rules: ConfigRules = serde_json::from_value(serde_json::json!({
+            "rules": [{
+                "if": { "field": "region", "op": "in", "value": items },
+                "then": "listed"
+            }]
+        }))
+        .unwrap();
+
+        let sets = MembershipSets::for_rules(&rules);
+        let Condition::Simple {
+            value: Value::Array(items),
+            ..
+        } = &rules.rules[0].condition
+        else {
+            panic!("Expected simple condition");
+        };
+        assert_eq!(sets.lists.len(), 1);
+        assert!(sets.contains(items, "R39"));
+        assert!(sets.contains(items, "7.0"));
+        assert!(!sets.contains(items, "R40"));
+    }
+}
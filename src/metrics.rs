@@ -0,0 +1,90 @@
+//! Rule hit counters and short-circuit statistics
+//!
+//! Collection is opt-in through [`ConfigEvaluator::with_metrics`]; counters
+//! are atomics shared by clones of the evaluator, so they can be read from
+//! any thread while evaluations run. Traced evaluations (`evaluate_with_trace`,
+//! `evaluate_report`) are not counted.
+//!
+//! [`ConfigEvaluator::with_metrics`]: crate::ConfigEvaluator::with_metrics
+
+use crate::report::Phase;
+use crate::ConfigRules;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Live counters of an evaluator
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    evaluations: AtomicU64,
+    conditions: AtomicU64,
+    pre: Vec<AtomicU64>,
+    rules: Vec<AtomicU64>,
+    post: Vec<AtomicU64>,
+}
+
+impl Counters {
+    pub(crate) fn for_rules(rules: &ConfigRules) -> Self {
+        let zeros = |len: usize| (0..len).map(|_| AtomicU64::new(0)).collect();
+        Counters {
+            pre: zeros(rules.pre.len()),
+            rules: zeros(rules.rules.len()),
+            post: zeros(rules.post.len()),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn record_evaluation(&self) {
+        self.evaluations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_conditions(&self, visited: u64) {
+        self.conditions.fetch_add(visited, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_hit(&self, phase: Phase, index: usize) {
+        let hits = match phase {
+            Phase::Pre => &self.pre,
+            Phase::Main => &self.rules,
+            Phase::Post => &self.post,
+        };
+        hits[index].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> Metrics {
+        let load = |hits: &[AtomicU64]| hits.iter().map(|h| h.load(Ordering::Relaxed)).collect();
+        Metrics {
+            evaluations: self.evaluations.load(Ordering::Relaxed),
+            conditions_evaluated: self.conditions.load(Ordering::Relaxed),
+            pre_hits: load(&self.pre),
+            rule_hits: load(&self.rules),
+            post_hits: load(&self.post),
+        }
+    }
+}
+
+/// Snapshot of an evaluator's metrics
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Metrics {
+    /// Evaluations run
+    pub evaluations: u64,
+    /// Condition nodes (leaves, `and`, `or` and `not`) visited, with nested
+    /// chains of the same kind flattened; branches skipped by
+    /// short-circuiting are not counted
+    pub conditions_evaluated: u64,
+    /// Matches of each pre-phase rule, indexed like `pre`
+    pub pre_hits: Vec<u64>,
+    /// Matches of each main rule, indexed like `rules`
+    pub rule_hits: Vec<u64>,
+    /// Matches of each post-phase rule, indexed like `post`
+    pub post_hits: Vec<u64>,
+}
+
+impl Metrics {
+    /// Average number of condition nodes visited per evaluation
+    pub fn average_condition_depth(&self) -> f64 {
+        if self.evaluations == 0 {
+            return 0.0;
+        }
+        self.conditions_evaluated as f64 / self.evaluations as f64
+    }
+}
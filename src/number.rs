@@ -0,0 +1,147 @@
+//! Numbers compared without losing integer precision
+//!
+//! Numeric strings without a fraction or exponent are read as `i128`, so
+//! 64-bit IDs compare exactly instead of being rounded to the nearest `f64`
+//! (`9007199254740993` is not equal to `9007199254740992`). Other numbers
+//! are `f64`; an integer and a float still compare exactly.
+//!
+//! With the `decimal` feature, numbers with a fraction are read as
+//! [`Decimal`]s instead, and arithmetic in numeric condition values is
+//! decimal too, so `${a} + ${b}` with `0.1` and `0.2` equals `0.3`.
+
+#[cfg(feature = "decimal")]
+use crate::prelude::*;
+use core::cmp::Ordering;
+#[cfg(feature = "decimal")]
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+
+/// An integer, decimal or floating-point number
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Number {
+    Int(i128),
+    #[cfg(feature = "decimal")]
+    Decimal(Decimal),
+    Float(f64),
+}
+
+impl Number {
+    /// Parse a numeric string, as an integer when it has no fraction or
+    /// exponent
+    pub(crate) fn parse(s: &str) -> Option<Number> {
+        if let Ok(n) = s.parse::<i128>() {
+            return Some(Number::Int(n));
+        }
+        #[cfg(feature = "decimal")]
+        if let Some(n) = parse_decimal(s) {
+            return Some(Number::Decimal(n));
+        }
+        s.parse::<f64>().ok().map(Number::Float)
+    }
+
+    /// Read a JSON number, keeping integers exact
+    pub(crate) fn from_json(n: &serde_json::Number) -> Option<Number> {
+        if let Some(n) = n.as_i64() {
+            return Some(Number::Int(n.into()));
+        }
+        if let Some(n) = n.as_u64() {
+            return Some(Number::Int(n.into()));
+        }
+        // JSON numbers print as the shortest string reading back the same
+        // float, which is how they were written in the rules
+        #[cfg(feature = "decimal")]
+        if let Some(n) = parse_decimal(&n.to_string()) {
+            return Some(Number::Decimal(n));
+        }
+        n.as_f64().map(Number::Float)
+    }
+}
+
+/// Parse a decimal number, also in scientific notation
+#[cfg(feature = "decimal")]
+pub(crate) fn parse_decimal(s: &str) -> Option<Decimal> {
+    s.parse::<Decimal>()
+        .or_else(|_| Decimal::from_scientific(s))
+        .ok()
+}
+
+/// Compare an integer with a float exactly
+fn compare_mixed(int: i128, float: f64) -> Option<Ordering> {
+    if float.is_nan() {
+        return None;
+    }
+    // Saturating cast, truncating toward zero; exact for the floats that
+    // fit, which are integers beyond 2^53
+    let whole = float as i128;
+    match int.cmp(&whole) {
+        Ordering::Equal => 0.0_f64.partial_cmp(&(float - whole as f64)),
+        ordering => Some(ordering),
+    }
+}
+
+/// Compare a decimal with an integer, which may be beyond the decimal range
+#[cfg(feature = "decimal")]
+fn compare_decimal_int(decimal: Decimal, int: i128) -> Ordering {
+    match Decimal::try_from_i128_with_scale(int, 0) {
+        Ok(int) => decimal.cmp(&int),
+        Err(_) if int > 0 => Ordering::Less,
+        Err(_) => Ordering::Greater,
+    }
+}
+
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (*self, *other) {
+            (Number::Int(a), Number::Int(b)) => Some(a.cmp(&b)),
+            (Number::Float(a), Number::Float(b)) => a.partial_cmp(&b),
+            (Number::Int(a), Number::Float(b)) => compare_mixed(a, b),
+            (Number::Float(a), Number::Int(b)) => compare_mixed(b, a).map(Ordering::reverse),
+            #[cfg(feature = "decimal")]
+            (Number::Decimal(a), Number::Decimal(b)) => Some(a.cmp(&b)),
+            #[cfg(feature = "decimal")]
+            (Number::Decimal(a), Number::Int(b)) => Some(compare_decimal_int(a, b)),
+            #[cfg(feature = "decimal")]
+            (Number::Int(a), Number::Decimal(b)) => Some(compare_decimal_int(b, a).reverse()),
+            #[cfg(feature = "decimal")]
+            (Number::Decimal(a), Number::Float(b)) => a.to_f64()?.partial_cmp(&b),
+            #[cfg(feature = "decimal")]
+            (Number::Float(a), Number::Decimal(b)) => a.partial_cmp(&b.to_f64()?),
+        }
+    }
+}
+
+impl PartialEq for Number {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exact_comparison() {
+        let n = |s| Number::parse(s).unwrap();
+        assert!(n("9007199254740993") > n("9007199254740992"));
+        assert!(n("18446744073709551615") > n("18446744073709551614"));
+        assert!(n("-170141183460469231731687303715884105728") < n("0"));
+        assert_eq!(n("10"), n("10.0"));
+        assert!(n("10") < n("10.5"));
+        assert!(n("-10") > n("-10.5"));
+        assert!(n("9007199254740993") > n("9007199254740992.0"));
+        assert!(n("1") < Number::Float(f64::INFINITY));
+        assert!(n("1") != Number::Float(f64::NAN));
+        assert!(Number::parse("abc").is_none());
+        assert_eq!(
+            Number::from_json(&serde_json::Number::from(u64::MAX)).unwrap(),
+            n("18446744073709551615")
+        );
+        #[cfg(feature = "decimal")]
+        {
+            assert!(matches!(n("0.1"), Number::Decimal(_)));
+            assert!(n("0.30000000000000001") > n("0.3"));
+            assert!(n("1e30") > n("1.5"));
+            assert!(n("100000000000000000000000000000000") > n("0.5"));
+        }
+    }
+}
@@ -0,0 +1,254 @@
+//! Condition simplification
+//!
+//! [`ConfigRules::optimize`] rewrites conditions into equivalent ones that
+//! are cheaper to evaluate. Every rule keeps its place, so rule indices,
+//! ids and results are unchanged.
+
+use crate::prelude::*;
+use crate::{Condition, ConfigRules, Operator};
+use alloc::collections::BTreeMap;
+
+/// Cost of conditions whose cost is unknown, such as unresolved refs
+const UNKNOWN_COST: u8 = 2;
+
+impl ConfigRules {
+    /// Simplify the conditions of every rule and definition
+    ///
+    /// - nested `and`s within `and`s and `or`s within `or`s are flattened
+    /// - repeated sub-conditions are removed
+    /// - constant branches are folded: a condition `or` its negation always
+    ///   holds and is dropped from `and`s, a condition `and` its negation
+    ///   never holds and is dropped from `or`s; double negations cancel
+    /// - sub-conditions are reordered so cheap checks like `equals` and `in`
+    ///   run before string searches, numeric comparisons, then `regex` and
+    ///   `glob`, then scripts and custom operators, keeping the order of
+    ///   equally cheap ones
+    ///
+    /// Rules whose condition never holds are disabled. Conditions that
+    /// always hold as a whole are kept as written, since rule sets have no
+    /// literal for them. Reordering assumes checks have no side effects;
+    /// traces list the simplified conditions.
+    pub fn optimize(mut self) -> ConfigRules {
+        let definitions = self.definitions.clone();
+        let optimizer = Optimizer {
+            definitions: &definitions,
+        };
+        for condition in self.definitions.values_mut() {
+            if let Folded::Condition(simplified) = optimizer.simplify(condition.clone()) {
+                *condition = simplified;
+            }
+        }
+        for rule in self
+            .pre
+            .iter_mut()
+            .chain(&mut self.rules)
+            .chain(&mut self.post)
+        {
+            match optimizer.simplify(rule.condition.clone()) {
+                Folded::Condition(simplified) => rule.condition = simplified,
+                Folded::Never => rule.enabled = false,
+                Folded::Always => {}
+            }
+        }
+        self
+    }
+
+    /// Check whether a condition folds to one that always holds
+    pub(crate) fn always_holds(&self, condition: &Condition) -> bool {
+        let optimizer = Optimizer {
+            definitions: &self.definitions,
+        };
+        matches!(optimizer.simplify(condition.clone()), Folded::Always)
+    }
+}
+
+/// A simplified condition, or the constant it folded to
+enum Folded {
+    Always,
+    Never,
+    Condition(Condition),
+}
+
+/// Simplifies conditions, looking up the cost of refs in the definitions
+struct Optimizer<'a> {
+    definitions: &'a BTreeMap<String, Condition>,
+}
+
+impl Optimizer<'_> {
+    fn simplify(&self, condition: Condition) -> Folded {
+        match condition {
+            Condition::And { and } => self.junction(and, true),
+            Condition::Or { or } => self.junction(or, false),
+            Condition::Not { not } => match self.simplify(*not) {
+                Folded::Always => Folded::Never,
+                Folded::Never => Folded::Always,
+                Folded::Condition(Condition::Not { not }) => Folded::Condition(*not),
+                Folded::Condition(c) => Folded::Condition(Condition::Not { not: Box::new(c) }),
+            },
+            simple_or_ref => Folded::Condition(simple_or_ref),
+        }
+    }
+
+    /// Simplify the sub-conditions of an `and` (`all`) or an `or`
+    fn junction(&self, conditions: Vec<Condition>, all: bool) -> Folded {
+        let (identity, absorbing) = if all {
+            (Folded::Always, Folded::Never)
+        } else {
+            (Folded::Never, Folded::Always)
+        };
+
+        let mut flat = Vec::with_capacity(conditions.len());
+        for condition in conditions {
+            match (self.simplify(condition), all) {
+                (Folded::Always, true) | (Folded::Never, false) => {}
+                (Folded::Always, false) | (Folded::Never, true) => return absorbing,
+                (Folded::Condition(Condition::And { and }), true) => flat.extend(and),
+                (Folded::Condition(Condition::Or { or }), false) => flat.extend(or),
+                (Folded::Condition(condition), _) => flat.push(condition),
+            }
+        }
+
+        let mut unique: Vec<Condition> = Vec::with_capacity(flat.len());
+        for condition in flat {
+            if !unique.contains(&condition) {
+                unique.push(condition);
+            }
+        }
+        let complementary = unique
+            .iter()
+            .any(|condition| matches!(condition, Condition::Not { not } if unique.contains(not)));
+        if complementary {
+            return absorbing;
+        }
+
+        unique.sort_by_cached_key(|condition| self.cost(condition, self.definitions.len()));
+        match unique.len() {
+            0 => identity,
+            1 => unique.pop().map_or(identity, Folded::Condition),
+            _ if all => Folded::Condition(Condition::And { and: unique }),
+            _ => Folded::Condition(Condition::Or { or: unique }),
+        }
+    }
+
+    /// Relative cost of evaluating a condition; `depth` bounds the refs
+    /// followed, in case they form a cycle
+    fn cost(&self, condition: &Condition, depth: usize) -> u8 {
+        match condition {
+            Condition::Simple { op, .. } => operator_cost(op),
+            Condition::And { and: conditions } | Condition::Or { or: conditions } => conditions
+                .iter()
+                .map(|c| self.cost(c, depth))
+                .max()
+                .unwrap_or(0),
+            Condition::Not { not } => self.cost(not, depth),
+            Condition::Ref { name } => match self.definitions.get(name) {
+                Some(definition) if depth > 0 => self.cost(definition, depth - 1),
+                _ => UNKNOWN_COST,
+            },
+        }
+    }
+}
+
+fn operator_cost(op: &Operator) -> u8 {
+    match op {
+        Operator::Equals
+        | Operator::IEquals
+        | Operator::In
+        | Operator::NotIn
+        | Operator::Exists
+        | Operator::Missing => 0,
+        Operator::Contains
+        | Operator::Prefix
+        | Operator::Suffix
+        | Operator::IContains
+        | Operator::IPrefix
+        | Operator::ISuffix
+        | Operator::LenGt
+        | Operator::LenLt
+        | Operator::LenEq => 1,
+        Operator::GreaterThan
+        | Operator::LessThan
+        | Operator::GreaterThanOrEqual
+        | Operator::LessThanOrEqual
+        | Operator::SemverEq
+        | Operator::SemverGt
+        | Operator::SemverGe
+        | Operator::SemverLt
+        | Operator::SemverLe
+        | Operator::PercentLt
+        | Operator::BucketIn
+        | Operator::TimeBetween
+        | Operator::CronMatch
+        | Operator::Intersects
+        | Operator::SubsetOf
+        | Operator::IpInCidr
+        | Operator::LangMatches
+        | Operator::Before
+        | Operator::After
+        | Operator::Between => 2,
+        Operator::Regex | Operator::Glob => 3,
+        Operator::Script | Operator::Custom(_) => 4,
+    }
+}
+
+#[cfg(all(test, feature = "regex"))]
+mod tests {
+    use super::*;
+    use crate::{ConfigEvaluator, RuleResult};
+
+    #[test]
+    fn test_optimize() {
+        let rules: ConfigRules = serde_json::from_str(
+            r#"{
+                "definitions": { "is_cn": { "and": ["region == \"CN\"", "region == \"CN\""] } },
+                "rules": [
+                    {
+                        "if": {
+                            "and": [
+                                "platform ~ \"^RTD\"",
+                                { "and": ["score >= 60", "region == \"CN\"", "beta == \"1\" || !(beta == \"1\")"] },
+                                "region == \"CN\""
+                            ]
+                        },
+                        "then": "cn_rtd"
+                    },
+                    { "if": "tier == \"gold\" && region == \"CN\" && !(region == \"CN\")", "then": "never" },
+                    { "if": { "not": { "not": { "or": [{ "ref": "is_cn" }, "tier == \"gold\""] } } }, "then": "gold" },
+                    { "if": "beta == \"1\" || !(beta == \"1\")", "then": "always" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let optimized = rules.clone().optimize();
+        let condition = |json: &str| serde_json::from_str::<Condition>(json).unwrap();
+
+        assert_eq!(
+            optimized.rules[0].condition,
+            condition(r#""region == \"CN\" && score >= 60 && platform ~ \"^RTD\"""#)
+        );
+        assert!(!optimized.rules[1].enabled);
+        assert_eq!(
+            optimized.rules[2].condition,
+            condition(r#"{ "or": [{ "ref": "is_cn" }, "tier == \"gold\""] }"#)
+        );
+        assert_eq!(optimized.rules[3].condition, rules.rules[3].condition);
+        assert_eq!(
+            optimized.definitions["is_cn"],
+            condition(r#""region == \"CN\"""#)
+        );
+
+        let original = ConfigEvaluator::new(rules).unwrap();
+        let evaluator = ConfigEvaluator::new(optimized).unwrap();
+        for (region, score, expected) in [
+            ("CN", "75", "cn_rtd"),
+            ("CN", "40", "gold"),
+            ("US", "90", "always"),
+        ] {
+            let params =
+                HashMap::from([("region", region), ("score", score), ("platform", "RTD-1")]);
+            let result = evaluator.evaluate(&params);
+            assert_eq!(result, Some(RuleResult::String(expected.to_string())));
+            assert_eq!(result, original.evaluate(&params));
+        }
+    }
+}
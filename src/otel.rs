@@ -0,0 +1,97 @@
+//! OpenTelemetry instrumentation for loading and evaluating rule sets
+
+use crate::{ConfigExprError, ConfigRules, Outcome};
+use opentelemetry::global::{self, BoxedSpan};
+use opentelemetry::trace::{Span, Status, Tracer};
+use opentelemetry::KeyValue;
+use std::time::Instant;
+
+const TRACER_NAME: &str = "clia-config-expr";
+
+fn start_span(name: &'static str, rules: &ConfigRules) -> BoxedSpan {
+    let mut span = global::tracer(TRACER_NAME).start(name);
+    if let Some(version) = &rules.version {
+        span.set_attribute(KeyValue::new("config_expr.rules.version", version.clone()));
+    }
+    span
+}
+
+fn record_error(span: &mut BoxedSpan, error: &ConfigExprError) {
+    span.set_status(Status::error(error.to_string()));
+}
+
+/// Span covering one evaluation
+pub(crate) struct EvaluationSpan {
+    span: BoxedSpan,
+    started: Instant,
+}
+
+impl EvaluationSpan {
+    pub(crate) fn start(rules: &ConfigRules) -> Self {
+        EvaluationSpan {
+            span: start_span("config_expr.evaluate", rules),
+            started: Instant::now(),
+        }
+    }
+
+    pub(crate) fn finish(
+        mut self,
+        rules: &ConfigRules,
+        outcome: &Result<Outcome, ConfigExprError>,
+    ) {
+        let duration_ms = self.started.elapsed().as_secs_f64() * 1000.0;
+        self.span
+            .set_attribute(KeyValue::new("config_expr.duration_ms", duration_ms));
+        match outcome {
+            Ok(outcome) => {
+                if let Some(index) = outcome.rule_index {
+                    let rule = &rules.rules[index];
+                    let id = rule.id.clone().unwrap_or_else(|| index.to_string());
+                    self.span
+                        .set_attribute(KeyValue::new("config_expr.rule.index", index as i64));
+                    self.span
+                        .set_attribute(KeyValue::new("config_expr.rule.id", id));
+                }
+                let fallback = outcome.rule_index.is_none() && rules.fallback.is_some();
+                self.span
+                    .set_attribute(KeyValue::new("config_expr.fallback", fallback));
+                self.span.set_attribute(KeyValue::new(
+                    "config_expr.matched",
+                    outcome.result.is_some(),
+                ));
+            }
+            Err(error) => record_error(&mut self.span, error),
+        }
+        self.span.end();
+    }
+}
+
+/// Span covering loading (or reloading) a rule set
+pub(crate) struct LoadSpan {
+    span: BoxedSpan,
+    started: Instant,
+}
+
+impl LoadSpan {
+    pub(crate) fn start(rules: &ConfigRules) -> Self {
+        let mut span = start_span("config_expr.load", rules);
+        span.set_attribute(KeyValue::new(
+            "config_expr.rules.count",
+            rules.rules.len() as i64,
+        ));
+        LoadSpan {
+            span,
+            started: Instant::now(),
+        }
+    }
+
+    pub(crate) fn finish(mut self, result: &Result<(), ConfigExprError>) {
+        let duration_ms = self.started.elapsed().as_secs_f64() * 1000.0;
+        self.span
+            .set_attribute(KeyValue::new("config_expr.duration_ms", duration_ms));
+        if let Err(error) = result {
+            record_error(&mut self.span, error);
+        }
+        self.span.end();
+    }
+}
@@ -0,0 +1,156 @@
+//! Dot-path access to nested params
+//!
+//! A field such as `device.info.platform` or `items[0].id` names a value
+//! inside a param holding JSON text. A param whose key matches the whole
+//! field exactly always takes precedence.
+
+use crate::prelude::*;
+use crate::ParamSource;
+use alloc::borrow::Cow;
+
+/// One step of a field path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Segment<'a> {
+    Key(&'a str),
+    Index(usize),
+}
+
+/// Check whether a field is a path rather than a plain param name
+fn is_path(field: &str) -> bool {
+    field.contains(['.', '[', ']'])
+}
+
+/// Parse a field path into segments
+pub(crate) fn parse(path: &str) -> Result<Vec<Segment<'_>>, String> {
+    let mut segments = Vec::new();
+    for part in path.split('.') {
+        let (key, mut rest) = match part.find('[') {
+            Some(start) => part.split_at(start),
+            None => (part, ""),
+        };
+        if key.is_empty() {
+            return Err("empty segment".to_string());
+        }
+        if key.contains(']') {
+            return Err(format!("unexpected ']' in '{}'", part));
+        }
+        segments.push(Segment::Key(key));
+        while !rest.is_empty() {
+            let Some(end) = rest.find(']').filter(|_| rest.starts_with('[')) else {
+                return Err(format!("malformed index in '{}'", part));
+            };
+            let index = rest[1..end]
+                .parse::<usize>()
+                .map_err(|_| format!("invalid index '{}' in '{}'", &rest[1..end], part))?;
+            segments.push(Segment::Index(index));
+            rest = &rest[end + 1..];
+        }
+    }
+    Ok(segments)
+}
+
+/// Validate a field name, returning the reason when it is a malformed path
+pub(crate) fn validate(field: &str) -> Result<(), String> {
+    if is_path(field) {
+        parse(field)?;
+    }
+    Ok(())
+}
+
+/// Look up a param by name or by path into a nested JSON param
+///
+/// Strings resolve to their contents, other JSON values to their JSON text,
+/// and `null` or missing values to `None`.
+pub(crate) fn lookup<'a>(params: &'a dyn ParamSource, field: &str) -> Option<Cow<'a, str>> {
+    if let Some(value) = params.get(field) {
+        return Some(value);
+    }
+    if !is_path(field) {
+        return None;
+    }
+    let segments = parse(field).ok()?;
+    let (Segment::Key(root), rest) = segments.split_first()? else {
+        return None;
+    };
+    let mut value: serde_json::Value = serde_json::from_str(&params.get(root)?).ok()?;
+    for segment in rest {
+        value = match segment {
+            Segment::Key(key) => value.get_mut(*key)?.take(),
+            Segment::Index(index) => value.get_mut(*index)?.take(),
+        };
+    }
+    text(value)
+}
+
+/// Look up every value of a param, for conditions with a `mode`
+///
+/// The values are those of a repeated param, or the items of a param or
+/// path holding a JSON array, such as array fields of
+/// [`evaluate_value`](crate::ConfigEvaluator::evaluate_value) params.
+pub(crate) fn lookup_all<'a>(params: &'a dyn ParamSource, field: &str) -> Vec<Cow<'a, str>> {
+    let mut values = params.get_all(field);
+    if values.is_empty() {
+        values.extend(lookup(params, field));
+    }
+    match values.as_slice() {
+        [value] if value.starts_with('[') => {
+            match serde_json::from_str::<Vec<serde_json::Value>>(value) {
+                Ok(items) => items.into_iter().filter_map(text).collect(),
+                Err(_) => values,
+            }
+        }
+        _ => values,
+    }
+}
+
+/// Text of a JSON value compared by conditions
+fn text(value: serde_json::Value) -> Option<Cow<'static, str>> {
+    match value {
+        serde_json::Value::Null => None,
+        serde_json::Value::String(s) => Some(Cow::Owned(s)),
+        other => Some(Cow::Owned(other.to_string())),
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_parse_path() {
+        assert_eq!(
+            parse("items[0].tags[2]").unwrap(),
+            vec![
+                Segment::Key("items"),
+                Segment::Index(0),
+                Segment::Key("tags"),
+                Segment::Index(2)
+            ]
+        );
+        assert!(validate("platform").is_ok());
+        assert!(validate("device..platform").is_err());
+        assert!(validate("items[x]").is_err());
+        assert!(validate("items[0").is_err());
+        assert!(validate("items]0[").is_err());
+        assert!(validate("items[0]id").is_err());
+    }
+
+    #[test]
+    fn test_lookup() {
+        let params = HashMap::from([
+            (
+                "device".to_string(),
+                r#"{ "info": { "platform": "RTD-2000", "cores": 4 }, "items": [{ "id": "a1" }] }"#
+                    .to_string(),
+            ),
+            ("device.region".to_string(), "CN".to_string()),
+        ]);
+        assert_eq!(lookup(&params, "device.info.platform").unwrap(), "RTD-2000");
+        assert_eq!(lookup(&params, "device.info.cores").unwrap(), "4");
+        assert_eq!(lookup(&params, "device.items[0].id").unwrap(), "a1");
+        assert_eq!(lookup(&params, "device.region").unwrap(), "CN");
+        assert!(lookup(&params, "device.items[1].id").is_none());
+        assert!(lookup(&params, "device.info.missing").is_none());
+    }
+}
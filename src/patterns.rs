@@ -0,0 +1,147 @@
+//! Limits on compiling the patterns of `regex` conditions
+//!
+//! Rule files from untrusted sources can hold patterns that compile to huge
+//! automata. [`RegexConfig`] bounds the memory a pattern may compile to;
+//! rule sets with larger patterns fail validation with the
+//! `regex_too_large` error code.
+
+#[cfg(feature = "regex")]
+use crate::report::Phase;
+#[cfg(feature = "regex")]
+use crate::validation::Site;
+#[cfg(feature = "regex")]
+use crate::{Condition, ConfigExprError, ConfigRules};
+#[cfg(feature = "regex")]
+use regex::{Regex, RegexBuilder};
+
+/// Size limits of compiled `regex` patterns
+///
+/// The defaults are those of the `regex` crate. Matching takes time linear
+/// in the input, whatever the pattern, so there is no match timeout; the
+/// limits bound the memory, and with it the compile time, of each pattern.
+/// The `params_spec` patterns are compiled with the same limits, as are
+/// patterns read from params with `value_field`, which fail to match when
+/// they exceed them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegexConfig {
+    /// Most heap memory, in bytes, a compiled pattern may use
+    pub size_limit: usize,
+    /// Most heap memory, in bytes, of the lazy DFA cache of a pattern
+    pub dfa_size_limit: usize,
+}
+
+impl Default for RegexConfig {
+    fn default() -> Self {
+        RegexConfig {
+            size_limit: 10 * (1 << 20),
+            dfa_size_limit: 2 * (1 << 20),
+        }
+    }
+}
+
+#[cfg(feature = "regex")]
+impl RegexConfig {
+    /// Compile a pattern within the limits
+    pub(crate) fn compile(&self, pattern: &str) -> Result<Regex, regex::Error> {
+        RegexBuilder::new(pattern)
+            .size_limit(self.size_limit)
+            .dfa_size_limit(self.dfa_size_limit)
+            .build()
+    }
+
+    /// Check that the patterns of the `regex` conditions compile within the
+    /// limits
+    ///
+    /// Other pattern errors are left to validation.
+    pub(crate) fn check(&self, rules: &ConfigRules) -> Result<(), ConfigExprError> {
+        let phases = [
+            (Phase::Pre, &rules.pre),
+            (Phase::Main, &rules.rules),
+            (Phase::Post, &rules.post),
+        ];
+        for (phase, phase_rules) in phases {
+            for (index, rule) in phase_rules.iter().enumerate() {
+                self.check_condition(&rule.condition, &Site::rule(phase, index).at("if"))?;
+            }
+        }
+        for (name, condition) in &rules.definitions {
+            self.check_condition(condition, &Site::definition(name))?;
+        }
+        Ok(())
+    }
+
+    fn check_condition(&self, condition: &Condition, site: &Site) -> Result<(), ConfigExprError> {
+        match condition {
+            Condition::Simple {
+                field,
+                op: crate::Operator::Regex,
+                value: serde_json::Value::String(pattern),
+                ..
+            } => match self.compile(pattern) {
+                Err(regex::Error::CompiledTooBig(limit)) => {
+                    let site = site.at("value");
+                    Err(site
+                        .error(
+                            "regex_too_large",
+                            format!(
+                                "Regex '{}' in {} exceeds the size limit of {} bytes",
+                                pattern, site, limit
+                            ),
+                        )
+                        .field(field)
+                        .value(pattern.as_str())
+                        .into())
+                }
+                _ => Ok(()),
+            },
+            Condition::Simple { .. } | Condition::Ref { .. } => Ok(()),
+            Condition::And { and } => and
+                .iter()
+                .enumerate()
+                .try_for_each(|(i, c)| self.check_condition(c, &site.at("and").at(i))),
+            Condition::Or { or } => or
+                .iter()
+                .enumerate()
+                .try_for_each(|(i, c)| self.check_condition(c, &site.at("or").at(i))),
+            Condition::Not { not } => self.check_condition(not, &site.at("not")),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "regex"))]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+    use crate::{ConfigEvaluator, RuleResult};
+
+    #[test]
+    fn test_regex_limits() {
+        let json = r#"{ "rules": [{ "if": { "field": "id", "op": "regex", "value": "^\\w{50}$" }, "then": "long" }] }"#;
+        let rules: ConfigRules = serde_json::from_str(json).unwrap();
+        let small = RegexConfig {
+            size_limit: 1 << 10,
+            ..RegexConfig::default()
+        };
+
+        let error = ConfigEvaluator::new_with_regex_config(rules.clone(), small).unwrap_err();
+        assert_eq!(error.error_code(), "regex_too_large");
+        assert!(error.to_string().contains("rule 0"));
+
+        let evaluator = ConfigEvaluator::new(rules.clone()).unwrap();
+        let params = HashMap::from([("id", "x".repeat(50))]);
+        assert_eq!(
+            evaluator.evaluate(&params),
+            Some(RuleResult::String("long".to_string()))
+        );
+
+        // Raised limits apply to compilation as well
+        let large = RegexConfig {
+            size_limit: 1 << 30,
+            dfa_size_limit: 1 << 30,
+        };
+        let evaluator = ConfigEvaluator::new_with_regex_config(rules, large).unwrap();
+        assert_eq!(evaluator.regex_config(), large);
+        let evaluator = evaluator.with_rules(evaluator.rules().clone()).unwrap();
+        assert_eq!(evaluator.regex_config(), large);
+    }
+}
@@ -0,0 +1,13 @@
+//! Names of the standard prelude, imported from `alloc` so that the crate
+//! also builds without `std`
+//!
+//! Without `std`, hash collections come from hashbrown, the implementation
+//! `std` itself wraps.
+
+pub(crate) use alloc::boxed::Box;
+pub(crate) use alloc::string::{String, ToString};
+pub(crate) use alloc::vec::Vec;
+#[cfg(not(feature = "std"))]
+pub(crate) use hashbrown::{HashMap, HashSet};
+#[cfg(feature = "std")]
+pub(crate) use std::collections::{HashMap, HashSet};
@@ -0,0 +1,173 @@
+//! Adapters for layered configuration crates (`config` and `figment`)
+//!
+//! Applications that already layer their configuration from files and
+//! environment variables can keep the rules section in the same stack and
+//! build an evaluator from it. With `config`, the matched result can in turn
+//! be layered back as a [`RuleOverlay`] of conditional overrides.
+
+use crate::{ConfigEvaluator, ConfigExprError, ConfigRules};
+#[cfg(feature = "config")]
+use crate::{ParamSource, RuleResult};
+
+#[cfg(feature = "config")]
+impl ConfigEvaluator {
+    /// Create an evaluator from the rules section at `key` of a `config::Config`
+    pub fn from_config(config: &config::Config, key: &str) -> Result<Self, ConfigExprError> {
+        let rules: ConfigRules = config.get(key)?;
+        Self::new(rules)
+    }
+
+    /// Evaluate params into a `config` source overriding the keys of the
+    /// matched object result
+    ///
+    /// When nothing matches the overlay is empty and overrides nothing.
+    /// Results other than JSON objects fail with a validation error, as do
+    /// assertion rules.
+    pub fn config_overlay(
+        &self,
+        params: &impl ParamSource,
+    ) -> Result<RuleOverlay, ConfigExprError> {
+        let overrides = match self.evaluate_source(params)? {
+            None => serde_json::Map::new(),
+            Some(RuleResult::Object(serde_json::Value::Object(overrides))) => overrides,
+            Some(other) => {
+                return Err(ConfigExprError::ValidationError(format!(
+                    "Overlay result must be a JSON object, got {}",
+                    serde_json::to_string(&other)?
+                )))
+            }
+        };
+        Ok(RuleOverlay { overrides })
+    }
+}
+
+/// Object result of a rule set as a `config` source
+///
+/// Added after an application's other sources, the matched rule overrides
+/// their values: nested objects are merged key by key, other values
+/// replaced. Created by [`ConfigEvaluator::config_overlay`].
+#[cfg(feature = "config")]
+#[derive(Debug, Clone)]
+pub struct RuleOverlay {
+    overrides: serde_json::Map<String, serde_json::Value>,
+}
+
+#[cfg(feature = "config")]
+impl config::Source for RuleOverlay {
+    fn clone_into_box(&self) -> Box<dyn config::Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> Result<config::Map<String, config::Value>, config::ConfigError> {
+        let json = serde_json::Value::Object(self.overrides.clone()).to_string();
+        config::File::from_str(&json, config::FileFormat::Json).collect()
+    }
+}
+
+#[cfg(feature = "figment")]
+impl ConfigEvaluator {
+    /// Create an evaluator from the rules section at `key` of a `figment::Figment`
+    pub fn from_figment(figment: &figment::Figment, key: &str) -> Result<Self, ConfigExprError> {
+        let rules: ConfigRules = figment
+            .extract_inner(key)
+            .map_err(|e| ConfigExprError::FigmentError(Box::new(e)))?;
+        Self::new(rules)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::RuleResult;
+    use std::collections::HashMap;
+
+    const APP_CONFIG: &str = r#"
+    {
+        "server": { "host": "0.0.0.0", "port": 8080 },
+        "chip_rules": {
+            "rules": [
+                {
+                    "if": { "field": "platform", "op": "equals", "value": "RTD" },
+                    "then": "chip_rtd"
+                }
+            ],
+            "fallback": "default_chip"
+        }
+    }
+    "#;
+
+    fn rtd_params() -> HashMap<String, String> {
+        HashMap::from([("platform".to_string(), "RTD".to_string())])
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn test_from_config() {
+        let config = config::Config::builder()
+            .add_source(config::File::from_str(APP_CONFIG, config::FileFormat::Json))
+            .build()
+            .unwrap();
+
+        let evaluator = crate::ConfigEvaluator::from_config(&config, "chip_rules").unwrap();
+        assert_eq!(
+            evaluator.evaluate(&rtd_params()),
+            Some(RuleResult::String("chip_rtd".to_string()))
+        );
+        assert!(crate::ConfigEvaluator::from_config(&config, "missing").is_err());
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn test_config_overlay() {
+        let evaluator = crate::ConfigEvaluator::from_json(
+            r#"{
+                "rules": [
+                    { "if": "platform == \"RTD\"", "then": { "server": { "port": 9090 }, "log_level": "debug" } },
+                    { "if": "platform == \"MT\"", "then": "chip_mt" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let layered = |overlay| {
+            config::Config::builder()
+                .add_source(config::File::from_str(APP_CONFIG, config::FileFormat::Json))
+                .add_source(overlay)
+                .build()
+                .unwrap()
+        };
+
+        let config = layered(evaluator.config_overlay(&rtd_params()).unwrap());
+        assert_eq!(config.get::<u16>("server.port").unwrap(), 9090);
+        assert_eq!(config.get::<String>("server.host").unwrap(), "0.0.0.0");
+        assert_eq!(config.get::<String>("log_level").unwrap(), "debug");
+        // Keys the overlay does not set keep their values
+        assert!(config.get::<crate::ConfigRules>("chip_rules").is_ok());
+
+        // Nothing matched, nothing overridden
+        let config = layered(
+            evaluator
+                .config_overlay(&HashMap::<String, String>::new())
+                .unwrap(),
+        );
+        assert_eq!(config.get::<u16>("server.port").unwrap(), 8080);
+
+        let mt = HashMap::from([("platform".to_string(), "MT".to_string())]);
+        let error = evaluator.config_overlay(&mt).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Validation error: Overlay result must be a JSON object, got \"chip_mt\""
+        );
+    }
+
+    #[cfg(feature = "figment")]
+    #[test]
+    fn test_from_figment() {
+        use figment::providers::{Format, Json};
+
+        let figment = figment::Figment::new().merge(Json::string(APP_CONFIG));
+        let evaluator = crate::ConfigEvaluator::from_figment(&figment, "chip_rules").unwrap();
+        assert_eq!(
+            evaluator.evaluate(&rtd_params()),
+            Some(RuleResult::String("chip_rtd".to_string()))
+        );
+    }
+}
@@ -0,0 +1,196 @@
+//! Regression guard for rule set changes
+//!
+//! A [`Baseline`] records the results of a corpus of parameter sets under the
+//! current rules. Verifying a proposed rule set against it lists every case
+//! whose result changed, so a CI job on a rules repository can fail unless
+//! the changes were explicitly allowed.
+
+use crate::prelude::*;
+use crate::{ConfigEvaluator, ConfigExprError, ParamSource, RuleResult};
+use alloc::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+
+/// Results of a corpus under a rule set
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Baseline {
+    /// Version of the rule set the baseline was recorded with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    pub cases: Vec<BaselineCase>,
+}
+
+/// A recorded parameter set and its result
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BaselineCase {
+    pub params: BTreeMap<String, String>,
+    pub result: Option<RuleResult>,
+}
+
+/// A change the reviewer accepted: case `case` may now return `result`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AllowedChange {
+    pub case: usize,
+    pub result: Option<RuleResult>,
+}
+
+/// A baseline case whose result differs under the proposed rules
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct ResultChange {
+    /// Index of the case in the baseline
+    pub case: usize,
+    pub params: BTreeMap<String, String>,
+    pub expected: Option<RuleResult>,
+    pub actual: Option<RuleResult>,
+}
+
+/// Outcome of verifying a rule set against a baseline
+#[derive(Debug, Clone, Default, Serialize, PartialEq, Eq)]
+pub struct RegressionReport {
+    /// Number of cases evaluated
+    pub checked: usize,
+    /// Changes that were not allowed
+    pub unexpected: Vec<ResultChange>,
+    /// Changes matching an allowed change
+    pub allowed: Vec<ResultChange>,
+}
+
+impl RegressionReport {
+    /// Check whether every change was allowed
+    pub fn is_clean(&self) -> bool {
+        self.unexpected.is_empty()
+    }
+}
+
+impl Baseline {
+    /// Record the results of `corpus` under the evaluator's rules
+    ///
+    /// Cases store the [`ParamSource::entries`] of each params set.
+    pub fn record<'a, I, P>(evaluator: &ConfigEvaluator, corpus: I) -> Self
+    where
+        I: IntoIterator<Item = &'a P>,
+        P: ParamSource + 'a,
+    {
+        let cases = corpus
+            .into_iter()
+            .map(|params| BaselineCase {
+                params: params.entries().into_iter().collect(),
+                result: evaluator.evaluate(params),
+            })
+            .collect();
+        Baseline {
+            version: evaluator.rules.version.clone(),
+            cases,
+        }
+    }
+
+    /// Parse a baseline from JSON
+    pub fn from_json(json: &str) -> Result<Self, ConfigExprError> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Serialize the baseline to JSON
+    pub fn to_json(&self) -> Result<String, ConfigExprError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Verify that the evaluator reproduces every recorded result
+    pub fn verify(&self, evaluator: &ConfigEvaluator) -> RegressionReport {
+        self.verify_allowing(evaluator, &[])
+    }
+
+    /// Verify the evaluator, accepting the listed changes
+    ///
+    /// A change is accepted only when the new result equals the allowed one,
+    /// so an allowed case that changes to something else is still reported.
+    pub fn verify_allowing(
+        &self,
+        evaluator: &ConfigEvaluator,
+        allowed: &[AllowedChange],
+    ) -> RegressionReport {
+        let mut report = RegressionReport {
+            checked: self.cases.len(),
+            ..RegressionReport::default()
+        };
+        for (case, recorded) in self.cases.iter().enumerate() {
+            let params: HashMap<String, String> = recorded
+                .params
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+            let actual = evaluator.evaluate(&params);
+            if actual == recorded.result {
+                continue;
+            }
+            let accepted = allowed
+                .iter()
+                .any(|change| change.case == case && change.result == actual);
+            let change = ResultChange {
+                case,
+                params: recorded.params.clone(),
+                expected: recorded.result.clone(),
+                actual,
+            };
+            if accepted {
+                report.allowed.push(change);
+            } else {
+                report.unexpected.push(change);
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluator(rtd_result: &str) -> ConfigEvaluator {
+        let json = format!(
+            r#"
+            {{
+                "rules": [
+                    {{
+                        "if": {{ "field": "platform", "op": "prefix", "value": "RTD" }},
+                        "then": "{rtd_result}"
+                    }}
+                ],
+                "fallback": "default_chip"
+            }}
+            "#
+        );
+        ConfigEvaluator::from_json(&json).unwrap()
+    }
+
+    #[test]
+    fn test_baseline_verify() {
+        let corpus = vec![
+            HashMap::from([("platform".to_string(), "RTD-2000".to_string())]),
+            HashMap::from([("platform".to_string(), "MT9950".to_string())]),
+        ];
+        let baseline = Baseline::record(&evaluator("chip_rtd"), &corpus);
+        let baseline = Baseline::from_json(&baseline.to_json().unwrap()).unwrap();
+        assert!(baseline.verify(&evaluator("chip_rtd")).is_clean());
+
+        let proposed = evaluator("chip_rtd_v2");
+        let report = baseline.verify(&proposed);
+        assert_eq!(report.checked, 2);
+        assert_eq!(report.unexpected.len(), 1);
+        assert_eq!(report.unexpected[0].case, 0);
+        assert_eq!(
+            report.unexpected[0].actual,
+            Some(RuleResult::String("chip_rtd_v2".to_string()))
+        );
+
+        let allowed = [AllowedChange {
+            case: 0,
+            result: Some(RuleResult::String("chip_rtd_v2".to_string())),
+        }];
+        let report = baseline.verify_allowing(&proposed, &allowed);
+        assert!(report.is_clean());
+        assert_eq!(report.allowed.len(), 1);
+
+        // An allowance for a different result does not cover the change
+        let report = baseline.verify_allowing(&evaluator("chip_rtd_v3"), &allowed);
+        assert!(!report.is_clean());
+    }
+}
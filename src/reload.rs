@@ -0,0 +1,181 @@
+//! Hot-reloadable evaluator backed by a watched rule file
+//!
+//! [`ReloadableEvaluator`] watches the directory containing the rule file, so
+//! editors and deploy tools that replace the file by renaming are picked up
+//! as well as in-place writes. A new rule set only replaces the current one
+//! once it loads and validates; otherwise the old rules stay in effect.
+
+use crate::{ConfigEvaluator, ConfigExprError, ParamSource, RuleResult, SharedEvaluator};
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Callback receiving errors of background reloads
+type ErrorHandler = Box<dyn Fn(&ConfigExprError) + Send + Sync>;
+
+/// State shared with the watcher thread
+struct Shared {
+    path: PathBuf,
+    current: SharedEvaluator,
+    on_error: ErrorHandler,
+}
+
+impl Shared {
+    fn reload(&self) -> Result<(), ConfigExprError> {
+        let evaluator = ConfigEvaluator::from_file(&self.path)?;
+        self.current.replace(evaluator);
+        Ok(())
+    }
+
+    fn handle_event(&self, event: notify::Result<Event>) {
+        match event {
+            Ok(event) if event.kind.is_access() || event.kind.is_remove() => {}
+            Ok(event) => {
+                let file_name = self.path.file_name();
+                if event.paths.iter().any(|path| path.file_name() == file_name) {
+                    if let Err(error) = self.reload() {
+                        (self.on_error)(&error);
+                    }
+                }
+            }
+            Err(error) => (self.on_error)(&ConfigExprError::WatchError(Box::new(error))),
+        }
+    }
+}
+
+/// An evaluator that reloads its rules whenever the rule file changes
+///
+/// Evaluations use a snapshot of the current rules, so a reload never
+/// changes the rules in the middle of an evaluation.
+pub struct ReloadableEvaluator {
+    shared: Arc<Shared>,
+    _watcher: RecommendedWatcher,
+}
+
+impl ReloadableEvaluator {
+    /// Load a rule file and watch it for changes
+    ///
+    /// Fails if the initial rules cannot be loaded. Errors of later reloads
+    /// are discarded; use [`ReloadableEvaluator::watch_with_error_handler`]
+    /// to observe them.
+    pub fn watch(path: impl AsRef<Path>) -> Result<Self, ConfigExprError> {
+        Self::watch_with_error_handler(path, |_| {})
+    }
+
+    /// Load a rule file and watch it, reporting failed reloads to `on_error`
+    ///
+    /// `on_error` runs on the watcher thread.
+    pub fn watch_with_error_handler(
+        path: impl AsRef<Path>,
+        on_error: impl Fn(&ConfigExprError) + Send + Sync + 'static,
+    ) -> Result<Self, ConfigExprError> {
+        let path = path.as_ref().to_path_buf();
+        let evaluator = ConfigEvaluator::from_file(&path)?;
+        let shared = Arc::new(Shared {
+            path,
+            current: SharedEvaluator::new(evaluator),
+            on_error: Box::new(on_error),
+        });
+
+        let handler = Arc::clone(&shared);
+        let mut watcher = notify::recommended_watcher(move |event| handler.handle_event(event))
+            .map_err(|e| ConfigExprError::WatchError(Box::new(e)))?;
+        let dir = match shared.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map_err(|e| ConfigExprError::WatchError(Box::new(e)))?;
+        Ok(Self {
+            shared,
+            _watcher: watcher,
+        })
+    }
+
+    /// The rule file being watched
+    pub fn path(&self) -> &Path {
+        &self.shared.path
+    }
+
+    /// Snapshot of the current evaluator
+    pub fn current(&self) -> Arc<ConfigEvaluator> {
+        self.shared.current.current()
+    }
+
+    /// Reload the rule file now, keeping the current rules if it fails
+    pub fn reload(&self) -> Result<(), ConfigExprError> {
+        self.shared.reload()
+    }
+
+    /// Evaluate request parameters with the current rules
+    pub fn evaluate(&self, params: &impl ParamSource) -> Option<RuleResult> {
+        self.current().evaluate(params)
+    }
+}
+
+impl fmt::Debug for ReloadableEvaluator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReloadableEvaluator")
+            .field("path", &self.shared.path)
+            .field("current", &self.current())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+
+    fn rules(result: &str) -> String {
+        format!(
+            r#"{{
+                "rules": [
+                    {{
+                        "if": {{ "field": "platform", "op": "prefix", "value": "RTD" }},
+                        "then": "{result}"
+                    }}
+                ]
+            }}"#
+        )
+    }
+
+    #[test]
+    fn test_reload_on_change() {
+        let dir = std::env::temp_dir().join(format!("config-expr-reload-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("rules.json");
+        fs::write(&path, rules("chip_rtd")).unwrap();
+
+        let (errors, failed) = mpsc::channel();
+        let evaluator = ReloadableEvaluator::watch_with_error_handler(&path, move |e| {
+            let _ = errors.send(e.to_string());
+        })
+        .unwrap();
+        let params = HashMap::from([("platform".to_string(), "RTD-2000".to_string())]);
+        let expect = |result: &str| {
+            let expected = Some(RuleResult::String(result.to_string()));
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while evaluator.evaluate(&params) != expected && Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            assert_eq!(evaluator.evaluate(&params), expected);
+        };
+        expect("chip_rtd");
+
+        fs::write(&path, rules("chip_rtd_v2")).unwrap();
+        expect("chip_rtd_v2");
+
+        // An invalid file is reported and the previous rules stay in effect
+        fs::write(&path, "{ \"rules\": [").unwrap();
+        let error = failed.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(error.contains("rules.json"));
+        assert!(evaluator.reload().is_err());
+        expect("chip_rtd_v2");
+    }
+}
@@ -0,0 +1,334 @@
+//! Rules polled from an HTTP(S) endpoint
+//!
+//! [`RemoteRuleSource`] fetches a JSON rule document from a URL on a tokio
+//! runtime and swaps it into a [`SharedEvaluator`]. Requests carry the last
+//! `ETag` in `If-None-Match`, so an unchanged document costs a `304`. New
+//! rules only replace the current ones once they validate; failed polls are
+//! retried with exponential backoff while the old rules stay in effect.
+
+use crate::{parse_json_rules, ConfigEvaluator, ConfigExprError, ConfigRules, SharedEvaluator};
+use reqwest::header::{ETAG, IF_NONE_MATCH};
+use reqwest::StatusCode;
+use std::fmt;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// Callback receiving errors of background polls
+type ErrorHandler = Box<dyn Fn(&ConfigExprError) + Send + Sync>;
+
+/// A rule set applied from a [`RemoteRuleSource`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteUpdate {
+    /// `version` of the rule set, if it declares one
+    pub version: Option<String>,
+    /// `ETag` the server sent with the rules
+    pub etag: Option<String>,
+}
+
+/// Polls a URL for rules and feeds them to a [`SharedEvaluator`]
+///
+/// Build one with [`RemoteRuleSource::new`] or [`RemoteRuleSource::connect`],
+/// adjust it with the `with_*` methods and start polling with
+/// [`RemoteRuleSource::spawn`]:
+///
+/// ```no_run
+/// # async fn run() -> Result<(), clia_config_expr::ConfigExprError> {
+/// use clia_config_expr::RemoteRuleSource;
+/// use std::time::Duration;
+///
+/// let source = RemoteRuleSource::connect("https://config.example.com/rules.json")
+///     .await?
+///     .with_interval(Duration::from_secs(60));
+/// let evaluator = source.evaluator();
+/// let mut updates = source.subscribe();
+/// source.spawn();
+/// while updates.changed().await.is_ok() {
+///     println!("rules updated: {:?}", *updates.borrow());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct RemoteRuleSource {
+    url: String,
+    client: reqwest::Client,
+    current: SharedEvaluator,
+    interval: Duration,
+    min_backoff: Duration,
+    max_backoff: Duration,
+    etag: Option<String>,
+    updates: watch::Sender<Option<RemoteUpdate>>,
+    on_error: ErrorHandler,
+}
+
+impl RemoteRuleSource {
+    /// Poll `url` for rules replacing those of `evaluator`
+    ///
+    /// Polls every 30 seconds; failed polls are retried after 1 second,
+    /// doubling up to 5 minutes.
+    pub fn new(url: impl Into<String>, evaluator: SharedEvaluator) -> Self {
+        RemoteRuleSource {
+            url: url.into(),
+            client: reqwest::Client::new(),
+            current: evaluator,
+            interval: Duration::from_secs(30),
+            min_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(300),
+            etag: None,
+            updates: watch::Sender::new(None),
+            on_error: Box::new(|_| {}),
+        }
+    }
+
+    /// Fetch the initial rules from `url` and poll it for changes
+    ///
+    /// Fails if the initial rules cannot be fetched or are invalid.
+    pub async fn connect(url: impl Into<String>) -> Result<Self, ConfigExprError> {
+        let empty = ConfigEvaluator::from_json(r#"{ "rules": [] }"#)?;
+        let mut source = Self::new(url, SharedEvaluator::new(empty));
+        source.poll().await?;
+        Ok(source)
+    }
+
+    /// Time between polls
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Delay before retrying a failed poll, doubled after every further
+    /// failure up to `max`
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.min_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    /// HTTP client for the requests, e.g. with timeouts, proxies or default
+    /// authentication headers
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Report failed background polls to `on_error`
+    ///
+    /// `on_error` runs on the polling task.
+    pub fn with_error_handler(
+        mut self,
+        on_error: impl Fn(&ConfigExprError) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_error = Box::new(on_error);
+        self
+    }
+
+    /// The URL being polled
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Handle to the evaluator the rules are fed to
+    pub fn evaluator(&self) -> SharedEvaluator {
+        self.current.clone()
+    }
+
+    /// Receiver notified whenever new rules are applied
+    ///
+    /// Holds `None` until the first rules are applied.
+    pub fn subscribe(&self) -> watch::Receiver<Option<RemoteUpdate>> {
+        self.updates.subscribe()
+    }
+
+    /// Fetch the rules once, returning whether they changed
+    ///
+    /// A `304 Not Modified` response or a document with the current rules
+    /// leaves the evaluator untouched. Failed requests, other error statuses
+    /// and invalid rules fail the poll and keep the current rules.
+    pub async fn poll(&mut self) -> Result<bool, ConfigExprError> {
+        let error = |message: String| ConfigExprError::RemoteError(message);
+        let mut request = self.client.get(&self.url);
+        if let Some(etag) = &self.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| error(format!("{}: {}", self.url, e)))?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(false);
+        }
+        if !response.status().is_success() {
+            return Err(error(format!("{}: HTTP {}", self.url, response.status())));
+        }
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_string);
+        let body = response
+            .text()
+            .await
+            .map_err(|e| error(format!("{}: {}", self.url, e)))?;
+
+        let rules: ConfigRules = parse_json_rules(body.as_bytes())?;
+        let current = self.current.current();
+        let evaluator = current.with_rules(rules)?;
+        self.etag = etag;
+        if evaluator.rules() == current.rules() {
+            return Ok(false);
+        }
+        let update = RemoteUpdate {
+            version: evaluator.rules().version.clone(),
+            etag: self.etag.clone(),
+        };
+        self.current.replace(evaluator);
+        self.updates.send_replace(Some(update));
+        Ok(true)
+    }
+
+    /// Poll forever, waiting the interval after successful polls and the
+    /// backoff after failed ones
+    pub async fn run(mut self) {
+        let mut backoff = self.min_backoff;
+        loop {
+            let delay = match self.poll().await {
+                Ok(_) => {
+                    backoff = self.min_backoff;
+                    self.interval
+                }
+                Err(error) => {
+                    (self.on_error)(&error);
+                    let delay = backoff;
+                    backoff = (backoff * 2).min(self.max_backoff);
+                    delay
+                }
+            };
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Poll in a task on the current tokio runtime, waiting the interval
+    /// first
+    ///
+    /// Abort the returned handle to stop polling.
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            tokio::time::sleep(self.interval).await;
+            self.run().await
+        })
+    }
+}
+
+impl fmt::Debug for RemoteRuleSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RemoteRuleSource")
+            .field("url", &self.url)
+            .field("interval", &self.interval)
+            .field("etag", &self.etag)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RuleResult;
+    use std::collections::HashMap;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    fn rules(result: &str) -> String {
+        format!(
+            r#"{{ "version": "{result}", "rules": [{{ "if": "platform == \"RTD\"", "then": "{result}" }}] }}"#
+        )
+    }
+
+    /// Serve canned responses, one per connection, sending the received
+    /// requests back
+    fn serve(responses: Vec<String>) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/rules.json", listener.local_addr().unwrap());
+        let (requests, received) = mpsc::channel();
+        std::thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let _ = requests.send(String::from_utf8_lossy(&request).to_lowercase());
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (url, received)
+    }
+
+    fn response(status: &str, etag: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {status}\r\nETag: {etag}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+    }
+
+    #[test]
+    fn test_remote_rule_source() {
+        let (url, requests) = serve(vec![
+            response("200 OK", "\"v1\"", &rules("v1")),
+            response("304 Not Modified", "\"v1\"", ""),
+            response("200 OK", "\"v2\"", "{ \"rules\": ["),
+            response("500 Internal Server Error", "\"v2\"", ""),
+            response("200 OK", "\"v2\"", &rules("v2")),
+        ]);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let params = HashMap::from([("platform".to_string(), "RTD".to_string())]);
+
+        runtime.block_on(async {
+            let (errors, failed) = mpsc::channel();
+            let mut source = RemoteRuleSource::connect(url.as_str())
+                .await
+                .unwrap()
+                .with_interval(Duration::from_millis(10))
+                .with_backoff(Duration::from_millis(10), Duration::from_millis(20))
+                .with_error_handler(move |e| {
+                    let _ = errors.send(e.error_code());
+                });
+            let evaluator = source.evaluator();
+            assert_eq!(
+                evaluator.evaluate(&params),
+                Some(RuleResult::String("v1".to_string()))
+            );
+            assert!(!requests.recv().unwrap().contains("if-none-match"));
+
+            assert!(!source.poll().await.unwrap());
+            assert!(requests.recv().unwrap().contains("if-none-match: \"v1\""));
+
+            // Failed polls are retried and the rules stay in effect meanwhile
+            let mut updates = source.subscribe();
+            updates.mark_unchanged();
+            let polling = source.spawn();
+            updates.changed().await.unwrap();
+            assert_eq!(
+                *updates.borrow(),
+                Some(RemoteUpdate {
+                    version: Some("v2".to_string()),
+                    etag: Some("\"v2\"".to_string()),
+                })
+            );
+            polling.abort();
+            assert_eq!(
+                failed.try_iter().collect::<Vec<_>>(),
+                ["json_error", "remote_error"]
+            );
+            assert_eq!(
+                evaluator.evaluate(&params),
+                Some(RuleResult::String("v2".to_string()))
+            );
+        });
+    }
+}
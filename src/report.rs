@@ -0,0 +1,814 @@
+//! Evaluation traces and reports
+//!
+//! [`ConfigEvaluator::evaluate_with_trace`] records every condition visited
+//! during one evaluation, so the decision can be explained in logs or a UI.
+//! [`ConfigEvaluator::evaluate_report`] bundles everything support engineers
+//! need to understand one evaluation: the outcome, every rule that was
+//! considered and why it matched or not, non-fatal problems with the input,
+//! and timing.
+
+use crate::expr::{self, Expr};
+use crate::prelude::*;
+use crate::time::Stopwatch;
+use crate::{path, text, version};
+use crate::{Condition, ConfigEvaluator, Operator, ParamSource, Rule, RuleResult};
+use alloc::borrow::Cow;
+use alloc::collections::BTreeMap;
+use core::fmt;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Evaluation phase a rule belongs to
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    Pre,
+    Main,
+    Post,
+}
+
+impl Phase {
+    /// Label used for rules of this phase in messages
+    pub(crate) fn label(self, index: usize) -> String {
+        match self {
+            Phase::Pre => format!("pre rule {}", index),
+            Phase::Main => format!("rule {}", index),
+            Phase::Post => format!("post rule {}", index),
+        }
+    }
+}
+
+/// Why a rule was left out before its condition was checked
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SkipReason {
+    /// The rule has `"enabled": false`
+    Disabled,
+    /// The evaluation time is outside the rule's `active_from` / `active_until` window
+    Window,
+    /// The input falls outside the rule's rollout percentage
+    Rollout,
+    /// The rule's schedule is not active
+    Schedule,
+}
+
+/// A rule considered during evaluation
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct TraceStep {
+    pub phase: Phase,
+    /// Index of the rule within its phase
+    pub rule_index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// The rule's `metadata`
+    #[serde(skip_serializing_if = "Value::is_null")]
+    pub metadata: Value,
+    pub matched: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<SkipReason>,
+    /// The rule's condition as evaluated, absent when the rule was skipped
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub condition: Option<ConditionTrace>,
+}
+
+/// A condition visited during evaluation
+///
+/// `and` and `or` stop at the first child that decides their outcome, so
+/// only the children actually evaluated are listed.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum ConditionTrace {
+    Simple {
+        field: String,
+        op: Operator,
+        /// The condition value, or the value of `value_field` (`null` when
+        /// that param is missing)
+        value: Value,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        value_field: Option<String>,
+        /// The param value the condition was checked against, after `trim`
+        /// and `normalize`
+        actual: Option<String>,
+        matched: bool,
+    },
+    And {
+        matched: bool,
+        conditions: Vec<ConditionTrace>,
+    },
+    Or {
+        matched: bool,
+        conditions: Vec<ConditionTrace>,
+    },
+    Not {
+        matched: bool,
+        condition: Box<ConditionTrace>,
+    },
+    /// A named condition, with the trace of its definition
+    Ref {
+        name: String,
+        matched: bool,
+        condition: Box<ConditionTrace>,
+    },
+}
+
+impl ConditionTrace {
+    /// Whether the condition held
+    pub fn matched(&self) -> bool {
+        match self {
+            ConditionTrace::Simple { matched, .. }
+            | ConditionTrace::And { matched, .. }
+            | ConditionTrace::Or { matched, .. }
+            | ConditionTrace::Not { matched, .. }
+            | ConditionTrace::Ref { matched, .. } => *matched,
+        }
+    }
+
+    /// Visit the simple conditions of the trace in evaluation order
+    pub(crate) fn for_each_simple<'a>(&'a self, f: &mut impl FnMut(&'a ConditionTrace)) {
+        match self {
+            ConditionTrace::Simple { .. } => f(self),
+            ConditionTrace::And { conditions, .. } | ConditionTrace::Or { conditions, .. } => {
+                conditions.iter().for_each(|c| c.for_each_simple(f))
+            }
+            ConditionTrace::Not { condition, .. } | ConditionTrace::Ref { condition, .. } => {
+                condition.for_each_simple(f)
+            }
+        }
+    }
+
+    fn write_indented(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        let indent = "  ".repeat(depth);
+        match self {
+            ConditionTrace::Simple {
+                field,
+                op,
+                value,
+                value_field,
+                actual,
+                matched,
+            } => {
+                let actual = match actual {
+                    Some(actual) => format!("{:?}", actual),
+                    None => "missing".to_string(),
+                };
+                let value = match value_field {
+                    Some(value_field) => format!("{}={}", value_field, value),
+                    None => value.to_string(),
+                };
+                writeln!(
+                    f,
+                    "{}{} {} {} (actual {}): {}",
+                    indent,
+                    field,
+                    op.name(),
+                    value,
+                    actual,
+                    matched
+                )
+            }
+            ConditionTrace::And {
+                matched,
+                conditions,
+            }
+            | ConditionTrace::Or {
+                matched,
+                conditions,
+            } => {
+                let kind = if matches!(self, ConditionTrace::And { .. }) {
+                    "and"
+                } else {
+                    "or"
+                };
+                writeln!(f, "{}{}: {}", indent, kind, matched)?;
+                for condition in conditions {
+                    condition.write_indented(f, depth + 1)?;
+                }
+                Ok(())
+            }
+            ConditionTrace::Not { matched, condition } => {
+                writeln!(f, "{}not: {}", indent, matched)?;
+                condition.write_indented(f, depth + 1)
+            }
+            ConditionTrace::Ref {
+                name,
+                matched,
+                condition,
+            } => {
+                writeln!(f, "{}ref {}: {}", indent, name, matched)?;
+                condition.write_indented(f, depth + 1)
+            }
+        }
+    }
+}
+
+/// The outcome of one evaluation with every rule and condition visited
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct EvaluationTrace {
+    pub result: Option<RuleResult>,
+    /// Error that aborted the evaluation, such as a failed assertion
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Index of the main-phase rule that selected the result
+    pub rule_index: Option<usize>,
+    /// Rules considered, in evaluation order
+    pub steps: Vec<TraceStep>,
+}
+
+/// Renders the trace as an indented, human-readable explanation
+impl fmt::Display for EvaluationTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for step in &self.steps {
+            write!(f, "{}", step.phase.label(step.rule_index))?;
+            if let Some(id) = &step.rule_id {
+                write!(f, " ({})", id)?;
+            }
+            match step.skipped {
+                Some(SkipReason::Disabled) => writeln!(f, ": skipped as disabled")?,
+                Some(SkipReason::Window) => writeln!(f, ": skipped outside active window")?,
+                Some(SkipReason::Rollout) => writeln!(f, ": skipped by rollout")?,
+                Some(SkipReason::Schedule) => writeln!(f, ": skipped by schedule")?,
+                None if step.matched => writeln!(f, ": matched")?,
+                None => writeln!(f, ": not matched")?,
+            }
+            if let Some(condition) = &step.condition {
+                condition.write_indented(f, 1)?;
+            }
+        }
+        match (&self.error, &self.result) {
+            (Some(error), _) => write!(f, "error: {}", error),
+            (None, Some(result)) => write!(
+                f,
+                "result: {}",
+                serde_json::to_string(result).map_err(|_| fmt::Error)?
+            ),
+            (None, None) => write!(f, "result: none"),
+        }
+    }
+}
+
+/// Everything about one evaluation, in a single serializable payload
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct EvaluationReport {
+    /// Version of the rule set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// The params as given by the caller
+    pub params: BTreeMap<String, String>,
+    pub result: Option<RuleResult>,
+    /// Error that aborted the evaluation, such as a failed assertion
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Index of the main-phase rule that selected the result
+    pub rule_index: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule_id: Option<String>,
+    /// Whether the result came from the fallback
+    pub fallback: bool,
+    /// Rules considered, in evaluation order
+    pub trace: Vec<TraceStep>,
+    /// Problems with the input that did not abort evaluation
+    pub soft_errors: Vec<String>,
+    /// Evaluation time in microseconds, including tracing overhead
+    pub duration_us: u64,
+}
+
+/// Collects trace steps during evaluation when enabled
+pub(crate) struct Recorder<'a> {
+    steps: Option<Vec<TraceStep>>,
+    soft_errors: Vec<String>,
+    /// Whether the evaluation and its rule hits count towards the metrics
+    counted: bool,
+    /// Rules the caller limited the evaluation to
+    filter: Option<&'a dyn Fn(&Rule) -> bool>,
+    /// First missing param a condition read, noted in strict mode
+    missing_field: Option<String>,
+}
+
+impl<'a> Recorder<'a> {
+    pub(crate) fn disabled() -> Self {
+        Recorder {
+            steps: None,
+            soft_errors: Vec::new(),
+            counted: true,
+            filter: None,
+            missing_field: None,
+        }
+    }
+
+    /// Recorder of an evaluation considering only the rules `filter` accepts
+    pub(crate) fn filtered(filter: &'a dyn Fn(&Rule) -> bool) -> Self {
+        Recorder {
+            filter: Some(filter),
+            ..Self::disabled()
+        }
+    }
+
+    /// Recorder of an evaluation that is neither traced nor counted, such as
+    /// a pass finding the fields an async evaluation still has to resolve
+    #[cfg(feature = "async")]
+    pub(crate) fn probe() -> Self {
+        Recorder {
+            counted: false,
+            ..Self::disabled()
+        }
+    }
+
+    /// Check whether steps are being recorded
+    pub(crate) fn is_tracing(&self) -> bool {
+        self.steps.is_some()
+    }
+
+    /// Check whether the evaluation counts towards the metrics; traced
+    /// evaluations do not
+    pub(crate) fn is_counted(&self) -> bool {
+        self.counted && !self.is_tracing()
+    }
+
+    /// Check whether the result may come from or go to the result cache:
+    /// traced and filtered evaluations bypass it
+    #[cfg(feature = "std")]
+    pub(crate) fn is_cacheable(&self) -> bool {
+        !self.is_tracing() && self.filter.is_none()
+    }
+
+    /// Take the first missing param a condition read in strict mode
+    pub(crate) fn take_missing_field(&mut self) -> Option<String> {
+        self.missing_field.take()
+    }
+
+    /// Check whether a rule takes part in the evaluation
+    pub(crate) fn considers(&self, rule: &Rule) -> bool {
+        self.filter.is_none_or(|filter| filter(rule))
+    }
+
+    fn enabled() -> Self {
+        Recorder {
+            steps: Some(Vec::new()),
+            counted: false,
+            ..Self::disabled()
+        }
+    }
+}
+
+impl ConfigEvaluator {
+    /// Check whether a rule matches, recording the check when tracing
+    pub(crate) fn rule_matches_traced(
+        &self,
+        recorder: &mut Recorder,
+        phase: Phase,
+        index: usize,
+        rule: &Rule,
+        params: &dyn ParamSource,
+    ) -> bool {
+        if !recorder.considers(rule) {
+            return false;
+        }
+        let Some(steps) = &mut recorder.steps else {
+            return self.rule_matches(
+                phase,
+                index,
+                rule,
+                params,
+                recorder.counted,
+                &mut recorder.missing_field,
+            );
+        };
+
+        let skipped = self.skip_reason(rule, params);
+        let condition = skipped
+            .is_none()
+            .then(|| self.trace_condition(&rule.condition, params));
+        let matched = condition.as_ref().is_some_and(ConditionTrace::matched);
+        if let Some(condition) = condition.as_ref().filter(|_| self.strict_fields) {
+            recorder.missing_field = recorder
+                .missing_field
+                .take()
+                .or_else(|| missing_field(condition));
+        }
+        if let Some(condition) = &condition {
+            condition.for_each_simple(&mut |leaf| {
+                let ConditionTrace::Simple {
+                    field,
+                    op,
+                    value,
+                    value_field,
+                    actual,
+                    ..
+                } = leaf
+                else {
+                    return;
+                };
+                let error = match value_field {
+                    Some(value_field) if value.is_null() => {
+                        Some(format!("Field '{}' not found", value_field))
+                    }
+                    _ => self.soft_error(field, op, value, actual.as_deref(), params),
+                };
+                if let Some(error) = error {
+                    recorder
+                        .soft_errors
+                        .push(format!("{} in {}", error, phase.label(index)));
+                }
+            });
+        }
+        steps.push(TraceStep {
+            phase,
+            rule_index: index,
+            rule_id: rule.id.clone(),
+            description: rule.description.clone(),
+            tags: rule.tags.clone(),
+            metadata: rule.metadata.clone(),
+            matched,
+            skipped,
+            condition,
+        });
+        matched
+    }
+
+    /// Evaluate a condition, recording every condition visited
+    pub(crate) fn trace_condition(
+        &self,
+        condition: &Condition,
+        params: &dyn ParamSource,
+    ) -> ConditionTrace {
+        match condition {
+            Condition::Simple {
+                field,
+                op,
+                value,
+                value_field,
+                trim,
+                normalize,
+                ..
+            } => {
+                let prepare = |value| text::prepare_param(value, *trim, *normalize).into_owned();
+                ConditionTrace::Simple {
+                    field: field.clone(),
+                    op: op.clone(),
+                    value: match value_field {
+                        Some(value_field) => path::lookup(params, value_field)
+                            .map_or(Value::Null, |other| Value::String(prepare(other))),
+                        None => text::prepare_condition_value(value, *normalize).into_owned(),
+                    },
+                    value_field: value_field.clone(),
+                    actual: path::lookup(params, field).map(prepare),
+                    matched: self.evaluate_condition(condition, params),
+                }
+            }
+            Condition::And { and } => {
+                let mut conditions = Vec::new();
+                for c in and {
+                    let trace = self.trace_condition(c, params);
+                    let matched = trace.matched();
+                    conditions.push(trace);
+                    if !matched {
+                        break;
+                    }
+                }
+                ConditionTrace::And {
+                    matched: conditions.iter().all(ConditionTrace::matched),
+                    conditions,
+                }
+            }
+            Condition::Or { or } => {
+                let mut conditions = Vec::new();
+                for c in or {
+                    let trace = self.trace_condition(c, params);
+                    let matched = trace.matched();
+                    conditions.push(trace);
+                    if matched {
+                        break;
+                    }
+                }
+                ConditionTrace::Or {
+                    matched: conditions.iter().any(ConditionTrace::matched),
+                    conditions,
+                }
+            }
+            Condition::Not { not } => {
+                let condition = self.trace_condition(not, params);
+                ConditionTrace::Not {
+                    matched: !condition.matched(),
+                    condition: Box::new(condition),
+                }
+            }
+            Condition::Ref { name } => {
+                let condition = match self.rules.definitions.get(name) {
+                    Some(definition) => self.trace_condition(definition, params),
+                    // Like an empty OR, an unknown ref never matches
+                    None => ConditionTrace::Or {
+                        matched: false,
+                        conditions: Vec::new(),
+                    },
+                };
+                ConditionTrace::Ref {
+                    name: name.clone(),
+                    matched: condition.matched(),
+                    condition: Box::new(condition),
+                }
+            }
+        }
+    }
+
+    /// Describe why a simple condition could not be checked meaningfully
+    fn soft_error(
+        &self,
+        field: &str,
+        op: &Operator,
+        value: &Value,
+        actual: Option<&str>,
+        params: &dyn ParamSource,
+    ) -> Option<String> {
+        if let Operator::Custom(custom) = op {
+            if !self.custom_operators.contains_key(custom.name()) {
+                return Some(format!(
+                    "Custom operator '{}' is not registered",
+                    custom.name()
+                ));
+            }
+        }
+        let Some(actual) = actual else {
+            // `equals null` expects the param to be missing
+            return (!value.is_null()).then(|| format!("Field '{}' not found", field));
+        };
+        let range = crate::numeric_range(value).filter(|_| *op == Operator::Between);
+        let numeric =
+            op.is_numeric() || (value.is_number() && *op == Operator::Equals) || range.is_some();
+        if numeric && actual.parse::<f64>().is_err() {
+            return Some(format!(
+                "Param '{}' value '{}' is not a number",
+                field, actual
+            ));
+        }
+        if op.is_semver() && version::parse(actual).is_none() {
+            return Some(format!(
+                "Param '{}' value '{}' is not a version",
+                field, actual
+            ));
+        }
+        if op.is_datetime() && range.is_none() && self.parse_datetime(actual).is_none() {
+            return Some(format!(
+                "Param '{}' value '{}' is not a datetime",
+                field, actual
+            ));
+        }
+        if value.is_boolean() && actual.parse::<bool>().is_err() {
+            return Some(format!(
+                "Param '{}' value '{}' is not a bool",
+                field, actual
+            ));
+        }
+        match (op, value) {
+            (_, Value::String(source)) if op.is_numeric() && expr::has_placeholders(source) => {
+                Expr::parse(source)
+                    .and_then(|expr| expr.eval(params))
+                    .err()
+                    .map(|e| e.to_string())
+            }
+            #[cfg(feature = "scripting")]
+            (Operator::Script, Value::String(source)) => self
+                .scripts
+                .check_condition(source, actual, params)
+                .err()
+                .map(|e| e.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Evaluate request parameters, recording why each rule matched or not
+    ///
+    /// The trace lists every rule considered in evaluation order with the
+    /// conditions visited, the param values they saw and their outcome. Its
+    /// `Display` output is a readable explanation of the decision.
+    pub fn evaluate_with_trace(&self, params: &impl ParamSource) -> EvaluationTrace {
+        let mut recorder = Recorder::enabled();
+        let (result, error, rule_index) = match self.evaluate_outcome(params, &mut recorder) {
+            Ok(outcome) => (
+                outcome.result.map(Cow::into_owned),
+                None,
+                outcome.rule_index,
+            ),
+            Err(error) => (None, Some(error.to_string()), None),
+        };
+        EvaluationTrace {
+            result,
+            error,
+            rule_index,
+            steps: recorder.steps.unwrap_or_default(),
+        }
+    }
+
+    /// Evaluate request parameters and report the outcome with a full trace
+    ///
+    /// Intended for debugging endpoints; use [`ConfigEvaluator::evaluate`]
+    /// on hot paths, since tracing records every condition visited.
+    pub fn evaluate_report(&self, params: &impl ParamSource) -> EvaluationReport {
+        let started = Stopwatch::start();
+        let mut recorder = Recorder::enabled();
+        let outcome = self.evaluate_outcome(params, &mut recorder);
+        let duration_us = started.elapsed().as_micros() as u64;
+
+        let mut soft_errors = Vec::new();
+        if let Err(error) = self.validate_params(params) {
+            soft_errors.push(error.to_string());
+        }
+        soft_errors.append(&mut recorder.soft_errors);
+
+        let (result, error, rule_index) = match outcome {
+            Ok(outcome) => (
+                outcome.result.map(Cow::into_owned),
+                None,
+                outcome.rule_index,
+            ),
+            Err(error) => (None, Some(error.to_string()), None),
+        };
+        let fallback = error.is_none() && rule_index.is_none() && self.rules.fallback.is_some();
+        EvaluationReport {
+            version: self.rules.version.clone(),
+            params: params.entries().into_iter().collect(),
+            result,
+            error,
+            rule_index,
+            rule_id: rule_index.and_then(|index| self.rules.rules[index].id.clone()),
+            fallback,
+            trace: recorder.steps.unwrap_or_default(),
+            soft_errors,
+            duration_us,
+        }
+    }
+}
+
+/// Field of the first condition visited that read a missing param,
+/// except conditions meant for missing params
+fn missing_field(condition: &ConditionTrace) -> Option<String> {
+    let mut missing = None;
+    condition.for_each_simple(&mut |leaf| {
+        let ConditionTrace::Simple {
+            field,
+            op,
+            value,
+            value_field,
+            actual,
+            ..
+        } = leaf
+        else {
+            return;
+        };
+        if missing.is_some() {
+            return;
+        }
+        missing = match value_field {
+            _ if actual.is_none() && !op.reads_absence(value, value_field.is_some()) => {
+                Some(field.clone())
+            }
+            // The value of a comparison against a missing param is `null`
+            Some(value_field) if value.is_null() => Some(value_field.clone()),
+            _ => None,
+        };
+    });
+    missing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evaluate_report() {
+        let json = r#"
+        {
+            "version": "7",
+            "params_spec": { "region": { "required": true } },
+            "rules": [
+                {
+                    "id": "legacy",
+                    "if": { "field": "firmware", "op": "lt", "value": "2" },
+                    "then": "chip_legacy"
+                },
+                {
+                    "id": "rtd",
+                    "description": "Realtek and MediaTek boards",
+                    "tags": ["chips", "tv"],
+                    "metadata": { "owner": "platform-team", "ticket": 42 },
+                    "if": {
+                        "or": [
+                            { "field": "platform", "op": "prefix", "value": "RTD" },
+                            { "field": "platform", "op": "prefix", "value": "MT" }
+                        ]
+                    },
+                    "then": "chip_rtd"
+                }
+            ],
+            "fallback": "default_chip"
+        }
+        "#;
+
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+        let params = HashMap::from([
+            ("platform".to_string(), "RTD-2000".to_string()),
+            ("firmware".to_string(), "unknown".to_string()),
+        ]);
+        let report = evaluator.evaluate_report(&params);
+
+        assert_eq!(
+            report.result,
+            Some(RuleResult::String("chip_rtd".to_string()))
+        );
+        assert_eq!(report.rule_index, Some(1));
+        assert_eq!(report.rule_id.as_deref(), Some("rtd"));
+        assert!(!report.fallback);
+        assert_eq!(report.trace.len(), 2);
+        assert!(!report.trace[0].matched);
+        assert!(report.trace[1].matched);
+        // `or` stops at the first matching branch
+        let Some(ConditionTrace::Or { conditions, .. }) = &report.trace[1].condition else {
+            panic!("Expected or condition");
+        };
+        assert_eq!(conditions.len(), 1);
+        assert!(conditions[0].matched());
+        assert_eq!(
+            report.soft_errors,
+            vec![
+                "Validation error: Missing required param 'region'".to_string(),
+                "Param 'firmware' value 'unknown' is not a number in rule 0".to_string(),
+            ]
+        );
+
+        let payload = serde_json::to_value(&report).unwrap();
+        assert_eq!(payload["version"], "7");
+        assert_eq!(payload["trace"][0]["phase"], "main");
+        assert!(payload["trace"][0].get("metadata").is_none());
+        assert_eq!(
+            payload["trace"][1]["tags"],
+            serde_json::json!(["chips", "tv"])
+        );
+        assert_eq!(payload["trace"][1]["metadata"]["owner"], "platform-team");
+        assert_eq!(
+            report.trace[1].description.as_deref(),
+            Some("Realtek and MediaTek boards")
+        );
+
+        // Metadata survives serialization of the rules
+        let rules = serde_json::to_value(evaluator.rules()).unwrap();
+        assert_eq!(rules["rules"][1]["metadata"]["ticket"], 42);
+        assert!(rules["rules"][0].get("tags").is_none());
+    }
+
+    #[test]
+    fn test_evaluate_with_trace() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "id": "rtd_cn",
+                    "if": {
+                        "and": [
+                            { "field": "platform", "op": "prefix", "value": "RTD" },
+                            { "not": { "field": "region", "op": "equals", "value": "US" } }
+                        ]
+                    },
+                    "then": "chip_rtd"
+                }
+            ],
+            "fallback": "default_chip"
+        }
+        "#;
+
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+        let params = HashMap::from([
+            ("platform".to_string(), "RTD-2000".to_string()),
+            ("region".to_string(), "CN".to_string()),
+        ]);
+        let trace = evaluator.evaluate_with_trace(&params);
+        assert_eq!(trace.rule_index, Some(0));
+        assert!(trace.steps[0].matched);
+        assert_eq!(
+            trace.to_string(),
+            [
+                "rule 0 (rtd_cn): matched",
+                "  and: true",
+                "    platform prefix \"RTD\" (actual \"RTD-2000\"): true",
+                "    not: true",
+                "      region equals \"US\" (actual \"CN\"): false",
+                "result: \"chip_rtd\"",
+            ]
+            .join("\n")
+        );
+
+        // The first failing branch of `and` ends the trace of that rule
+        let params = HashMap::from([("platform".to_string(), "MT9950".to_string())]);
+        let trace = evaluator.evaluate_with_trace(&params);
+        let Some(ConditionTrace::And { conditions, .. }) = &trace.steps[0].condition else {
+            panic!("Expected and condition");
+        };
+        assert_eq!(conditions.len(), 1);
+        assert_eq!(
+            trace.result,
+            Some(RuleResult::String("default_chip".to_string()))
+        );
+    }
+}
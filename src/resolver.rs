@@ -0,0 +1,237 @@
+//! Evaluation with params resolved asynchronously, on demand
+//!
+//! [`ConfigEvaluator::evaluate_async`] evaluates the rules in passes over the
+//! fields resolved so far. Each pass notes the first unresolved fields its
+//! conditions reach, up to the resolver's concurrency limit; those are
+//! fetched concurrently from the [`AsyncParamSource`] and the next pass
+//! continues with them, until a pass completes without reaching new fields.
+//! Fields no condition reaches are never fetched.
+
+use crate::report::Recorder;
+use crate::{ConfigEvaluator, ConfigExprError, ParamSource, RuleResult};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+
+/// Default for [`AsyncParamSource::max_concurrency`]
+const DEFAULT_CONCURRENCY: usize = 8;
+
+/// Source of params fetched asynchronously, e.g. from Redis or an HTTP service
+///
+/// Fields are requested by the names conditions use; for dot-path fields
+/// like `device.info.platform` the root param (`device`) is requested as
+/// well, as with [`ParamSource`].
+pub trait AsyncParamSource {
+    /// Resolve a param, `None` when it does not exist
+    fn get(&self, field: &str) -> impl Future<Output = Result<Option<String>, ConfigExprError>>;
+
+    /// Most fields resolved at once within one evaluation
+    ///
+    /// Zero is treated as one.
+    fn max_concurrency(&self) -> usize {
+        DEFAULT_CONCURRENCY
+    }
+}
+
+/// Params resolved by an async closure, e.g.
+/// `AsyncFnSource(|name| async move { redis.get(&name).await.map_err(..) })`
+pub struct AsyncFnSource<F>(pub F);
+
+impl<F, Fut> AsyncParamSource for AsyncFnSource<F>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<Option<String>, ConfigExprError>>,
+{
+    fn get(&self, field: &str) -> impl Future<Output = Result<Option<String>, ConfigExprError>> {
+        (self.0)(field.to_string())
+    }
+}
+
+/// Fields resolved so far, noting the first unresolved fields evaluation
+/// asks for
+struct Resolved<'a> {
+    values: &'a HashMap<String, Option<String>>,
+    /// Unresolved fields in the order they were reached
+    unresolved: RefCell<Vec<String>>,
+    limit: usize,
+}
+
+impl ParamSource for Resolved<'_> {
+    fn get(&self, field: &str) -> Option<Cow<'_, str>> {
+        if let Some(value) = self.values.get(field) {
+            return value.as_deref().map(Cow::Borrowed);
+        }
+        let mut unresolved = self.unresolved.borrow_mut();
+        if unresolved.len() < self.limit && !unresolved.iter().any(|f| f == field) {
+            unresolved.push(field.to_string());
+        }
+        None
+    }
+
+    fn entries(&self) -> Vec<(String, String)> {
+        present(self.values)
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
+    }
+}
+
+fn present(values: &HashMap<String, Option<String>>) -> impl Iterator<Item = (&String, &String)> {
+    values
+        .iter()
+        .filter_map(|(field, value)| Some((field, value.as_ref()?)))
+}
+
+impl ConfigEvaluator {
+    /// Evaluate params pulled from an [`AsyncParamSource`] on demand
+    ///
+    /// Only fields the conditions reach are resolved, each at most once.
+    /// Up to [`AsyncParamSource::max_concurrency`] fields are resolved at a
+    /// time: the first unresolved field a pass reaches and those reached
+    /// after it, treating it as missing meanwhile, so a field may be fetched
+    /// that its value turns out to make unnecessary. Script conditions see
+    /// the params resolved so far.
+    ///
+    /// Like [`ConfigEvaluator::evaluate_checked`], assertion rules fail the
+    /// evaluation, as do resolver errors. The metrics and result cache see
+    /// a single evaluation.
+    pub async fn evaluate_async(
+        &self,
+        resolver: &impl AsyncParamSource,
+    ) -> Result<Option<RuleResult>, ConfigExprError> {
+        let limit = resolver.max_concurrency().max(1);
+        let mut values = HashMap::new();
+        loop {
+            let resolved = Resolved {
+                values: &values,
+                unresolved: RefCell::default(),
+                limit,
+            };
+            // Errors of a pass may come from fields still unresolved
+            let _ = self.run_phases(&resolved, &mut Recorder::probe());
+            let unresolved = resolved.unresolved.into_inner();
+            if unresolved.is_empty() {
+                break;
+            }
+            let fetched: Vec<(String, Option<String>)> = stream::iter(unresolved)
+                .map(|field| async move {
+                    let value = resolver.get(&field).await?;
+                    Ok::<_, ConfigExprError>((field, value))
+                })
+                .buffer_unordered(limit)
+                .try_collect()
+                .await?;
+            values.extend(fetched);
+        }
+        let params: HashMap<String, String> = present(&values)
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        self.evaluate_source(&params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use std::cell::Cell;
+    use std::task::Poll;
+
+    /// Resolver recording requests and the most requests in flight at once
+    struct Remote {
+        values: HashMap<&'static str, &'static str>,
+        requests: RefCell<Vec<String>>,
+        in_flight: Cell<usize>,
+        peak: Cell<usize>,
+    }
+
+    impl AsyncParamSource for Remote {
+        async fn get(&self, field: &str) -> Result<Option<String>, ConfigExprError> {
+            self.requests.borrow_mut().push(field.to_string());
+            self.in_flight.set(self.in_flight.get() + 1);
+            self.peak.set(self.peak.get().max(self.in_flight.get()));
+            // Yield once so the other requests of the pass start
+            let mut yielded = false;
+            futures::future::poll_fn(|cx| {
+                if yielded {
+                    return Poll::Ready(());
+                }
+                yielded = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            })
+            .await;
+            self.in_flight.set(self.in_flight.get() - 1);
+            match self.values.get(field) {
+                Some(&"!") => Err(ConfigExprError::ResolverError("timeout".to_string())),
+                value => Ok(value.map(|value| value.to_string())),
+            }
+        }
+
+        fn max_concurrency(&self) -> usize {
+            2
+        }
+    }
+
+    fn remote(values: &[(&'static str, &'static str)]) -> Remote {
+        Remote {
+            values: values.iter().copied().collect(),
+            requests: RefCell::default(),
+            in_flight: Cell::new(0),
+            peak: Cell::new(0),
+        }
+    }
+
+    #[test]
+    fn test_evaluate_async() {
+        let evaluator = ConfigEvaluator::from_json(
+            r#"{
+                "rules": [
+                    { "if": "tier == \"gold\"", "then": "gold" },
+                    { "if": { "and": ["region == \"CN\"", "score >= 60", "plan == \"pro\""] }, "then": "cn_pro" },
+                    { "if": "beta == \"1\"", "then": "beta" }
+                ],
+                "fallback": "default"
+            }"#,
+        )
+        .unwrap()
+        .with_metrics();
+        let evaluate = |remote: &Remote| block_on(evaluator.evaluate_async(remote));
+
+        // `region` is reached while `tier` is unresolved and fetched with it,
+        // up to the limit of two fields at a time
+        let gold = remote(&[("tier", "gold"), ("region", "CN")]);
+        assert_eq!(
+            evaluate(&gold).unwrap(),
+            Some(RuleResult::String("gold".to_string()))
+        );
+        assert_eq!(*gold.requests.borrow(), ["tier", "region"]);
+        assert_eq!(gold.peak.get(), 2);
+        // Passes finding the fields to resolve are not counted
+        assert_eq!(evaluator.metrics().unwrap().evaluations, 1);
+
+        let cn = remote(&[("region", "CN"), ("score", "75"), ("plan", "pro")]);
+        assert_eq!(
+            evaluate(&cn).unwrap(),
+            Some(RuleResult::String("cn_pro".to_string()))
+        );
+        // `beta` is reached while `score` is unresolved, `plan` only once it is
+        assert_eq!(
+            *cn.requests.borrow(),
+            ["tier", "region", "score", "beta", "plan"]
+        );
+
+        let failing = remote(&[("region", "CN"), ("score", "!")]);
+        assert_eq!(
+            evaluate(&failing).unwrap_err().error_code(),
+            "resolver_error"
+        );
+
+        let fetch = |field: String| async move { Ok((field == "beta").then(|| "1".to_string())) };
+        assert_eq!(
+            block_on(evaluator.evaluate_async(&AsyncFnSource(fetch))).unwrap(),
+            Some(RuleResult::String("beta".to_string()))
+        );
+    }
+}
@@ -0,0 +1,305 @@
+//! Recurring activation schedules for rules
+
+use crate::prelude::*;
+use crate::time::{self, LocalTime};
+use crate::ConfigExprError;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "std")]
+use std::time::SystemTime;
+
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+/// Recurring schedule controlling when a rule participates in evaluation
+///
+/// Either a 5-field `cron` expression (`minute hour day-of-month month
+/// day-of-week`) or a `days`/`hours` spec such as `["mon", "fri"]` and
+/// `"09:00-17:00"`. Hour windows may wrap past midnight (`"22:00-06:00"`).
+/// Times are interpreted in `timezone`, a fixed UTC offset like `+08:00`
+/// (default `UTC`).
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct Schedule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cron: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub days: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hours: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+}
+
+impl Schedule {
+    /// Validate the schedule spec
+    pub fn validate(&self) -> Result<(), ConfigExprError> {
+        self.offset()?;
+        match &self.cron {
+            Some(cron) => {
+                if !self.days.is_empty() || self.hours.is_some() {
+                    return Err(ConfigExprError::ValidationError(
+                        "Schedule cannot combine cron with days/hours".to_string(),
+                    ));
+                }
+                CronExpr::parse(cron)?;
+            }
+            None => {
+                if self.days.is_empty() && self.hours.is_none() {
+                    return Err(ConfigExprError::ValidationError(
+                        "Schedule must specify cron, days or hours".to_string(),
+                    ));
+                }
+                self.weekdays()?;
+                self.window()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check whether the schedule is active at the given time
+    ///
+    /// Invalid specs never match; they are rejected by [`Schedule::validate`].
+    #[cfg(feature = "std")]
+    pub fn is_active(&self, now: SystemTime) -> bool {
+        self.is_active_at(time::unix_seconds(now))
+    }
+
+    /// Check whether the schedule is active at a Unix timestamp
+    pub(crate) fn is_active_at(&self, unix: i64) -> bool {
+        let Ok(offset) = self.offset() else {
+            return false;
+        };
+        let local = time::local_time(unix, offset);
+
+        if let Some(cron) = &self.cron {
+            return CronExpr::parse(cron).is_ok_and(|cron| cron.matches(&local));
+        }
+
+        let Ok(weekdays) = self.weekdays() else {
+            return false;
+        };
+        if !self.days.is_empty() && weekdays & (1 << local.weekday) == 0 {
+            return false;
+        }
+        match self.window() {
+            Ok(Some((start, end))) => {
+                let minute = local.hour * 60 + local.minute;
+                if start <= end {
+                    (start..end).contains(&minute)
+                } else {
+                    minute >= start || minute < end
+                }
+            }
+            Ok(None) => true,
+            Err(_) => false,
+        }
+    }
+
+    fn offset(&self) -> Result<i64, ConfigExprError> {
+        match &self.timezone {
+            Some(tz) => time::parse_utc_offset(tz).ok_or_else(|| {
+                ConfigExprError::ValidationError(format!("Invalid schedule timezone '{}'", tz))
+            }),
+            None => Ok(0),
+        }
+    }
+
+    /// Weekday bitmask, bit 0 = Sunday
+    fn weekdays(&self) -> Result<u64, ConfigExprError> {
+        self.days.iter().try_fold(0, |mask, day| {
+            let index = name_index(day, &WEEKDAYS).ok_or_else(|| {
+                ConfigExprError::ValidationError(format!("Invalid schedule day '{}'", day))
+            })?;
+            Ok(mask | (1 << index))
+        })
+    }
+
+    /// Hour window as minutes since midnight, end exclusive
+    fn window(&self) -> Result<Option<(u32, u32)>, ConfigExprError> {
+        let Some(hours) = &self.hours else {
+            return Ok(None);
+        };
+        let invalid = || {
+            ConfigExprError::ValidationError(format!(
+                "Invalid schedule hours '{}', expected HH:MM-HH:MM",
+                hours
+            ))
+        };
+        let (start, end) = hours.split_once('-').ok_or_else(invalid)?;
+        let start = time::parse_time_of_day(start).ok_or_else(invalid)?;
+        let end = time::parse_time_of_day(end).ok_or_else(invalid)?;
+        Ok(Some((start, end)))
+    }
+}
+
+/// Look up a case-insensitive three-letter name such as `mon` or `jan`
+fn name_index(name: &str, names: &[&str]) -> Option<usize> {
+    names
+        .iter()
+        .position(|candidate| candidate.eq_ignore_ascii_case(name))
+}
+
+/// Parsed 5-field cron expression, each field stored as a bitmask
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CronExpr {
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    dom_restricted: bool,
+    dow_restricted: bool,
+}
+
+impl CronExpr {
+    /// Parse a cron expression like `*/15 9-17 * * mon-fri`
+    pub(crate) fn parse(expr: &str) -> Result<CronExpr, ConfigExprError> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(ConfigExprError::ValidationError(format!(
+                "Invalid cron expression '{}': expected 5 fields",
+                expr
+            )));
+        }
+        let parse = |field: &str, min: u32, max: u32, names: &[&str]| {
+            parse_cron_field(field, min, max, names).ok_or_else(|| {
+                ConfigExprError::ValidationError(format!(
+                    "Invalid cron field '{}' in '{}'",
+                    field, expr
+                ))
+            })
+        };
+
+        let mut days_of_week = parse(fields[4], 0, 7, &WEEKDAYS)?;
+        // Both 0 and 7 mean Sunday
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week |= 1;
+        }
+        Ok(CronExpr {
+            minutes: parse(fields[0], 0, 59, &[])?,
+            hours: parse(fields[1], 0, 23, &[])?,
+            days_of_month: parse(fields[2], 1, 31, &[])?,
+            months: parse(fields[3], 1, 12, &MONTHS)?,
+            days_of_week,
+            dom_restricted: fields[2] != "*",
+            dow_restricted: fields[4] != "*",
+        })
+    }
+
+    /// Check whether the cron expression matches a local time (to the minute)
+    pub(crate) fn matches(&self, t: &LocalTime) -> bool {
+        let bit = |mask: u64, value: u32| mask & (1 << value) != 0;
+        let dom = bit(self.days_of_month, t.day);
+        let dow = bit(self.days_of_week, t.weekday);
+        // Like classic cron, restricting both day fields matches either one
+        let day_matches = if self.dom_restricted && self.dow_restricted {
+            dom || dow
+        } else {
+            dom && dow
+        };
+        bit(self.minutes, t.minute)
+            && bit(self.hours, t.hour)
+            && bit(self.months, t.month)
+            && day_matches
+    }
+}
+
+/// Parse one cron field (`*`, `a`, `a-b`, `*/n`, `a-b/n` and comma lists)
+fn parse_cron_field(field: &str, min: u32, max: u32, names: &[&str]) -> Option<u64> {
+    let value = |s: &str| -> Option<u32> {
+        let v = match s.parse::<u32>() {
+            Ok(v) => v,
+            // Month names map to 1..=12, weekday names to 0..=6
+            Err(_) => name_index(s, names)? as u32 + u32::from(min == 1),
+        };
+        (min..=max).contains(&v).then_some(v)
+    };
+
+    let mut mask = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|s| *s > 0)?),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((a, b)) => (value(a)?, value(b)?),
+                None => {
+                    let v = value(range)?;
+                    (v, if part.contains('/') { max } else { v })
+                }
+            },
+        };
+        if start > end {
+            return None;
+        }
+        for v in (start..=end).step_by(step as usize) {
+            mask |= 1 << v;
+        }
+    }
+    Some(mask)
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::time::FixedClock;
+
+    // 2024-02-29T13:45:00Z, a Thursday
+    const THURSDAY_AFTERNOON: i64 = 1_709_214_300;
+
+    fn at(unix: i64) -> SystemTime {
+        FixedClock::from_unix_seconds(unix).0
+    }
+
+    fn schedule(json: &str) -> Schedule {
+        let schedule: Schedule = serde_json::from_str(json).unwrap();
+        schedule.validate().unwrap();
+        schedule
+    }
+
+    #[test]
+    fn test_days_and_hours() {
+        let business =
+            schedule(r#"{ "days": ["mon", "tue", "wed", "thu", "fri"], "hours": "09:00-17:00" }"#);
+        assert!(business.is_active(at(THURSDAY_AFTERNOON)));
+        // 21:45 in +08:00
+        let shanghai = schedule(r#"{ "hours": "09:00-17:00", "timezone": "+08:00" }"#);
+        assert!(!shanghai.is_active(at(THURSDAY_AFTERNOON)));
+
+        let night = schedule(r#"{ "hours": "22:00-06:00", "timezone": "+08:00" }"#);
+        assert!(!night.is_active(at(THURSDAY_AFTERNOON)));
+        assert!(night.is_active(at(THURSDAY_AFTERNOON + 3600)));
+    }
+
+    #[test]
+    fn test_cron() {
+        let cron = schedule(r#"{ "cron": "*/15 9-17 * * mon-fri" }"#);
+        assert!(cron.is_active(at(THURSDAY_AFTERNOON)));
+        assert!(!cron.is_active(at(THURSDAY_AFTERNOON + 60)));
+
+        let weekend = schedule(r#"{ "cron": "* * * * sat,sun" }"#);
+        assert!(!weekend.is_active(at(THURSDAY_AFTERNOON)));
+        assert!(weekend.is_active(at(THURSDAY_AFTERNOON + 2 * 86_400)));
+    }
+
+    #[test]
+    fn test_invalid_schedules() {
+        let invalid = [
+            r#"{}"#,
+            r#"{ "cron": "* * *" }"#,
+            r#"{ "cron": "61 * * * *" }"#,
+            r#"{ "cron": "* * * * *", "hours": "09:00-17:00" }"#,
+            r#"{ "days": ["someday"] }"#,
+            r#"{ "hours": "9-17" }"#,
+            r#"{ "hours": "09:00-17:00", "timezone": "Mars/Olympus" }"#,
+        ];
+        for json in invalid {
+            let schedule: Schedule = serde_json::from_str(json).unwrap();
+            assert!(schedule.validate().is_err(), "{}", json);
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! JSON Schema of rule documents
+//!
+//! Most rule types derive their schema; conditions, custom operators and
+//! table columns, which deserialize by hand, describe theirs here.
+
+use crate::{
+    Column, Condition, ConfigRules, CustomOperator, MultiValueMode, Normalization, Operator,
+};
+use schemars::{json_schema, JsonSchema, Schema, SchemaGenerator};
+use std::borrow::Cow;
+
+impl ConfigRules {
+    /// JSON Schema (draft 2020-12) of rule documents
+    ///
+    /// Editors and CI can use it to validate rule files and offer
+    /// autocompletion. It checks structure only: regexes, datetimes and
+    /// other values are still validated when the rules are loaded.
+    pub fn json_schema() -> serde_json::Value {
+        schemars::schema_for!(ConfigRules).to_value()
+    }
+}
+
+impl JsonSchema for Condition {
+    fn schema_name() -> Cow<'static, str> {
+        "Condition".into()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        let condition = generator.subschema_for::<Condition>();
+        let conditions = generator.subschema_for::<Vec<Condition>>();
+        let operator = generator.subschema_for::<Operator>();
+        let normalization = generator.subschema_for::<Normalization>();
+        let mode = generator.subschema_for::<MultiValueMode>();
+        json_schema!({
+            "description": "Condition expression, as a JSON tree or in the textual syntax",
+            "anyOf": [
+                {
+                    "description": "Condition in the textual syntax, e.g. `platform ~ \"^RTD\" && region == \"CN\"`",
+                    "type": "string"
+                },
+                {
+                    "description": "Simple condition: field comparison",
+                    "type": "object",
+                    "properties": {
+                        "field": { "type": "string" },
+                        "op": operator,
+                        "value": true,
+                        "value_field": {
+                            "description": "Param compared against instead of `value`",
+                            "type": "string"
+                        },
+                        "seed": { "type": "string" },
+                        "trim": {
+                            "description": "Strip surrounding whitespace from the compared params",
+                            "type": "boolean"
+                        },
+                        "normalize": normalization,
+                        "mode": mode
+                    },
+                    "required": ["field", "op"]
+                },
+                {
+                    "description": "AND condition: all sub-conditions must be satisfied",
+                    "type": "object",
+                    "properties": { "and": conditions },
+                    "required": ["and"]
+                },
+                {
+                    "description": "OR condition: at least one sub-condition must be satisfied",
+                    "type": "object",
+                    "properties": { "or": conditions },
+                    "required": ["or"]
+                },
+                {
+                    "description": "NOT condition: the sub-condition must not be satisfied",
+                    "type": "object",
+                    "properties": { "not": condition },
+                    "required": ["not"]
+                },
+                {
+                    "description": "Named condition of `definitions`",
+                    "type": "object",
+                    "properties": { "ref": { "type": "string" } },
+                    "required": ["ref"]
+                }
+            ]
+        })
+    }
+}
+
+impl JsonSchema for CustomOperator {
+    fn schema_name() -> Cow<'static, str> {
+        "CustomOperator".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "description": "Application-registered operator",
+            "type": "string",
+            "pattern": "^custom:.+"
+        })
+    }
+}
+
+impl JsonSchema for Column {
+    fn schema_name() -> Cow<'static, str> {
+        "Column".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "description": "Decision table column: a field, compared with `equals`, or `field:op`",
+            "type": "string",
+            "pattern": "^[^:]+(:.+)?$"
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_schema() {
+        let schema = ConfigRules::json_schema();
+        assert_eq!(schema["title"], "ConfigRules");
+        assert_eq!(schema["required"], serde_json::json!(["rules"]));
+
+        let defs = &schema["$defs"];
+        let condition = defs["Condition"]["anyOf"].as_array().unwrap();
+        assert_eq!(condition.len(), 6);
+        assert_eq!(condition[1]["required"], serde_json::json!(["field", "op"]));
+
+        let operators = serde_json::to_string(&defs["Operator"]).unwrap();
+        for op in [
+            "\"equals\"",
+            "\"gt\"",
+            "\"percent_lt\"",
+            "#/$defs/CustomOperator",
+        ] {
+            assert!(operators.contains(op), "missing {} in {}", op, operators);
+        }
+        assert_eq!(defs["CustomOperator"]["pattern"], "^custom:.+");
+    }
+}
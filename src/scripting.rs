@@ -0,0 +1,161 @@
+//! Sandboxed Rhai scripts for conditions and result transforms
+//!
+//! Scripts run in an engine without module loading or `eval`, and with
+//! limits on operations, call depth, expression depth and data sizes, so a
+//! runaway script fails instead of stalling evaluation.
+
+use crate::{ConfigExprError, ConfigRules, ParamSource, RuleResult};
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+const MAX_OPERATIONS: u64 = 100_000;
+const MAX_CALL_LEVELS: usize = 32;
+const MAX_EXPR_DEPTH: usize = 64;
+const MAX_STRING_SIZE: usize = 64 * 1024;
+const MAX_COLLECTION_SIZE: usize = 10_000;
+
+/// Rhai engine with a cache of compiled scripts
+pub(crate) struct ScriptEngine {
+    engine: Engine,
+    cache: RwLock<HashMap<String, Arc<AST>>>,
+}
+
+impl std::fmt::Debug for ScriptEngine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ScriptEngine").finish_non_exhaustive()
+    }
+}
+
+impl Default for ScriptEngine {
+    fn default() -> Self {
+        let mut engine = Engine::new();
+        engine
+            .set_max_operations(MAX_OPERATIONS)
+            .set_max_call_levels(MAX_CALL_LEVELS)
+            .set_max_expr_depths(MAX_EXPR_DEPTH, MAX_EXPR_DEPTH)
+            .set_max_string_size(MAX_STRING_SIZE)
+            .set_max_array_size(MAX_COLLECTION_SIZE)
+            .set_max_map_size(MAX_COLLECTION_SIZE)
+            .disable_symbol("eval");
+        engine.on_print(|_| {});
+        engine.on_debug(|_, _, _| {});
+        ScriptEngine {
+            engine,
+            cache: RwLock::new(HashMap::new()),
+        }
+    }
+}
+
+fn script_error(e: impl std::fmt::Display) -> ConfigExprError {
+    ConfigExprError::ScriptError(e.to_string())
+}
+
+impl ScriptEngine {
+    /// Create an engine with every script of the rule set precompiled
+    pub(crate) fn for_rules(rules: &ConfigRules) -> Self {
+        let engine = ScriptEngine::default();
+        for source in rules.scripts() {
+            // Scripts were checked during validation
+            let _ = engine.compile(source);
+        }
+        engine
+    }
+
+    /// Compile a script, reusing the cached AST when available
+    pub(crate) fn compile(&self, source: &str) -> Result<Arc<AST>, ConfigExprError> {
+        if let Some(ast) = self
+            .cache
+            .read()
+            .ok()
+            .and_then(|cache| cache.get(source).cloned())
+        {
+            return Ok(ast);
+        }
+        let ast = Arc::new(self.engine.compile(source).map_err(script_error)?);
+        if let Ok(mut cache) = self.cache.write() {
+            cache.insert(source.to_string(), ast.clone());
+        }
+        Ok(ast)
+    }
+
+    fn scope(params: &dyn ParamSource) -> Scope<'static> {
+        let params: Map = params
+            .entries()
+            .into_iter()
+            .map(|(key, value)| (key.into(), Dynamic::from(value)))
+            .collect();
+        let mut scope = Scope::new();
+        scope.push_constant("params", params);
+        scope
+    }
+
+    /// Run a condition script with the field `value` and all `params` in scope
+    pub(crate) fn check_condition(
+        &self,
+        source: &str,
+        value: &str,
+        params: &dyn ParamSource,
+    ) -> Result<bool, ConfigExprError> {
+        let ast = self.compile(source)?;
+        let mut scope = Self::scope(params);
+        scope.push_constant("value", value.to_string());
+        self.engine
+            .eval_ast_with_scope::<bool>(&mut scope, &ast)
+            .map_err(script_error)
+    }
+
+    /// Run a transform script with the matched `result` and all `params` in scope
+    pub(crate) fn transform(
+        &self,
+        source: &str,
+        result: &RuleResult,
+        params: &dyn ParamSource,
+    ) -> Result<RuleResult, ConfigExprError> {
+        let ast = self.compile(source)?;
+        let mut scope = Self::scope(params);
+        // The result is mutable so scripts can adjust it in place
+        scope.push(
+            "result",
+            rhai::serde::to_dynamic(result).map_err(script_error)?,
+        );
+        let output = self
+            .engine
+            .eval_ast_with_scope::<Dynamic>(&mut scope, &ast)
+            .map_err(script_error)?;
+        let value: serde_json::Value = rhai::serde::from_dynamic(&output).map_err(script_error)?;
+        Ok(match value {
+            serde_json::Value::String(s) => RuleResult::String(s),
+            other => RuleResult::Object(other),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_condition_script() {
+        let engine = ScriptEngine::default();
+        let params = HashMap::from([("region".to_string(), "CN".to_string())]);
+        let script = r#"value.len() > 3 && params.region == "CN""#;
+        assert!(engine.check_condition(script, "RTD-2000", &params).unwrap());
+        assert!(!engine.check_condition(script, "RTD", &params).unwrap());
+    }
+
+    #[test]
+    fn test_runaway_script_is_limited() {
+        let engine = ScriptEngine::default();
+        let err = engine
+            .check_condition("loop {}", "RTD", &HashMap::<String, String>::new())
+            .unwrap_err();
+        assert!(matches!(err, ConfigExprError::ScriptError(_)));
+    }
+
+    #[test]
+    fn test_eval_is_disabled() {
+        let engine = ScriptEngine::default();
+        assert!(engine.compile(r#"eval("1 + 1")"#).is_err());
+    }
+}
@@ -0,0 +1,124 @@
+//! Evaluator shared between threads with runtime rule replacement
+//!
+//! [`SharedEvaluator`] holds the current evaluator behind an `Arc`. Readers
+//! only hold the lock long enough to clone that `Arc` and then evaluate
+//! without it, so replacing the rules never waits for running evaluations
+//! and never changes the rules in the middle of one.
+
+use crate::{ConfigEvaluator, ConfigExprError, ConfigRules, ParamSource, RuleResult};
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+/// A cloneable handle to an evaluator whose rules can be swapped at runtime
+///
+/// Clones share the same current evaluator, so a server can hand one to
+/// every request handler and call [`SharedEvaluator::update_rules`] from an
+/// admin endpoint or a background task.
+#[derive(Clone)]
+pub struct SharedEvaluator {
+    current: Arc<RwLock<Arc<ConfigEvaluator>>>,
+}
+
+impl SharedEvaluator {
+    /// Share an evaluator
+    pub fn new(evaluator: ConfigEvaluator) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(Arc::new(evaluator))),
+        }
+    }
+
+    /// Snapshot of the current evaluator
+    pub fn current(&self) -> Arc<ConfigEvaluator> {
+        Arc::clone(&self.current.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Replace the rules, keeping the current clock, custom operators and
+    /// metrics setting
+    ///
+    /// The rules are validated first; if they are invalid the current rules
+    /// stay in effect. Metrics, when enabled, start again from zero.
+    pub fn update_rules(&self, rules: ConfigRules) -> Result<(), ConfigExprError> {
+        let evaluator = self.current().with_rules(rules)?;
+        self.replace(evaluator);
+        Ok(())
+    }
+
+    /// Replace the whole evaluator
+    pub fn replace(&self, evaluator: ConfigEvaluator) {
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(evaluator);
+    }
+
+    /// Evaluate request parameters with the current rules
+    pub fn evaluate(&self, params: &impl ParamSource) -> Option<RuleResult> {
+        self.current().evaluate(params)
+    }
+}
+
+impl From<ConfigEvaluator> for SharedEvaluator {
+    fn from(evaluator: ConfigEvaluator) -> Self {
+        Self::new(evaluator)
+    }
+}
+
+impl fmt::Debug for SharedEvaluator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedEvaluator")
+            .field("current", &self.current())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn rules(result: &str) -> ConfigRules {
+        serde_json::from_value(serde_json::json!({
+            "rules": [{
+                "if": { "field": "platform", "op": "prefix", "value": "RTD" },
+                "then": result
+            }]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_update_rules_across_threads() {
+        let shared = SharedEvaluator::new(ConfigEvaluator::new(rules("chip_rtd")).unwrap());
+        let params = HashMap::from([("platform".to_string(), "RTD-2000".to_string())]);
+        let snapshot = shared.current();
+
+        let writer = shared.clone();
+        std::thread::spawn(move || writer.update_rules(rules("chip_rtd_v2")).unwrap())
+            .join()
+            .unwrap();
+        assert_eq!(
+            shared.evaluate(&params),
+            Some(RuleResult::String("chip_rtd_v2".to_string()))
+        );
+        // Snapshots taken earlier keep the old rules
+        assert_eq!(
+            snapshot.evaluate(&params),
+            Some(RuleResult::String("chip_rtd".to_string()))
+        );
+
+        // Invalid rules leave the current ones in effect
+        let mut invalid = rules("chip_rtd_v3");
+        invalid.rules[0].condition = crate::Condition::Simple {
+            field: "platform".to_string(),
+            op: crate::Operator::Regex,
+            value: serde_json::json!("("),
+            value_field: None,
+            seed: String::new(),
+            trim: false,
+            normalize: None,
+            mode: None,
+        };
+        assert!(shared.update_rules(invalid).is_err());
+        assert_eq!(
+            shared.evaluate(&params),
+            Some(RuleResult::String("chip_rtd_v2".to_string()))
+        );
+    }
+}
@@ -0,0 +1,338 @@
+//! Param sources other than a `HashMap`
+//!
+//! Evaluation reads params through [`ParamSource`], so rules can be checked
+//! directly against HTTP headers, environment variables or a database row
+//! without first copying every value into a map.
+
+use crate::keys::{Keyed, ParamKeys};
+use crate::prelude::*;
+use alloc::borrow::Cow;
+use alloc::collections::BTreeMap;
+use core::borrow::Borrow;
+use core::cell::RefCell;
+use core::hash::{BuildHasher, Hash};
+use hashbrown::HashMap as HashbrownMap;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "std")]
+use std::collections::HashMap as StdHashMap;
+
+/// Source of the params conditions are evaluated against
+///
+/// Only [`ParamSource::get`] is required. Fields are looked up by their full
+/// name first; for dot-path fields like `device.info.platform`, the root
+/// param (`device`) is then read as JSON.
+pub trait ParamSource {
+    /// Look up a param by name
+    fn get(&self, field: &str) -> Option<Cow<'_, str>>;
+
+    /// Look up every value of a repeated param, e.g. both `capability`
+    /// values of `?capability=wifi&capability=bt`
+    ///
+    /// Conditions with a `mode` compare each value. Sources without repeated
+    /// params keep the default, the single value of [`ParamSource::get`].
+    fn get_all(&self, field: &str) -> Vec<Cow<'_, str>> {
+        self.get(field).into_iter().collect()
+    }
+
+    /// List every param, for Rhai scripts that receive the whole `params` map
+    ///
+    /// Sources that cannot enumerate their params keep the default, and
+    /// scripts then see an empty `params` map.
+    fn entries(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
+}
+
+/// Implement [`ParamSource`] for a hash map type and for
+/// [`MultiParams`] of it
+macro_rules! hash_map_sources {
+    ($map:ident) => {
+        /// Maps keyed by `String` or `&str` with `String`, `&str` or
+        /// `Cow<str>` values
+        impl<K, V, S> ParamSource for $map<K, V, S>
+        where
+            K: Borrow<str> + Eq + Hash,
+            V: AsRef<str>,
+            S: BuildHasher,
+        {
+            fn get(&self, field: &str) -> Option<Cow<'_, str>> {
+                $map::get(self, field).map(|value| Cow::Borrowed(value.as_ref()))
+            }
+
+            fn entries(&self) -> Vec<(String, String)> {
+                self.iter()
+                    .map(|(k, v)| (k.borrow().to_string(), v.as_ref().to_string()))
+                    .collect()
+            }
+        }
+
+        impl<K, V, S> ParamSource for MultiParams<$map<K, Vec<V>, S>>
+        where
+            K: Borrow<str> + Eq + Hash,
+            V: AsRef<str>,
+            S: BuildHasher,
+        {
+            fn get(&self, field: &str) -> Option<Cow<'_, str>> {
+                self.get_all(field).into_iter().next()
+            }
+
+            fn get_all(&self, field: &str) -> Vec<Cow<'_, str>> {
+                self.0
+                    .get(field)
+                    .map_or_else(Vec::new, |values| borrow_all(values))
+            }
+
+            fn entries(&self) -> Vec<(String, String)> {
+                first_entries(self.0.iter().map(|(k, v)| (k.borrow(), v.as_slice())))
+            }
+        }
+    };
+}
+
+// hashbrown's map in every configuration, so params built with it keep
+// working when another crate enables `std`, and std's alongside it
+hash_map_sources!(HashbrownMap);
+#[cfg(feature = "std")]
+hash_map_sources!(StdHashMap);
+
+impl<K, V> ParamSource for BTreeMap<K, V>
+where
+    K: Borrow<str> + Ord,
+    V: AsRef<str>,
+{
+    fn get(&self, field: &str) -> Option<Cow<'_, str>> {
+        BTreeMap::get(self, field).map(|value| Cow::Borrowed(value.as_ref()))
+    }
+
+    fn entries(&self) -> Vec<(String, String)> {
+        self.iter()
+            .map(|(k, v)| (k.borrow().to_string(), v.as_ref().to_string()))
+            .collect()
+    }
+}
+
+/// Params with repeated values, e.g.
+/// `MultiParams(HashMap<String, Vec<String>>)`
+///
+/// [`ParamSource::get`] yields the first value of a param, and conditions
+/// with a `mode` see all of them.
+#[derive(Debug, Clone, Default)]
+pub struct MultiParams<M>(pub M);
+
+impl<K, V> ParamSource for MultiParams<BTreeMap<K, Vec<V>>>
+where
+    K: Borrow<str> + Ord,
+    V: AsRef<str>,
+{
+    fn get(&self, field: &str) -> Option<Cow<'_, str>> {
+        self.get_all(field).into_iter().next()
+    }
+
+    fn get_all(&self, field: &str) -> Vec<Cow<'_, str>> {
+        self.0
+            .get(field)
+            .map_or_else(Vec::new, |values| borrow_all(values))
+    }
+
+    fn entries(&self) -> Vec<(String, String)> {
+        first_entries(self.0.iter().map(|(k, v)| (k.borrow(), v.as_slice())))
+    }
+}
+
+fn borrow_all<V: AsRef<str>>(values: &[V]) -> Vec<Cow<'_, str>> {
+    values
+        .iter()
+        .map(|value| Cow::Borrowed(value.as_ref()))
+        .collect()
+}
+
+/// Entries of the first values of repeated params
+fn first_entries<'a, V: AsRef<str> + 'a>(
+    params: impl Iterator<Item = (&'a str, &'a [V])>,
+) -> Vec<(String, String)> {
+    params
+        .filter_map(|(k, values)| Some((k.to_string(), values.first()?.as_ref().to_string())))
+        .collect()
+}
+
+/// How a condition with a `mode` combines the values of a repeated param
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum MultiValueMode {
+    /// Some value must satisfy the condition
+    Any,
+    /// Every value must satisfy the condition
+    All,
+}
+
+impl MultiValueMode {
+    /// Combine whether each value satisfies the condition
+    pub(crate) fn matches<T>(self, values: Vec<T>, condition: impl FnMut(T) -> bool) -> bool {
+        match self {
+            MultiValueMode::Any => values.into_iter().any(condition),
+            MultiValueMode::All => values.into_iter().all(condition),
+        }
+    }
+}
+
+/// Params resolved by a closure, e.g. `FnSource(|name| std::env::var(name).ok())`
+pub struct FnSource<F>(pub F);
+
+impl<F> ParamSource for FnSource<F>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    fn get(&self, field: &str) -> Option<Cow<'_, str>> {
+        (self.0)(field).map(Cow::Owned)
+    }
+}
+
+/// Wrapper caching the params of an expensive source
+///
+/// Each field is resolved at most once, on first use, so fields no condition
+/// reaches are never fetched and fields checked by several rules are fetched
+/// once. Create one per evaluation, since cached values are never refreshed.
+pub struct LazyParams<P> {
+    resolver: P,
+    cache: RefCell<HashMap<String, Option<String>>>,
+}
+
+impl<P: ParamSource> LazyParams<P> {
+    /// Wrap a resolver with an empty cache
+    pub fn new(resolver: P) -> Self {
+        LazyParams {
+            resolver,
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Fields resolved so far, present or not, in sorted order
+    pub fn resolved_fields(&self) -> Vec<String> {
+        let mut fields: Vec<String> = self.cache.borrow().keys().cloned().collect();
+        fields.sort();
+        fields
+    }
+
+    /// Unwrap the resolver, dropping the cache
+    pub fn into_inner(self) -> P {
+        self.resolver
+    }
+}
+
+impl<P: ParamSource> ParamSource for LazyParams<P> {
+    fn get(&self, field: &str) -> Option<Cow<'_, str>> {
+        if let Some(value) = self.cache.borrow().get(field) {
+            return value.clone().map(Cow::Owned);
+        }
+        let value = self.resolver.get(field).map(Cow::into_owned);
+        self.cache
+            .borrow_mut()
+            .insert(field.to_string(), value.clone());
+        value.map(Cow::Owned)
+    }
+
+    /// Repeated values are not cached
+    fn get_all(&self, field: &str) -> Vec<Cow<'_, str>> {
+        self.resolver.get_all(field)
+    }
+
+    fn entries(&self) -> Vec<(String, String)> {
+        self.resolver.entries()
+    }
+}
+
+/// Params of another source, with keys matched by the evaluator's
+/// [`ParamKeys`], and the updates of matching pre-phase rules
+pub(crate) struct Overlay<'a> {
+    base: Keyed<'a>,
+    updates: HashMap<String, String>,
+}
+
+impl<'a> Overlay<'a> {
+    pub(crate) fn new(base: &'a dyn ParamSource, keys: &'a ParamKeys) -> Self {
+        Overlay {
+            base: Keyed::new(base, keys),
+            updates: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn insert(&mut self, field: String, value: String) {
+        self.updates.insert(field, value);
+    }
+}
+
+impl ParamSource for Overlay<'_> {
+    fn get(&self, field: &str) -> Option<Cow<'_, str>> {
+        match self.updates.get(field) {
+            Some(value) => Some(Cow::Borrowed(value)),
+            None => self.base.get(field),
+        }
+    }
+
+    fn get_all(&self, field: &str) -> Vec<Cow<'_, str>> {
+        match self.updates.get(field) {
+            Some(value) => vec![Cow::Borrowed(value.as_str())],
+            None => self.base.get_all(field),
+        }
+    }
+
+    fn entries(&self) -> Vec<(String, String)> {
+        let mut entries = self.base.entries();
+        entries.retain(|(field, _)| !self.updates.contains_key(field));
+        entries.extend(self.updates.iter().map(|(k, v)| (k.clone(), v.clone())));
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+
+    #[test]
+    fn test_lazy_params_cache() {
+        let calls = Cell::new(0);
+        let params = LazyParams::new(FnSource(|name: &str| {
+            calls.set(calls.get() + 1);
+            (name == "region").then(|| "CN".to_string())
+        }));
+        assert_eq!(params.get("region").as_deref(), Some("CN"));
+        assert_eq!(params.get("region").as_deref(), Some("CN"));
+        assert!(params.get("platform").is_none());
+        assert!(params.get("platform").is_none());
+        assert_eq!(calls.get(), 2);
+        assert_eq!(params.resolved_fields(), vec!["platform", "region"]);
+    }
+
+    #[test]
+    fn test_borrowed_params() {
+        let evaluator = crate::ConfigEvaluator::from_json(
+            r#"{ "rules": [{ "if": "region == \"CN\" && score >= 60", "then": "cn" }] }"#,
+        )
+        .unwrap();
+        let query = String::from("region=CN&score=75");
+        let params: HashMap<&str, &str> = query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .collect();
+        let cn = Some(crate::RuleResult::String("cn".to_string()));
+        assert_eq!(evaluator.evaluate(&params), cn);
+        // The crate's `HashMap` is accepted alongside std's
+        let params: crate::HashMap<&str, &str> = params.into_iter().collect();
+        assert_eq!(evaluator.evaluate(&params), cn);
+
+        let params = BTreeMap::from([
+            ("region", Cow::Borrowed("CN")),
+            ("score", Cow::Owned("75".to_string())),
+        ]);
+        assert_eq!(evaluator.evaluate_source(&params).unwrap(), cn);
+        assert_eq!(
+            params.entries(),
+            [
+                ("region".to_string(), "CN".to_string()),
+                ("score".to_string(), "75".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,260 @@
+//! Decision tables read from CSV files (requires the `csv` feature)
+//!
+//! Mappings maintained in a spreadsheet are exported as CSV, with a header
+//! naming each condition column like a [`DecisionTable`] column (`field` or
+//! `field:op`) and a column of results:
+//!
+//! ```text
+//! platform:prefix,region,score:ge,then
+//! RTD,CN,60,rtd_cn_high
+//! RTD,CN,,rtd_cn
+//! MT,,,mt
+//! ```
+
+use crate::{
+    Column, ConfigExprError, ConfigRules, DecisionTable, MatchMode, MergeStrategy, Operator,
+    RuleResult, TableRow,
+};
+use serde_json::Value;
+use std::io::Read;
+
+/// Layout of the CSV files read by [`ConfigRules::from_csv`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvSchema {
+    /// Header of the column of results, `then` by default
+    pub result: String,
+    /// Header of the optional column of rule ids, `id` by default
+    pub id: String,
+    /// Header of the optional column of rule names, `name` by default
+    pub name: String,
+    /// Separator of the items of `in`, `not_in`, `intersects`, `subset_of`,
+    /// `between`, `ip_in_cidr`, `lang_matches` and `time_between` cells, `|`
+    /// by default
+    pub list_separator: char,
+    /// Delimiter of the cells, `,` by default
+    pub delimiter: u8,
+}
+
+impl Default for CsvSchema {
+    fn default() -> Self {
+        CsvSchema {
+            result: "then".to_string(),
+            id: "id".to_string(),
+            name: "name".to_string(),
+            list_separator: '|',
+            delimiter: b',',
+        }
+    }
+}
+
+/// What a CSV column holds
+enum CsvColumn {
+    Condition(Column),
+    Result,
+    Id,
+    Name,
+}
+
+impl ConfigRules {
+    /// Read a rule set of one decision table from CSV
+    ///
+    /// Each line after the header becomes a row of the table, and with it a
+    /// main-phase rule. Cells are trimmed, and empty cells match any value.
+    /// Condition cells are strings, except in list columns, whose cells are
+    /// split at the schema's `list_separator`, and in `exists` and `missing`
+    /// columns, where any text applies the condition. Results starting with
+    /// `{` are parsed as JSON objects.
+    pub fn from_csv(reader: impl Read, schema: &CsvSchema) -> Result<ConfigRules, ConfigExprError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(schema.delimiter)
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        let csv_columns = reader
+            .headers()
+            .map_err(csv_error)?
+            .iter()
+            .map(|header| {
+                Ok(match header {
+                    _ if header == schema.result => CsvColumn::Result,
+                    _ if header == schema.id => CsvColumn::Id,
+                    _ if header == schema.name => CsvColumn::Name,
+                    _ => {
+                        CsvColumn::Condition(serde_json::from_value(Value::from(header)).map_err(
+                            |e| ConfigExprError::CsvError(format!("header '{}': {}", header, e)),
+                        )?)
+                    }
+                })
+            })
+            .collect::<Result<Vec<_>, ConfigExprError>>()?;
+        if !csv_columns.iter().any(|c| matches!(c, CsvColumn::Result)) {
+            return Err(ConfigExprError::CsvError(format!(
+                "header has no '{}' column",
+                schema.result
+            )));
+        }
+
+        let mut rows = Vec::new();
+        for record in reader.records() {
+            let record = record.map_err(csv_error)?;
+            let line = record.position().map_or(0, |position| position.line());
+            let mut row = TableRow {
+                id: None,
+                name: None,
+                when: Vec::new(),
+                result: RuleResult::String(String::new()),
+            };
+            for (column, cell) in csv_columns.iter().zip(record.iter()) {
+                let text = (!cell.is_empty()).then(|| cell.to_string());
+                match column {
+                    CsvColumn::Condition(column) => {
+                        row.when.push(cell_value(&column.op, cell, schema))
+                    }
+                    CsvColumn::Result if cell.starts_with('{') => {
+                        let object = serde_json::from_str(cell).map_err(|e| {
+                            ConfigExprError::CsvError(format!("line {}: result: {}", line, e))
+                        })?;
+                        row.result = RuleResult::Object(object);
+                    }
+                    CsvColumn::Result if cell.is_empty() => {
+                        return Err(ConfigExprError::CsvError(format!(
+                            "line {}: missing result",
+                            line
+                        )));
+                    }
+                    CsvColumn::Result => row.result = RuleResult::String(cell.to_string()),
+                    CsvColumn::Id => row.id = text,
+                    CsvColumn::Name => row.name = text,
+                }
+            }
+            rows.push(row);
+        }
+
+        let columns = csv_columns
+            .into_iter()
+            .filter_map(|column| match column {
+                CsvColumn::Condition(column) => Some(column),
+                _ => None,
+            })
+            .collect();
+        Ok(ConfigRules {
+            version: None,
+            include: Vec::new(),
+            vars: Default::default(),
+            definitions: Default::default(),
+            pre: Vec::new(),
+            rules: Vec::new(),
+            post: Vec::new(),
+            tables: vec![DecisionTable { columns, rows }],
+            fallback: None,
+            params_spec: Default::default(),
+            match_mode: MatchMode::First,
+            merge_strategy: MergeStrategy::First,
+            datetime_format: None,
+        })
+    }
+}
+
+/// Condition value of a cell of a column comparing with `op`
+fn cell_value(op: &Operator, cell: &str, schema: &CsvSchema) -> Value {
+    if cell.is_empty() {
+        return Value::Null;
+    }
+    match op {
+        Operator::In
+        | Operator::NotIn
+        | Operator::Intersects
+        | Operator::SubsetOf
+        | Operator::Between
+        | Operator::IpInCidr
+        | Operator::LangMatches
+        | Operator::TimeBetween => cell
+            .split(schema.list_separator)
+            .map(|item| Value::from(item.trim()))
+            .collect(),
+        Operator::Exists | Operator::Missing => Value::Bool(true),
+        _ => Value::from(cell),
+    }
+}
+
+fn csv_error(error: csv::Error) -> ConfigExprError {
+    let message = match error.kind() {
+        csv::ErrorKind::UnequalLengths {
+            pos: Some(position),
+            expected_len,
+            len,
+        } => format!(
+            "line {}: {} cells for {} columns",
+            position.line(),
+            len,
+            expected_len
+        ),
+        _ => error.to_string(),
+    };
+    ConfigExprError::CsvError(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ConfigEvaluator;
+
+    #[test]
+    fn test_from_csv() {
+        let csv = "\
+id, platform:prefix, region:in, score:ge, beta:exists, then
+rtd_high, RTD, CN|JP, 60, , rtd_high
+, RTD, , , x, rtd_beta
+mt, MT, , , ,\"{\"\"chip\"\": \"\"mt\"\"}\"
+";
+        let rules = ConfigRules::from_csv(csv.as_bytes(), &CsvSchema::default()).unwrap();
+        let table = &rules.tables[0];
+        assert_eq!(table.columns.len(), 4);
+        assert_eq!(table.rows[0].id.as_deref(), Some("rtd_high"));
+        assert_eq!(
+            table.rows[0].when,
+            [
+                Value::from("RTD"),
+                serde_json::json!(["CN", "JP"]),
+                Value::from("60"),
+                Value::Null
+            ]
+        );
+
+        let evaluator = ConfigEvaluator::new(rules).unwrap();
+        let evaluate = |params: &[(&str, &str)]| {
+            evaluator.evaluate(&params.iter().copied().collect::<crate::HashMap<_, _>>())
+        };
+        assert_eq!(
+            evaluate(&[("platform", "RTD-1"), ("region", "JP"), ("score", "75")]),
+            Some(RuleResult::String("rtd_high".to_string()))
+        );
+        assert_eq!(
+            evaluate(&[("platform", "RTD-1"), ("beta", "1")]),
+            Some(RuleResult::String("rtd_beta".to_string()))
+        );
+        assert_eq!(evaluate(&[("platform", "RTD-1")]), None);
+        assert_eq!(
+            evaluate(&[("platform", "MT9632")]),
+            Some(RuleResult::Object(serde_json::json!({ "chip": "mt" })))
+        );
+
+        let error = |csv: &str| {
+            ConfigRules::from_csv(csv.as_bytes(), &CsvSchema::default())
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error("platform,result\nRTD,x\n"),
+            "CSV error: header has no 'then' column"
+        );
+        assert!(error("platform:nope,then\nRTD,x\n").contains("header 'platform:nope'"));
+        assert_eq!(
+            error("platform,then\nRTD,\n"),
+            "CSV error: line 2: missing result"
+        );
+        assert_eq!(
+            error("platform,then\nRTD,x,y\n"),
+            "CSV error: line 2: 3 cells for 2 columns"
+        );
+    }
+}
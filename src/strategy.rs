@@ -0,0 +1,200 @@
+//! proptest strategies generating rule sets and params
+//!
+//! The strategies draw fields and values from small vocabularies, so
+//! generated params often satisfy generated conditions and every rule gets
+//! exercised. Generated rule sets are valid, for checking evaluation
+//! invariants:
+//!
+//! ```ignore
+//! proptest! {
+//!     #[test]
+//!     fn evaluation_is_deterministic(rules in strategy::rules(), params in strategy::params()) {
+//!         let evaluator = ConfigEvaluator::new(rules).unwrap();
+//!         prop_assert_eq!(evaluator.evaluate(&params), evaluator.evaluate(&params));
+//!     }
+//! }
+//! ```
+//!
+//! Parsing is fuzzed with arbitrary bytes and
+//! [`parse_and_validate`](crate::parse_and_validate) instead.
+
+use crate::prelude::*;
+use crate::{Condition, ConfigRules, MatchMode, MergeStrategy, Operator, Rule, RuleResult};
+use alloc::collections::BTreeMap;
+use proptest::prelude::*;
+use proptest::sample::select;
+use serde_json::Value;
+
+/// Fields of generated conditions and params
+const FIELDS: &[&str] = &["platform", "region", "tier", "score"];
+
+/// String values of generated conditions, params and results
+const VALUES: &[&str] = &["RTD", "MT", "CN", "US", "gold", "free", "10", "75", ""];
+
+/// A field of the vocabulary
+pub fn field() -> impl Strategy<Value = String> {
+    select(FIELDS).prop_map(str::to_string)
+}
+
+/// A string value of the vocabulary
+pub fn value() -> impl Strategy<Value = String> {
+    select(VALUES).prop_map(str::to_string)
+}
+
+/// A simple condition with a value its operator accepts
+pub fn simple_condition() -> impl Strategy<Value = Condition> {
+    let string_ops = select(vec![
+        Operator::Equals,
+        Operator::Contains,
+        Operator::Prefix,
+        Operator::Suffix,
+        Operator::IEquals,
+    ]);
+    let numeric_ops = select(vec![
+        Operator::GreaterThan,
+        Operator::LessThan,
+        Operator::GreaterThanOrEqual,
+        Operator::LessThanOrEqual,
+    ]);
+    prop_oneof![
+        (field(), string_ops, value()).prop_map(|(field, op, value)| simple(
+            field,
+            op,
+            Value::String(value)
+        )),
+        (field(), numeric_ops, -100i64..100).prop_map(|(field, op, n)| simple(
+            field,
+            op,
+            Value::from(n)
+        )),
+        (
+            field(),
+            select(vec![Operator::In, Operator::NotIn]),
+            proptest::collection::vec(value(), 1..4)
+        )
+            .prop_map(|(field, op, items)| simple(
+                field,
+                op,
+                items.into_iter().map(Value::String).collect()
+            )),
+        (field(), select(vec![Operator::Exists, Operator::Missing]))
+            .prop_map(|(field, op)| simple(field, op, Value::Null)),
+    ]
+}
+
+/// A condition of simple conditions combined with `and`, `or` and `not`, at
+/// most four levels deep
+pub fn condition() -> impl Strategy<Value = Condition> {
+    simple_condition().prop_recursive(3, 24, 4, |inner| {
+        prop_oneof![
+            proptest::collection::vec(inner.clone(), 1..4).prop_map(|and| Condition::And { and }),
+            proptest::collection::vec(inner.clone(), 1..4).prop_map(|or| Condition::Or { or }),
+            inner.prop_map(|not| Condition::Not { not: Box::new(not) }),
+        ]
+    })
+}
+
+/// A string or object result
+pub fn result() -> impl Strategy<Value = RuleResult> {
+    prop_oneof![
+        value().prop_map(RuleResult::String),
+        (value(), -100i64..100).prop_map(|(name, n)| RuleResult::Object(
+            serde_json::json!({ "name": name, "value": n })
+        )),
+    ]
+}
+
+/// A main-phase rule, possibly disabled or prioritized
+pub fn rule() -> impl Strategy<Value = Rule> {
+    (condition(), result(), prop::bool::weighted(0.9), -2i32..3).prop_map(
+        |(condition, result, enabled, priority)| Rule {
+            id: None,
+            name: None,
+            description: None,
+            tags: Vec::new(),
+            metadata: Value::Null,
+            condition,
+            result,
+            enabled,
+            active_from: None,
+            active_until: None,
+            rollout: None,
+            schedule: None,
+            requires: Vec::new(),
+            conflicts_with: Vec::new(),
+            transform: None,
+            priority,
+            interpolate: false,
+            stage: None,
+        },
+    )
+}
+
+/// A rule set of up to eight main-phase rules, with an optional fallback,
+/// in either match mode
+pub fn rules() -> impl Strategy<Value = ConfigRules> {
+    (
+        proptest::collection::vec(rule(), 0..8),
+        proptest::option::of(result()),
+        select(vec![MatchMode::First, MatchMode::All]),
+    )
+        .prop_map(|(rules, fallback, match_mode)| ConfigRules {
+            version: None,
+            include: Vec::new(),
+            vars: BTreeMap::new(),
+            definitions: BTreeMap::new(),
+            pre: Vec::new(),
+            rules,
+            post: Vec::new(),
+            tables: Vec::new(),
+            fallback,
+            params_spec: BTreeMap::new(),
+            match_mode,
+            merge_strategy: MergeStrategy::First,
+            datetime_format: None,
+        })
+}
+
+/// Params with some of the vocabulary's fields
+pub fn params() -> impl Strategy<Value = HashMap<String, String>> {
+    proptest::collection::hash_map(field(), value(), 0..=FIELDS.len())
+}
+
+fn simple(field: String, op: Operator, value: Value) -> Condition {
+    Condition::Simple {
+        field,
+        op,
+        value,
+        value_field: None,
+        seed: String::new(),
+        trim: false,
+        normalize: None,
+        mode: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_and_validate, ConfigEvaluator};
+
+    proptest! {
+        #[test]
+        fn test_generated_rules(rules in rules(), params in params()) {
+            let json = serde_json::to_vec(&rules).unwrap();
+            let validated = parse_and_validate(&json).unwrap();
+            prop_assert_eq!(validated.rules(), &rules);
+
+            let evaluator = validated.into_evaluator();
+            let result = evaluator.evaluate(&params);
+            prop_assert_eq!(&result, &evaluator.evaluate_with_trace(&params).result);
+            let optimized = ConfigEvaluator::new(rules.optimize()).unwrap();
+            prop_assert_eq!(&result, &optimized.evaluate(&params));
+        }
+
+        #[test]
+        fn test_parse_arbitrary_bytes(bytes in any::<Vec<u8>>()) {
+            let _ = parse_and_validate(&bytes);
+        }
+    }
+}
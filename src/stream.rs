@@ -0,0 +1,65 @@
+//! Streaming evaluation for event pipelines
+
+use crate::{ConfigEvaluator, ConfigExprError, RuleResult};
+use futures::stream::{Stream, StreamExt};
+use std::collections::HashMap;
+
+impl ConfigEvaluator {
+    /// Evaluate a stream of parameter sets, yielding results in input order
+    ///
+    /// At most `concurrency` evaluations are in flight; the input stream is
+    /// only polled when there is room, so a slow consumer applies
+    /// backpressure to the producer. A `concurrency` of zero is treated as one.
+    pub fn evaluate_stream<'a, S>(
+        &'a self,
+        params: S,
+        concurrency: usize,
+    ) -> impl Stream<Item = Result<Option<RuleResult>, ConfigExprError>> + 'a
+    where
+        S: Stream<Item = HashMap<String, String>> + 'a,
+    {
+        params
+            .map(move |params| async move { self.evaluate_checked(&params) })
+            .buffered(concurrency.max(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::stream;
+
+    #[test]
+    fn test_evaluate_stream() {
+        let json = r#"
+        {
+            "rules": [
+                {
+                    "if": { "field": "platform", "op": "prefix", "value": "RTD" },
+                    "then": "chip_rtd"
+                },
+                {
+                    "if": { "field": "platform", "op": "equals", "value": "bad" },
+                    "then": { "error": "unsupported platform" }
+                }
+            ]
+        }
+        "#;
+
+        let evaluator = ConfigEvaluator::from_json(json).unwrap();
+        let inputs = ["RTD-2000", "MT9950", "bad"]
+            .into_iter()
+            .map(|platform| HashMap::from([("platform".to_string(), platform.to_string())]));
+        let results: Vec<_> =
+            block_on(evaluator.evaluate_stream(stream::iter(inputs), 2).collect());
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(
+            results[0].as_ref().unwrap(),
+            &Some(RuleResult::String("chip_rtd".to_string()))
+        );
+        assert_eq!(results[1].as_ref().unwrap(), &None);
+        assert!(results[2].is_err());
+    }
+}
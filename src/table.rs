@@ -0,0 +1,281 @@
+//! Decision tables: rules written as rows of values under shared columns
+//!
+//! A table lists its columns once, each a field and the operator comparing
+//! it, and every row gives the values of its columns and a result:
+//!
+//! ```json
+//! {
+//!     "columns": ["platform:prefix", "region"],
+//!     "rows": [
+//!         { "when": ["RTD", "CN"], "then": "rtd_cn" },
+//!         { "when": ["MT", null], "then": "mt" }
+//!     ]
+//! }
+//! ```
+//!
+//! When the rules are loaded, each row becomes a main-phase rule whose
+//! condition is the `and` of its non-null cells.
+
+use crate::prelude::*;
+use crate::validation::Site;
+use crate::{Condition, ConfigExprError, ConfigRules, Operator, Rule, RuleResult};
+use core::fmt;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+/// Rules sharing the fields and operators of their conditions
+///
+/// The rows of the `tables` of a rule set follow its explicit main-phase
+/// rules, in table and row order, and are reported as those rules once
+/// loaded: the first row of a set with three rules is `rule 3`.
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct DecisionTable {
+    /// Compared fields, written `"field"` for `equals` or `"field:op"`
+    pub columns: Vec<Column>,
+    pub rows: Vec<TableRow>,
+}
+
+/// Field of a decision table and the operator comparing it
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Column {
+    pub field: String,
+    pub op: Operator,
+}
+
+/// Row of a decision table, with a value per column
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct TableRow {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Condition values in column order; `null` matches any value, and
+    /// `exists` and `missing` columns take `true` to apply and `false` not to
+    pub when: Vec<Value>,
+    #[serde(rename = "then")]
+    pub result: RuleResult,
+}
+
+impl fmt::Display for Column {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.op {
+            Operator::Equals => f.write_str(&self.field),
+            ref op => write!(f, "{}:{}", self.field, op.name()),
+        }
+    }
+}
+
+impl Serialize for Column {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Column {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let column = String::deserialize(deserializer)?;
+        let (field, op) = match column.split_once(':') {
+            Some((field, op)) => {
+                let op = serde_json::from_value(Value::from(op)).map_err(|_| {
+                    serde::de::Error::custom(format!(
+                        "unknown operator '{}' in column '{}'",
+                        op, column
+                    ))
+                })?;
+                (field, op)
+            }
+            None => (column.as_str(), Operator::Equals),
+        };
+        if field.is_empty() {
+            return Err(serde::de::Error::custom(format!(
+                "missing field in column '{}'",
+                column
+            )));
+        }
+        Ok(Column {
+            field: field.to_string(),
+            op,
+        })
+    }
+}
+
+impl DecisionTable {
+    /// The rules of the rows
+    fn into_rules(self, site: &Site) -> Result<Vec<Rule>, ConfigExprError> {
+        let columns = self.columns;
+        self.rows
+            .into_iter()
+            .enumerate()
+            .map(|(index, row)| {
+                let site = site.at("rows").at(index).at("when");
+                if row.when.len() != columns.len() {
+                    return Err(site
+                        .error(
+                            "table_row_length",
+                            format!(
+                                "Row {} of {} has {} values for {} columns",
+                                index,
+                                site,
+                                row.when.len(),
+                                columns.len()
+                            ),
+                        )
+                        .into());
+                }
+                let mut conditions: Vec<Condition> = columns
+                    .iter()
+                    .zip(row.when)
+                    .filter_map(|(column, value)| {
+                        let value = match (&column.op, value) {
+                            (_, Value::Null) => return None,
+                            (Operator::Exists | Operator::Missing, Value::Bool(applies)) => {
+                                applies.then_some(Value::Null)?
+                            }
+                            (_, value) => value,
+                        };
+                        Some((column, value))
+                    })
+                    .map(|(column, value)| Condition::Simple {
+                        field: column.field.clone(),
+                        op: column.op.clone(),
+                        value,
+                        value_field: None,
+                        seed: String::new(),
+                        trim: false,
+                        normalize: None,
+                        mode: None,
+                    })
+                    .collect();
+                let condition = match conditions.len() {
+                    0 => {
+                        return Err(site
+                            .error(
+                                "empty_condition",
+                                format!(
+                                    "Row {} of {} has no values; use the fallback for results of any input",
+                                    index, site
+                                ),
+                            )
+                            .into())
+                    }
+                    1 => conditions.remove(0),
+                    _ => Condition::And { and: conditions },
+                };
+                Ok(Rule {
+                    id: row.id,
+                    name: row.name,
+                    description: None,
+                    tags: Vec::new(),
+                    metadata: Value::Null,
+                    condition,
+                    result: row.result,
+                    enabled: true,
+                    active_from: None,
+                    active_until: None,
+                    rollout: None,
+                    schedule: None,
+                    requires: Vec::new(),
+                    conflicts_with: Vec::new(),
+                    transform: None,
+                    priority: 0,
+                    interpolate: false,
+                    stage: None,
+                })
+            })
+            .collect()
+    }
+}
+
+impl ConfigRules {
+    /// Append the rows of the decision tables to the main-phase rules
+    pub(crate) fn expand_tables(&mut self) -> Result<(), ConfigExprError> {
+        for (index, table) in core::mem::take(&mut self.tables).into_iter().enumerate() {
+            let rules = table.into_rules(&Site::table(index))?;
+            self.rules.extend(rules);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ConfigEvaluator;
+
+    #[test]
+    fn test_decision_table() {
+        let json = r#"{
+            "rules": [{ "if": "tier == \"internal\"", "then": "debug" }],
+            "tables": [{
+                "columns": ["platform:prefix", "region", "score:ge", "beta:exists"],
+                "rows": [
+                    { "id": "rtd_cn", "when": ["RTD", "CN", 60, false], "then": "rtd_cn" },
+                    { "when": ["RTD", null, null, null], "then": "rtd" },
+                    { "when": [null, "JP", null, true], "then": "jp" }
+                ]
+            }],
+            "fallback": "default"
+        }"#;
+        let rules: ConfigRules = serde_json::from_str(json).unwrap();
+        assert_eq!(rules.tables[0].columns[0].op, Operator::Prefix);
+        let round_trip: ConfigRules =
+            serde_json::from_str(&serde_json::to_string(&rules).unwrap()).unwrap();
+        assert_eq!(round_trip, rules);
+
+        let evaluator = ConfigEvaluator::new(rules).unwrap();
+        let loaded = evaluator.rules();
+        assert!(loaded.tables.is_empty());
+        assert_eq!(loaded.rules.len(), 4);
+        assert_eq!(loaded.rules[1].id.as_deref(), Some("rtd_cn"));
+        assert_eq!(
+            loaded.rules[2].condition,
+            serde_json::from_str::<Condition>(r#""platform prefix \"RTD\"""#).unwrap()
+        );
+
+        let evaluate = |params: &[(&str, &str)]| {
+            evaluator
+                .evaluate(&params.iter().copied().collect::<HashMap<_, _>>())
+                .map(|result| result.deserialize_into::<String>().unwrap())
+        };
+        assert_eq!(
+            evaluate(&[("platform", "RTD-2000"), ("region", "CN"), ("score", "75")]),
+            Some("rtd_cn".to_string())
+        );
+        assert_eq!(
+            evaluate(&[("platform", "RTD-2000"), ("region", "CN"), ("score", "10")]),
+            Some("rtd".to_string())
+        );
+        assert_eq!(
+            evaluate(&[("platform", "MT"), ("region", "JP"), ("beta", "")]),
+            Some("jp".to_string())
+        );
+        assert_eq!(
+            evaluate(&[("platform", "MT"), ("region", "JP")]),
+            Some("default".to_string())
+        );
+        assert_eq!(
+            evaluate(&[("platform", "RTD"), ("tier", "internal")]),
+            Some("debug".to_string())
+        );
+
+        let error = |json: &str| ConfigEvaluator::from_json(json).unwrap_err();
+        let short = error(
+            r#"{ "rules": [], "tables": [{ "columns": ["a", "b"], "rows": [{ "when": [1], "then": "x" }] }] }"#,
+        );
+        let rule_error = short.rule_error().unwrap();
+        assert_eq!(rule_error.code, "table_row_length");
+        assert_eq!(rule_error.pointer, "/tables/0/rows/0/when");
+        assert_eq!(
+            error(r#"{ "rules": [], "tables": [{ "columns": ["a"], "rows": [{ "when": [null], "then": "x" }] }] }"#)
+                .error_code(),
+            "empty_condition"
+        );
+        assert!(
+            error(r#"{ "rules": [], "tables": [{ "columns": ["a:nope"], "rows": [] }] }"#)
+                .to_string()
+                .contains("tables[0].columns[0]")
+        );
+    }
+}
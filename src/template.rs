@@ -0,0 +1,152 @@
+//! `${field}` placeholders in the results of rules with `interpolate: true`
+//!
+//! Placeholders name a param or a dot-path into a nested param. `$$` stands
+//! for a literal `$`, so `$${field}` renders as `${field}`; any other `$` is
+//! kept as is. Computed expressions (strings starting with `=`) are left to
+//! the expression evaluator.
+
+use crate::prelude::*;
+use crate::{path, ConfigExprError, ParamSource, RuleResult};
+
+/// Part of a template string
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Piece<'a> {
+    Text(&'a str),
+    Field(&'a str),
+}
+
+/// Split a template into literal text and placeholders
+fn parse(template: &str) -> Result<Vec<Piece<'_>>, String> {
+    let mut pieces = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('$') {
+        let after = &rest[start + 1..];
+        if let Some(escaped) = after.strip_prefix('$') {
+            pieces.push(Piece::Text(&rest[..start + 1]));
+            rest = escaped;
+        } else if let Some(inner) = after.strip_prefix('{') {
+            pieces.push(Piece::Text(&rest[..start]));
+            let end = inner
+                .find('}')
+                .ok_or_else(|| "unterminated placeholder".to_string())?;
+            let field = inner[..end].trim();
+            if field.is_empty() {
+                return Err("empty placeholder".to_string());
+            }
+            path::validate(field)?;
+            pieces.push(Piece::Field(field));
+            rest = &inner[end + 1..];
+        } else {
+            pieces.push(Piece::Text(&rest[..start + 1]));
+            rest = after;
+        }
+    }
+    pieces.push(Piece::Text(rest));
+    Ok(pieces)
+}
+
+/// Check a template, returning the reason when it is malformed
+pub(crate) fn validate(template: &str) -> Result<(), String> {
+    parse(template).map(|_| ())
+}
+
+/// Fields referenced by the placeholders of a template
+pub(crate) fn fields(template: &str) -> Vec<&str> {
+    parse(template)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|piece| match piece {
+            Piece::Field(field) => Some(field),
+            Piece::Text(_) => None,
+        })
+        .collect()
+}
+
+/// Substitute the placeholders of a template from the params
+pub(crate) fn render(template: &str, params: &dyn ParamSource) -> Result<String, ConfigExprError> {
+    let pieces = parse(template).map_err(|e| {
+        ConfigExprError::ExpressionError(format!("Invalid template '{}': {}", template, e))
+    })?;
+    let mut rendered = String::with_capacity(template.len());
+    for piece in pieces {
+        match piece {
+            Piece::Text(text) => rendered.push_str(text),
+            Piece::Field(field) => rendered.push_str(
+                &path::lookup(params, field)
+                    .ok_or_else(|| ConfigExprError::MissingPlaceholder(field.to_string()))?,
+            ),
+        }
+    }
+    Ok(rendered)
+}
+
+/// The template strings of a result
+pub(crate) fn templates(result: &RuleResult) -> Vec<&str> {
+    fn visit<'a>(value: &'a serde_json::Value, out: &mut Vec<&'a str>) {
+        match value {
+            serde_json::Value::String(s) if !s.starts_with('=') => out.push(s),
+            serde_json::Value::Array(items) => items.iter().for_each(|item| visit(item, out)),
+            serde_json::Value::Object(obj) => obj.values().for_each(|item| visit(item, out)),
+            _ => {}
+        }
+    }
+
+    let mut out = Vec::new();
+    match result {
+        RuleResult::String(s) => out.push(s.as_str()),
+        RuleResult::Object(value) => visit(value, &mut out),
+    }
+    out
+}
+
+/// Render every template string inside a result value
+pub(crate) fn render_value(
+    value: &mut serde_json::Value,
+    params: &dyn ParamSource,
+) -> Result<(), ConfigExprError> {
+    match value {
+        serde_json::Value::String(s) if !s.starts_with('=') => *s = render(s, params)?,
+        serde_json::Value::Array(items) => {
+            for item in items {
+                render_value(item, params)?;
+            }
+        }
+        serde_json::Value::Object(obj) => {
+            for item in obj.values_mut() {
+                render_value(item, params)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_render() {
+        let params = HashMap::from([
+            ("platform".to_string(), "RTD".to_string()),
+            ("device".to_string(), r#"{ "region": "CN" }"#.to_string()),
+        ]);
+        assert_eq!(
+            render("chip_${platform}_${device.region}", &params).unwrap(),
+            "chip_RTD_CN"
+        );
+        assert_eq!(
+            render("$${platform} costs $5 or $$5", &params).unwrap(),
+            "${platform} costs $5 or $5"
+        );
+        assert!(matches!(
+            render("chip_${model}", &params),
+            Err(ConfigExprError::MissingPlaceholder(field)) if field == "model"
+        ));
+        assert!(validate("chip_${platform").is_err());
+        assert!(validate("chip_${}").is_err());
+        assert!(validate("chip_${a..b}").is_err());
+        assert_eq!(fields("${a}-${b.c}-$${d}"), vec!["a", "b.c"]);
+    }
+}
@@ -0,0 +1,114 @@
+//! Whitespace trimming and Unicode normalization of compared strings
+//!
+//! Conditions with `trim: true` strip surrounding whitespace from the param
+//! values they compare. With `normalize`, param values and string condition
+//! values are brought to the same Unicode normalization form first, so
+//! `"Cafe\u{301}"` and `"Café"` compare equal.
+
+use crate::prelude::*;
+use alloc::borrow::Cow;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use unicode_normalization::{is_nfc_quick, is_nfkc_quick, IsNormalized, UnicodeNormalization};
+
+/// Unicode normalization form applied before comparing strings
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Normalization {
+    /// Canonical composition: `e` + combining acute accent becomes `é`
+    Nfc,
+    /// Compatibility composition: also folds variants such as full-width
+    /// letters and ligatures (`Ｆｏｏ` becomes `Foo`)
+    Nfkc,
+}
+
+impl Normalization {
+    /// Normalize a string, borrowing it when it is already normalized
+    pub fn apply(self, s: &str) -> Cow<'_, str> {
+        let quick = match self {
+            Normalization::Nfc => is_nfc_quick(s.chars()),
+            Normalization::Nfkc => is_nfkc_quick(s.chars()),
+        };
+        if quick == IsNormalized::Yes {
+            return Cow::Borrowed(s);
+        }
+        Cow::Owned(match self {
+            Normalization::Nfc => s.nfc().collect(),
+            Normalization::Nfkc => s.nfkc().collect(),
+        })
+    }
+}
+
+/// Prepare a param value for comparison
+pub(crate) fn prepare_param(
+    value: Cow<'_, str>,
+    trim: bool,
+    normalize: Option<Normalization>,
+) -> Cow<'_, str> {
+    let value = match value {
+        Cow::Borrowed(s) if trim => Cow::Borrowed(s.trim()),
+        Cow::Owned(s) if trim && s.trim().len() != s.len() => Cow::Owned(s.trim().to_string()),
+        value => value,
+    };
+    match normalize {
+        Some(normalize) => match normalize.apply(&value) {
+            Cow::Borrowed(_) => value,
+            Cow::Owned(s) => Cow::Owned(s),
+        },
+        None => value,
+    }
+}
+
+/// Normalize the strings of a condition value, including list items
+pub(crate) fn prepare_condition_value(
+    value: &Value,
+    normalize: Option<Normalization>,
+) -> Cow<'_, Value> {
+    let Some(normalize) = normalize else {
+        return Cow::Borrowed(value);
+    };
+    match value {
+        Value::String(s) => match normalize.apply(s) {
+            Cow::Borrowed(_) => Cow::Borrowed(value),
+            Cow::Owned(s) => Cow::Owned(Value::String(s)),
+        },
+        Value::Array(items) => Cow::Owned(Value::Array(
+            items
+                .iter()
+                .map(|item| prepare_condition_value(item, Some(normalize)).into_owned())
+                .collect(),
+        )),
+        _ => Cow::Borrowed(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prepare_param() {
+        let decomposed = "Cafe\u{301} ";
+        assert_eq!(
+            prepare_param(Cow::Borrowed(decomposed), true, Some(Normalization::Nfc)),
+            "Café"
+        );
+        assert_eq!(
+            prepare_param(Cow::Borrowed(decomposed), false, None),
+            decomposed
+        );
+        assert_eq!(
+            prepare_param(
+                Cow::Owned(" Ｆｏｏ ".to_string()),
+                true,
+                Some(Normalization::Nfkc)
+            ),
+            "Foo"
+        );
+        assert!(matches!(
+            prepare_param(Cow::Borrowed(" Café "), true, Some(Normalization::Nfc)),
+            Cow::Borrowed("Café")
+        ));
+    }
+}
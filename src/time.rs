@@ -0,0 +1,465 @@
+//! Clock abstraction and civil time helpers
+//!
+//! `std::time` has no clock on `wasm32-unknown-unknown`; there the system
+//! clock reads `Date.now()` and durations are measured with it too. Without
+//! `std` there is no system clock at all: applications supply a [`Clock`]
+//! reading the device's time, and durations are not measured.
+
+use crate::prelude::*;
+use core::fmt;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Source of the current time for time-dependent rules
+///
+/// The one required method is the same with and without `std`, so a clock
+/// written for a device keeps compiling when another crate enables `std`.
+pub trait Clock: Send + Sync + fmt::Debug {
+    /// Return the current time as seconds since the Unix epoch, or `None`
+    /// while it is unknown (e.g. before the device has synchronized it)
+    ///
+    /// Rules with an activation window or schedule never match while the
+    /// time is unknown.
+    fn unix_seconds(&self) -> Option<i64>;
+
+    /// Return the current time, or `None` while it is unknown
+    #[cfg(feature = "std")]
+    fn now(&self) -> Option<SystemTime> {
+        self.unix_seconds().map(system_time)
+    }
+}
+
+/// Current time of a clock as seconds since the Unix epoch
+pub(crate) fn now(clock: &dyn Clock) -> Option<i64> {
+    clock.unix_seconds()
+}
+
+/// Clock of evaluators without `std`, until one is set with
+/// [`ConfigEvaluator::with_clock`](crate::ConfigEvaluator::with_clock)
+#[cfg(not(feature = "std"))]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct UnknownTime;
+
+#[cfg(not(feature = "std"))]
+impl Clock for UnknownTime {
+    fn unix_seconds(&self) -> Option<i64> {
+        None
+    }
+}
+
+/// Clock reading the system time
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+#[cfg(feature = "std")]
+impl SystemClock {
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    fn system_time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs_f64(js_sys::Date::now() / 1000.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Clock for SystemClock {
+    fn unix_seconds(&self) -> Option<i64> {
+        Some(unix_seconds(self.system_time()))
+    }
+}
+
+/// Measures elapsed wall time, also on targets without `Instant`
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Stopwatch {
+    #[cfg(all(
+        feature = "std",
+        not(all(target_arch = "wasm32", target_os = "unknown"))
+    ))]
+    started: std::time::Instant,
+    #[cfg(all(feature = "std", target_arch = "wasm32", target_os = "unknown"))]
+    started: SystemTime,
+}
+
+impl Stopwatch {
+    pub(crate) fn start() -> Self {
+        Stopwatch {
+            #[cfg(all(
+                feature = "std",
+                not(all(target_arch = "wasm32", target_os = "unknown"))
+            ))]
+            started: std::time::Instant::now(),
+            #[cfg(all(feature = "std", target_arch = "wasm32", target_os = "unknown"))]
+            started: SystemClock.system_time(),
+        }
+    }
+
+    /// Time since the start, zero without `std`
+    pub(crate) fn elapsed(&self) -> Duration {
+        #[cfg(all(
+            feature = "std",
+            not(all(target_arch = "wasm32", target_os = "unknown"))
+        ))]
+        return self.started.elapsed();
+        #[cfg(all(feature = "std", target_arch = "wasm32", target_os = "unknown"))]
+        return SystemClock
+            .system_time()
+            .duration_since(self.started)
+            .unwrap_or_default();
+        #[cfg(not(feature = "std"))]
+        return Duration::ZERO;
+    }
+}
+
+/// Clock always returning the same time, useful in tests
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub SystemTime);
+
+#[cfg(feature = "std")]
+impl FixedClock {
+    /// Create a fixed clock from seconds since the Unix epoch
+    pub fn from_unix_seconds(seconds: i64) -> Self {
+        FixedClock(system_time(seconds))
+    }
+}
+
+#[cfg(feature = "std")]
+impl Clock for FixedClock {
+    fn unix_seconds(&self) -> Option<i64> {
+        Some(unix_seconds(self.0))
+    }
+}
+
+/// Broken-down local time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct LocalTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    /// Day of week, 0 = Sunday
+    pub weekday: u32,
+}
+
+/// Convert seconds since the Unix epoch to a system time
+#[cfg(feature = "std")]
+fn system_time(seconds: i64) -> SystemTime {
+    let offset = Duration::from_secs(seconds.unsigned_abs());
+    if seconds >= 0 {
+        UNIX_EPOCH + offset
+    } else {
+        UNIX_EPOCH - offset
+    }
+}
+
+/// Convert a system time to seconds since the Unix epoch
+#[cfg(feature = "std")]
+pub(crate) fn unix_seconds(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    }
+}
+
+/// Break down a Unix timestamp shifted by a UTC offset
+pub(crate) fn local_time(unix: i64, offset_seconds: i64) -> LocalTime {
+    let local = unix + offset_seconds;
+    let days = local.div_euclid(86_400);
+    let seconds_of_day = local.rem_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+    LocalTime {
+        year,
+        month,
+        day,
+        hour: (seconds_of_day / 3600) as u32,
+        minute: (seconds_of_day % 3600 / 60) as u32,
+        // 1970-01-01 was a Thursday
+        weekday: (days + 4).rem_euclid(7) as u32,
+    }
+}
+
+/// Convert days since the Unix epoch to a (year, month, day) civil date
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Convert a (year, month, day) civil date to days since the Unix epoch
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = i64::from((month + 9) % 12);
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Parse an RFC 3339 timestamp like `2024-03-01T08:00:00+08:00` into
+/// seconds since the Unix epoch
+///
+/// Fractional seconds are accepted and truncated.
+pub(crate) fn parse_rfc3339(s: &str) -> Option<i64> {
+    let (date, rest) = s.split_once(['T', 't', ' '])?;
+    let mut date_parts = date.splitn(3, '-');
+    let year: i64 = date_parts.next().filter(|y| y.len() == 4)?.parse().ok()?;
+    let month: u32 = date_parts.next().filter(|m| m.len() == 2)?.parse().ok()?;
+    let day: u32 = date_parts.next().filter(|d| d.len() == 2)?.parse().ok()?;
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+
+    let tz_start = rest.find(['Z', 'z', '+', '-'])?;
+    let (clock, tz) = rest.split_at(tz_start);
+    let offset = match tz {
+        "Z" | "z" => 0,
+        _ if tz.len() == 6 => parse_utc_offset(tz)?,
+        _ => return None,
+    };
+    let clock = match clock.split_once('.') {
+        Some((clock, fraction))
+            if !fraction.is_empty() && fraction.bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            clock
+        }
+        Some(_) => return None,
+        None => clock,
+    };
+    let mut clock_parts = clock.splitn(3, ':');
+    let mut field = |max: u32| -> Option<i64> {
+        let part = clock_parts.next().filter(|p| p.len() == 2)?;
+        let value: u32 = part.parse().ok()?;
+        (value <= max).then_some(i64::from(value))
+    };
+    // Second 60 is allowed for leap seconds
+    let (hour, minute, second) = (field(23)?, field(59)?, field(60)?);
+
+    let days = days_from_civil(year, month, day);
+    Some(days * 86_400 + hour * 3600 + minute * 60 + second - offset)
+}
+
+/// Parse a datetime for the `before` / `after` / `between` operators
+///
+/// Without a format, accepts RFC 3339 timestamps and bare `YYYY-MM-DD` dates
+/// (midnight UTC); otherwise see [`parse_with_format`].
+pub(crate) fn parse_datetime(s: &str, format: Option<&str>) -> Option<i64> {
+    let s = s.trim();
+    match format {
+        Some(format) => parse_with_format(s, format),
+        None => parse_rfc3339(s).or_else(|| parse_with_format(s, "%Y-%m-%d")),
+    }
+}
+
+/// Parse a timestamp with a strftime-style format
+///
+/// Supports `%Y`, `%m`, `%d`, `%H`, `%M`, `%S`, `%z` (`Z`, `+08:00` or
+/// `+0800`) and `%%`. Fields missing from the format default to the first
+/// day of the month at midnight, UTC.
+pub(crate) fn parse_with_format(s: &str, format: &str) -> Option<i64> {
+    fn digits(input: &mut &str, count: usize) -> Option<u32> {
+        let part = input.get(..count)?;
+        if !part.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        *input = &input[count..];
+        part.parse().ok()
+    }
+
+    let mut input = s;
+    let (mut year, mut month, mut day) = (1970, 1, 1);
+    let (mut hour, mut minute, mut second) = (0, 0, 0);
+    let mut offset = 0;
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            input = input.strip_prefix(c)?;
+            continue;
+        }
+        match chars.next()? {
+            'Y' => year = i64::from(digits(&mut input, 4)?),
+            'm' => month = digits(&mut input, 2)?,
+            'd' => day = digits(&mut input, 2)?,
+            'H' => hour = digits(&mut input, 2)?,
+            'M' => minute = digits(&mut input, 2)?,
+            'S' => second = digits(&mut input, 2)?,
+            'z' => {
+                if let Some(rest) = input.strip_prefix(['Z', 'z']) {
+                    input = rest;
+                    offset = 0;
+                    continue;
+                }
+                let sign = match input.as_bytes().first()? {
+                    b'+' => 1,
+                    b'-' => -1,
+                    _ => return None,
+                };
+                input = &input[1..];
+                let hours = digits(&mut input, 2)?;
+                input = input.strip_prefix(':').unwrap_or(input);
+                let minutes = digits(&mut input, 2)?;
+                if hours > 23 || minutes > 59 {
+                    return None;
+                }
+                offset = sign * i64::from(hours * 3600 + minutes * 60);
+            }
+            '%' => input = input.strip_prefix('%')?,
+            _ => return None,
+        }
+    }
+    if !input.is_empty()
+        || !(1..=12).contains(&month)
+        || day == 0
+        || day > days_in_month(year, month)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day);
+    Some(days * 86_400 + i64::from(hour * 3600 + minute * 60 + second) - offset)
+}
+
+/// Check that a strftime-style format only uses supported specifiers
+pub(crate) fn validate_format(format: &str) -> Result<(), String> {
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c == '%' {
+            match chars.next() {
+                Some('Y' | 'm' | 'd' | 'H' | 'M' | 'S' | 'z' | '%') => {}
+                Some(other) => return Err(format!("unsupported specifier '%{}'", other)),
+                None => return Err("trailing '%'".to_string()),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Parse a timezone given as `UTC`, `Z` or a fixed offset like `+08:00`
+pub(crate) fn parse_utc_offset(tz: &str) -> Option<i64> {
+    if tz.eq_ignore_ascii_case("utc") || tz == "Z" {
+        return Some(0);
+    }
+    let (sign, rest) = match tz.as_bytes().first()? {
+        b'+' => (1, &tz[1..]),
+        b'-' => (-1, &tz[1..]),
+        _ => return None,
+    };
+    let (hours, minutes) = rest.split_once(':').unwrap_or((rest, "0"));
+    let hours: i64 = hours.parse().ok()?;
+    let minutes: i64 = minutes.parse().ok()?;
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Parse a `HH:MM` time of day into minutes since midnight
+pub(crate) fn parse_time_of_day(s: &str) -> Option<u32> {
+    let (hours, minutes) = s.trim().split_once(':')?;
+    let hours: u32 = hours.parse().ok()?;
+    let minutes: u32 = minutes.parse().ok()?;
+    (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_time() {
+        // 2024-02-29T13:45:00Z, a Thursday
+        let t = local_time(1_709_214_300, 0);
+        assert_eq!((t.year, t.month, t.day), (2024, 2, 29));
+        assert_eq!((t.hour, t.minute, t.weekday), (13, 45, 4));
+
+        // Shifted into the next day by +12:00
+        let t = local_time(1_709_214_300, 12 * 3600);
+        assert_eq!((t.month, t.day, t.hour, t.weekday), (3, 1, 1, 5));
+    }
+
+    #[test]
+    fn test_clock() {
+        // A device clock implements the same method with or without `std`
+        #[derive(Debug)]
+        struct DeviceClock(Option<i64>);
+
+        impl Clock for DeviceClock {
+            fn unix_seconds(&self) -> Option<i64> {
+                self.0
+            }
+        }
+
+        assert_eq!(now(&DeviceClock(None)), None);
+        assert_eq!(now(&DeviceClock(Some(-1))), Some(-1));
+        #[cfg(feature = "std")]
+        {
+            let clock = FixedClock::from_unix_seconds(1_709_214_300);
+            assert_eq!(clock.now(), Some(clock.0));
+            assert_eq!(DeviceClock(Some(1_709_214_300)).now(), Some(clock.0));
+            assert_eq!(DeviceClock(None).now(), None);
+        }
+    }
+
+    #[test]
+    fn test_parse_utc_offset() {
+        assert_eq!(parse_utc_offset("UTC"), Some(0));
+        assert_eq!(parse_utc_offset("+08:00"), Some(8 * 3600));
+        assert_eq!(parse_utc_offset("-05:30"), Some(-(5 * 3600 + 30 * 60)));
+        assert_eq!(parse_utc_offset("Asia/Shanghai"), None);
+    }
+
+    #[test]
+    fn test_parse_rfc3339() {
+        assert_eq!(parse_rfc3339("2024-02-29T13:45:00Z"), Some(1_709_214_300));
+        assert_eq!(
+            parse_rfc3339("2024-02-29T21:45:00.250+08:00"),
+            Some(1_709_214_300)
+        );
+        assert_eq!(parse_rfc3339("1969-12-31T23:59:59Z"), Some(-1));
+        assert_eq!(parse_rfc3339("2023-02-29T00:00:00Z"), None);
+        assert_eq!(parse_rfc3339("2024-02-29T13:45:00"), None);
+        assert_eq!(parse_rfc3339("2024-02-29"), None);
+    }
+
+    #[test]
+    fn test_parse_datetime() {
+        assert_eq!(parse_datetime("2024-02-29", None), Some(1_709_164_800));
+        assert_eq!(
+            parse_datetime("2024-02-29T13:45:00Z", None),
+            Some(1_709_214_300)
+        );
+        assert_eq!(
+            parse_datetime("29/02/2024 21:45 +0800", Some("%d/%m/%Y %H:%M %z")),
+            Some(1_709_214_300)
+        );
+        assert_eq!(parse_datetime("29/02/2024", Some("%Y-%m-%d")), None);
+        assert_eq!(parse_datetime("2024-02-30", None), None);
+        assert!(validate_format("%Y-%m-%dT%H:%M:%S%z").is_ok());
+        assert!(validate_format("%Y-%j").is_err());
+    }
+}
@@ -0,0 +1,191 @@
+//! `tracing` instrumentation of evaluations
+//!
+//! Evaluations run in an `INFO` span `config_expr.evaluate`, which records the
+//! selected rule once the evaluation finishes. Rule checks are events: matches
+//! at `DEBUG`, misses and skipped rules at `TRACE`, the latter along with the
+//! outcome of every simple condition of the rule. The subscriber's level
+//! filter for the `clia_config_expr` target picks the verbosity.
+
+use crate::report::{ConditionTrace, Phase, SkipReason};
+use crate::{ConfigEvaluator, ConfigExprError, ConfigRules, Outcome, ParamSource, Rule};
+use tracing::field::Empty;
+use tracing::span::EnteredSpan;
+use tracing::Level;
+
+/// Span covering one evaluation, entered until it finishes
+pub(crate) struct EvaluationSpan(EnteredSpan);
+
+impl EvaluationSpan {
+    pub(crate) fn start(rules: &ConfigRules) -> Self {
+        let span = tracing::info_span!(
+            "config_expr.evaluate",
+            rules.version = rules.version.as_deref(),
+            rule.index = Empty,
+            rule.id = Empty,
+            fallback = Empty,
+            matched = Empty,
+        );
+        EvaluationSpan(span.entered())
+    }
+
+    pub(crate) fn finish(self, rules: &ConfigRules, outcome: &Result<Outcome, ConfigExprError>) {
+        match outcome {
+            Ok(outcome) => {
+                if let Some(index) = outcome.rule_index {
+                    self.0.record("rule.index", index);
+                    self.0.record("rule.id", rules.rules[index].id.as_deref());
+                }
+                let fallback = outcome.rule_index.is_none() && rules.fallback.is_some();
+                self.0.record("fallback", fallback);
+                self.0.record("matched", outcome.result.is_some());
+            }
+            Err(error) => tracing::debug!(%error, "evaluation failed"),
+        }
+    }
+}
+
+impl ConfigEvaluator {
+    /// Emit the events of a rule check
+    pub(crate) fn trace_rule_check(
+        &self,
+        phase: Phase,
+        index: usize,
+        rule: &Rule,
+        params: &dyn ParamSource,
+        skipped: Option<SkipReason>,
+        matched: bool,
+    ) {
+        let id = rule.id.as_deref();
+        let name = rule.name.as_deref();
+        if let Some(reason) = skipped {
+            tracing::trace!(
+                ?phase,
+                rule.index = index,
+                rule.id = id,
+                rule.name = name,
+                ?reason,
+                "rule skipped"
+            );
+            return;
+        }
+        if matched {
+            tracing::debug!(
+                ?phase,
+                rule.index = index,
+                rule.id = id,
+                rule.name = name,
+                "rule matched"
+            );
+        }
+        if !tracing::enabled!(Level::TRACE) {
+            return;
+        }
+        if !matched {
+            tracing::trace!(
+                ?phase,
+                rule.index = index,
+                rule.id = id,
+                rule.name = name,
+                "rule missed"
+            );
+        }
+        // The compiled conditions keep no outcomes, so they are traced anew
+        self.trace_condition(&rule.condition, params)
+            .for_each_simple(&mut |leaf| {
+                if let ConditionTrace::Simple {
+                    field,
+                    op,
+                    actual,
+                    matched,
+                    ..
+                } = leaf
+                {
+                    tracing::trace!(
+                        rule.index = index,
+                        field = field.as_str(),
+                        ?op,
+                        actual = actual.as_deref(),
+                        matched,
+                        "condition checked"
+                    );
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ConfigEvaluator;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use tracing::Level;
+    use tracing_subscriber::fmt::format::FmtSpan;
+
+    /// Collect the formatted output of evaluating `params` at `level`
+    fn capture(evaluator: &ConfigEvaluator, params: &[(&str, &str)], level: Level) -> String {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let writer = output.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(level)
+            .with_span_events(FmtSpan::CLOSE)
+            .without_time()
+            .with_writer(move || Writer(writer.clone()))
+            .finish();
+        let params: HashMap<String, String> = params
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        tracing::subscriber::with_default(subscriber, || evaluator.evaluate(&params));
+        let output = output.lock().unwrap();
+        String::from_utf8(output.clone()).unwrap()
+    }
+
+    struct Writer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Writer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_tracing_events() {
+        let evaluator = ConfigEvaluator::from_json(
+            r#"{
+                "version": "v7",
+                "rules": [
+                    { "id": "off", "enabled": false, "if": "platform == \"RTD\"", "then": "off" },
+                    { "id": "cn", "if": { "and": ["region == \"CN\"", "score >= 60"] }, "then": "cn" },
+                    { "id": "rtd", "name": "RTD devices", "if": "platform == \"RTD\"", "then": "rtd" }
+                ],
+                "fallback": "default"
+            }"#,
+        )
+        .unwrap();
+        let params = [("region", "CN"), ("score", "40"), ("platform", "RTD")];
+
+        let info = capture(&evaluator, &params, Level::INFO);
+        assert!(info.contains("config_expr.evaluate"));
+        assert!(info.contains("rules.version=\"v7\""));
+        assert!(info.contains("rule.index=2 rule.id=\"rtd\" fallback=false matched=true"));
+        assert!(!info.contains("rule matched"));
+
+        let debug = capture(&evaluator, &params, Level::DEBUG);
+        assert!(debug.contains("rule matched"));
+        assert!(debug.contains("rule.name=\"RTD devices\""));
+        assert!(!debug.contains("rule missed"));
+
+        let trace = capture(&evaluator, &params, Level::TRACE);
+        assert!(
+            trace.contains("rule skipped phase=Main rule.index=0 rule.id=\"off\" reason=Disabled")
+        );
+        assert!(trace.contains("rule missed phase=Main rule.index=1"));
+        assert!(trace.contains(
+            "condition checked rule.index=1 field=\"score\" op=GreaterThanOrEqual actual=\"40\" matched=false"
+        ));
+    }
+}
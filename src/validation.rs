@@ -0,0 +1,190 @@
+//! Structured details of rule validation failures
+//!
+//! Rule validators report where they are through a [`Site`]: the rule's
+//! phase and index, a label for messages (`rule 3`) and a JSON pointer that
+//! grows as they descend into the rule (`/rules/3/if/and/1/value`).
+
+use crate::prelude::*;
+use crate::report::Phase;
+use crate::ConfigExprError;
+use core::fmt;
+use serde::Serialize;
+
+/// A rule (or fallback) that failed validation, with machine-readable
+/// details for API error responses
+///
+/// Conditions written in the textual syntax are addressed as the JSON tree
+/// they parse into, so their pointers may go below the string in the
+/// document.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RuleError {
+    /// Stable reason code, e.g. `invalid_regex` or `unsupported_value`
+    pub code: &'static str,
+    /// Phase of the offending rule; `None` for the fallback and definitions
+    pub phase: Option<Phase>,
+    /// Index of the offending rule within its phase; `None` for the fallback
+    /// and definitions
+    pub rule_index: Option<usize>,
+    /// JSON pointer to the offending value in the rule document
+    pub pointer: String,
+    /// Param the offending condition or setting refers to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    /// The offending value
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<serde_json::Value>,
+    /// Human-readable description, naming the rule
+    pub message: String,
+}
+
+impl RuleError {
+    /// Attach the param the failure refers to
+    pub(crate) fn field(mut self, field: &str) -> Self {
+        self.field = Some(field.to_string());
+        self
+    }
+
+    /// Attach the offending value
+    pub(crate) fn value(mut self, value: impl Into<serde_json::Value>) -> Self {
+        self.value = Some(value.into());
+        self
+    }
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl From<RuleError> for ConfigExprError {
+    fn from(error: RuleError) -> Self {
+        ConfigExprError::InvalidRule(Box::new(error))
+    }
+}
+
+/// Location inside a rule document being validated
+#[derive(Debug, Clone)]
+pub(crate) struct Site {
+    rule: Option<(Phase, usize)>,
+    /// Name of the named condition, outside rules
+    definition: Option<String>,
+    /// Index of the decision table, outside rules
+    table: Option<usize>,
+    pointer: String,
+}
+
+impl Site {
+    /// A rule of a phase
+    pub(crate) fn rule(phase: Phase, index: usize) -> Self {
+        let key = match phase {
+            Phase::Pre => "pre",
+            Phase::Main => "rules",
+            Phase::Post => "post",
+        };
+        Site {
+            rule: Some((phase, index)),
+            definition: None,
+            table: None,
+            pointer: format!("/{}/{}", key, index),
+        }
+    }
+
+    /// The fallback result
+    pub(crate) fn fallback() -> Self {
+        Site {
+            rule: None,
+            definition: None,
+            table: None,
+            pointer: "/fallback".to_string(),
+        }
+    }
+
+    /// A named condition of `definitions`
+    pub(crate) fn definition(name: &str) -> Self {
+        let site = Site {
+            rule: None,
+            definition: Some(name.to_string()),
+            table: None,
+            pointer: "/definitions".to_string(),
+        };
+        site.at(name)
+    }
+
+    /// A decision table of `tables`
+    pub(crate) fn table(index: usize) -> Self {
+        Site {
+            rule: None,
+            definition: None,
+            table: Some(index),
+            pointer: format!("/tables/{}", index),
+        }
+    }
+
+    /// The location of a key or index below this one
+    pub(crate) fn at(&self, key: impl fmt::Display) -> Self {
+        let key = key.to_string().replace('~', "~0").replace('/', "~1");
+        Site {
+            rule: self.rule,
+            definition: self.definition.clone(),
+            table: self.table,
+            pointer: format!("{}/{}", self.pointer, key),
+        }
+    }
+
+    /// A validation failure at this location
+    pub(crate) fn error(&self, code: &'static str, message: String) -> RuleError {
+        RuleError {
+            code,
+            phase: self.rule.map(|(phase, _)| phase),
+            rule_index: self.rule.map(|(_, index)| index),
+            pointer: self.pointer.clone(),
+            field: None,
+            value: None,
+            message,
+        }
+    }
+}
+
+/// The rule label used in messages, e.g. `post rule 2`, `definition
+/// 'is_cn_prod'`, `table 1` or `fallback`
+impl fmt::Display for Site {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.rule, &self.definition) {
+            (Some((phase, index)), _) => f.write_str(&phase.label(index)),
+            (None, Some(name)) => write!(f, "definition '{}'", name),
+            (None, None) => match self.table {
+                Some(index) => write!(f, "table {}", index),
+                None => f.write_str("fallback"),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_site_pointer() {
+        let site = Site::rule(Phase::Post, 2).at("if").at("and").at(1);
+        let error = site
+            .at("value")
+            .error("invalid_regex", format!("Invalid regex in {}", site))
+            .field("platform")
+            .value("(");
+        assert_eq!(error.pointer, "/post/2/if/and/1/value");
+        assert_eq!(error.message, "Invalid regex in post rule 2");
+        assert_eq!(
+            (error.phase, error.rule_index),
+            (Some(Phase::Post), Some(2))
+        );
+        assert_eq!(
+            Site::fallback()
+                .at("a/b~c")
+                .error("x", String::new())
+                .pointer,
+            "/fallback/a~1b~0c"
+        );
+    }
+}
@@ -0,0 +1,230 @@
+//! Constants of the `vars` section substituted into condition values
+//!
+//! A condition value that is exactly `"${vars.name}"` is replaced by the
+//! var's JSON value, so it may be a list or a number; inside longer strings
+//! the placeholder is replaced by the var's text. `$${vars.name}` stands for
+//! a literal `${vars.name}`.
+
+use crate::prelude::*;
+use crate::report::Phase;
+use crate::validation::Site;
+use crate::{Condition, ConfigExprError, ConfigRules};
+use alloc::collections::BTreeMap;
+use serde_json::Value;
+
+const PREFIX: &str = "${vars.";
+
+impl ConfigRules {
+    /// Replace the `${vars.name}` placeholders of condition values
+    pub(crate) fn substitute_vars(&mut self) -> Result<(), ConfigExprError> {
+        let vars = &self.vars;
+        let phases = [
+            (Phase::Pre, &mut self.pre),
+            (Phase::Main, &mut self.rules),
+            (Phase::Post, &mut self.post),
+        ];
+        for (phase, phase_rules) in phases {
+            for (index, rule) in phase_rules.iter_mut().enumerate() {
+                substitute_condition(
+                    &mut rule.condition,
+                    vars,
+                    &Site::rule(phase, index).at("if"),
+                )?;
+            }
+        }
+        for (name, condition) in &mut self.definitions {
+            substitute_condition(condition, vars, &Site::definition(name))?;
+        }
+        Ok(())
+    }
+}
+
+fn substitute_condition(
+    condition: &mut Condition,
+    vars: &BTreeMap<String, Value>,
+    site: &Site,
+) -> Result<(), ConfigExprError> {
+    match condition {
+        Condition::Simple { field, value, .. } => {
+            substitute_value(value, vars, field, &site.at("value"))
+        }
+        Condition::And { and } => and
+            .iter_mut()
+            .enumerate()
+            .try_for_each(|(i, c)| substitute_condition(c, vars, &site.at("and").at(i))),
+        Condition::Or { or } => or
+            .iter_mut()
+            .enumerate()
+            .try_for_each(|(i, c)| substitute_condition(c, vars, &site.at("or").at(i))),
+        Condition::Not { not } => substitute_condition(not, vars, &site.at("not")),
+        Condition::Ref { .. } => Ok(()),
+    }
+}
+
+fn substitute_value(
+    value: &mut Value,
+    vars: &BTreeMap<String, Value>,
+    field: &str,
+    site: &Site,
+) -> Result<(), ConfigExprError> {
+    match value {
+        Value::String(s) if s.contains(PREFIX) => *value = substitute_str(s, vars, field, site)?,
+        Value::Array(items) => {
+            for (i, item) in items.iter_mut().enumerate() {
+                substitute_value(item, vars, field, &site.at(i))?;
+            }
+        }
+        Value::Object(obj) => {
+            for (key, item) in obj.iter_mut() {
+                substitute_value(item, vars, field, &site.at(key))?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Substitute the placeholders of a string value
+fn substitute_str(
+    s: &str,
+    vars: &BTreeMap<String, Value>,
+    field: &str,
+    site: &Site,
+) -> Result<Value, ConfigExprError> {
+    let lookup = |name: &str| {
+        vars.get(name).ok_or_else(|| {
+            ConfigExprError::from(
+                site.error("unknown_var", format!("Unknown var '{}' in {}", name, site))
+                    .field(field)
+                    .value(s),
+            )
+        })
+    };
+    if let Some(name) = s
+        .strip_prefix(PREFIX)
+        .and_then(|rest| rest.strip_suffix('}'))
+        .filter(|name| !name.contains('}'))
+    {
+        return lookup(name).cloned();
+    }
+
+    let mut out = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find(PREFIX) {
+        let after = &rest[start + PREFIX.len()..];
+        if rest[..start].ends_with('$') {
+            out.push_str(&rest[..start - 1]);
+            out.push_str(PREFIX);
+            rest = after;
+            continue;
+        }
+        out.push_str(&rest[..start]);
+        let Some(end) = after.find('}') else {
+            // Not a placeholder
+            out.push_str(&rest[start..]);
+            rest = "";
+            break;
+        };
+        let name = &after[..end];
+        match lookup(name)? {
+            Value::String(text) => out.push_str(text),
+            text @ (Value::Number(_) | Value::Bool(_)) => out.push_str(&text.to_string()),
+            _ => {
+                return Err(site
+                    .error(
+                        "invalid_var",
+                        format!(
+                            "Var '{}' is not a string, number or boolean and must be the whole value in {}",
+                            name, site
+                        ),
+                    )
+                    .field(field)
+                    .value(s)
+                    .into())
+            }
+        }
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(Value::String(out))
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{ConfigEvaluator, RuleResult};
+    use std::collections::HashMap;
+
+    fn rules(json: serde_json::Value) -> ConfigRules {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_vars() {
+        let evaluator = ConfigEvaluator::new(rules(serde_json::json!({
+            "vars": { "prod_regions": ["CN", "JP"], "min_score": 60, "env": "prod" },
+            "definitions": {
+                "is_prod": { "field": "env", "op": "equals", "value": "${vars.env}" }
+            },
+            "rules": [
+                {
+                    "if": {
+                        "and": [
+                            { "ref": "is_prod" },
+                            "region in \"${vars.prod_regions}\"",
+                            { "field": "score", "op": "ge", "value": "${vars.min_score}" }
+                        ]
+                    },
+                    "then": "prod"
+                },
+                { "if": "label == \"${vars.env}-$${vars.env}\"", "then": "label" }
+            ]
+        })))
+        .unwrap();
+        let Condition::And { and } = &evaluator.rules().rules[0].condition else {
+            panic!("Expected AND");
+        };
+        assert!(matches!(
+            &and[1],
+            Condition::Simple { value, .. } if *value == serde_json::json!(["CN", "JP"])
+        ));
+
+        let params = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<_, _>>()
+        };
+        let prod = params(&[("env", "prod"), ("region", "JP"), ("score", "75")]);
+        assert_eq!(
+            evaluator.evaluate(&prod),
+            Some(RuleResult::String("prod".to_string()))
+        );
+        let label = params(&[("label", "prod-${vars.env}")]);
+        assert_eq!(
+            evaluator.evaluate(&label),
+            Some(RuleResult::String("label".to_string()))
+        );
+
+        let Err(ConfigExprError::InvalidRule(error)) = ConfigEvaluator::new(rules(
+            serde_json::json!({
+                "vars": { "regions": ["CN"] },
+                "rules": [{ "if": { "field": "region", "op": "in", "value": ["${vars.region}"] }, "then": "x" }]
+            }),
+        )) else {
+            panic!("Expected an invalid rule");
+        };
+        assert_eq!(error.code, "unknown_var");
+        assert_eq!(error.pointer, "/rules/0/if/value/0");
+        assert_eq!(error.message, "Unknown var 'region' in rule 0");
+
+        let error = crate::validate_json(
+            r#"{
+                "vars": { "regions": ["CN"] },
+                "rules": [{ "if": "region == \"in ${vars.regions}\"", "then": "x" }]
+            }"#,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("must be the whole value"));
+    }
+}
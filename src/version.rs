@@ -0,0 +1,60 @@
+//! Semantic version comparison for the `semver_*` operators
+//!
+//! Versions are parsed with the `semver` crate after light normalization
+//! common in firmware strings: a leading `v` is ignored and missing minor or
+//! patch components count as zero, so `v1.10` reads as `1.10.0`.
+
+use crate::Operator;
+use core::cmp::Ordering;
+use semver::Version;
+
+/// Parse a version string leniently
+pub(crate) fn parse(s: &str) -> Option<Version> {
+    let s = s.trim();
+    let s = s.strip_prefix(['v', 'V']).unwrap_or(s);
+    if let Ok(version) = Version::parse(s) {
+        return Some(version);
+    }
+    // Pad the numeric core to three components, keeping any suffix
+    let split = s.find(['-', '+']).unwrap_or(s.len());
+    let (core, suffix) = s.split_at(split);
+    let parts = core.split('.').count();
+    if parts >= 3 {
+        return None;
+    }
+    let padded = format!("{}{}{}", core, ".0".repeat(3 - parts), suffix);
+    Version::parse(&padded).ok()
+}
+
+/// Compare two version strings, `None` when either is not a version
+pub(crate) fn compare(a: &str, b: &str) -> Option<Ordering> {
+    Some(parse(a)?.cmp_precedence(&parse(b)?))
+}
+
+/// Check whether a param version satisfies a `semver_*` comparison
+pub(crate) fn satisfies(op: &Operator, field_value: &str, target: &str) -> bool {
+    compare(field_value, target).is_some_and(|ordering| match op {
+        Operator::SemverEq => ordering.is_eq(),
+        Operator::SemverGt => ordering.is_gt(),
+        Operator::SemverGe => ordering.is_ge(),
+        Operator::SemverLt => ordering.is_lt(),
+        Operator::SemverLe => ordering.is_le(),
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_versions() {
+        assert_eq!(compare("1.10.2", "1.9.0"), Some(Ordering::Greater));
+        assert_eq!(compare("v2.1", "2.1.0"), Some(Ordering::Equal));
+        assert_eq!(compare("3", "3.0.1"), Some(Ordering::Less));
+        assert_eq!(compare("1.0.0-beta.2", "1.0.0"), Some(Ordering::Less));
+        assert_eq!(compare("1.0.0+build.5", "1.0.0"), Some(Ordering::Equal));
+        assert_eq!(compare("firmware", "1.0.0"), None);
+        assert_eq!(compare("1.2.3.4", "1.0.0"), None);
+    }
+}
@@ -0,0 +1,31 @@
+//! JavaScript bindings for browsers and other `wasm32` hosts
+//!
+//! Built as a `cdylib` with the `wasm` feature and bound with `wasm-bindgen`,
+//! the crate exposes `evaluateJson(rules, params)` and `validateJson(rules)`
+//! to JavaScript. Params are a plain object; like the other non-string param
+//! sources, numbers and booleans are converted to strings and `null` values
+//! dropped.
+
+use crate::params_from_value;
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+/// Evaluate JSON rules against a params object
+///
+/// Returns the matching result as a JavaScript value, or `null` when no
+/// rule matches. Throws on invalid rules or params.
+#[wasm_bindgen(js_name = evaluateJson)]
+pub fn evaluate_json(json: &str, params: JsValue) -> Result<JsValue, JsError> {
+    let params: serde_json::Value = serde_wasm_bindgen::from_value(params)?;
+    let params = params_from_value(params)?;
+    let result = crate::evaluate_json(json, &params)?;
+    let serializer = serde_wasm_bindgen::Serializer::new().serialize_maps_as_objects(true);
+    Ok(result.serialize(&serializer)?)
+}
+
+/// Validate JSON rules, throwing an error describing the first problem
+#[wasm_bindgen(js_name = validateJson)]
+pub fn validate_json(json: &str) -> Result<(), JsError> {
+    crate::validate_json(json)?;
+    Ok(())
+}